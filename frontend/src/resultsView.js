@@ -4,12 +4,18 @@ import { searchTemplate } from './search.js';
 import ndjsonStream from 'can-ndjson-stream';
 import { routers } from './cache.js';
 
-const resultTemplate = (result, havePeerColumn, dnsMap, asnMap, communityMap) => html`
+const asnTitle = ({ name, org, country }) =>
+	[name, org, country].filter(part => !!part).join(", ");
+
+const geoTitle = ({ country, asn, as_org }) =>
+	[country, asn && `AS${asn}`, as_org].filter(part => !!part).join(", ");
+
+const resultTemplate = (result, havePeerColumn, dnsMap, asnMap, communityMap, geoMap) => html`
 	<tr class=${result.state}>
 		<td><span>${result.client_name}</span></td>
 		${havePeerColumn ? html`<td><span>${result.peer_address}</span></td>` : ``}
-		<td><span>${result.net}</span></td>
-		<td><span>${result.as_path.map(asn => html`
+		<td><span title=${result.net in geoMap ? geoMap[result.net] : ''}>${result.net}</span></td>
+		<td><span>${result.as_path.map((asn, i) => html`
 			${asn in asnMap
 			? html`<span title=${asnMap[asn]}>${asn}</span>`
 			: html`<span>${asn}</span>`}
@@ -29,14 +35,14 @@ const resultTemplate = (result, havePeerColumn, dnsMap, asnMap, communityMap) =>
 		<td><span>${result.local_pref}</span></td>
 		<td>
 			${result.nexthop in dnsMap
-			? html`<span title=${result.nexthop}>${dnsMap[result.nexthop]}</span>`
-			: html`<span>${result.nexthop}</span>`}
+			? html`<span title=${[result.nexthop, geoMap[result.nexthop]].filter(part => !!part).join(", ")}>${dnsMap[result.nexthop]}</span>`
+			: html`<span title=${result.nexthop in geoMap ? geoMap[result.nexthop] : ''}>${result.nexthop}</span>`}
 		</td>
 		<td><span>${result.state}</span></td>
 	</tr>
 `;
 
-const resultsTemplate = (query, { routeResults, dnsMap, asnMap, communityMap }, done) => html`
+const resultsTemplate = (query, { routeResults, dnsMap, asnMap, communityMap, geoMap }, done) => html`
 	${searchTemplate(query)}
 
 	<div class="results">
@@ -57,7 +63,7 @@ const resultsTemplate = (query, { routeResults, dnsMap, asnMap, communityMap },
 					</tr>
 				</thead>
 				<tbody>
-					${routeResults.map(result => resultTemplate(result, routeResults.some(result => result.peer_address), dnsMap, asnMap, communityMap))}
+					${routeResults.map(result => resultTemplate(result, routeResults.some(result => result.peer_address), dnsMap, asnMap, communityMap, geoMap))}
 				</tbody>
 			</table>
 		` : ''}
@@ -82,11 +88,18 @@ const processResults = (results) => {
 	const routeResults = results.filter(r => !!r.Route).map(r => r.Route);
 	const dnsResults = results.filter(r => !!r.ReverseDns).map(r => r.ReverseDns);
 	const asnResults = results.filter(r => !!r.AsnName).map(r => r.AsnName);
+	const asnInfoResults = results.filter(r => !!r.AsnInfo).map(r => r.AsnInfo);
 	const communityResults = results.filter(r => !!r.CommunityDescription).map(r => r.CommunityDescription);
+	const geoResults = results.filter(r => !!r.GeoIp).map(r => r.GeoIp);
 
 	const dnsMap = Object.fromEntries(dnsResults.map(r => [r.nexthop, r.nexthop_resolved]));
-	const asnMap = Object.fromEntries(asnResults.map(r => [r.asn, r.asn_name ]));
+	// names from the asn_info file take precedence over the DNS lookups
+	const asnMap = Object.fromEntries([
+		...asnResults.map(r => [r.asn, r.asn_name ]),
+		...asnInfoResults.map(r => [r.asn, asnTitle(r) ]),
+	]);
 	const communityMap = Object.fromEntries(communityResults.map(r => [r.community, r.community_description ]));
+	const geoMap = Object.fromEntries(geoResults.map(r => [r.target, geoTitle(r) ]));
 
 	console.log(asnMap, communityMap, dnsMap);
 
@@ -161,7 +174,7 @@ const processResults = (results) => {
 		return 0;
 	});
 
-	return { routeResults: newResults, asnMap, communityMap, dnsMap };
+	return { routeResults: newResults, asnMap, communityMap, dnsMap, geoMap };
 };
 
 export const resultsView = async (query) => {
@@ -170,6 +183,10 @@ export const resultsView = async (query) => {
 
 	const searchParams = new URLSearchParams(optionsString);
 	searchParams.append(mode, ip);
+	// the API includes stale routes by default
+	if (!searchParams.has("include_stale")) {
+		searchParams.set("include_stale", "false");
+	}
 
 	const param_router = searchParams.get("Router");
 	if (param_router !== null) {
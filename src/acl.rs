@@ -0,0 +1,81 @@
+//! Source address filter for the connections accepted by the collectors, so a BMP or BGP port
+//! reachable from the internet only accepts sessions from the own routers.
+
+use ipnet::IpNet;
+use log::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AclAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AclConfig {
+    /// Only accept connections from these sources, all sources are accepted if empty
+    #[serde(default)]
+    pub allowed_sources: Vec<IpNet>,
+    /// Allow or deny single clients regardless of `allowed_sources`
+    #[serde(default)]
+    pub clients: HashMap<IpAddr, AclAction>,
+}
+
+fn matches(nets: &[IpNet], addr: IpAddr) -> bool {
+    nets.is_empty() || nets.iter().any(|net| net.contains(&addr))
+}
+
+impl AclConfig {
+    /// Whether `addr` may connect to a listener which additionally restricts its sources to
+    /// `listener_sources`. Client overrides also apply to the listener restriction.
+    pub fn allows(&self, addr: IpAddr, listener_sources: &[IpNet]) -> bool {
+        match self.clients.get(&addr) {
+            Some(action) => *action == AclAction::Allow,
+            None => matches(&self.allowed_sources, addr) && matches(listener_sources, addr),
+        }
+    }
+}
+
+/// Log a connection refused by the ACL and count it in `fernglas_rejected_connections_total`
+pub fn reject(client_addr: SocketAddr, listener: SocketAddr) {
+    info!(
+        "rejected connection from {} to {}, source is not allowed",
+        client_addr, listener
+    );
+    crate::metrics::REJECTED_CONNECTIONS
+        .with_label_values(&[&listener.to_string()])
+        .inc();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_overrides() {
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+        let acl = AclConfig {
+            allowed_sources: vec!["192.0.2.0/24".parse().unwrap()],
+            clients: [
+                (addr("192.0.2.66"), AclAction::Deny),
+                (addr("198.51.100.1"), AclAction::Allow),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let listener = ["192.0.2.0/25".parse().unwrap()];
+
+        assert!(acl.allows(addr("192.0.2.1"), &[]));
+        assert!(!acl.allows(addr("192.0.2.66"), &[]));
+        assert!(acl.allows(addr("198.51.100.1"), &[]));
+        assert!(!acl.allows(addr("203.0.113.1"), &[]));
+
+        assert!(!acl.allows(addr("192.0.2.200"), &listener));
+        assert!(acl.allows(addr("198.51.100.1"), &listener));
+        assert!(AclConfig::default().allows(addr("203.0.113.1"), &[]));
+    }
+}
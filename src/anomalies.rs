@@ -0,0 +1,152 @@
+//! Checks for suspicious routes which are worth a look by an operator, listed by `/api/anomalies`
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::ext_community::ExtCommunity;
+use crate::route_distinguisher::RouteDistinguisher;
+use crate::store::{PathId, TableSelector};
+
+/// Route distinguishers and route targets of a VPN, e.g. of one customer
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VpnInstanceConfig {
+    pub name: String,
+    pub route_distinguishers: Vec<RouteDistinguisher>,
+    /// Route targets the routes of the instance may carry, written like route distinguishers
+    pub route_targets: Vec<RouteDistinguisher>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ForeignRouteTarget {
+    pub route_target: RouteDistinguisher,
+    /// Instance the route target belongs to
+    pub instance: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "anomaly", rename_all = "snake_case")]
+pub enum Anomaly {
+    /// A VPN route carries route targets of another instance than the one of its route
+    /// distinguisher, a common cause of route leaks between VPNs
+    RouteTargetMismatch {
+        #[serde(flatten)]
+        table: TableSelector,
+        net: IpNet,
+        path_id: PathId,
+        /// Instance of the route distinguisher
+        instance: String,
+        foreign_route_targets: Vec<ForeignRouteTarget>,
+    },
+}
+
+/// Cross-check of the route distinguishers of VPN routes against their route targets
+#[derive(Debug, Default)]
+pub struct RouteTargetCheck {
+    instances: Vec<VpnInstanceConfig>,
+    by_route_distinguisher: HashMap<RouteDistinguisher, usize>,
+    by_route_target: HashMap<RouteDistinguisher, Vec<usize>>,
+}
+
+impl RouteTargetCheck {
+    pub fn new(instances: &[VpnInstanceConfig]) -> Self {
+        let mut check = RouteTargetCheck {
+            instances: instances.to_vec(),
+            ..Default::default()
+        };
+        for (index, instance) in instances.iter().enumerate() {
+            for rd in &instance.route_distinguishers {
+                check.by_route_distinguisher.insert(*rd, index);
+            }
+            for rt in &instance.route_targets {
+                check.by_route_target.entry(*rt).or_default().push(index);
+            }
+        }
+        check
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Name of the instance of the route distinguisher and the route targets among
+    /// `ext_communities` which only belong to other instances. Route targets which are not
+    /// configured for any instance are not reported.
+    pub fn check<'a>(
+        &self,
+        rd: &RouteDistinguisher,
+        ext_communities: impl IntoIterator<Item = &'a ExtCommunity>,
+    ) -> Option<(String, Vec<ForeignRouteTarget>)> {
+        let own = *self.by_route_distinguisher.get(rd)?;
+        let mut foreign = vec![];
+        for community in ext_communities {
+            let ExtCommunity::RouteTarget(rt) = community else {
+                continue;
+            };
+            let Some(instances) = self.by_route_target.get(rt) else {
+                continue;
+            };
+            if instances.contains(&own) {
+                continue;
+            }
+            for &index in instances {
+                foreign.push(ForeignRouteTarget {
+                    route_target: *rt,
+                    instance: self.instances[index].name.clone(),
+                });
+            }
+        }
+        if foreign.is_empty() {
+            return None;
+        }
+        Some((self.instances[own].name.clone(), foreign))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn route_target_mismatch() {
+        let rd = |s: &str| s.parse::<RouteDistinguisher>().unwrap();
+        let check = RouteTargetCheck::new(&[
+            VpnInstanceConfig {
+                name: "customer-a".to_string(),
+                route_distinguishers: vec![rd("64496:100")],
+                route_targets: vec![rd("64496:100"), rd("64496:1")],
+            },
+            VpnInstanceConfig {
+                name: "customer-b".to_string(),
+                route_distinguishers: vec![rd("64496:200")],
+                route_targets: vec![rd("64496:200"), rd("64496:1")],
+            },
+        ]);
+
+        // shared and unknown route targets are fine
+        let own = [
+            ExtCommunity::RouteTarget(rd("64496:100")),
+            ExtCommunity::RouteTarget(rd("64496:1")),
+            ExtCommunity::RouteTarget(rd("64511:1")),
+        ];
+        assert_eq!(check.check(&rd("64496:100"), &own), None);
+
+        let leaked = [
+            ExtCommunity::RouteTarget(rd("64496:100")),
+            ExtCommunity::RouteTarget(rd("64496:200")),
+        ];
+        assert_eq!(
+            check.check(&rd("64496:100"), &leaked),
+            Some((
+                "customer-a".to_string(),
+                vec![ForeignRouteTarget {
+                    route_target: rd("64496:200"),
+                    instance: "customer-b".to_string(),
+                }]
+            ))
+        );
+        // route distinguishers of no instance are not checked
+        assert_eq!(check.check(&rd("64511:1"), &leaked), None);
+    }
+}
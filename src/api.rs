@@ -1,40 +1,61 @@
-use crate::store::{NetQuery, Query, QueryLimits, QueryResult, Store};
+use crate::as_path::{AsPathMatchType, AsPathMatcher};
+use crate::asn_info::AsnInfo;
+use crate::change_filter::ChangeFilter;
+use crate::enrichment::{EnricherConfig, EnrichmentFuture, EnrichmentPipeline, EnrichmentSources};
+use crate::flowspec::FlowspecQuery;
+use crate::geoip::{GeoIpConfig, GeoIpInfo};
+use crate::irr::IrrStatus;
+use crate::mrt_export::MrtUpdateWriter;
+use crate::ownership::OwnedPrefix;
+use crate::query_cache::{QueryCache, QueryCacheConfig};
+use crate::route_distinguisher::RouteDistinguisher;
+use crate::rpki::RpkiStatus;
+use crate::show_route::TextStyle;
+use crate::store::{
+    default_include_stale, Client, ClientConnection, CommunityFormat, NetQuery, PathId, Query,
+    QueryLimits, QueryResult, RouteAttrs, RouteChange, RouteState, Session, SessionId, Store,
+    StoreError, SyncProgress, TableQuery, TableScope, TableSelector, TableType,
+};
+use crate::tls::TlsConfig;
 use axum::body::Body;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
 use axum::extract::FromRef;
 use axum::extract::{Query as AxumQuery, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
-use futures_util::{FutureExt, StreamExt};
+use axum_server::tls_rustls::RustlsConfig;
+use futures_util::{FutureExt, Stream, StreamExt};
 use hickory_resolver::config::LookupIpStrategy;
 use hickory_resolver::TokioAsyncResolver;
-use ipnet::IpNet;
+use ipnet::{IpNet, Ipv4Net};
 use log::*;
-use regex::Regex;
-use regex::RegexSet;
 use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
-use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 
 #[cfg(feature = "embed-static")]
 static STATIC_DIR: include_dir::Dir<'_> = include_dir::include_dir!("$CARGO_MANIFEST_DIR/static");
 
-static COMMUNITIES_LIST: &[u8] = include_bytes!("communities.json");
-
 fn default_asn_dns_zone() -> Option<String> {
     Some("as{}.asn.cymru.com.".to_string())
 }
 
+fn default_max_bulk_addresses() -> usize {
+    1000
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApiServerConfig {
-    bind: SocketAddr,
+    pub(crate) bind: SocketAddr,
     #[serde(default)]
     query_limits: QueryLimits,
     #[cfg(feature = "embed-static")]
@@ -45,11 +66,29 @@ pub struct ApiServerConfig {
     pub asn_dns_zone: Option<String>,
     /// Path to alternative communities.json
     communities_file: Option<String>,
+    /// Enrichers which add information to query results, in order
+    #[serde(default = "crate::enrichment::default_enrichers")]
+    enrichers: Vec<EnricherConfig>,
+    /// Address space operated by us, which query results are annotated with
+    #[serde(default)]
+    owned_prefixes: Vec<OwnedPrefix>,
+    /// Serve repeated queries from a short lived cache
+    query_cache: Option<QueryCacheConfig>,
+    /// Tables searched by queries which do not select any
+    #[serde(default)]
+    default_table_scope: TableScope,
+    /// Maximum number of addresses in a bulk lookup
+    #[serde(default = "default_max_bulk_addresses")]
+    max_bulk_addresses: usize,
+    /// Serve the API via HTTPS
+    tls: Option<TlsConfig>,
+    /// MaxMind databases for the `GeoIp` enricher
+    geoip: Option<GeoIpConfig>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub enum ApiResult {
-    Route(QueryResult),
+    Route(Box<QueryResult>),
     ReverseDns {
         nexthop: IpAddr,
         nexthop_resolved: String,
@@ -62,19 +101,71 @@ pub enum ApiResult {
         community: String,
         community_description: String,
     },
+    Ownership {
+        net: IpNet,
+        owned_prefixes: Vec<OwnedPrefix>,
+    },
+    GeoIp {
+        /// Prefix or nexthop
+        target: String,
+        #[serde(flatten)]
+        info: GeoIpInfo,
+    },
+    AsnInfo {
+        asn: u32,
+        #[serde(flatten)]
+        info: AsnInfo,
+    },
+    RpkiStatus {
+        net: IpNet,
+        origin_asn: Option<u32>,
+        rpki_status: RpkiStatus,
+    },
+    IrrStatus {
+        net: IpNet,
+        origin_asn: Option<u32>,
+        irr_status: IrrStatus,
+    },
 }
 
 // Make our own error that wraps `anyhow::Error`.
-struct AppError(anyhow::Error);
+struct AppError {
+    status: StatusCode,
+    error: anyhow::Error,
+}
+
+impl AppError {
+    /// The request itself is invalid, e.g. contains a pattern which can not be compiled
+    fn bad_request<E: Into<anyhow::Error>>(err: E) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            error: err.into(),
+        }
+    }
+
+    /// Invalid queries are the fault of the client, everything else of the store
+    fn from_store(err: StoreError) -> Self {
+        let status = match err {
+            StoreError::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            StoreError::TableMissing(_) => StatusCode::NOT_FOUND,
+            StoreError::LimitExceeded(_) | StoreError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self {
+            status,
+            error: err.into(),
+        }
+    }
+}
 
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        let message = match self.status {
+            StatusCode::BAD_REQUEST => format!("Invalid request: {}", self.error),
+            StatusCode::NOT_FOUND => format!("Not found: {}", self.error),
+            _ => format!("Something went wrong: {}", self.error),
+        };
+        (self.status, message).into_response()
     }
 }
 
@@ -85,7 +176,10 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            error: err.into(),
+        }
     }
 }
 
@@ -93,10 +187,62 @@ where
 struct AppState<T: Clone> {
     cfg: Arc<ApiServerConfig>,
     resolver: TokioAsyncResolver,
-    community_lists: Arc<CompiledCommunitiesLists>,
+    enrichers: Arc<EnrichmentPipeline>,
+    query_cache: Option<Arc<QueryCache>>,
     store: T,
 }
 
+impl<T: Store> AppState<T> {
+    fn query_store(
+        &self,
+        query: Query,
+    ) -> Result<Pin<Box<dyn Stream<Item = QueryResult> + Send>>, AppError> {
+        match &self.query_cache {
+            Some(query_cache) => query_cache.get_routes(&self.store, query),
+            None => self.store.get_routes(query),
+        }
+        .map_err(AppError::from_store)
+    }
+
+    fn get_routes(
+        &self,
+        query: Query,
+    ) -> Result<Pin<Box<dyn Stream<Item = QueryResult> + Send>>, AppError> {
+        let aggregate = query.aggregate;
+        let embedded_query = embedded_ipv4_query(&query);
+        let mut routes = self.query_store(query)?;
+        if let Some(embedded_query) = embedded_query {
+            routes = routes.chain(self.query_store(embedded_query)?).boxed();
+        }
+        if !aggregate {
+            return Ok(routes);
+        }
+        Ok(futures_util::stream::once(async move {
+            let results = aggregate_results(routes.collect().await);
+            futures_util::stream::iter(results)
+        })
+        .flatten()
+        .boxed())
+    }
+}
+
+/// The query for the IPv4 address embedded in the queried one, if requested
+fn embedded_ipv4_query(query: &Query) -> Option<Query> {
+    query
+        .embedded_ipv4
+        .then(|| embedded_ipv4(query.net_query.net()))
+        .flatten()
+        .map(|net| Query {
+            net_query: match query.net_query {
+                NetQuery::Contains(_) => NetQuery::Contains(net),
+                NetQuery::MostSpecific(_) => NetQuery::MostSpecific(net),
+                NetQuery::Exact(_) => NetQuery::Exact(net),
+                NetQuery::OrLonger(_) => NetQuery::OrLonger(net),
+            },
+            ..query.clone()
+        })
+}
+
 impl<T: Clone> FromRef<AppState<T>> for Arc<ApiServerConfig> {
     fn from_ref(app_state: &AppState<T>) -> Self {
         app_state.cfg.clone()
@@ -109,18 +255,48 @@ impl<T: Clone> FromRef<AppState<T>> for TokioAsyncResolver {
     }
 }
 
-impl<T: Clone> FromRef<AppState<T>> for Arc<CompiledCommunitiesLists> {
+impl<T: Clone> FromRef<AppState<T>> for Arc<EnrichmentPipeline> {
     fn from_ref(app_state: &AppState<T>) -> Self {
-        app_state.community_lists.clone()
+        app_state.enrichers.clone()
+    }
+}
+
+/// IPv4-mapped IPv6 addresses like `::ffff:192.0.2.1`, as often found in logs of dual-stack
+/// services, as the IPv4 address they represent
+fn unmap_ipv4(net: IpNet) -> IpNet {
+    match net {
+        IpNet::V6(v6) if v6.prefix_len() >= 96 => match v6.network().to_ipv4_mapped() {
+            Some(v4) => Ipv4Net::new(v4, v6.prefix_len() - 96).unwrap().into(),
+            None => net,
+        },
+        _ => net,
     }
 }
 
+/// The IPv4 space embedded in a 6to4 (`2002::/16`) or NAT64 well-known prefix (`64:ff9b::/96`)
+/// address or prefix
+fn embedded_ipv4(net: &IpNet) -> Option<IpNet> {
+    let IpNet::V6(v6) = net else {
+        return None;
+    };
+    let (v4, prefix_len) = match v6.network().octets() {
+        [0x20, 0x02, a, b, c, d, ..] if v6.prefix_len() >= 16 => {
+            (Ipv4Addr::new(a, b, c, d), (v6.prefix_len() - 16).min(32))
+        }
+        [0, 0x64, 0xff, 0x9b, 0, 0, 0, 0, 0, 0, 0, 0, a, b, c, d] if v6.prefix_len() >= 96 => {
+            (Ipv4Addr::new(a, b, c, d), v6.prefix_len() - 96)
+        }
+        _ => return None,
+    };
+    Some(Ipv4Net::new(v4, prefix_len).ok()?.trunc().into())
+}
+
 async fn parse_or_resolve(resolver: &TokioAsyncResolver, name: String) -> anyhow::Result<IpNet> {
     if let Ok(net) = name.parse() {
-        return Ok(net);
+        return Ok(unmap_ipv4(net));
     }
     if let Ok(addr) = name.parse::<IpAddr>() {
-        return Ok(addr.into());
+        return Ok(unmap_ipv4(addr.into()));
     }
 
     Ok(resolver
@@ -132,101 +308,184 @@ async fn parse_or_resolve(resolver: &TokioAsyncResolver, name: String) -> anyhow
         .into())
 }
 
-#[derive(Deserialize)]
-struct CommunitiesLists {
-    regular: CommunitiesList,
-    large: CommunitiesList,
-}
-impl CommunitiesLists {
-    fn compile(self) -> anyhow::Result<CompiledCommunitiesLists> {
-        Ok(CompiledCommunitiesLists {
-            regular: self.regular.compile()?,
-            large: self.large.compile()?,
-        })
+/// The addresses of a host, at most one per address family
+async fn resolve_addresses(
+    resolver: &TokioAsyncResolver,
+    name: &str,
+) -> anyhow::Result<Vec<IpAddr>> {
+    if let Ok(addr) = name.parse::<IpAddr>() {
+        return Ok(vec![addr]);
     }
+
+    let lookup = resolver.lookup_ip(&format!("{}.", name)).await?;
+    let addrs = [
+        lookup.iter().find(|addr| addr.is_ipv4()),
+        lookup.iter().find(|addr| addr.is_ipv6()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        anyhow::bail!("Name resolution failure");
+    }
+    Ok(addrs)
 }
 
-struct CompiledCommunitiesLists {
-    regular: CompiledCommunitiesList,
-    large: CompiledCommunitiesList,
+/// A single path in the grouped response, without the information shared with its group
+#[derive(Debug, Clone, Serialize)]
+struct GroupedPath {
+    state: RouteState,
+    #[serde(rename = "type")]
+    table_type: TableType,
+    #[serde(skip_serializing_if = "RouteDistinguisher::is_default")]
+    route_distinguisher: RouteDistinguisher,
+    stale: bool,
+    #[serde(flatten)]
+    attrs: RouteAttrs,
 }
 
-#[derive(Deserialize)]
-struct CommunitiesList(HashMap<String, String>);
-
-impl CommunitiesList {
-    fn compile(self) -> anyhow::Result<CompiledCommunitiesList> {
-        let mut sorted = self.0.into_iter().collect::<Vec<_>>();
-        sorted.sort_by(|a, b| a.0.len().cmp(&b.0.len()));
-        Ok(CompiledCommunitiesList {
-            regex_set: RegexSet::new(sorted.iter().map(|(regex, _desc)| format!("^{}$", regex)))?,
-            list: sorted
-                .into_iter()
-                .map(|(key, value)| Ok((Regex::new(&format!("^{}$", key))?, value)))
-                .collect::<anyhow::Result<_>>()?,
-        })
-    }
+#[derive(Debug, Clone, Serialize)]
+struct PeerGroup {
+    peer_address: IpAddr,
+    #[serde(flatten)]
+    session: Option<Session>,
+    path_count: usize,
+    /// Index into `paths` of the path with the most preferred route state
+    best_path: usize,
+    paths: Vec<GroupedPath>,
 }
 
-struct CompiledCommunitiesList {
-    regex_set: RegexSet,
-    list: Vec<(Regex, String)>,
-}
-impl CompiledCommunitiesList {
-    fn lookup(&self, community: &str) -> Option<Cow<str>> {
-        self.regex_set
-            .matches(community)
-            .iter()
-            .next()
-            .map(|index| {
-                let (regex, desc) = &self.list[index];
-                let mut desc_templated: Cow<str> = desc.into();
-                for (i, subcapture) in regex
-                    .captures(community)
-                    .unwrap()
-                    .iter()
-                    .skip(1)
-                    .enumerate()
-                {
-                    if let Some(subcapture) = subcapture {
-                        let searchstr = format!("${}", i);
-                        if desc_templated.contains(&searchstr) {
-                            desc_templated =
-                                desc_templated.replace(&searchstr, subcapture.into()).into()
-                        }
-                    }
-                }
-                desc_templated
-            })
+#[derive(Debug, Clone, Serialize)]
+struct RouterGroup {
+    from_client: SocketAddr,
+    #[serde(flatten)]
+    client: Client,
+    peers: Vec<PeerGroup>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct PrefixGroup {
+    net: IpNet,
+    routers: Vec<RouterGroup>,
+}
+
+/// The prefix groups of the routes of a single route distinguisher
+#[derive(Debug, Clone, Serialize)]
+struct RouteDistinguisherGroup {
+    route_distinguisher: RouteDistinguisher,
+    prefixes: Vec<PrefixGroup>,
+}
+
+type PeerGroups = (Client, BTreeMap<IpAddr, PeerGroup>);
+
+fn group_results(results: Vec<QueryResult>) -> Vec<PrefixGroup> {
+    let mut grouped: BTreeMap<IpNet, BTreeMap<SocketAddr, PeerGroups>> = BTreeMap::new();
+
+    for result in results {
+        let (_, peers) = grouped
+            .entry(result.net)
+            .or_default()
+            .entry(result.table.session_id.from_client)
+            .or_insert_with(|| (result.client.clone(), BTreeMap::new()));
+        let peer = peers
+            .entry(result.table.session_id.peer_address)
+            .or_insert_with(|| PeerGroup {
+                peer_address: result.table.session_id.peer_address,
+                session: result.session.clone(),
+                path_count: 0,
+                best_path: 0,
+                paths: vec![],
+            });
+        if !peer.paths.is_empty() && peer.paths[peer.best_path].state < result.state {
+            peer.best_path = peer.paths.len();
+        }
+        peer.paths.push(GroupedPath {
+            state: result.state,
+            table_type: result.table.table_type,
+            route_distinguisher: result.table.route_distinguisher,
+            stale: result.stale,
+            attrs: result.attrs,
+        });
+        peer.path_count = peer.paths.len();
     }
+
+    grouped
+        .into_iter()
+        .map(|(net, routers)| PrefixGroup {
+            net,
+            routers: routers
+                .into_iter()
+                .map(|(from_client, (client, peers))| RouterGroup {
+                    from_client,
+                    client,
+                    peers: peers.into_values().collect(),
+                })
+                .collect(),
+        })
+        .collect()
 }
 
-async fn query<T: Store>(
-    State(AppState {
-        cfg,
-        resolver,
-        store,
-        community_lists,
-    }): State<AppState<T>>,
-    AxumQuery(query): AxumQuery<Query<String>>,
-) -> Result<impl IntoResponse, AppError> {
+async fn prepare_query<T: Store>(
+    state: &AppState<T>,
+    query: Query<String>,
+) -> Result<Query, AppError> {
+    let (cfg, resolver) = (&state.cfg, &state.resolver);
     trace!("request: {}", serde_json::to_string_pretty(&query).unwrap());
 
     let net_query = match query.net_query {
-        NetQuery::Contains(name) => NetQuery::Contains(parse_or_resolve(&resolver, name).await?),
+        NetQuery::Contains(name) => NetQuery::Contains(parse_or_resolve(resolver, name).await?),
         NetQuery::MostSpecific(name) => {
-            NetQuery::MostSpecific(parse_or_resolve(&resolver, name).await?)
+            NetQuery::MostSpecific(parse_or_resolve(resolver, name).await?)
         }
-        NetQuery::Exact(name) => NetQuery::Exact(parse_or_resolve(&resolver, name).await?),
-        NetQuery::OrLonger(name) => NetQuery::OrLonger(parse_or_resolve(&resolver, name).await?),
+        NetQuery::Exact(name) => NetQuery::Exact(parse_or_resolve(resolver, name).await?),
+        NetQuery::OrLonger(name) => NetQuery::OrLonger(parse_or_resolve(resolver, name).await?),
     };
 
+    if let Some(at) = query.at {
+        match state.store.get_history_start() {
+            Some(start) if at >= start => {}
+            Some(start) => {
+                return Err(AppError::bad_request(anyhow::anyhow!(
+                    "the history only reaches back to {}",
+                    start
+                )))
+            }
+            None => return Err(AppError::bad_request(anyhow::anyhow!("no history is kept"))),
+        }
+    }
+
+    // reject invalid patterns here, the store can not return errors for running queries
+    if let Some(as_path_regex) = &query.as_path_regex {
+        AsPathMatcher::compile(query.as_path_match, as_path_regex)
+            .map_err(AppError::bad_request)?;
+    }
+
     let mut query = Query {
         table_query: query.table_query,
         net_query,
         limits: query.limits,
         as_path_regex: query.as_path_regex,
+        as_path_match: query.as_path_match,
         route_distinguisher: query.route_distinguisher,
+        route_distinguishers: query.route_distinguishers,
+        include_stale: query.include_stale,
+        aggregate: query.aggregate,
+        embedded_ipv4: query.embedded_ipv4,
+        origin_asn: query.origin_asn,
+        min_age: query.min_age,
+        max_age: query.max_age,
+        nexthop: query.nexthop,
+        paginate: query.paginate,
+        after: query.after,
+        sort: query.sort,
+        at: query.at,
+        communities: query.communities,
+        large_communities: query.large_communities,
+        ext_communities: query.ext_communities,
+        community_format: query.community_format,
+        table_scope: query.table_scope.or(Some(cfg.default_table_scope)),
+        min_prepend_count: query.min_prepend_count,
+        collapse_prepends: query.collapse_prepends,
     };
 
     let mut limits = query.limits.take().unwrap_or(cfg.query_limits.clone());
@@ -237,107 +496,1022 @@ async fn query<T: Store>(
     );
     query.limits = Some(limits);
 
-    // for deduplicating the nexthop resolutions
-    let mut have_resolved = HashSet::new();
-    let mut have_asn = HashSet::new();
-    let mut have_community = HashSet::new();
-    let mut have_large_community = HashSet::new();
+    Ok(query)
+}
 
-    let stream = store
-        .get_routes(query)
-        .flat_map_unordered(None, move |route| {
-            let futures = futures_util::stream::FuturesUnordered::<
-                Pin<Box<dyn std::future::Future<Output = Option<ApiResult>> + Send>>,
-            >::new();
-
-            futures.push(Box::pin(futures_util::future::ready(Some(
-                ApiResult::Route(route.clone()),
-            ))));
-
-            if let Some(nexthop) = route.attrs.nexthop {
-                if have_resolved.insert(nexthop) {
-                    let resolver = resolver.clone();
-                    futures.push(Box::pin(async move {
-                        resolver
-                            .reverse_lookup(nexthop)
-                            .await
-                            .ok()
-                            .and_then(|reverse| reverse.iter().next().map(|x| x.0.to_string()))
-                            .map(|nexthop_resolved| ApiResult::ReverseDns {
-                                nexthop,
-                                nexthop_resolved,
-                            })
-                    }))
-                }
+async fn query_grouped<T: Store>(
+    State(state): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<Query<String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = prepare_query(&state, query).await?;
+    let format = ResponseFormat::of(&query);
+    let route_distinguishers = query.route_distinguishers.clone();
+    let results = state.get_routes(query)?.collect::<Vec<_>>().await;
+
+    // with a list of route distinguishers, the results are grouped by them first in the order
+    // they were given
+    let Some(route_distinguishers) = route_distinguishers else {
+        return Ok(to_json(&group_results(results), format));
+    };
+    let mut by_rd: HashMap<RouteDistinguisher, Vec<QueryResult>> = HashMap::new();
+    for result in results {
+        by_rd
+            .entry(result.table.route_distinguisher)
+            .or_default()
+            .push(result);
+    }
+    let groups = route_distinguishers
+        .0
+        .into_iter()
+        .map(|route_distinguisher| RouteDistinguisherGroup {
+            route_distinguisher,
+            prefixes: group_results(by_rd.remove(&route_distinguisher).unwrap_or_default()),
+        })
+        .collect::<Vec<_>>();
+    Ok(to_json(&groups, format))
+}
+
+/// Results in the text format of the "show route" command of routers
+async fn query_text<T: Store>(
+    State(state): State<AppState<T>>,
+    axum::extract::Path(style): axum::extract::Path<TextStyle>,
+    AxumQuery(query): AxumQuery<Query<String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = prepare_query(&state, query).await?;
+    let results = state.get_routes(query)?.collect::<Vec<_>>().await;
+    let rpki = enrichments(&state.enrichers, &results)
+        .await
+        .into_iter()
+        .filter_map(|result| match result {
+            ApiResult::RpkiStatus {
+                net,
+                origin_asn,
+                rpki_status,
+            } => Some(((net, origin_asn), rpki_status)),
+            _ => None,
+        })
+        .collect();
+
+    Ok(crate::show_route::render(style, &results, &rpki))
+}
+
+/// Results as MRT BGP4MP update stream, to replay them into other BGP tooling
+async fn query_mrt<T: Store>(
+    State(state): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<Query<String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = prepare_query(&state, query).await?;
+    let results = state.get_routes(query)?.collect::<Vec<_>>().await;
+
+    let mut writer = MrtUpdateWriter::new();
+    for result in &results {
+        writer.add_result(result);
+    }
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
+        writer.finish(),
+    ))
+}
+
+/// Summarize contiguous prefixes of a table which have identical attributes, e.g. a de-aggregated
+/// block announced as many more-specifics
+fn aggregate_results(results: Vec<QueryResult>) -> Vec<QueryResult> {
+    let mut groups: HashMap<String, (QueryResult, Vec<IpNet>)> = HashMap::new();
+    for result in results {
+        let key =
+            serde_json::to_string(&(&result.table, result.state, result.stale, &result.attrs_id))
+                .unwrap();
+        groups
+            .entry(key)
+            .or_insert_with(|| (result.clone(), vec![]))
+            .1
+            .push(result.net);
+    }
+
+    let mut aggregated = groups
+        .into_values()
+        .flat_map(|(result, nets)| {
+            IpNet::aggregate(&nets)
+                .into_iter()
+                .map(move |net| QueryResult {
+                    net,
+                    ..result.clone()
+                })
+        })
+        .collect::<Vec<_>>();
+    aggregated.sort_by_key(|result| result.net);
+    aggregated
+}
+
+/// The minimal set of prefixes covering exactly the prefixes of the results
+async fn query_aggregates<T: Store>(
+    State(state): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<Query<String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = prepare_query(&state, query).await?;
+    let nets = state
+        .get_routes(query)?
+        .map(|route| route.net)
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(serde_json::to_string(&IpNet::aggregate(&nets))?)
+}
+
+async fn query<T: Store>(
+    State(state): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<Query<String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = prepare_query(&state, query).await?;
+    let format = ResponseFormat::of(&query);
+
+    Ok(enriched_response(
+        &state.enrichers,
+        format,
+        state.get_routes(query)?,
+    ))
+}
+
+/// How the query would be executed, with a second plan for the embedded IPv4 address if requested
+async fn query_explain<T: Store>(
+    State(state): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<Query<String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = prepare_query(&state, query).await?;
+    let plans = std::iter::once(query.clone())
+        .chain(embedded_ipv4_query(&query))
+        .map(|query| state.store.explain_query(&query))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(AppError::from_store)?;
+
+    Ok(serde_json::to_string(&plans)?)
+}
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    /// IP address or hostname
+    name: String,
+    #[serde(default = "default_include_stale")]
+    include_stale: bool,
+    #[serde(default)]
+    embedded_ipv4: bool,
+    #[serde(default)]
+    community_format: CommunityFormat,
+}
+
+/// Most specific routes for all addresses of a host, both IPv4 and IPv6
+async fn lookup<T: Store>(
+    State(state): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<LookupQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let addrs = resolve_addresses(&state.resolver, &query.name).await?;
+
+    let streams = addrs
+        .into_iter()
+        .map(|addr| {
+            state.get_routes(Query {
+                table_query: None,
+                net_query: NetQuery::MostSpecific(unmap_ipv4(addr.into())),
+                limits: Some(state.cfg.query_limits.clone()),
+                as_path_regex: None,
+                as_path_match: Default::default(),
+                route_distinguisher: Default::default(),
+                route_distinguishers: None,
+                include_stale: query.include_stale,
+                aggregate: false,
+                embedded_ipv4: query.embedded_ipv4,
+                origin_asn: None,
+                min_age: None,
+                max_age: None,
+                nexthop: None,
+                paginate: false,
+                after: None,
+                sort: None,
+                at: None,
+                communities: None,
+                large_communities: None,
+                ext_communities: None,
+                community_format: query.community_format,
+                table_scope: Some(state.cfg.default_table_scope),
+                min_prepend_count: None,
+                collapse_prepends: false,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(enriched_response(
+        &state.enrichers,
+        ResponseFormat::communities(query.community_format),
+        futures_util::stream::iter(streams).flatten(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct BulkLookupQuery {
+    /// Addresses separated by commas or newlines, otherwise they are read from the request body
+    addresses: Option<String>,
+    #[serde(default = "default_include_stale")]
+    include_stale: bool,
+    #[serde(default)]
+    community_format: CommunityFormat,
+}
+
+#[derive(Serialize)]
+struct BulkLookupResult {
+    address: IpAddr,
+    #[serde(flatten)]
+    route: QueryResult,
+}
+
+/// Parse a list of addresses separated by commas, newlines or other whitespace, without duplicates
+fn parse_address_list(list: &str) -> anyhow::Result<Vec<IpAddr>> {
+    let mut addrs = vec![];
+    for item in list.split(|c: char| c == ',' || c.is_whitespace()) {
+        if item.is_empty() {
+            continue;
+        }
+        let addr = item
+            .parse::<IpAddr>()
+            .map_err(|_| anyhow::anyhow!("invalid address: {}", item))?;
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    Ok(addrs)
+}
+
+/// Only the routes with the most specific prefix of each router, which can still be several if
+/// the router has the prefix in multiple tables
+fn most_specific_per_router(routes: Vec<QueryResult>) -> Vec<QueryResult> {
+    let mut longest = HashMap::<SocketAddr, u8>::new();
+    for route in &routes {
+        let prefix_len = longest.entry(*route.table.client_addr()).or_default();
+        *prefix_len = std::cmp::max(*prefix_len, route.net.prefix_len());
+    }
+    routes
+        .into_iter()
+        .filter(|route| longest[route.table.client_addr()] == route.net.prefix_len())
+        .collect()
+}
+
+/// Most specific route per router for each address of a list, given either as query parameter or
+/// as request body, e.g. an uploaded file
+async fn bulk_lookup<T: Store>(
+    State(state): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<BulkLookupQuery>,
+    body: String,
+) -> Result<impl IntoResponse, AppError> {
+    let addrs = parse_address_list(query.addresses.as_deref().unwrap_or(&body))
+        .map_err(AppError::bad_request)?;
+    if addrs.len() > state.cfg.max_bulk_addresses {
+        return Err(AppError::bad_request(anyhow::anyhow!(
+            "at most {} addresses can be looked up at once",
+            state.cfg.max_bulk_addresses
+        )));
+    }
+
+    let mut results = vec![];
+    for addr in addrs {
+        let routes = state
+            .query_store(Query {
+                table_query: None,
+                net_query: NetQuery::MostSpecific(unmap_ipv4(addr.into())),
+                limits: Some(state.cfg.query_limits.clone()),
+                as_path_regex: None,
+                as_path_match: Default::default(),
+                route_distinguisher: Default::default(),
+                route_distinguishers: None,
+                include_stale: query.include_stale,
+                aggregate: false,
+                embedded_ipv4: false,
+                origin_asn: None,
+                min_age: None,
+                max_age: None,
+                nexthop: None,
+                paginate: false,
+                after: None,
+                sort: None,
+                at: None,
+                communities: None,
+                large_communities: None,
+                ext_communities: None,
+                community_format: query.community_format,
+                table_scope: Some(state.cfg.default_table_scope),
+                min_prepend_count: None,
+                collapse_prepends: false,
+            })?
+            .collect::<Vec<_>>()
+            .await;
+        results.extend(most_specific_per_router(routes).into_iter().map(|route| {
+            BulkLookupResult {
+                address: addr,
+                route,
             }
-            if let Some(asn_dns_zone) = &cfg.asn_dns_zone {
-                for asn in route.attrs.as_path.into_iter().flatten() {
-                    if have_asn.insert(asn) {
-                        let resolver = resolver.clone();
-                        let asn_dns_zone = asn_dns_zone.clone();
-                        futures.push(Box::pin(async move {
-                            resolver
-                                .txt_lookup(asn_dns_zone.replace("{}", &asn.to_string()))
-                                .await
-                                .ok()
-                                .and_then(|txt| {
-                                    txt.iter().next().and_then(|x| {
-                                        x.iter()
-                                            .next()
-                                            .and_then(|data| std::str::from_utf8(data).ok())
-                                            .and_then(|s| {
-                                                s.split(" | ").nth(4).map(|name| name.to_string())
-                                            })
-                                    })
-                                })
-                                .map(|asn_name| ApiResult::AsnName { asn, asn_name })
-                        }))
+        }));
+    }
+
+    Ok(to_json(
+        &results,
+        ResponseFormat::communities(query.community_format),
+    ))
+}
+
+/// Rewrite the lists of numbers of communities and large communities to their text form
+fn communities_as_strings(value: &mut serde_json::Value) {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), value) {
+                    ("communities" | "large_communities", Value::Array(communities)) => {
+                        for community in communities.iter_mut() {
+                            if let Value::Array(parts) = community {
+                                let text = parts
+                                    .iter()
+                                    .map(|part| part.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(":");
+                                *community = Value::String(text);
+                            }
+                        }
                     }
+                    (_, value) => communities_as_strings(value),
                 }
             }
-            for community in route.attrs.communities.into_iter().flatten() {
-                if have_community.insert(community) {
-                    let community_str = format!("{}:{}", community.0, community.1);
-                    if let Some(lookup) = community_lists.regular.lookup(&community_str) {
-                        futures.push(Box::pin(futures_util::future::ready(Some(
-                            ApiResult::CommunityDescription {
-                                community: community_str,
-                                community_description: lookup.to_string(),
-                            },
-                        ))));
+        }
+        Value::Array(values) => values.iter_mut().for_each(communities_as_strings),
+        _ => {}
+    }
+}
+
+/// Replace runs of the same ASN in AS paths by `{"asn": 64496, "count": 3}`
+fn collapse_prepends(value: &mut serde_json::Value) {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), value) {
+                    ("as_path", Value::Array(as_path)) => {
+                        let mut collapsed: Vec<(Value, usize)> = vec![];
+                        for element in as_path.drain(..) {
+                            match collapsed.last_mut() {
+                                Some((last, count)) if element.is_u64() && *last == element => {
+                                    *count += 1
+                                }
+                                _ => collapsed.push((element, 1)),
+                            }
+                        }
+                        *as_path = collapsed
+                            .into_iter()
+                            .map(|(element, count)| match count {
+                                1 => element,
+                                count => serde_json::json!({ "asn": element, "count": count }),
+                            })
+                            .collect();
                     }
+                    (_, value) => collapse_prepends(value),
                 }
             }
-            for large_community in route.attrs.large_communities.into_iter().flatten() {
-                if have_large_community.insert(large_community) {
-                    let large_community_str = format!(
-                        "{}:{}:{}",
-                        large_community.0, large_community.1, large_community.2
-                    );
-                    if let Some(lookup) = community_lists.large.lookup(&large_community_str) {
-                        futures.push(Box::pin(futures_util::future::ready(Some(
-                            ApiResult::CommunityDescription {
-                                community: large_community_str,
-                                community_description: lookup.to_string(),
-                            },
-                        ))));
-                    }
-                }
+        }
+        Value::Array(values) => values.iter_mut().for_each(collapse_prepends),
+        _ => {}
+    }
+}
+
+/// Options of a query which change how its results are serialized
+#[derive(Debug, Clone, Copy, Default)]
+struct ResponseFormat {
+    community_format: CommunityFormat,
+    collapse_prepends: bool,
+}
+
+impl ResponseFormat {
+    fn of(query: &Query) -> Self {
+        Self {
+            community_format: query.community_format,
+            collapse_prepends: query.collapse_prepends,
+        }
+    }
+
+    fn communities(community_format: CommunityFormat) -> Self {
+        Self {
+            community_format,
+            ..Default::default()
+        }
+    }
+}
+
+fn to_json(value: &impl Serialize, format: ResponseFormat) -> String {
+    if format.community_format == CommunityFormat::List && !format.collapse_prepends {
+        return serde_json::to_string(value).unwrap();
+    }
+    let mut value = serde_json::to_value(value).unwrap();
+    if format.community_format == CommunityFormat::String {
+        communities_as_strings(&mut value);
+    }
+    if format.collapse_prepends {
+        collapse_prepends(&mut value);
+    }
+    value.to_string()
+}
+
+/// All enrichments of the results, for responses which are not streamed
+async fn enrichments(
+    enrichers: &Arc<EnrichmentPipeline>,
+    results: &[QueryResult],
+) -> Vec<ApiResult> {
+    let mut enrichment = enrichers.start();
+    let futures = results
+        .iter()
+        .flat_map(|result| enrichment.enrich(result))
+        .collect::<Vec<_>>();
+    futures_util::future::join_all(futures)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Stream the routes followed by their enrichments as newline delimited JSON
+fn enriched_response(
+    enrichers: &Arc<EnrichmentPipeline>,
+    format: ResponseFormat,
+    routes: impl Stream<Item = QueryResult> + Send + 'static,
+) -> Body {
+    let mut enrichment = enrichers.start();
+
+    let stream = routes
+        .flat_map_unordered(None, move |route| {
+            let futures = futures_util::stream::FuturesUnordered::<EnrichmentFuture>::new();
+
+            let enrichments = enrichment.enrich(&route);
+            futures
+                .push(futures_util::future::ready(Some(ApiResult::Route(Box::new(route)))).boxed());
+            for future in enrichments {
+                futures.push(future);
             }
 
             futures
         })
         .filter_map(futures_util::future::ready)
-        .map(|result| {
-            let json = serde_json::to_string(&result).unwrap();
+        .map(move |result| {
+            let json = to_json(&result, format);
             Ok::<_, Infallible>(format!("{}\n", json))
         });
 
-    Ok(Body::from_stream(stream))
+    Body::from_stream(stream)
+}
+
+#[derive(Serialize)]
+struct ExternalAnnouncement {
+    owned_prefix: IpNet,
+    #[serde(flatten)]
+    route: QueryResult,
+}
+
+#[derive(Serialize)]
+struct ExternalAnnouncements {
+    announcements: Vec<ExternalAnnouncement>,
+    /// The query limits were hit for at least one owned prefix, so the list is incomplete
+    truncated: bool,
+}
+
+/// Routes within the owned prefixes which are originated by other ASNs, once per prefix and session
+async fn external_announcements<T: Store>(
+    State(AppState { cfg, store, .. }): State<AppState<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let limits = &cfg.query_limits;
+    let mut seen = HashSet::new();
+    let mut announcements = vec![];
+    let mut truncated = false;
+    for owned in &cfg.owned_prefixes {
+        // one more result than allowed, to find out whether the limits were hit
+        let query = Query {
+            table_query: None,
+            net_query: NetQuery::OrLonger(owned.net),
+            limits: Some(QueryLimits {
+                max_results_per_table: limits.max_results_per_table + 1,
+                max_results: limits.max_results + 1,
+            }),
+            as_path_regex: None,
+            as_path_match: Default::default(),
+            route_distinguisher: Default::default(),
+            route_distinguishers: None,
+            include_stale: false,
+            aggregate: false,
+            embedded_ipv4: false,
+            origin_asn: None,
+            min_age: None,
+            max_age: None,
+            nexthop: None,
+            paginate: false,
+            after: None,
+            sort: None,
+            at: None,
+            communities: None,
+            large_communities: None,
+            ext_communities: None,
+            community_format: Default::default(),
+            table_scope: None,
+            min_prepend_count: None,
+            collapse_prepends: false,
+        };
+        let mut routes = store
+            .get_routes(query)
+            .map_err(AppError::from_store)?
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut per_table: HashMap<&TableSelector, usize> = HashMap::new();
+        for route in &routes {
+            *per_table.entry(&route.table).or_default() += 1;
+        }
+        if routes.len() > limits.max_results
+            || per_table
+                .values()
+                .any(|count| *count > limits.max_results_per_table)
+        {
+            truncated = true;
+            routes.truncate(limits.max_results);
+        }
+
+        announcements.extend(
+            routes
+                .into_iter()
+                .filter(|route| owned.is_external(route))
+                .filter(|route| seen.insert((route.net, route.table.session_id.clone())))
+                .map(|route| ExternalAnnouncement {
+                    owned_prefix: owned.net,
+                    route,
+                }),
+        );
+    }
+
+    Ok(serde_json::to_string(&ExternalAnnouncements {
+        announcements,
+        truncated,
+    })?)
+}
+
+async fn decode_errors<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_decode_errors()).unwrap()
+}
+
+#[derive(Serialize)]
+struct RouterEntry {
+    #[serde(flatten)]
+    client: Client,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection: Option<ClientConnection>,
+    /// All sessions of the router are synced
+    synced: bool,
 }
 
 async fn routers<T: Store>(State(AppState { store, .. }): State<AppState<T>>) -> impl IntoResponse {
-    serde_json::to_string(&store.get_routers()).unwrap()
+    let mut connections = store.get_client_connections();
+    let sessions = store.get_sessions();
+    let routers = store
+        .get_routers()
+        .into_iter()
+        .map(|(client_addr, client)| {
+            let connection = connections.remove(&client_addr);
+            let synced = sessions
+                .iter()
+                .filter(|(session_id, _)| session_id.from_client == client_addr)
+                .all(|(_, session)| session.synced);
+            (
+                client_addr,
+                RouterEntry {
+                    client,
+                    connection,
+                    synced,
+                },
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    serde_json::to_string(&routers).unwrap()
+}
+
+#[derive(Serialize)]
+struct SessionEntry {
+    #[serde(flatten)]
+    session_id: SessionId,
+    #[serde(flatten)]
+    session: Session,
+    /// Progress of the initial table dump after the session came up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync_progress: Option<SyncProgress>,
+}
+
+async fn sessions<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    let mut sync_progress = store.get_sync_progress();
+    let sessions = store
+        .get_sessions()
+        .into_iter()
+        .map(|(session_id, session)| SessionEntry {
+            sync_progress: sync_progress.remove(&session_id),
+            session_id,
+            session,
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_string(&sessions).unwrap()
+}
+
+async fn anomalies<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_anomalies()).unwrap()
+}
+
+async fn peers<T: Store>(State(AppState { store, .. }): State<AppState<T>>) -> impl IntoResponse {
+    serde_json::to_string(&store.get_peers()).unwrap()
+}
+
+async fn session_down_events<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_session_down_events()).unwrap()
+}
+
+/// Flowspec rules with their actions, e.g. to see which DDoS mitigations are active for a prefix
+async fn flowspec<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<FlowspecQuery>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_flowspec(query)).unwrap()
+}
+
+/// IGP topology learned via BGP-LS, as graph of nodes and links
+async fn topology<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_topology()).unwrap()
+}
+
+async fn table_stats<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_table_stats()).unwrap()
+}
+
+async fn peer_stats<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_peer_stats()).unwrap()
+}
+
+async fn peer_funnel<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_peer_funnels()).unwrap()
+}
+
+#[derive(Deserialize)]
+struct OriginChangesQuery {
+    net: Option<IpNet>,
+}
+
+async fn origin_changes<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<OriginChangesQuery>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_origin_changes(query.net)).unwrap()
+}
+
+#[derive(Deserialize)]
+struct PrefixHistoryQuery {
+    net: IpNet,
+}
+
+async fn prefix_history<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<PrefixHistoryQuery>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_prefix_history(query.net)).unwrap()
+}
+
+fn default_flaps_window() -> u64 {
+    300
+}
+
+fn default_flaps_limit() -> usize {
+    100
+}
+
+#[derive(Deserialize)]
+struct FlapsQuery {
+    /// Seconds
+    #[serde(default = "default_flaps_window")]
+    window: u64,
+    #[serde(default = "default_flaps_limit")]
+    limit: usize,
+}
+
+/// The prefixes with the most updates and withdraws within the window, most changes first
+async fn flaps<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<FlapsQuery>,
+) -> impl IntoResponse {
+    serde_json::to_string(&store.get_flaps(query.window, query.limit)).unwrap()
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    /// Version returned by a previous export
+    since: Option<u64>,
+    #[serde(flatten)]
+    filter: ChangeFilter,
+}
+
+#[derive(Serialize)]
+struct ChangesVersion {
+    version: u64,
+}
+
+/// The version followed by the changes as newline delimited JSON
+async fn changes<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<ChangesQuery>,
+) -> Response {
+    let Some((version, changes)) = store.get_changes_since(query.since) else {
+        return (
+            StatusCode::GONE,
+            "version is no longer available, request a full snapshot without `since`",
+        )
+            .into_response();
+    };
+    let header = serde_json::to_string(&ChangesVersion { version }).unwrap();
+    let lines = futures_util::stream::once(futures_util::future::ready(header)).chain(
+        changes
+            .filter(move |change| futures_util::future::ready(query.filter.matches(change)))
+            .map(|change| serde_json::to_string(&change).unwrap()),
+    );
+    Body::from_stream(lines.map(|line| Ok::<_, Infallible>(format!("{}\n", line)))).into_response()
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    /// Unix timestamp in seconds
+    since: u64,
+    #[serde(flatten)]
+    table_query: Option<TableQuery>,
+}
+
+/// Paths which were added, withdrawn or changed since a point in time, from the history
+async fn diff<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<DiffQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let diffs = store
+        .get_diff_since(query.table_query, query.since)
+        .map_err(AppError::from_store)?;
+    Ok(serde_json::to_string(&diffs)?)
+}
+
+#[derive(Deserialize)]
+struct MrtExportQuery {
+    #[serde(flatten)]
+    table_query: Option<TableQuery>,
+}
+
+/// RIB snapshot in MRT TABLE_DUMP_V2 format
+async fn export_mrt<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<MrtExportQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let dump = tokio::task::spawn_blocking(move || store.export_mrt(query.table_query)).await?;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "application/octet-stream")],
+        dump,
+    ))
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    /// Only changes of routes within this prefix
+    net: Option<IpNet>,
+    #[serde(flatten)]
+    table_query: Option<TableQuery>,
+    /// Only announcements with a matching AS path, withdrawals are not filtered by AS path
+    as_path_regex: Option<String>,
+    #[serde(default)]
+    as_path_match: AsPathMatchType,
+    /// Milliseconds during which the changes of each path are collapsed into the latest one
+    coalesce_ms: Option<u64>,
+    #[serde(flatten)]
+    filter: ChangeFilter,
+}
+
+/// Changes collected during the coalescing interval of a stream, keeping only the latest change of
+/// each path in the order the paths first changed
+#[derive(Default)]
+struct CoalescedChanges {
+    changes: Vec<Option<RouteChange>>,
+    paths: HashMap<(TableSelector, IpNet, PathId), usize>,
+}
+
+impl CoalescedChanges {
+    fn push(&mut self, change: RouteChange) {
+        match &change {
+            RouteChange::Update {
+                table,
+                net,
+                path_id,
+                ..
+            }
+            | RouteChange::Withdraw {
+                table,
+                net,
+                path_id,
+            } => {
+                let key = (table.clone(), *net, *path_id);
+                if let Some(index) = self.paths.get(&key) {
+                    self.changes[*index] = Some(change);
+                    return;
+                }
+                self.paths.insert(key, self.changes.len());
+            }
+            RouteChange::TableRemoved { table } => {
+                // the removal of the table supersedes the earlier changes of its paths
+                let changes = &mut self.changes;
+                self.paths.retain(|(path_table, _, _), index| {
+                    if path_table == table {
+                        changes[*index] = None;
+                    }
+                    path_table != table
+                });
+            }
+        }
+        self.changes.push(Some(change));
+    }
+
+    fn take(&mut self) -> impl Iterator<Item = RouteChange> {
+        self.paths.clear();
+        std::mem::take(&mut self.changes).into_iter().flatten()
+    }
+}
+
+impl StreamQuery {
+    fn matches<T: Store>(
+        &self,
+        store: &T,
+        as_path_matcher: Option<&AsPathMatcher>,
+        change: &RouteChange,
+    ) -> bool {
+        if let (Some(filter), Some(net)) = (&self.net, change.net()) {
+            if !filter.contains(net) {
+                return false;
+            }
+        }
+        let table = change.table();
+        let table_matches = match &self.table_query {
+            None => true,
+            Some(TableQuery::Table(selector)) => selector == table,
+            Some(TableQuery::Session(session_id)) => &table.session_id == session_id,
+            Some(TableQuery::Client(client_addr)) => table.client_addr() == client_addr,
+            Some(TableQuery::Router(router_id)) => store
+                .get_routers()
+                .get(table.client_addr())
+                .map(|client| &client.router_id == router_id)
+                .unwrap_or(false),
+        };
+        if !table_matches || !self.filter.matches(change) {
+            return false;
+        }
+        match (as_path_matcher, change) {
+            (Some(matcher), RouteChange::Update { attrs, .. }) => attrs
+                .as_path
+                .as_ref()
+                .map(|as_path| matcher.is_match(as_path))
+                .unwrap_or(false),
+            _ => true,
+        }
+    }
+}
+
+/// Push changes of the routes to the client as they happen, as JSON text messages
+async fn stream<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    AxumQuery(query): AxumQuery<StreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let as_path_matcher = query
+        .as_path_regex
+        .as_ref()
+        .map(|pattern| AsPathMatcher::compile(query.as_path_match, pattern))
+        .transpose()
+        .map_err(AppError::bad_request)?;
+    Ok(ws.on_upgrade(move |socket| stream_changes(socket, store, query, as_path_matcher)))
+}
+
+async fn stream_changes<T: Store>(
+    mut socket: WebSocket,
+    store: T,
+    query: StreamQuery,
+    as_path_matcher: Option<AsPathMatcher>,
+) {
+    let mut changes = store.subscribe_changes();
+    let mut coalesce_interval = query
+        .coalesce_ms
+        .filter(|ms| *ms > 0)
+        .map(|ms| tokio::time::interval(Duration::from_millis(ms)));
+    let coalescing = coalesce_interval.is_some();
+    let mut coalesced = CoalescedChanges::default();
+    'outer: loop {
+        let flush = async {
+            match &mut coalesce_interval {
+                Some(interval) => interval.tick().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(change) => {
+                    if !query.matches(&store, as_path_matcher.as_ref(), &change) {
+                        continue;
+                    }
+                    if coalescing {
+                        coalesced.push(change);
+                        continue;
+                    }
+                    let json = serde_json::to_string(&change).unwrap();
+                    if socket.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("stream subscriber lagged behind by {} changes", skipped);
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AGAIN,
+                            reason: "subscriber too slow, changes were dropped".into(),
+                        })))
+                        .await;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = flush => {
+                for change in coalesced.take() {
+                    let json = serde_json::to_string(&change).unwrap();
+                    if socket.send(Message::Text(json)).await.is_err() {
+                        break 'outer;
+                    }
+                }
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Push router connects/disconnects and sessions going up or down to the client as they happen, as
+/// JSON text messages
+async fn stream_sessions<T: Store>(
+    State(AppState { store, .. }): State<AppState<T>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| stream_session_events(socket, store))
+}
+
+async fn stream_session_events<T: Store>(mut socket: WebSocket, store: T) {
+    let mut events = store.subscribe_session_events();
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let json = serde_json::to_string(&event).unwrap();
+                    if socket.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("session event subscriber lagged behind by {} events", skipped);
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AGAIN,
+                            reason: "subscriber too slow, events were dropped".into(),
+                        })))
+                        .await;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
 }
 
 async fn routing_instances<T: Store>(
@@ -352,31 +1526,76 @@ async fn routing_instances<T: Store>(
     serde_json::to_string(&instances).unwrap()
 }
 
-async fn make_api<T: Store>(cfg: ApiServerConfig, store: T) -> anyhow::Result<Router> {
+async fn make_api<T: Store>(
+    cfg: ApiServerConfig,
+    store: T,
+    sources: EnrichmentSources,
+) -> anyhow::Result<Router> {
     let resolver = {
         let (rcfg, mut ropts) = hickory_resolver::system_conf::read_system_conf()?;
         ropts.ip_strategy = LookupIpStrategy::Ipv6thenIpv4; // strange people set strange default settings
         TokioAsyncResolver::tokio(rcfg, ropts)
     };
 
-    let community_lists: CommunitiesLists = if let Some(ref path) = cfg.communities_file {
-        let path = path.clone();
-        serde_json::from_slice(&tokio::task::spawn_blocking(move || std::fs::read(path)).await??)?
-    } else {
-        serde_json::from_slice(COMMUNITIES_LIST)?
-    };
+    let enrichers = Arc::new(
+        EnrichmentPipeline::new(
+            &cfg.enrichers,
+            &resolver,
+            cfg.asn_dns_zone.as_ref(),
+            cfg.communities_file.as_ref(),
+            &cfg.owned_prefixes,
+            cfg.geoip.as_ref(),
+            &sources,
+        )
+        .await?,
+    );
 
-    let community_lists = Arc::new(community_lists.compile()?);
+    let query_cache = cfg.query_cache.as_ref().map(|query_cache_cfg| {
+        let query_cache = Arc::new(QueryCache::new(query_cache_cfg));
+        tokio::task::spawn(
+            query_cache
+                .clone()
+                .run_invalidation(store.subscribe_changes()),
+        );
+        query_cache
+    });
 
     Ok(Router::new()
         .route("/query", get(query::<T>))
+        .route("/lookup", get(lookup::<T>))
+        .route("/lookup/bulk", get(bulk_lookup::<T>).post(bulk_lookup::<T>))
+        .route("/query/aggregates", get(query_aggregates::<T>))
+        .route("/query/grouped", get(query_grouped::<T>))
+        .route("/query/text/:style", get(query_text::<T>))
+        .route("/query/mrt", get(query_mrt::<T>))
+        .route("/query/explain", get(query_explain::<T>))
         .route("/routers", get(routers::<T>))
+        .route("/decode-errors", get(decode_errors::<T>))
+        .route("/sessions", get(sessions::<T>))
+        .route("/peer-stats", get(peer_stats::<T>))
+        .route("/peer-funnel", get(peer_funnel::<T>))
+        .route("/tables", get(table_stats::<T>))
+        .route("/flowspec", get(flowspec::<T>))
+        .route("/topology", get(topology::<T>))
+        .route("/peers", get(peers::<T>))
+        .route("/anomalies", get(anomalies::<T>))
+        .route("/session-down-events", get(session_down_events::<T>))
+        .route("/origin-changes", get(origin_changes::<T>))
+        .route("/flaps", get(flaps::<T>))
+        .route("/history", get(prefix_history::<T>))
+        .route("/changes", get(changes::<T>))
+        .route("/diff", get(diff::<T>))
+        .route("/stream", get(stream::<T>))
+        .route("/stream/sessions", get(stream_sessions::<T>))
+        .route("/export/mrt", get(export_mrt::<T>))
+        .route("/owned-prefixes/external", get(external_announcements::<T>))
         .route("/routing-instances", get(routing_instances::<T>))
         .with_state(AppState {
             cfg: Arc::new(cfg),
             resolver,
             store,
-            community_lists,
+            enrichers,
+            query_cache,
         }))
 }
 
@@ -415,7 +1634,8 @@ async fn static_path(axum::extract::Path(path): axum::extract::Path<String>) ->
 pub async fn run_api_server<T: Store>(
     cfg: ApiServerConfig,
     store: T,
-    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    sources: EnrichmentSources,
+    shutdown: tokio::sync::watch::Receiver<bool>,
 ) -> anyhow::Result<()> {
     let mut router = Router::new();
 
@@ -425,11 +1645,35 @@ pub async fn run_api_server<T: Store>(
     }
 
     router = router
-        .nest("/api", make_api(cfg.clone(), store).await?)
+        .nest("/api", make_api(cfg.clone(), store, sources).await?)
         .route("/metrics", get(get_metrics));
 
+    serve(&cfg, router, shutdown).await
+}
+
+/// Serve `router` on the configured address, via HTTPS if TLS is configured
+pub(crate) async fn serve(
+    cfg: &ApiServerConfig,
+    router: Router,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
     let make_service = router.into_make_service();
 
+    if let Some(tls) = &cfg.tls {
+        let tls_config = RustlsConfig::from_config(tls.server_config()?);
+        let handle = axum_server::Handle::new();
+        let shutdown_handle = handle.clone();
+        tokio::spawn(async move {
+            let _ = shutdown.changed().await;
+            shutdown_handle.graceful_shutdown(None);
+        });
+        axum_server::bind_rustls(cfg.bind, tls_config)
+            .handle(handle)
+            .serve(make_service)
+            .await?;
+        return Ok(());
+    }
+
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind(&cfg.bind).await?;
     axum::serve(listener, make_service)
@@ -0,0 +1,410 @@
+use regex::{Regex, RegexBuilder};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+
+const AS_SET: u8 = 1;
+const AS_CONFED_SEQUENCE: u8 = 3;
+const AS_CONFED_SET: u8 = 4;
+
+/// A segment of an AS path (RFC 4271, RFC 5065)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AsPathSegment {
+    Sequence(Vec<u32>),
+    /// Unordered set of ASNs, usually the result of aggregation
+    Set(Vec<u32>),
+    ConfedSequence(Vec<u32>),
+    ConfedSet(Vec<u32>),
+}
+
+impl AsPathSegment {
+    pub fn asns(&self) -> &[u32] {
+        match self {
+            AsPathSegment::Sequence(asns)
+            | AsPathSegment::Set(asns)
+            | AsPathSegment::ConfedSequence(asns)
+            | AsPathSegment::ConfedSet(asns) => asns,
+        }
+    }
+
+    fn asns_mut(&mut self) -> &mut Vec<u32> {
+        match self {
+            AsPathSegment::Sequence(asns)
+            | AsPathSegment::Set(asns)
+            | AsPathSegment::ConfedSequence(asns)
+            | AsPathSegment::ConfedSet(asns) => asns,
+        }
+    }
+}
+
+/// AS path with its segment boundaries. The text form has the ASNs separated by spaces, with sets
+/// in braces, confederation sequences in parentheses and confederation sets in brackets, e.g.
+/// `64496 (65001 65002) 64497 {64498,64499}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AsPath(pub Vec<AsPathSegment>);
+
+impl AsPath {
+    pub fn from_sequence(asns: Vec<u32>) -> Self {
+        AsPath(vec![AsPathSegment::Sequence(asns)])
+    }
+
+    /// Decode the value of an AS_PATH attribute. Without the four-octet AS number capability the
+    /// ASNs are encoded with two octets (RFC 6793).
+    pub fn decode(mut value: &[u8], four_octet_asns: bool) -> anyhow::Result<Self> {
+        let asn_len = if four_octet_asns { 4 } else { 2 };
+        let mut segments = vec![];
+        while !value.is_empty() {
+            anyhow::ensure!(value.len() >= 2, "truncated AS path segment");
+            let segment_type = value[0];
+            let count = value[1] as usize;
+            value = &value[2..];
+            anyhow::ensure!(value.len() >= count * asn_len, "truncated AS path segment");
+            let (asns, rest) = value.split_at(count * asn_len);
+            value = rest;
+            let asns = asns
+                .chunks(asn_len)
+                .map(|asn| asn.iter().fold(0u32, |acc, byte| (acc << 8) | *byte as u32))
+                .collect();
+            segments.push(match segment_type {
+                AS_SET => AsPathSegment::Set(asns),
+                AS_CONFED_SEQUENCE => AsPathSegment::ConfedSequence(asns),
+                AS_CONFED_SET => AsPathSegment::ConfedSet(asns),
+                _ => AsPathSegment::Sequence(asns),
+            });
+        }
+        Ok(AsPath(segments))
+    }
+
+    /// All ASNs in the order they appear, including those of sets and confederation segments
+    pub fn asns(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0
+            .iter()
+            .flat_map(|segment| segment.asns().iter().copied())
+    }
+
+    /// Length as counted by the best path selection: a set counts as one AS, confederation
+    /// segments are not counted (RFC 4271 section 9.1.2.2, RFC 5065 section 5.3)
+    pub fn len(&self) -> usize {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                AsPathSegment::Sequence(asns) => asns.len(),
+                AsPathSegment::Set(asns) => (!asns.is_empty()) as usize,
+                AsPathSegment::ConfedSequence(_) | AsPathSegment::ConfedSet(_) => 0,
+            })
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.asns().next().is_none()
+    }
+
+    /// The AS the route was received from, confederation segments are skipped
+    pub fn neighbor_asn(&self) -> Option<u32> {
+        let first = self.0.iter().find(|segment| {
+            !matches!(
+                segment,
+                AsPathSegment::ConfedSequence(_) | AsPathSegment::ConfedSet(_)
+            )
+        })?;
+        match first {
+            AsPathSegment::Sequence(asns) => asns.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// The AS which originated the route. None if the path ends with a set, as then the origin
+    /// can not be determined (RFC 6907 section 7.1.9).
+    pub fn origin_asn(&self) -> Option<u32> {
+        match self
+            .0
+            .iter()
+            .rev()
+            .find(|segment| !segment.asns().is_empty())?
+        {
+            AsPathSegment::Sequence(asns) => asns.last().copied(),
+            _ => None,
+        }
+    }
+
+    /// Highest number of consecutive occurrences of the same ASN in the sequence segments, e.g. 3
+    /// for `64496 64497 64497 64497`. Paths without prepending have a count of 1.
+    pub fn max_prepend_count(&self) -> usize {
+        let mut max = 0;
+        let mut run: (Option<u32>, usize) = (None, 0);
+        for segment in &self.0 {
+            let AsPathSegment::Sequence(asns) = segment else {
+                run = (None, 0);
+                continue;
+            };
+            for &asn in asns {
+                run = if run.0 == Some(asn) {
+                    (run.0, run.1 + 1)
+                } else {
+                    (Some(asn), 1)
+                };
+                max = max.max(run.1);
+            }
+        }
+        max
+    }
+
+    /// Cut off the ASNs after the first `max` ones. Returns true if the path was longer.
+    pub fn truncate(&mut self, max: usize) -> bool {
+        let mut remaining = max;
+        let mut truncated = false;
+        for segment in self.0.iter_mut() {
+            let asns = segment.asns_mut();
+            if asns.len() > remaining {
+                asns.truncate(remaining);
+                truncated = true;
+            }
+            remaining -= asns.len();
+        }
+        self.0.retain(|segment| !segment.asns().is_empty());
+        truncated
+    }
+}
+
+impl fmt::Display for AsPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |asns: &[u32], separator: &str| {
+            asns.iter()
+                .map(|asn| asn.to_string())
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        let segments = self
+            .0
+            .iter()
+            .filter(|segment| !segment.asns().is_empty())
+            .map(|segment| match segment {
+                AsPathSegment::Sequence(asns) => join(asns, " "),
+                AsPathSegment::Set(asns) => format!("{{{}}}", join(asns, ",")),
+                AsPathSegment::ConfedSequence(asns) => format!("({})", join(asns, " ")),
+                AsPathSegment::ConfedSet(asns) => format!("[{}]", join(asns, ",")),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", segments.join(" "))
+    }
+}
+
+/// A list of ASNs, with sets as nested lists and confederation segments as objects, e.g.
+/// `[64496, {"confed_sequence": [65001]}, 64497, [64498, 64499]]`
+impl Serialize for AsPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Confed<'a>(&'static str, &'a [u32]);
+        impl Serialize for Confed<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(self.0, self.1)?;
+                map.end()
+            }
+        }
+
+        let mut seq = serializer.serialize_seq(None)?;
+        for segment in &self.0 {
+            match segment {
+                AsPathSegment::Sequence(asns) => {
+                    for asn in asns {
+                        seq.serialize_element(asn)?;
+                    }
+                }
+                AsPathSegment::Set(asns) => seq.serialize_element(asns)?,
+                AsPathSegment::ConfedSequence(asns) => {
+                    seq.serialize_element(&Confed("confed_sequence", asns))?
+                }
+                AsPathSegment::ConfedSet(asns) => {
+                    seq.serialize_element(&Confed("confed_set", asns))?
+                }
+            }
+        }
+        seq.end()
+    }
+}
+
+/// How the `as_path_regex` of a query is interpreted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AsPathMatchType {
+    /// Regex over the AS path as text, with the ASNs separated by spaces and sets in braces, e.g.
+    /// `64496 {64497,64498}`
+    #[default]
+    Regex,
+    /// Regex over whole ASNs, e.g. `^64496 .* (64497|64498)$`. `.` matches any single ASN.
+    Pattern,
+    /// The AS path is exactly the given sequence of ASNs
+    Exact,
+}
+
+/// Longest accepted pattern, in bytes
+const MAX_PATTERN_LENGTH: usize = 1024;
+/// Upper bound for the memory used by a compiled regex, so a query can not exhaust the memory or
+/// the CPU of the server with a huge regex like `(a{1000}){1000}`
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+fn build_regex(pattern: &str) -> anyhow::Result<Regex> {
+    Ok(RegexBuilder::new(pattern)
+        .size_limit(MAX_REGEX_SIZE)
+        .dfa_size_limit(MAX_REGEX_SIZE)
+        .build()?)
+}
+
+/// Symbol for ASNs which do not occur in a pattern, never used for a literal
+const OTHER_ASN: char = 'x';
+/// Literal ASNs of a pattern are mapped to characters of the private use area
+const FIRST_SYMBOL: u32 = 0xe000;
+const LAST_SYMBOL: u32 = 0xf8ff;
+
+pub enum AsPathMatcher {
+    Regex(Regex),
+    Pattern {
+        regex: Regex,
+        symbols: HashMap<u32, char>,
+    },
+    Exact(Vec<u32>),
+}
+
+impl AsPathMatcher {
+    pub fn compile(match_type: AsPathMatchType, pattern: &str) -> anyhow::Result<Self> {
+        if pattern.len() > MAX_PATTERN_LENGTH {
+            anyhow::bail!(
+                "AS path pattern is longer than {} characters",
+                MAX_PATTERN_LENGTH
+            );
+        }
+        Ok(match match_type {
+            AsPathMatchType::Regex => AsPathMatcher::Regex(build_regex(pattern)?),
+            AsPathMatchType::Pattern => compile_pattern(pattern)?,
+            AsPathMatchType::Exact => AsPathMatcher::Exact(
+                pattern
+                    .split_whitespace()
+                    .map(|asn| asn.parse())
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    /// Patterns and exact matches see the ASNs of all segments as one sequence
+    pub fn is_match(&self, as_path: &AsPath) -> bool {
+        match self {
+            AsPathMatcher::Regex(regex) => regex.is_match(&as_path.to_string()),
+            AsPathMatcher::Pattern { regex, symbols } => {
+                let encoded = as_path
+                    .asns()
+                    .map(|asn| symbols.get(&asn).copied().unwrap_or(OTHER_ASN))
+                    .collect::<String>();
+                regex.is_match(&encoded)
+            }
+            AsPathMatcher::Exact(expected) => as_path.asns().eq(expected.iter().copied()),
+        }
+    }
+}
+
+/// Translate a pattern over ASNs into a regex over one character per ASN, so the regex engine
+/// can match whole ASNs without any string conversion of the numbers
+fn compile_pattern(pattern: &str) -> anyhow::Result<AsPathMatcher> {
+    let mut symbols = HashMap::new();
+    let mut translated = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '0'..='9' => {
+                let mut asn = c.to_string();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    asn.push(digit);
+                }
+                let asn: u32 = asn.parse()?;
+                let symbol = match symbols.get(&asn) {
+                    Some(symbol) => *symbol,
+                    None => {
+                        let symbol = char::from_u32(FIRST_SYMBOL + symbols.len() as u32)
+                            .filter(|symbol| *symbol as u32 <= LAST_SYMBOL)
+                            .ok_or(anyhow::anyhow!(
+                                "too many different ASNs in AS path pattern"
+                            ))?;
+                        symbols.insert(asn, symbol);
+                        symbol
+                    }
+                };
+                translated.push(symbol);
+            }
+            '.' | '*' | '+' | '?' | '(' | ')' | '|' | '^' | '$' => translated.push(c),
+            c if c.is_whitespace() => {}
+            c => anyhow::bail!("unexpected character {:?} in AS path pattern", c),
+        }
+    }
+
+    Ok(AsPathMatcher::Pattern {
+        regex: build_regex(&translated)?,
+        symbols,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(asns: &[u32]) -> AsPath {
+        AsPath::from_sequence(asns.to_vec())
+    }
+
+    #[test]
+    fn test_pattern() {
+        let matcher = AsPathMatcher::compile(AsPathMatchType::Pattern, "^64496 .+ 64497$").unwrap();
+        assert!(matcher.is_match(&path(&[64496, 1, 2, 64497])));
+        assert!(!matcher.is_match(&path(&[64496, 64497])));
+        assert!(!matcher.is_match(&path(&[644960, 1, 64497])));
+
+        let matcher = AsPathMatcher::compile(AsPathMatchType::Pattern, "(64496|64497)+$").unwrap();
+        assert!(matcher.is_match(&path(&[1, 64496, 64497, 64496])));
+        assert!(!matcher.is_match(&path(&[64496, 1])));
+
+        assert!(AsPathMatcher::compile(AsPathMatchType::Pattern, "64496 [1]").is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        assert!(AsPathMatcher::compile(AsPathMatchType::Regex, "(").is_err());
+        assert!(AsPathMatcher::compile(AsPathMatchType::Regex, "((a{1000}){1000}){1000}").is_err());
+        assert!(AsPathMatcher::compile(AsPathMatchType::Regex, &"1".repeat(2000)).is_err());
+    }
+
+    #[test]
+    fn test_segments() {
+        let as_path = AsPath(vec![
+            AsPathSegment::ConfedSequence(vec![65001]),
+            AsPathSegment::Sequence(vec![64496, 64497]),
+            AsPathSegment::Set(vec![64498, 64499]),
+        ]);
+        assert_eq!(as_path.to_string(), "(65001) 64496 64497 {64498,64499}");
+        assert_eq!(as_path.max_prepend_count(), 1);
+        assert_eq!(
+            path(&[64496, 64497, 64497, 64497, 64498]).max_prepend_count(),
+            3
+        );
+        assert_eq!(
+            serde_json::to_string(&as_path).unwrap(),
+            r#"[{"confed_sequence":[65001]},64496,64497,[64498,64499]]"#
+        );
+        assert_eq!(as_path.len(), 3);
+        assert_eq!(as_path.neighbor_asn(), Some(64496));
+        assert_eq!(as_path.origin_asn(), None);
+
+        let matcher = AsPathMatcher::compile(AsPathMatchType::Regex, r"\{.*64499.*\}$").unwrap();
+        assert!(matcher.is_match(&as_path));
+        let matcher = AsPathMatcher::compile(AsPathMatchType::Pattern, "64497 64498").unwrap();
+        assert!(matcher.is_match(&as_path));
+
+        let mut truncated = as_path.clone();
+        assert!(truncated.truncate(2));
+        assert_eq!(truncated.to_string(), "(65001) 64496");
+    }
+
+    #[test]
+    fn test_exact() {
+        let matcher = AsPathMatcher::compile(AsPathMatchType::Exact, "64496 64497").unwrap();
+        assert!(matcher.is_match(&path(&[64496, 64497])));
+        assert!(!matcher.is_match(&path(&[64496, 64497, 64497])));
+    }
+}
@@ -0,0 +1,158 @@
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+fn default_refresh_interval() -> u64 {
+    86400
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AsnInfoConfig {
+    /// AS names in the format of the RIPE NCC `asn.txt`, one `<asn> <name>, <country>` per line
+    pub path: String,
+    /// Seconds between reloads of the file
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AsnInfo {
+    /// Handle of the AS, e.g. `DTAG`
+    pub name: String,
+    /// Description of the organization operating the AS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+}
+
+/// AS numbers to their names
+#[derive(Default)]
+pub struct AsnNames(HashMap<u32, AsnInfo>);
+
+impl AsnNames {
+    pub fn get(&self, asn: u32) -> Option<&AsnInfo> {
+        self.0.get(&asn)
+    }
+
+    /// Parse lines like `3320 DTAG Internet service provider operations, DE`. Some names are
+    /// separated from the organization by ` - `, lines which can not be parsed are skipped.
+    fn parse(data: &str) -> Self {
+        let mut names = HashMap::new();
+        for line in data.lines() {
+            let Some((asn, rest)) = line.trim().split_once(' ') else {
+                continue;
+            };
+            let Ok(asn) = asn.trim_start_matches("AS").parse() else {
+                continue;
+            };
+            let (rest, country) = match rest.rsplit_once(", ") {
+                Some((rest, country))
+                    if country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase()) =>
+                {
+                    (rest, Some(country.to_string()))
+                }
+                _ => (rest, None),
+            };
+            let (name, org) = match rest.split_once(' ') {
+                Some((name, org)) => {
+                    let org = org.trim().trim_start_matches("- ").trim();
+                    (name, (!org.is_empty()).then(|| org.to_string()))
+                }
+                None => (rest, None),
+            };
+            if name.is_empty() {
+                continue;
+            }
+            names.insert(
+                asn,
+                AsnInfo {
+                    name: name.to_string(),
+                    org,
+                    country,
+                },
+            );
+        }
+        Self(names)
+    }
+}
+
+/// Shared handle to the most recently loaded AS names
+#[derive(Clone, Default)]
+pub struct AsnDb(Arc<RwLock<Option<Arc<AsnNames>>>>);
+
+impl AsnDb {
+    /// The current AS names, None if no source is configured or nothing was loaded yet
+    pub fn current(&self) -> Option<Arc<AsnNames>> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn replace(&self, names: AsnNames) {
+        crate::metrics::ASN_NAMES.set(names.0.len() as i64);
+        *self.0.write().unwrap() = Some(Arc::new(names));
+    }
+}
+
+async fn load(path: &str) -> anyhow::Result<AsnNames> {
+    let path = path.to_string();
+    let data = tokio::task::spawn_blocking(move || std::fs::read(path)).await??;
+    Ok(AsnNames::parse(&String::from_utf8_lossy(&data)))
+}
+
+pub async fn run(
+    cfg: AsnInfoConfig,
+    db: AsnDb,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.refresh_interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                match load(&cfg.path).await {
+                    Ok(names) => {
+                        debug!("loaded {} AS names", names.0.len());
+                        db.replace(names);
+                    }
+                    Err(e) => warn!("failed to load AS names: {}", e),
+                }
+            }
+            _ = shutdown.changed() => break Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let names = AsnNames::parse(
+            "3320 DTAG Internet service provider operations, DE\n\
+             64496 EXAMPLE-AS - Example Org, Inc., US\n\
+             64497 NO-COUNTRY\n\
+             invalid line\n",
+        );
+        assert_eq!(
+            names.get(3320),
+            Some(&AsnInfo {
+                name: "DTAG".to_string(),
+                org: Some("Internet service provider operations".to_string()),
+                country: Some("DE".to_string()),
+            })
+        );
+        assert_eq!(
+            names.get(64496),
+            Some(&AsnInfo {
+                name: "EXAMPLE-AS".to_string(),
+                org: Some("Example Org, Inc.".to_string()),
+                country: Some("US".to_string()),
+            })
+        );
+        assert_eq!(names.get(64497).unwrap().org, None);
+        assert_eq!(names.get(64498), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! BGP best path selection among the paths of a prefix, e.g. the paths received via ADD-PATH
+
+use std::cmp::Ordering;
+
+use crate::store::{PathId, RouteOrigin, RouterId};
+
+/// Local preference assumed for paths without the attribute
+const DEFAULT_LOCAL_PREF: u32 = 100;
+
+/// The attributes of a path which are considered in the best path selection
+#[derive(Debug, Clone, Copy)]
+pub struct PathCandidate<'a> {
+    pub path_id: PathId,
+    pub local_pref: Option<u32>,
+    /// As counted by the best path selection, see [`crate::as_path::AsPath::len`]
+    pub as_path_len: usize,
+    pub neighbor_asn: Option<u32>,
+    pub origin: Option<&'a RouteOrigin>,
+    pub med: Option<u32>,
+    /// Router ID of the neighbor the path was received from, if known
+    pub router_id: Option<RouterId>,
+}
+
+fn origin_rank(origin: Option<&RouteOrigin>) -> u8 {
+    match origin {
+        Some(RouteOrigin::Igp) => 0,
+        Some(RouteOrigin::Egp) => 1,
+        Some(RouteOrigin::Incomplete) | None => 2,
+    }
+}
+
+/// Which of two paths is preferred, `Ordering::Less` if it is `a`. The steps are those of RFC 4271
+/// which can be decided from the stored attributes: highest local preference, shortest AS path,
+/// lowest origin, lowest MED (only between paths from the same neighbor AS), lowest router ID and
+/// finally the lowest path ID.
+pub fn compare(a: &PathCandidate, b: &PathCandidate) -> Ordering {
+    let local_pref = |path: &PathCandidate| path.local_pref.unwrap_or(DEFAULT_LOCAL_PREF);
+    local_pref(b)
+        .cmp(&local_pref(a))
+        .then_with(|| a.as_path_len.cmp(&b.as_path_len))
+        .then_with(|| origin_rank(a.origin).cmp(&origin_rank(b.origin)))
+        .then_with(|| {
+            if a.neighbor_asn == b.neighbor_asn {
+                a.med.unwrap_or(0).cmp(&b.med.unwrap_or(0))
+            } else {
+                Ordering::Equal
+            }
+        })
+        .then_with(|| match (a.router_id, b.router_id) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => Ordering::Equal,
+        })
+        .then_with(|| a.path_id.cmp(&b.path_id))
+}
+
+/// The path ID of the best path. As the MED is only compared between some of the paths, the
+/// result depends on the order of the candidates, like on routers without deterministic MED.
+pub fn best_path<'a>(candidates: impl IntoIterator<Item = PathCandidate<'a>>) -> Option<PathId> {
+    candidates
+        .into_iter()
+        .reduce(|best, candidate| match compare(&candidate, &best) {
+            Ordering::Less => candidate,
+            _ => best,
+        })
+        .map(|best| best.path_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(path_id: PathId, as_path: &[u32]) -> PathCandidate<'static> {
+        PathCandidate {
+            path_id,
+            local_pref: None,
+            as_path_len: as_path.len(),
+            neighbor_asn: as_path.first().copied(),
+            origin: Some(&RouteOrigin::Igp),
+            med: None,
+            router_id: None,
+        }
+    }
+
+    #[test]
+    fn select_best_path() {
+        let short = candidate(1, &[64496]);
+        let long = candidate(2, &[64497, 64498]);
+        assert_eq!(best_path([long, short]), Some(1));
+
+        let preferred = PathCandidate {
+            local_pref: Some(200),
+            ..long
+        };
+        assert_eq!(best_path([short, preferred]), Some(2));
+
+        let incomplete = PathCandidate {
+            origin: Some(&RouteOrigin::Incomplete),
+            ..candidate(3, &[64497])
+        };
+        assert_eq!(best_path([incomplete, short]), Some(1));
+
+        // MED is only compared between paths from the same neighbor AS
+        let low_med = PathCandidate {
+            med: Some(10),
+            ..candidate(4, &[64496])
+        };
+        let high_med = PathCandidate {
+            med: Some(20),
+            ..candidate(5, &[64496])
+        };
+        assert_eq!(best_path([high_med, low_med]), Some(4));
+        let other_as = PathCandidate {
+            med: Some(20),
+            ..candidate(6, &[64499])
+        };
+        assert_eq!(best_path([other_as, low_med]), Some(4));
+        assert_eq!(best_path([high_med, other_as]), Some(5));
+
+        assert_eq!(best_path(Vec::new()), None);
+    }
+}
@@ -1,6 +1,10 @@
+use crate::acl::AclConfig;
 use crate::bgpdumper::BgpDumper;
 use crate::route_distinguisher::RouteDistinguisher;
-use crate::store::{Client, RouteState, SessionId, Store, TableSelector, TableType};
+use crate::store::{
+    negotiated_capabilities, open_message_asn, Client, DecodeError, RouteState, Session, SessionId,
+    Store, TableSelector, TableType,
+};
 use futures_util::future::join_all;
 use futures_util::{pin_mut, StreamExt};
 use log::*;
@@ -39,11 +43,23 @@ pub async fn run_peer(
     }
 
     let mut dumper = BgpDumper::new(
-        BgpSessionParams::new(cfg.asn, 180, BgpTransportMode::IPv4, cfg.router_id, caps),
+        BgpSessionParams::new(
+            cfg.asn,
+            cfg.hold_time,
+            BgpTransportMode::IPv4,
+            cfg.router_id,
+            caps.clone(),
+        ),
         stream,
     );
     let open_message = dumper.start_active().await?;
-    let stream = dumper.lifecycle();
+    let error_store = store.clone();
+    let stream = dumper.lifecycle(move |error, message| {
+        error_store.record_client_error(
+            client_addr,
+            DecodeError::new(Some(client_addr.ip()), error, message),
+        )
+    });
     pin_mut!(stream);
     let client_name = cfg
         .name_override
@@ -66,38 +82,71 @@ pub async fn run_peer(
             Client {
                 client_name,
                 router_id: open_message.router_id,
+                loc_rib_tables: Default::default(),
+                sys_descr: None,
+                metadata: Default::default(),
+            },
+        )
+        .await;
+    let session_id = SessionId {
+        from_client: client_addr,
+        peer_address: client_addr.ip(),
+    };
+    store
+        .session_up(
+            session_id.clone(),
+            Session {
+                capabilities: negotiated_capabilities(&caps, &open_message.caps),
+                peer_asn: Some(open_message_asn(&open_message)),
+                peer_router_id: Some(open_message.router_id),
+                local_asn: Some(cfg.asn),
+                hold_time: Some(std::cmp::min(cfg.hold_time, open_message.hold_time)),
+                ..Default::default()
             },
         )
         .await;
     loop {
-        let update = match stream.next().await {
+        let (update, raw_attrs) = match stream.next().await {
             Some(Ok(update)) => update,
             Some(Err(Ok(notification))) => break Ok(notification),
             Some(Err(Err(e))) => anyhow::bail!(e),
             None => panic!(),
         };
-        store
+        if let Err(e) = store
             .insert_bgp_update(
                 TableSelector {
-                    session_id: SessionId {
-                        from_client: client_addr,
-                        peer_address: client_addr.ip(),
-                    },
+                    session_id: session_id.clone(),
                     table_type: TableType::LocRib {
                         route_state: cfg.route_state,
                     },
                     route_distinguisher: RouteDistinguisher::Default,
+                    afi_safi: Default::default(),
                 },
                 update,
+                raw_attrs,
             )
-            .await;
+            .await
+        {
+            debug!("{}: update was not fully applied: {}", client_addr, e);
+        }
     }
 }
 
+fn default_hold_time() -> u16 {
+    180
+}
+
+fn default_reconnect_interval() -> u64 {
+    30
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct PeerConfig {
     pub asn: u32,
     pub router_id: Ipv4Addr,
+    /// Proposed hold time in seconds, the lower of both sides is used
+    #[serde(default = "default_hold_time")]
+    pub hold_time: u16,
     pub name_override: Option<String>,
     pub route_state: RouteState,
     pub add_path: bool,
@@ -106,10 +155,64 @@ pub struct PeerConfig {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct BgpCollectorConfig {
-    pub bind: SocketAddr,
+    /// Address to accept sessions on, sessions are only initiated if unset
+    pub bind: Option<SocketAddr>,
     #[serde(default)]
     pub peers: HashMap<IpAddr, PeerConfig>,
     pub default_peer_config: Option<PeerConfig>,
+    /// Peers to which sessions are initiated
+    #[serde(default)]
+    pub connect: HashMap<SocketAddr, PeerConfig>,
+    /// Seconds to wait before connecting again after an initiated session failed
+    #[serde(default = "default_reconnect_interval")]
+    pub reconnect_interval: u64,
+    /// Sources allowed to connect to `bind`
+    #[serde(default)]
+    pub acl: AclConfig,
+}
+
+/// Initiate a session to the peer, and again whenever it goes down
+async fn run_active_peer(
+    addr: SocketAddr,
+    cfg: PeerConfig,
+    reconnect_interval: u64,
+    store: impl Store,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            res = TcpStream::connect(addr) => match res {
+                Ok(io) => {
+                    info!("connected {:?}", addr);
+                    let reason = tokio::select! {
+                        res = run_peer(cfg.clone(), store.clone(), io, addr) => {
+                            match res {
+                                Err(e) => {
+                                    warn!("disconnected {} {}", addr, e);
+                                    e.to_string()
+                                }
+                                Ok(notification) => {
+                                    info!("disconnected {} {:?}", addr, notification);
+                                    format!("{:?}", notification)
+                                }
+                            }
+                        }
+                        _ = shutdown.changed() => {
+                            store.client_down(addr, Some("shutdown".to_string())).await;
+                            return;
+                        }
+                    };
+                    store.client_down(addr, Some(reason)).await;
+                }
+                Err(e) => warn!("failed to connect to {}: {}", addr, e),
+            },
+            _ = shutdown.changed() => return,
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(reconnect_interval)) => {}
+            _ = shutdown.changed() => return,
+        }
+    }
 }
 
 pub async fn run(
@@ -117,29 +220,59 @@ pub async fn run(
     store: impl Store,
     mut shutdown: tokio::sync::watch::Receiver<bool>,
 ) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(cfg.bind).await?;
+    let listener = match cfg.bind {
+        Some(bind) => Some((bind, TcpListener::bind(bind).await?)),
+        None => None,
+    };
     let mut running_tasks = vec![];
+    for (addr, peer_cfg) in &cfg.connect {
+        running_tasks.push(tokio::spawn(run_active_peer(
+            *addr,
+            peer_cfg.clone(),
+            cfg.reconnect_interval,
+            store.clone(),
+            shutdown.clone(),
+        )));
+    }
     loop {
+        let accept = async {
+            match &listener {
+                Some((bind, listener)) => listener
+                    .accept()
+                    .await
+                    .map(|(io, client_addr)| (io, client_addr, *bind)),
+                None => std::future::pending().await,
+            }
+        };
         tokio::select! {
-            new_conn = listener.accept() => {
-                let (io, client_addr) = new_conn?;
+            new_conn = accept => {
+                let (io, client_addr, bind) = new_conn?;
+                if !cfg.acl.allows(client_addr.ip(), &[]) {
+                    crate::acl::reject(client_addr, bind);
+                    continue;
+                }
                 info!("connected {:?}", client_addr);
 
                 if let Some(peer_cfg) = cfg.peers.get(&client_addr.ip()).or(cfg.default_peer_config.as_ref()).cloned() {
                     let store = store.clone();
                     let mut shutdown = shutdown.clone();
                     running_tasks.push(tokio::spawn(async move {
-                        tokio::select! {
+                        let reason = tokio::select! {
                             res = run_peer(peer_cfg, store.clone(), io, client_addr) => {
                                 match res {
-                                    Err(e) => warn!("disconnected {} {}", client_addr, e),
-                                    Ok(notification) => info!("disconnected {} {:?}", client_addr, notification),
+                                    Err(e) => {
+                                        warn!("disconnected {} {}", client_addr, e);
+                                        e.to_string()
+                                    }
+                                    Ok(notification) => {
+                                        info!("disconnected {} {:?}", client_addr, notification);
+                                        format!("{:?}", notification)
+                                    }
                                 }
                             }
-                            _ = shutdown.changed() => {
-                            }
+                            _ = shutdown.changed() => "shutdown".to_string(),
                         };
-                        store.client_down(client_addr).await;
+                        store.client_down(client_addr, Some(reason)).await;
                     }));
                 } else {
                     info!("unexpected connection from {}", client_addr);
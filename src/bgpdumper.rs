@@ -1,6 +1,7 @@
 // Based on the "bgpdumper" example of zettabgp by Vladimir
 // Melnikov, which is licensed under the MIT license.
 
+use crate::store::RawUpdateAttrs;
 use bytes::{Buf, BytesMut};
 use futures_util::Stream;
 use futures_util::StreamExt;
@@ -57,13 +58,18 @@ impl BgpDumper {
         }
         bom.decode_from(&self.params, &buf[..])?;
         debug!("{:?}", bom);
-        self.params.hold_time = bom.hold_time;
+        // the lower of both proposed hold times is used
+        self.params.hold_time = self.params.hold_time.min(bom.hold_time);
         self.params.caps.clone_from(&bom.caps);
         self.params.check_caps();
         Ok(bom)
     }
     fn start_keepalives(&self) -> oneshot::Sender<()> {
         let (tx, mut rx) = oneshot::channel();
+        // no keepalives are sent if the hold time is zero
+        if self.params.hold_time == 0 {
+            return tx;
+        }
         let slp = std::time::Duration::new((self.params.hold_time / 3) as u64, 0);
         let write = self.write.clone();
         tokio::task::spawn(async move {
@@ -95,15 +101,28 @@ impl BgpDumper {
         buf.truncate(msg.1);
         Ok((msg.0, buf))
     }
+    /// Undecodable updates are skipped and passed to `on_decode_error` with the error. Updates
+    /// are yielded with the attributes decoded from the raw message, see `RawUpdateAttrs`.
+    #[allow(clippy::type_complexity)]
     pub fn lifecycle(
         mut self,
-    ) -> impl Stream<Item = Result<BgpUpdateMessage, Result<BgpNotificationMessage, BgpError>>> + Send
-    {
+        on_decode_error: impl Fn(String, &[u8]) + Send + 'static,
+    ) -> impl Stream<
+        Item = Result<(BgpUpdateMessage, RawUpdateAttrs), Result<BgpNotificationMessage, BgpError>>,
+    > + Send {
         self.stop_keepalives = Some(self.start_keepalives());
+        let hold_time = std::time::Duration::from_secs(self.params.hold_time as u64);
 
         async_stream::try_stream! {
             loop {
-                let (msgtype, buf) = self.next_message().await.map_err(Err)?;
+                let message = if hold_time.is_zero() {
+                    self.next_message().await
+                } else {
+                    tokio::time::timeout(hold_time, self.next_message())
+                        .await
+                        .unwrap_or(Err(BgpError::static_str("hold timer expired")))
+                };
+                let (msgtype, buf) = message.map_err(Err)?;
                 if msgtype == BgpMessageType::Keepalive {
                     continue;
                 }
@@ -121,10 +140,13 @@ impl BgpDumper {
                         let mut msgupdate = BgpUpdateMessage::new();
                         if let Err(e) = msgupdate.decode_from(&self.params, &buf[..]) {
                             warn!("BGP update decode error: {:?}", e);
-                            warn!("{:x?}", &buf[..]);
+                            on_decode_error(format!("{:?}", e), &buf[..]);
                             continue;
                         }
-                        yield msgupdate;
+                        let raw_attrs = RawUpdateAttrs::decode(&buf[..], self.params.has_as32bit)
+                            .inspect_err(|e| warn!("BGP update attribute decode error: {:?}", e))
+                            .unwrap_or_default();
+                        yield (msgupdate, raw_attrs);
                     }
                 }
             }
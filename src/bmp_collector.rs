@@ -1,18 +1,33 @@
+use crate::acl::AclConfig;
+use crate::linkstate::{self, LinkStateUpdate};
 use crate::route_distinguisher::RouteDistinguisher;
-use crate::store::{Client, RouteState, Session, SessionId, Store, TableSelector, TableType};
+use crate::store::{
+    end_of_rib_marker, negotiated_capabilities, open_message_asn, BmpStatistics, Client,
+    DecodeError, PeerDownReason, RawUpdateAttrs, RouteState, Session, SessionId, Store,
+    TableSelector, TableType,
+};
+use crate::tls::TlsConfig;
+use anyhow::Context;
 use bitvec::prelude::Msb0;
 use bitvec::view::BitView;
-use futures_util::future::join_all;
+use bytes::{Buf, BytesMut};
+use futures_util::future::{join_all, try_join_all};
 use futures_util::{pin_mut, StreamExt};
+use ipnet::IpNet;
 use log::*;
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::net::{IpAddr, SocketAddr};
-use tokio::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::AsyncRead;
+use tokio::net::TcpListener;
 use tokio::sync::mpsc;
 use tokio_util::codec::length_delimited::LengthDelimitedCodec;
+use tokio_util::either::Either;
 use zettabgp::bmp::prelude::{
-    BmpMessagePeerDown, BmpMessagePeerHeader, BmpMessageRouteMonitoring, BmpMessageTermination,
+    BmpMessageInitiation, BmpMessagePeerDown, BmpMessagePeerHeader, BmpMessagePeerUp,
+    BmpMessageRouteMonitoring, BmpMessageTermination,
 };
 use zettabgp::bmp::BmpMessage;
 
@@ -20,10 +35,14 @@ fn table_selector_for_peer(
     client_addr: SocketAddr,
     peer: &BmpMessagePeerHeader,
 ) -> Option<TableSelector> {
-    let table_type = match (peer.peertype, peer.flags.view_bits::<Msb0>()[1]) {
-        (0, false) | (1, false) => TableType::PrePolicyAdjIn,
-        (0, true) | (1, true) => TableType::PostPolicyAdjIn,
-        (3, _) => TableType::LocRib {
+    let flags = peer.flags.view_bits::<Msb0>();
+    // L flag for post-policy, O flag for Adj-RIB-Out (RFC 8671)
+    let table_type = match (peer.peertype, flags[1], flags[3]) {
+        (0 | 1, false, false) => TableType::PrePolicyAdjIn,
+        (0 | 1, true, false) => TableType::PostPolicyAdjIn,
+        (0 | 1, false, true) => TableType::PrePolicyAdjOut,
+        (0 | 1, true, true) => TableType::PostPolicyAdjOut,
+        (3, _, _) => TableType::LocRib {
             route_state: RouteState::Selected,
         },
         _ => return None,
@@ -38,6 +57,7 @@ fn table_selector_for_peer(
     Some(TableSelector {
         route_distinguisher,
         table_type,
+        afi_safi: Default::default(),
         session_id: SessionId {
             from_client: client_addr,
             peer_address: peer.peeraddress,
@@ -45,12 +65,15 @@ fn table_selector_for_peer(
     })
 }
 
+/// Store the update of a Route Monitoring message, or of a Route Mirroring message if `mirrored`
 async fn process_route_monitoring(
     store: &impl Store,
     client_addr: SocketAddr,
     rm: BmpMessageRouteMonitoring,
+    raw_attrs: RawUpdateAttrs,
+    mirrored: bool,
 ) {
-    let session = match table_selector_for_peer(client_addr, &rm.peer) {
+    let mut session = match table_selector_for_peer(client_addr, &rm.peer) {
         Some(session) => session,
         None => {
             trace!(
@@ -61,111 +84,679 @@ async fn process_route_monitoring(
             return;
         }
     };
+    if mirrored {
+        // only the messages of peers are mirrored, not those of Loc-RIB instances
+        if session.session_id().is_none() {
+            return;
+        }
+        session.table_type = TableType::Mirrored;
+    }
+
+    if let Err(e) = store.insert_bgp_update(session, rm.update, raw_attrs).await {
+        debug!("{}: update was not fully applied: {}", client_addr, e);
+    }
+}
+
+const BMP_ROUTE_MONITORING: u8 = 0;
+const BMP_STATISTICS_REPORT: u8 = 1;
+const BMP_PEER_DOWN: u8 = 2;
+const BMP_ROUTE_MIRRORING: u8 = 6;
+
+const MIRRORING_BGP_MESSAGE: u16 = 0;
+const MIRRORING_INFORMATION: u16 = 1;
+const MIRRORING_ERRORED_PDU: u16 = 0;
+const MIRRORING_MESSAGES_LOST: u16 = 1;
+
+const BGP_UPDATE: u8 = 2;
+
+const STAT_REJECTED_PREFIXES: u16 = 0;
+const STAT_DUPLICATE_PREFIX_ADVERTISEMENTS: u16 = 1;
+const STAT_DUPLICATE_WITHDRAWS: u16 = 2;
+const STAT_ADJ_RIB_IN_ROUTES: u16 = 7;
+const STAT_LOC_RIB_ROUTES: u16 = 8;
+const STAT_UPDATES_TREATED_AS_WITHDRAW: u16 = 11;
+const STAT_DUPLICATE_UPDATES: u16 = 13;
+
+const PEER_UP_STRING: u16 = 0;
+const PEER_UP_TABLE_NAME: u16 = 3;
+
+/// A BMP message, or the contents of a Statistics Report, which zettabgp does not decode
+#[derive(Debug)]
+enum ClientMessage {
+    Bmp(BmpMessage),
+    /// Route Monitoring message with the raw attributes of its update, see `update_raw_attrs`
+    RouteMonitoring {
+        rm: BmpMessageRouteMonitoring,
+        raw_attrs: RawUpdateAttrs,
+    },
+    PeerUp {
+        peer_up: BmpMessagePeerUp,
+        info: PeerUpInfo,
+    },
+    PeerDown {
+        peer_down: BmpMessagePeerDown,
+        reason: Option<PeerDownReason>,
+    },
+    StatisticsReport {
+        peer_type: u8,
+        peer_address: IpAddr,
+        statistics: BmpStatistics,
+    },
+    /// Route Monitoring message with BGP-LS NLRI
+    LinkState {
+        peer_type: u8,
+        peer_address: IpAddr,
+        update: LinkStateUpdate,
+    },
+    /// UPDATE message mirrored by the router, rewritten into a Route Monitoring message
+    RouteMirroring {
+        rm: BmpMessageRouteMonitoring,
+        raw_attrs: RawUpdateAttrs,
+    },
+}
+
+/// Address of the peer in the per-peer header of a message, for the message types which have one
+fn per_peer_address(msg: &[u8]) -> Option<IpAddr> {
+    if !matches!(msg.get(5)?, 0 | 1 | 2 | 3 | 6) {
+        return None;
+    }
+    // peer type, flags, peer distinguisher and address
+    let header = msg.get(6..32)?;
+    let mut address = [0u8; 16];
+    address.copy_from_slice(&header[10..26]);
+    Some(if header[1] & 0x80 != 0 {
+        IpAddr::from(Ipv6Addr::from(address))
+    } else {
+        IpAddr::from(Ipv4Addr::new(
+            address[12],
+            address[13],
+            address[14],
+            address[15],
+        ))
+    })
+}
+
+/// Attributes of the UPDATE message following the per-peer header, decoded from the raw message
+/// as zettabgp doesn't keep them completely. The A flag marks peers which use two-octet ASNs
+/// (RFC 7854).
+fn update_raw_attrs(msg: &[u8]) -> RawUpdateAttrs {
+    let (Some(flags), Some(update)) = (msg.get(1), msg.get(42 + 19..)) else {
+        return Default::default();
+    };
+    // per-peer header and BGP header
+    RawUpdateAttrs::decode(update, flags & 0x20 == 0)
+        .inspect_err(|e| warn!("BMP update attribute decode error: {:?}", e))
+        .unwrap_or_default()
+}
+
+/// Parse a Statistics Report following the common header (RFC 7854 section 4.8)
+fn parse_statistics_report(mut buf: &[u8]) -> anyhow::Result<ClientMessage> {
+    anyhow::ensure!(buf.remaining() >= 42 + 4, "truncated statistics report");
+    let peer_type = buf.get_u8();
+    let peer_flags = buf.get_u8();
+    buf.advance(8); // peer distinguisher
+    let mut address = [0u8; 16];
+    buf.copy_to_slice(&mut address);
+    let peer_address = if peer_flags & 0x80 != 0 {
+        IpAddr::from(Ipv6Addr::from(address))
+    } else {
+        IpAddr::from(Ipv4Addr::new(
+            address[12],
+            address[13],
+            address[14],
+            address[15],
+        ))
+    };
+    buf.advance(16); // peer AS, BGP ID and timestamp
+
+    let mut statistics = BmpStatistics {
+        timestamp: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    let count = buf.get_u32();
+    for _ in 0..count {
+        anyhow::ensure!(buf.remaining() >= 4, "truncated statistics report");
+        let stat_type = buf.get_u16();
+        let len = buf.get_u16() as usize;
+        anyhow::ensure!(buf.remaining() >= len, "truncated statistics report");
+        let mut value = &buf[..len];
+        buf.advance(len);
+        let value = match len {
+            4 => value.get_u32() as u64,
+            8 => value.get_u64(),
+            // per address family gauges and unknown types
+            _ => continue,
+        };
+        let counter = match stat_type {
+            STAT_REJECTED_PREFIXES => &mut statistics.rejected_prefixes,
+            STAT_DUPLICATE_PREFIX_ADVERTISEMENTS => &mut statistics.duplicate_prefix_advertisements,
+            STAT_DUPLICATE_WITHDRAWS => &mut statistics.duplicate_withdraws,
+            STAT_ADJ_RIB_IN_ROUTES => &mut statistics.adj_rib_in_routes,
+            STAT_LOC_RIB_ROUTES => &mut statistics.loc_rib_routes,
+            STAT_UPDATES_TREATED_AS_WITHDRAW => &mut statistics.updates_treated_as_withdraw,
+            STAT_DUPLICATE_UPDATES => &mut statistics.duplicate_updates,
+            _ => continue,
+        };
+        *counter = Some(value);
+    }
+
+    Ok(ClientMessage::StatisticsReport {
+        peer_type,
+        peer_address,
+        statistics,
+    })
+}
+
+/// Contents of a Route Mirroring message
+enum RouteMirroring {
+    Update(BmpMessageRouteMonitoring, RawUpdateAttrs),
+    /// Mirrored messages other than UPDATE, e.g. OPEN or KEEPALIVE
+    Other,
+    /// The router could not parse the PDU it received
+    ErroredPdu,
+    /// The router could not mirror some messages
+    MessagesLost,
+}
+
+/// Parse a Route Mirroring message including the common header (RFC 7854 section 4.7). Route
+/// Mirroring and Route Monitoring messages both carry the BGP message after the per-peer header,
+/// so mirrored UPDATE messages are decoded as Route Monitoring messages.
+fn parse_route_mirroring(msg: &[u8]) -> anyhow::Result<RouteMirroring> {
+    anyhow::ensure!(msg.len() >= 6 + 42, "truncated route mirroring message");
+    let per_peer_header = &msg[6..6 + 42];
+    let mut tlvs = &msg[6 + 42..];
+    let mut pdu = None;
+    while tlvs.remaining() >= 4 {
+        let tlv_type = tlvs.get_u16();
+        let len = tlvs.get_u16() as usize;
+        anyhow::ensure!(tlvs.remaining() >= len, "truncated route mirroring TLV");
+        let value = &tlvs[..len];
+        tlvs.advance(len);
+        match tlv_type {
+            MIRRORING_BGP_MESSAGE => pdu = Some(value),
+            MIRRORING_INFORMATION if len >= 2 => match u16::from_be_bytes([value[0], value[1]]) {
+                MIRRORING_ERRORED_PDU => return Ok(RouteMirroring::ErroredPdu),
+                MIRRORING_MESSAGES_LOST => return Ok(RouteMirroring::MessagesLost),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    let pdu = pdu.context("route mirroring message without BGP message")?;
+    anyhow::ensure!(pdu.len() >= 19, "truncated mirrored BGP message");
+    if pdu[18] != BGP_UPDATE {
+        return Ok(RouteMirroring::Other);
+    }
+
+    let mut rm = Vec::with_capacity(1 + per_peer_header.len() + pdu.len());
+    rm.push(BMP_ROUTE_MONITORING);
+    rm.extend_from_slice(per_peer_header);
+    rm.extend_from_slice(pdu);
+    match BmpMessage::decode_from(&rm) {
+        Ok(BmpMessage::RouteMonitoring(msg)) => {
+            Ok(RouteMirroring::Update(msg, update_raw_attrs(&rm[1..])))
+        }
+        Ok(other) => anyhow::bail!("unexpected mirrored message: {:?}", other),
+        Err(e) => anyhow::bail!("malformed mirrored BGP message: {:?}", e),
+    }
+}
+
+fn count_mirroring_error(client: &str, peer: Option<IpAddr>, reason: &str) {
+    let peer = peer.map(|peer| peer.to_string()).unwrap_or_default();
+    crate::metrics::BMP_MIRRORING_ERRORS
+        .with_label_values(&[client, &peer, reason])
+        .inc();
+}
+
+/// Information TLVs of a Peer Up message which zettabgp does not decode
+#[derive(Debug, Default)]
+struct PeerUpInfo {
+    /// Free-form strings, usually the description of the peer
+    description: Option<String>,
+    /// Name of a Loc-RIB instance (RFC 9069)
+    table_name: Option<String>,
+}
+
+/// Information TLVs of a Peer Up message following the common header
+fn parse_peer_up_info(mut buf: &[u8]) -> PeerUpInfo {
+    let mut info = PeerUpInfo::default();
+    // per-peer header, local address and ports
+    if buf.remaining() < 42 + 20 {
+        return info;
+    }
+    buf.advance(42 + 20);
+    // sent and received OPEN messages
+    for _ in 0..2 {
+        if buf.remaining() < 19 {
+            return info;
+        }
+        let len = u16::from_be_bytes([buf[16], buf[17]]) as usize;
+        if buf.remaining() < len {
+            return info;
+        }
+        buf.advance(len);
+    }
+    let mut strings = vec![];
+    while buf.remaining() >= 4 {
+        let tlv_type = buf.get_u16();
+        let len = buf.get_u16() as usize;
+        if buf.remaining() < len {
+            break;
+        }
+        let value = String::from_utf8_lossy(&buf[..len]).into_owned();
+        match tlv_type {
+            PEER_UP_STRING if !value.is_empty() => strings.push(value),
+            PEER_UP_TABLE_NAME => info.table_name = Some(value),
+            _ => {}
+        }
+        buf.advance(len);
+    }
+    if !strings.is_empty() {
+        info.description = Some(strings.join(" "));
+    }
+    info
+}
+
+/// Reason of a Peer Down message following the common header (RFC 7854 section 4.9)
+fn parse_peer_down_reason(mut buf: &[u8]) -> Option<PeerDownReason> {
+    if buf.remaining() < 42 + 1 {
+        return None;
+    }
+    buf.advance(42);
+    // the NOTIFICATION message follows its 19 byte header
+    let notification = |buf: &[u8]| (buf.len() >= 21).then(|| (buf[19], buf[20]));
+    Some(match buf.get_u8() {
+        1 => {
+            let (code, subcode) = notification(buf)?;
+            PeerDownReason::LocalNotification { code, subcode }
+        }
+        2 if buf.remaining() >= 2 => PeerDownReason::LocalFsmEvent {
+            event: buf.get_u16(),
+        },
+        3 => {
+            let (code, subcode) = notification(buf)?;
+            PeerDownReason::RemoteNotification { code, subcode }
+        }
+        4 => PeerDownReason::RemoteNoNotification,
+        5 => PeerDownReason::PeerDeconfigured,
+        6 => PeerDownReason::LocalSystemClosed,
+        code => PeerDownReason::Unknown { code },
+    })
+}
+
+/// Peers are identified by address and distinguisher, e.g. the Loc-RIB instances of all VRFs
+/// have the address zero
+fn peer_key(peer: &BmpMessagePeerHeader) -> (IpAddr, RouteDistinguisher) {
+    (
+        peer.peeraddress,
+        RouteDistinguisher::try_from(peer.peerdistinguisher.clone()).unwrap_or_default(),
+    )
+}
+
+fn count_message(client: &str, msg_type: Option<u8>) {
+    let msg_type = match msg_type {
+        Some(BMP_ROUTE_MONITORING) => "route_monitoring",
+        Some(BMP_STATISTICS_REPORT) => "statistics_report",
+        Some(BMP_PEER_DOWN) => "peer_down",
+        Some(3) => "peer_up",
+        Some(4) => "initiation",
+        Some(5) => "termination",
+        Some(6) => "route_mirroring",
+        _ => "unknown",
+    };
+    crate::metrics::BMP_MESSAGES
+        .with_label_values(&[client, msg_type])
+        .inc();
+}
+
+/// The session as described by the Peer Up message, `msg1` is the OPEN message sent by the router
+/// and `msg2` the one received from the peer
+fn session_from_peer_up(peer_up: &BmpMessagePeerUp, info: &PeerUpInfo) -> Session {
+    Session {
+        capabilities: negotiated_capabilities(&peer_up.msg1.caps, &peer_up.msg2.caps),
+        description: info.description.clone(),
+        peer_asn: Some(peer_up.peer.asnum),
+        peer_router_id: Some(peer_up.msg2.router_id),
+        local_asn: Some(open_message_asn(&peer_up.msg1)),
+        local_address: Some(peer_up.localaddress),
+        local_port: Some(peer_up.localport),
+        peer_port: Some(peer_up.remoteport),
+        hold_time: Some(std::cmp::min(
+            peer_up.msg1.hold_time,
+            peer_up.msg2.hold_time,
+        )),
+        ..Default::default()
+    }
+}
 
-    store.insert_bgp_update(session, rm.update).await;
+/// Tracks how far behind the router is in exporting: the time between the router receiving an
+/// update from the peer (the timestamp of the per-peer header) and the update being processed
+/// here. Routes of the initial table dump carry the time they were originally received, so the
+/// lag is only measured once the router sent the End-of-RIB marker of the dump.
+struct IngestLag {
+    client_addr: String,
+    peer_address: String,
+    warning_threshold: Option<Duration>,
+    warned: bool,
+    last_report: Option<Instant>,
+    /// The initial table dump is complete
+    synced: bool,
+}
+
+impl IngestLag {
+    fn new(client_addr: SocketAddr, peer: &BmpMessagePeerHeader, cfg: &PeerConfig) -> Self {
+        Self {
+            client_addr: client_addr.to_string(),
+            peer_address: peer.peeraddress.to_string(),
+            warning_threshold: cfg.ingest_lag_warning.map(Duration::from_secs),
+            warned: false,
+            last_report: None,
+            synced: false,
+        }
+    }
+
+    fn observe(&mut self, peer: &BmpMessagePeerHeader) {
+        // routers which do not fill in the timestamp send zero
+        if !self.synced || peer.timestamp == 0 {
+            return;
+        }
+        let sent = SystemTime::UNIX_EPOCH
+            + Duration::from_secs(peer.timestamp >> 32)
+            + Duration::from_micros(peer.timestamp & 0xffff_ffff);
+        let lag = SystemTime::now().duration_since(sent).unwrap_or_default();
+
+        if let Some(threshold) = self.warning_threshold {
+            if lag > threshold && !self.warned {
+                warn!(
+                    "{} is lagging behind exporting routes of {} by {:?}",
+                    self.client_addr, self.peer_address, lag
+                );
+                self.warned = true;
+            } else if lag <= threshold && self.warned {
+                info!(
+                    "{} caught up exporting routes of {}",
+                    self.client_addr, self.peer_address
+                );
+                self.warned = false;
+            }
+        }
+
+        if self
+            .last_report
+            .map(|last| last.elapsed() >= Duration::from_secs(1))
+            .unwrap_or(true)
+        {
+            crate::metrics::SESSION_INGEST_LAG
+                .with_label_values(&[&self.client_addr, &self.peer_address])
+                .set(lag.as_secs_f64());
+            self.last_report = Some(Instant::now());
+        }
+    }
+}
+
+/// Messages of a client which are handled by the task of a peer
+pub enum PeerMessage {
+    RouteMonitoring(BmpMessageRouteMonitoring, RawUpdateAttrs),
+    RouteMirroring(BmpMessageRouteMonitoring, RawUpdateAttrs),
+    /// A new Peer Up for a peer which is already up, the router is going to dump the tables again
+    Refresh(Session),
+    PeerDown(Option<PeerDownReason>),
 }
 
 pub fn run_peer(
+    cfg: &PeerConfig,
     client_addr: SocketAddr,
     peer: BmpMessagePeerHeader,
+    session: Session,
+    table_name: Option<String>,
     store: &impl Store,
-) -> mpsc::Sender<Result<BmpMessageRouteMonitoring, BmpMessagePeerDown>> {
+) -> mpsc::Sender<PeerMessage> {
     let (tx, mut rx) = mpsc::channel(16);
     let store = store.clone();
+    let mut ingest_lag = IngestLag::new(client_addr, &peer, cfg);
 
     tokio::task::spawn(async move {
         trace!("{} {:?}", client_addr, peer);
-        if let Some(session_id) = table_selector_for_peer(client_addr, &peer)
-            .and_then(|store| store.session_id().cloned())
-        {
-            store.session_up(session_id, Session {}).await;
+        if let Some(table) = table_selector_for_peer(client_addr, &peer) {
+            match table.session_id() {
+                Some(session_id) => store.session_up(session_id.clone(), session).await,
+                None => store.loc_rib_up(table, table_name).await,
+            }
         }
 
-        loop {
+        let down_reason = loop {
             match rx.recv().await {
-                Some(Ok(rm)) => {
-                    process_route_monitoring(&store, client_addr, rm).await;
+                Some(PeerMessage::RouteMonitoring(rm, raw_attrs)) => {
+                    if end_of_rib_marker(&rm.update).is_some() {
+                        ingest_lag.synced = true;
+                    } else {
+                        ingest_lag.observe(&rm.peer);
+                    }
+                    process_route_monitoring(&store, client_addr, rm, raw_attrs, false).await;
+                }
+                Some(PeerMessage::RouteMirroring(rm, raw_attrs)) => {
+                    process_route_monitoring(&store, client_addr, rm, raw_attrs, true).await;
                 }
-                Some(Err(down_msg)) => {
-                    trace!("{} {:?}", client_addr, down_msg);
-                    break;
+                Some(PeerMessage::Refresh(session)) => {
+                    ingest_lag.synced = false;
+                    // routes which are not sent again until the End-of-RIB marker are purged
+                    if let Some(table) = table_selector_for_peer(client_addr, &peer) {
+                        if let Err(e) = store.mark_stale(table.clone()).await {
+                            warn!("{}: failed to mark routes as stale: {}", client_addr, e);
+                        }
+                        if let Some(session_id) = table.session_id() {
+                            store.session_up(session_id.clone(), session).await;
+                        }
+                    }
+                }
+                Some(PeerMessage::PeerDown(reason)) => {
+                    trace!("{} {:?} down: {:?}", client_addr, peer, reason);
+                    break reason;
                 }
                 None => {
                     trace!("{} {:?} stream ended", client_addr, peer);
-                    break;
+                    break None;
                 }
             }
-        }
-        if let Some(session_id) = table_selector_for_peer(client_addr, &peer)
-            .and_then(|store| store.session_id().cloned())
-        {
-            store.session_down(session_id, None).await;
+        };
+        if let Some(table) = table_selector_for_peer(client_addr, &peer) {
+            match table.session_id() {
+                Some(session_id) => {
+                    store
+                        .session_down(session_id.clone(), None, down_reason)
+                        .await
+                }
+                None => store.loc_rib_down(table).await,
+            }
         }
     });
 
     tx
 }
+/// Handle the BMP connection of a router, `name_prefix` is prepended to the name of the router
+/// The configured name of the router, otherwise the sysName of its Initiation message or its address
+fn client_name(
+    cfg: &PeerConfig,
+    name_prefix: Option<&str>,
+    init_msg: &BmpMessageInitiation,
+    client_addr: SocketAddr,
+) -> String {
+    let client_name = cfg
+        .name_override
+        .clone()
+        .or(init_msg.sys_name.clone().filter(|name| !name.is_empty()))
+        .unwrap_or(client_addr.ip().to_string());
+    match name_prefix {
+        Some(name_prefix) => format!("{}/{}", name_prefix, client_name),
+        None => client_name,
+    }
+}
+
+/// Decode a message of the client. Messages which can't be decoded are recorded as errors of the
+/// client and skipped.
+fn decode_client_message(
+    store: &impl Store,
+    client_addr: SocketAddr,
+    msg: Result<BytesMut, std::io::Error>,
+) -> Option<ClientMessage> {
+    let orig_msg = match msg {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("BMP Codec Error: {:?}", e);
+            store.record_client_error(client_addr, DecodeError::new(None, format!("{:?}", e), &[]));
+            return None;
+        }
+    };
+    if orig_msg.get(5) == Some(&BMP_STATISTICS_REPORT) {
+        return match parse_statistics_report(&orig_msg[6..]) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("BMP Parse Error: {:?}", e);
+                store.record_client_error(
+                    client_addr,
+                    DecodeError::new(per_peer_address(&orig_msg), format!("{:?}", e), &orig_msg),
+                );
+                None
+            }
+        };
+    }
+    if orig_msg.get(5) == Some(&BMP_ROUTE_MIRRORING) {
+        let peer_address = per_peer_address(&orig_msg);
+        let error = match parse_route_mirroring(&orig_msg) {
+            Ok(RouteMirroring::Update(rm, raw_attrs)) => {
+                return Some(ClientMessage::RouteMirroring { rm, raw_attrs })
+            }
+            Ok(RouteMirroring::Other) => return None,
+            Ok(RouteMirroring::MessagesLost) => {
+                count_mirroring_error(&client_addr.to_string(), peer_address, "messages_lost");
+                return None;
+            }
+            Ok(RouteMirroring::ErroredPdu) => {
+                count_mirroring_error(&client_addr.to_string(), peer_address, "errored_pdu");
+                "router reported an errored PDU".to_string()
+            }
+            Err(e) => {
+                count_mirroring_error(&client_addr.to_string(), peer_address, "malformed");
+                format!("{:?}", e)
+            }
+        };
+        warn!("BMP Route Mirroring Error: {}", error);
+        store.record_client_error(
+            client_addr,
+            DecodeError::new(peer_address, error, &orig_msg),
+        );
+        return None;
+    }
+    if orig_msg.get(5) == Some(&BMP_ROUTE_MONITORING) {
+        match linkstate::parse_route_monitoring(&orig_msg[6..]) {
+            Ok(Some((peer_type, peer_address, update))) => {
+                return Some(ClientMessage::LinkState {
+                    peer_type,
+                    peer_address,
+                    update,
+                })
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("BGP-LS Parse Error: {:?}", e);
+                store.record_client_error(
+                    client_addr,
+                    DecodeError::new(per_peer_address(&orig_msg), format!("{:?}", e), &orig_msg),
+                );
+                return None;
+            }
+        }
+    }
+    match BmpMessage::decode_from(&orig_msg[5..]) {
+        Ok(BmpMessage::PeerUpNotification(peer_up)) => Some(ClientMessage::PeerUp {
+            peer_up,
+            info: parse_peer_up_info(&orig_msg[6..]),
+        }),
+        Ok(BmpMessage::PeerDownNotification(peer_down)) => Some(ClientMessage::PeerDown {
+            peer_down,
+            reason: parse_peer_down_reason(&orig_msg[6..]),
+        }),
+        Ok(BmpMessage::RouteMonitoring(rm)) => Some(ClientMessage::RouteMonitoring {
+            rm,
+            raw_attrs: update_raw_attrs(&orig_msg[6..]),
+        }),
+        Ok(v) => Some(ClientMessage::Bmp(v)),
+        Err(e) => {
+            warn!("BMP Parse Error: {:?}", e);
+            store.record_client_error(
+                client_addr,
+                DecodeError::new(per_peer_address(&orig_msg), format!("{:?}", e), &orig_msg),
+            );
+            None
+        }
+    }
+}
+
 pub async fn run_client(
     cfg: PeerConfig,
-    io: TcpStream,
+    name_prefix: Option<String>,
+    io: impl AsyncRead + Unpin,
     client_addr: SocketAddr,
     store: &impl Store,
 ) -> anyhow::Result<BmpMessageTermination> {
+    let client_label = client_addr.to_string();
     let read = LengthDelimitedCodec::builder()
         .length_field_offset(1)
         .length_field_type::<u32>()
         .num_skip(0)
         .new_read(io)
-        .filter_map(|msg| async move {
-            let orig_msg = match msg {
-                Ok(v) => v,
-                Err(e) => {
-                    warn!("BMP Codec Error: {:?}", e);
-                    return None;
-                }
-            };
-            match BmpMessage::decode_from(&orig_msg[5..]) {
-                Ok(v) => Some(v),
-                Err(e) => {
-                    warn!("BMP Parse Error: {:?}", e);
-                    warn!("{:x?}", &orig_msg);
-                    None
-                }
+        .inspect(move |msg| {
+            if let Ok(msg) = msg {
+                count_message(&client_label, msg.get(5).copied());
+                store.count_client_message(client_addr, msg.len());
             }
         })
+        .filter_map(move |msg| std::future::ready(decode_client_message(store, client_addr, msg)))
         .peekable();
     pin_mut!(read);
     let init_msg = match read.next().await {
-        Some(BmpMessage::Initiation(i)) => i,
+        Some(ClientMessage::Bmp(BmpMessage::Initiation(i))) => i,
         other => {
             anyhow::bail!("expected initiation message, got: {:?}", other);
         }
     };
-    let first_peer_up = match read.next().await {
-        Some(BmpMessage::PeerUpNotification(n)) => n,
+    let (first_peer_up, first_info) = match read.next().await {
+        Some(ClientMessage::PeerUp { peer_up, info }) => (peer_up, info),
         other => {
             anyhow::bail!("expected initial peer up notification, got: {:?}", other);
         }
     };
-    let client_name = cfg
-        .name_override
-        .or(init_msg.sys_name)
-        .unwrap_or(client_addr.ip().to_string());
+    let name = client_name(&cfg, name_prefix.as_deref(), &init_msg, client_addr);
     store
         .client_up(
             client_addr,
             RouteState::Selected,
             Client {
-                client_name,
+                client_name: name,
                 router_id: first_peer_up.msg1.router_id,
+                loc_rib_tables: Default::default(),
+                sys_descr: init_msg.sys_descr.clone(),
+                metadata: Default::default(),
             },
         )
         .await;
 
-    let mut channels: HashMap<
-        IpAddr,
-        mpsc::Sender<Result<BmpMessageRouteMonitoring, BmpMessagePeerDown>>,
-    > = HashMap::new();
+    let mut channels: HashMap<(IpAddr, RouteDistinguisher), mpsc::Sender<PeerMessage>> =
+        HashMap::new();
+    channels.insert(
+        peer_key(&first_peer_up.peer),
+        run_peer(
+            &cfg,
+            client_addr,
+            first_peer_up.peer.clone(),
+            session_from_peer_up(&first_peer_up, &first_info),
+            first_info.table_name,
+            store,
+        ),
+    );
 
     loop {
         let msg = read
@@ -173,21 +764,90 @@ pub async fn run_client(
             .await
             .ok_or(anyhow::anyhow!("unexpected end of stream"))?;
 
-        match msg {
-            BmpMessage::RouteMonitoring(rm) => {
-                let channel = channels.entry(rm.peer.peeraddress).or_insert_with(|| {
+        let msg = match msg {
+            ClientMessage::Bmp(msg) => msg,
+            ClientMessage::PeerUp { peer_up, info } => {
+                let session = session_from_peer_up(&peer_up, &info);
+                let table_name = info.table_name;
+                match channels.get(&peer_key(&peer_up.peer)) {
+                    Some(channel) => channel.send(PeerMessage::Refresh(session)).await.unwrap(),
+                    None => {
+                        channels.insert(
+                            peer_key(&peer_up.peer),
+                            run_peer(&cfg, client_addr, peer_up.peer, session, table_name, store),
+                        );
+                    }
+                }
+                continue;
+            }
+            ClientMessage::PeerDown { peer_down, reason } => {
+                match channels.remove(&peer_key(&peer_down.peer)) {
+                    Some(channel) => channel.send(PeerMessage::PeerDown(reason)).await.unwrap(),
+                    None => warn!("message for nonexisting peer: {:?}", &peer_down),
+                }
+                continue;
+            }
+            ClientMessage::StatisticsReport {
+                peer_type,
+                peer_address,
+                statistics,
+            } => {
+                // statistics of Loc-RIB instances are not associated with a session
+                if peer_type == 0 || peer_type == 1 {
+                    let session_id = SessionId {
+                        from_client: client_addr,
+                        peer_address,
+                    };
+                    store.update_bmp_statistics(session_id, statistics).await;
+                }
+                continue;
+            }
+            ClientMessage::RouteMonitoring { rm, raw_attrs } => {
+                let channel = channels.entry(peer_key(&rm.peer)).or_insert_with(|| {
                     warn!("the bmp device {} sent a message for a nonexisting peer, we'll initialize the table now: {:?}", &client_addr, &rm);
-                    run_peer(client_addr, rm.peer.clone(), store)
+                    run_peer(&cfg, client_addr, rm.peer.clone(), Session::default(), None, store)
                 });
-                channel.send(Ok(rm)).await.unwrap();
+                channel
+                    .send(PeerMessage::RouteMonitoring(rm, raw_attrs))
+                    .await
+                    .unwrap();
+                continue;
             }
-            BmpMessage::PeerUpNotification(n) => {
-                channels.insert(n.peer.peeraddress, run_peer(client_addr, n.peer, store));
+            ClientMessage::RouteMirroring { rm, raw_attrs } => {
+                let channel = channels.entry(peer_key(&rm.peer)).or_insert_with(|| {
+                    warn!("the bmp device {} mirrored a message of a nonexisting peer, we'll initialize the table now: {:?}", &client_addr, &rm);
+                    run_peer(&cfg, client_addr, rm.peer.clone(), Session::default(), None, store)
+                });
+                channel
+                    .send(PeerMessage::RouteMirroring(rm, raw_attrs))
+                    .await
+                    .unwrap();
+                continue;
+            }
+            ClientMessage::LinkState {
+                peer_type,
+                peer_address,
+                update,
+            } => {
+                if peer_type == 0 || peer_type == 1 {
+                    let session_id = SessionId {
+                        from_client: client_addr,
+                        peer_address,
+                    };
+                    store.update_linkstate(session_id, update).await;
+                }
+                continue;
+            }
+        };
+
+        match msg {
+            BmpMessage::Initiation(init_msg) => {
+                let name = client_name(&cfg, name_prefix.as_deref(), &init_msg, client_addr);
+                info!("{} sent a new initiation message as {}", client_addr, name);
+                store
+                    .update_client(client_addr, name, init_msg.sys_descr)
+                    .await;
             }
-            BmpMessage::PeerDownNotification(n) => match channels.remove(&n.peer.peeraddress) {
-                Some(channel) => channel.send(Err(n)).await.unwrap(),
-                None => warn!("message for nonexisting peer: {:?}", &n),
-            },
             BmpMessage::Termination(n) => break Ok(n),
             msg => trace!("unknown message from {} {:#?}", client_addr, msg),
         }
@@ -197,44 +857,131 @@ pub async fn run_client(
 #[derive(Debug, Clone, Deserialize)]
 pub struct PeerConfig {
     pub name_override: Option<String>,
+    /// Log a warning if route monitoring messages are processed more than this many seconds after
+    /// the router received them, once the initial table dump of the peer is complete
+    pub ingest_lag_warning: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
-pub struct BmpCollectorConfig {
+#[serde(deny_unknown_fields)]
+pub struct BmpListenerConfig {
     pub bind: SocketAddr,
+    /// Prefix of the names of the routers connecting to this listener, e.g. the region
+    pub name: Option<String>,
+    /// Only accept connections from these sources, all sources are accepted if empty
+    #[serde(default)]
+    pub allowed_sources: Vec<IpNet>,
+    /// Only accept TLS connections
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BmpCollectorConfig {
+    /// Address of a single listener, in addition to `listeners`
+    pub bind: Option<SocketAddr>,
+    #[serde(default)]
+    pub listeners: Vec<BmpListenerConfig>,
     #[serde(default)]
     pub peers: HashMap<IpAddr, PeerConfig>,
     pub default_peer_config: Option<PeerConfig>,
+    /// Sources allowed to connect to any of the listeners
+    #[serde(default)]
+    pub acl: AclConfig,
+    /// TLS settings of the listeners which have none of their own
+    pub tls: Option<TlsConfig>,
+}
+
+impl BmpCollectorConfig {
+    fn all_listeners(&self) -> Vec<BmpListenerConfig> {
+        let mut listeners = self.listeners.clone();
+        if let Some(bind) = self.bind {
+            listeners.push(BmpListenerConfig {
+                bind,
+                name: None,
+                allowed_sources: vec![],
+                tls: None,
+            });
+        }
+        for listener in &mut listeners {
+            if listener.tls.is_none() {
+                listener.tls = self.tls.clone();
+            }
+        }
+        listeners
+    }
 }
 
 pub async fn run(
     cfg: BmpCollectorConfig,
     store: impl Store,
+    shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let listeners = cfg.all_listeners();
+    if listeners.is_empty() {
+        anyhow::bail!("BMP collector has neither bind address nor listeners");
+    }
+    let cfg = Arc::new(cfg);
+    try_join_all(listeners.into_iter().map(|listener_cfg| {
+        run_listener(listener_cfg, cfg.clone(), store.clone(), shutdown.clone())
+    }))
+    .await?;
+    Ok(())
+}
+
+async fn run_listener(
+    listener_cfg: BmpListenerConfig,
+    cfg: Arc<BmpCollectorConfig>,
+    store: impl Store,
     mut shutdown: tokio::sync::watch::Receiver<bool>,
 ) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(cfg.bind).await?;
+    let tls_acceptor = listener_cfg
+        .tls
+        .as_ref()
+        .map(TlsConfig::acceptor)
+        .transpose()?;
+    let listener = TcpListener::bind(listener_cfg.bind).await?;
     let mut running_tasks = vec![];
     loop {
         tokio::select! {
             new_conn = listener.accept() => {
                 let (io, client_addr) = new_conn?;
+                if !cfg.acl.allows(client_addr.ip(), &listener_cfg.allowed_sources) {
+                    crate::acl::reject(client_addr, listener_cfg.bind);
+                    continue;
+                }
                 info!("connected {:?}", client_addr);
 
                 let store = store.clone();
                 let mut shutdown = shutdown.clone();
+                let name_prefix = listener_cfg.name.clone();
+                let tls_acceptor = tls_acceptor.clone();
                 if let Some(peer_cfg) = cfg.peers.get(&client_addr.ip()).or(cfg.default_peer_config.as_ref()).cloned() {
                     running_tasks.push(tokio::spawn(async move {
-                        tokio::select! {
-                            res = run_client(peer_cfg, io, client_addr, &store) => {
+                        let client = async {
+                            let io = match tls_acceptor {
+                                Some(acceptor) => Either::Right(
+                                    acceptor.accept(io).await.context("TLS handshake failed")?,
+                                ),
+                                None => Either::Left(io),
+                            };
+                            run_client(peer_cfg, name_prefix, io, client_addr, &store).await
+                        };
+                        let reason = tokio::select! {
+                            res = client => {
                                 match res {
-                                    Err(e) => warn!("disconnected {} {}", client_addr, e),
-                                    Ok(notification) => info!("disconnected {} {:?}", client_addr, notification),
+                                    Err(e) => {
+                                        warn!("disconnected {} {}", client_addr, e);
+                                        e.to_string()
+                                    }
+                                    Ok(notification) => {
+                                        info!("disconnected {} {:?}", client_addr, notification);
+                                        format!("{:?}", notification)
+                                    }
                                 }
                             }
-                            _ = shutdown.changed() => {
-                            }
+                            _ = shutdown.changed() => "shutdown".to_string(),
                         };
-                        store.client_down(client_addr).await;
+                        store.client_down(client_addr, Some(reason)).await;
                     }));
                 } else {
                     info!("unexpected connection from {}", client_addr);
@@ -0,0 +1,160 @@
+//! Filter expressions for the route changes sent to consumers like `/api/stream` and
+//! `/api/changes`, so each consumer only receives the changes relevant to it. All parts of a
+//! filter are given as text, so the same filter can be used in query strings and in the config.
+
+use ipnet::IpNet;
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::Deserialize;
+
+use crate::community_filter::CommunityFilter;
+use crate::store::{RouteChange, TableScope};
+
+/// Comma separated list of prefixes, e.g. `192.0.2.0/24,2001:db8::/32`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PrefixSet(Vec<IpNet>);
+
+impl PrefixSet {
+    /// Whether `net` is within one of the prefixes
+    pub fn contains(&self, net: &IpNet) -> bool {
+        self.0.iter().any(|prefix| prefix.contains(net))
+    }
+}
+
+impl TryFrom<String> for PrefixSet {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| {
+                prefix
+                    .parse()
+                    .map_err(|_| format!("invalid prefix {:?}", prefix))
+            })
+            .collect::<Result<_, _>>()
+            .map(PrefixSet)
+    }
+}
+
+/// Comma separated list of table scopes, e.g. `LocRib,PostPolicyAdjIn`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TableScopeSet(Vec<TableScope>);
+
+impl TryFrom<String> for TableScopeSet {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(|scope| {
+                TableScope::deserialize(StrDeserializer::<ValueError>::new(scope))
+                    .map_err(|_| format!("invalid table type {:?}", scope))
+            })
+            .collect::<Result<_, _>>()
+            .map(TableScopeSet)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ChangeFilter {
+    /// Only changes of routes within one of these prefixes
+    pub prefixes: Option<PrefixSet>,
+    /// Only announcements carrying all of these communities, withdrawals are not filtered by
+    /// attributes as they carry none
+    pub communities: Option<CommunityFilter>,
+    pub large_communities: Option<CommunityFilter>,
+    pub ext_communities: Option<CommunityFilter>,
+    /// Only changes of tables of these types
+    pub table_types: Option<TableScopeSet>,
+}
+
+impl ChangeFilter {
+    pub fn matches(&self, change: &RouteChange) -> bool {
+        if let Some(table_types) = &self.table_types {
+            let table_type = &change.table().table_type;
+            if !table_types.0.iter().any(|scope| scope.contains(table_type)) {
+                return false;
+            }
+        }
+        if let (Some(prefixes), Some(net)) = (&self.prefixes, change.net()) {
+            if !prefixes.contains(net) {
+                return false;
+            }
+        }
+        let RouteChange::Update { attrs, .. } = change else {
+            return true;
+        };
+        if let Some(filter) = &self.communities {
+            if !filter.matches_communities(attrs.communities.as_deref().unwrap_or_default()) {
+                return false;
+            }
+        }
+        if let Some(filter) = &self.large_communities {
+            if !filter.matches_large_communities(attrs.large_communities.iter().flatten()) {
+                return false;
+            }
+        }
+        if let Some(filter) = &self.ext_communities {
+            let communities = attrs
+                .ext_communities
+                .iter()
+                .flatten()
+                .map(|community| community.to_string())
+                .collect::<Vec<_>>();
+            if !filter.matches(communities.iter().map(String::as_str)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::{AfiSafi, RouteAttrs, SessionId, TableSelector, TableType};
+
+    #[test]
+    fn change_filter() {
+        let table = TableSelector {
+            route_distinguisher: Default::default(),
+            session_id: SessionId {
+                from_client: "192.0.2.1:1234".parse().unwrap(),
+                peer_address: "192.0.2.2".parse().unwrap(),
+            },
+            table_type: TableType::PostPolicyAdjIn,
+            afi_safi: AfiSafi::Ipv4Unicast,
+        };
+        let update = RouteChange::Update {
+            table: table.clone(),
+            net: "198.51.100.0/24".parse().unwrap(),
+            path_id: 0,
+            attrs: Box::new(RouteAttrs {
+                communities: Some(vec![(64496, 100)]),
+                ..Default::default()
+            }),
+        };
+        let withdraw = RouteChange::Withdraw {
+            table,
+            net: "203.0.113.0/24".parse().unwrap(),
+            path_id: 0,
+        };
+
+        let filter: ChangeFilter = serde_json::from_str(
+            r#"{"prefixes": "198.51.100.0/22", "communities": "64496:*", "table_types": "AdjIn"}"#,
+        )
+        .unwrap();
+        assert!(filter.matches(&update));
+        assert!(!filter.matches(&withdraw));
+
+        let filter: ChangeFilter =
+            serde_json::from_str(r#"{"communities": "64497:*", "table_types": "LocRib"}"#).unwrap();
+        assert!(!filter.matches(&update));
+        assert!(ChangeFilter::default().matches(&withdraw));
+        assert!(PrefixSet::try_from("192.0.2.0/33".to_string()).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::compressed_attrs::*;
+use crate::store::*;
+
+/// A change of the contents of the store, as recorded in the change log
+#[derive(Debug, Clone)]
+pub enum LoggedChange {
+    Route {
+        table: TableSelector,
+        net: IpNet,
+        path_id: PathId,
+        /// None if the route was withdrawn
+        attrs: Option<Arc<CompressedRouteAttrs>>,
+    },
+    TableRemoved(TableSelector),
+}
+
+impl LoggedChange {
+    pub fn decompress(&self) -> RouteChange {
+        match self {
+            LoggedChange::Route {
+                table,
+                net,
+                path_id,
+                attrs: Some(attrs),
+            } => RouteChange::Update {
+                table: table.clone(),
+                net: *net,
+                path_id: *path_id,
+                attrs: Box::new(decompress_route_attrs(attrs)),
+            },
+            LoggedChange::Route {
+                table,
+                net,
+                path_id,
+                attrs: None,
+            } => RouteChange::Withdraw {
+                table: table.clone(),
+                net: *net,
+                path_id: *path_id,
+            },
+            LoggedChange::TableRemoved(table) => RouteChange::TableRemoved {
+                table: table.clone(),
+            },
+        }
+    }
+}
+
+/// Bounded log of the most recent changes, each identified by a monotonically increasing version
+pub struct ChangeLog {
+    version: u64,
+    entries: VecDeque<(u64, LoggedChange)>,
+    max_size: usize,
+}
+
+impl ChangeLog {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            version: 0,
+            entries: VecDeque::new(),
+            max_size,
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn push(&mut self, change: LoggedChange) {
+        self.version += 1;
+        if self.max_size == 0 {
+            return;
+        }
+        if self.entries.len() >= self.max_size {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((self.version, change));
+    }
+
+    /// The changes after `version`, only keeping the last change of each route. Returns None if
+    /// the log does not reach back far enough.
+    pub fn since(&self, version: u64) -> Option<Vec<LoggedChange>> {
+        if version > self.version {
+            return None;
+        }
+        let oldest = self
+            .entries
+            .front()
+            .map(|(v, _)| *v)
+            .unwrap_or(self.version + 1);
+        if version + 1 < oldest {
+            return None;
+        }
+
+        let mut latest = HashMap::new();
+        let mut changes = vec![];
+        for (_, change) in self.entries.iter().filter(|(v, _)| *v > version) {
+            if let LoggedChange::Route {
+                table,
+                net,
+                path_id,
+                ..
+            } = change
+            {
+                if let Some(index) = latest.insert((table, *net, *path_id), changes.len()) {
+                    changes[index] = None;
+                }
+            }
+            changes.push(Some(change.clone()));
+        }
+
+        Some(changes.into_iter().flatten().collect())
+    }
+}
@@ -0,0 +1,353 @@
+//! Cold tier of the tables. Paths which did not change for a while are moved from memory to a file
+//! per table, only a small index entry per path is kept in memory. Queries merge both tiers, and a
+//! path which is updated or withdrawn is removed from the cold tier again.
+//!
+//! The files are only valid while fernglas is running, leftovers of an earlier run are removed on
+//! startup.
+
+use ipnet::IpNet;
+use log::*;
+use nibbletree::Node;
+use serde::Deserialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::compressed_attrs::Caches;
+use crate::mrt_export::encode_attrs;
+use crate::mrt_import::parse_attrs;
+use crate::persistence::{put_labels, read_labels};
+use crate::store::*;
+use crate::store_impl::InMemoryStore;
+use crate::table_impl::RouteEntry;
+
+/// Files are only compacted once at least this many bytes are unused
+const COMPACT_MIN_GARBAGE: u64 = 1 << 20;
+
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+fn default_cold_after() -> u64 {
+    3600
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColdStorageConfig {
+    /// Directory for the files of the cold tier
+    pub path: String,
+    /// Paths which did not change for this many seconds are moved to the cold tier
+    #[serde(default = "default_cold_after")]
+    pub cold_after: u64,
+    /// Seconds between moving paths to the cold tier
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+/// Position of a path in the file of the cold tier
+#[derive(Debug, Clone)]
+struct ColdPath {
+    path_id: PathId,
+    stale: bool,
+    since: u64,
+    offset: u64,
+    len: u32,
+}
+
+pub struct ColdTier {
+    path: PathBuf,
+    file: File,
+    /// Bytes of records which are still in use
+    used: u64,
+    /// Bytes of records of paths which were removed since
+    garbage: u64,
+    index: Node<IpNet, Vec<ColdPath>>,
+    count: usize,
+}
+
+fn open(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+impl ColdTier {
+    pub fn create(dir: &Path) -> std::io::Result<Self> {
+        let path = dir.join(format!(
+            "{}.cold",
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        Ok(Self {
+            file: open(&path)?,
+            path,
+            used: 0,
+            garbage: 0,
+            index: Default::default(),
+            count: 0,
+        })
+    }
+
+    /// Number of paths in the cold tier
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Write the paths to the file. Either all or none of them are added.
+    pub fn append(&mut self, routes: &[(IpNet, RouteEntry)]) -> std::io::Result<()> {
+        let mut offset = (&self.file).seek(SeekFrom::End(0))?;
+        let mut writer = BufWriter::new(&self.file);
+        let mut entries = Vec::with_capacity(routes.len());
+        for (net, route) in routes {
+            let mut record = vec![route.attrs.truncated as u8];
+            put_labels(&mut record, route.attrs.labels.as_deref());
+            record.extend(encode_attrs(net, &route.attrs));
+            writer.write_all(&record)?;
+            let len = record.len() as u32;
+            entries.push((
+                *net,
+                ColdPath {
+                    path_id: route.path_id,
+                    stale: route.stale,
+                    since: route.since,
+                    offset,
+                    len,
+                },
+            ));
+            offset += len as u64;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        for (net, path) in entries {
+            self.used += path.len as u64;
+            match self.index.exact_mut(&net) {
+                Some(paths) => paths.push(path),
+                None => {
+                    self.index.insert(&net, vec![path]);
+                }
+            }
+        }
+        self.count += routes.len();
+        Ok(())
+    }
+
+    fn read(&self, net: IpNet, path: &ColdPath, caches: &Mutex<Caches>) -> Option<RouteEntry> {
+        let mut buf = vec![0; path.len as usize];
+        let attrs = self
+            .file
+            .read_exact_at(&mut buf, path.offset)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| {
+                let mut record = &buf[1..];
+                let labels = read_labels(&mut record)?;
+                let mut attrs = parse_attrs(record)?;
+                attrs.truncated = buf[0] != 0;
+                attrs.labels = labels;
+                Ok(attrs)
+            });
+        match attrs {
+            Ok(attrs) => Some(RouteEntry {
+                path_id: path.path_id,
+                attrs: caches.lock().unwrap().compress_route_attrs(attrs),
+                stale: path.stale,
+                since: path.since,
+            }),
+            Err(e) => {
+                error!(
+                    "failed to read path {} of {} from {}: {}",
+                    path.path_id,
+                    net,
+                    self.path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    pub fn contains(&self, net: IpNet, path_id: PathId) -> bool {
+        self.index
+            .exact(&net)
+            .map(|paths| paths.iter().any(|path| path.path_id == path_id))
+            .unwrap_or(false)
+    }
+
+    /// Remove the path from the cold tier, returns it if it was stored here
+    pub fn remove(
+        &mut self,
+        net: IpNet,
+        path_id: PathId,
+        caches: &Mutex<Caches>,
+    ) -> Option<RouteEntry> {
+        let paths = self.index.exact_mut(&net)?;
+        let index = paths.iter().position(|path| path.path_id == path_id)?;
+        let path = paths.swap_remove(index);
+        if paths.is_empty() {
+            self.index.remove(&net);
+        }
+        self.forget(&path);
+        self.read(net, &path, caches)
+    }
+
+    fn forget(&mut self, path: &ColdPath) {
+        self.count -= 1;
+        self.used -= path.len as u64;
+        self.garbage += path.len as u64;
+    }
+
+    pub fn mark_stale(&mut self) {
+        for paths in self.index.values_mut() {
+            for path in paths.iter_mut() {
+                path.stale = true;
+            }
+        }
+    }
+
+    /// Remove all paths which are still marked as stale, returns the removed paths
+    pub fn purge_stale(&mut self, caches: &Mutex<Caches>) -> Vec<(IpNet, RouteEntry)> {
+        let mut removed = vec![];
+        let mut empty_nets = vec![];
+        for (net, paths) in self.index.iter_mut() {
+            removed.extend(
+                paths
+                    .iter()
+                    .filter(|path| path.stale)
+                    .map(|path| (net, path.clone())),
+            );
+            paths.retain(|path| !path.stale);
+            if paths.is_empty() {
+                empty_nets.push(net);
+            }
+        }
+        for net in empty_nets {
+            self.index.remove(&net);
+        }
+        for (_, path) in &removed {
+            self.forget(path);
+        }
+        removed
+            .into_iter()
+            .filter_map(|(net, path)| Some((net, self.read(net, &path, caches)?)))
+            .collect()
+    }
+
+    /// The most specific prefix in the cold tier containing `net`
+    pub fn longest_match(&self, net: &IpNet) -> Option<IpNet> {
+        self.index.longest_match(net).map(|(net, _)| net)
+    }
+
+    pub fn get_routes<'a>(
+        &'a self,
+        net_query: Option<&NetQuery>,
+        caches: &'a Mutex<Caches>,
+    ) -> Box<dyn Iterator<Item = (IpNet, RouteEntry)> + Send + 'a> {
+        let iter: Box<dyn Iterator<Item = (IpNet, &Vec<ColdPath>)> + Send + '_> = match net_query {
+            None => Box::new(self.index.iter()),
+            Some(NetQuery::Exact(net)) => {
+                Box::new(self.index.exact(net).map(|x| (*net, x)).into_iter())
+            }
+            Some(NetQuery::MostSpecific(net)) => {
+                Box::new(self.index.longest_match(net).into_iter())
+            }
+            Some(NetQuery::Contains(net)) => Box::new(self.index.matches(net)),
+            Some(NetQuery::OrLonger(net)) => Box::new(self.index.or_longer(net)),
+        };
+        Box::new(iter.flat_map(move |(net, paths)| {
+            paths
+                .iter()
+                .filter_map(move |path| Some((net, self.read(net, path, caches)?)))
+        }))
+    }
+
+    /// Rewrite the file without the records of removed paths, once they make up most of it
+    pub fn compact(&mut self) -> std::io::Result<()> {
+        if self.garbage < COMPACT_MIN_GARBAGE || self.garbage < self.used {
+            return Ok(());
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let file = open(&tmp_path)?;
+        let mut writer = BufWriter::new(&file);
+        let mut buf = vec![];
+        let mut offsets = Vec::with_capacity(self.count);
+        let mut offset = 0;
+        for path in self.index.values().flatten() {
+            buf.resize(path.len as usize, 0);
+            self.file.read_exact_at(&mut buf, path.offset)?;
+            writer.write_all(&buf)?;
+            offsets.push(offset);
+            offset += path.len as u64;
+        }
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        for (path, offset) in self.index.values_mut().flatten().zip(offsets) {
+            path.offset = offset;
+        }
+        self.file = file;
+        self.used = offset;
+        self.garbage = 0;
+        Ok(())
+    }
+}
+
+impl Drop for ColdTier {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Create the directory, or remove the files left over by an earlier run
+fn prepare_dir(path: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)?;
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("cold" | "tmp")
+        ) {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Periodically move the paths which did not change for `cold_after` seconds to the cold tier
+pub async fn run(
+    cfg: ColdStorageConfig,
+    store: InMemoryStore,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    prepare_dir(&cfg.path)?;
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let store = store.clone();
+                let dir = PathBuf::from(&cfg.path);
+                let before = unix_timestamp().saturating_sub(cfg.cold_after);
+                match tokio::task::spawn_blocking(move || store.demote_stable_routes(before, &dir)).await? {
+                    Ok(moved) => debug!("moved {} paths to the cold tier", moved),
+                    Err(e) => warn!("failed to move paths to the cold tier in {}: {}", cfg.path, e),
+                }
+            }
+            _ = shutdown.changed() => break Ok(()),
+        }
+    }
+}
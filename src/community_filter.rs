@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+
+/// A part of a community pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PartPattern {
+    /// `*`
+    Any,
+    Number(u64),
+    /// Inclusive range like `[100-199]`
+    Range(u64, u64),
+    /// Non-numeric parts, like the type of extended communities
+    Text(String),
+}
+
+impl PartPattern {
+    fn parse(part: &str) -> Option<Self> {
+        if part == "*" {
+            return Some(PartPattern::Any);
+        }
+        if let Some(range) = part.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            let (start, end) = range.split_once('-')?;
+            let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+            return (start <= end).then_some(PartPattern::Range(start, end));
+        }
+        if part.is_empty() {
+            return None;
+        }
+        // numbers are compared by value, so e.g. `064496` still matches
+        Some(match part.parse() {
+            Ok(number) => PartPattern::Number(number),
+            Err(_) => PartPattern::Text(part.to_string()),
+        })
+    }
+
+    fn matches_number(&self, value: u64) -> bool {
+        match self {
+            PartPattern::Any => true,
+            PartPattern::Number(number) => *number == value,
+            PartPattern::Range(start, end) => (*start..=*end).contains(&value),
+            PartPattern::Text(_) => false,
+        }
+    }
+
+    fn matches(&self, part: &str) -> bool {
+        match self {
+            PartPattern::Any => true,
+            PartPattern::Text(text) => text == part,
+            _ => part
+                .parse()
+                .map(|value| self.matches_number(value))
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl std::fmt::Display for PartPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PartPattern::Any => write!(f, "*"),
+            PartPattern::Number(number) => write!(f, "{}", number),
+            PartPattern::Range(start, end) => write!(f, "[{}-{}]", start, end),
+            PartPattern::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// A community in its textual form, where each part can be `*` to match any value or a range
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CommunityPattern(Vec<PartPattern>);
+
+impl CommunityPattern {
+    fn matches(&self, community: &str) -> bool {
+        let mut parts = community.split(':');
+        self.0.iter().all(|pattern| match parts.next() {
+            None => false,
+            Some(part) => pattern.matches(part),
+        }) && parts.next().is_none()
+    }
+
+    fn matches_numbers(&self, community: &[u64]) -> bool {
+        self.0.len() == community.len()
+            && self
+                .0
+                .iter()
+                .zip(community)
+                .all(|(pattern, value)| pattern.matches_number(*value))
+    }
+}
+
+/// Comma separated list of communities which all have to be attached to a route, in the textual
+/// form like `64496:100`, `64496:1:2` or `target:64496:100`. Any part can be `*` or an inclusive
+/// range, e.g. `64496:*` or `64496:[100-199]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CommunityFilter(Vec<CommunityPattern>);
+
+impl CommunityFilter {
+    pub fn matches<'a>(&self, communities: impl Iterator<Item = &'a str> + Clone) -> bool {
+        self.0.iter().all(|pattern| {
+            communities
+                .clone()
+                .any(|community| pattern.matches(community))
+        })
+    }
+
+    /// Match standard communities without formatting them as text
+    pub fn matches_communities(&self, communities: &[(u16, u16)]) -> bool {
+        self.0.iter().all(|pattern| {
+            communities
+                .iter()
+                .any(|(asn, value)| pattern.matches_numbers(&[*asn as u64, *value as u64]))
+        })
+    }
+
+    /// Match large communities without formatting them as text
+    pub fn matches_large_communities<'a>(
+        &self,
+        communities: impl Iterator<Item = &'a (u32, u32, u32)> + Clone,
+    ) -> bool {
+        self.0.iter().all(|pattern| {
+            communities.clone().any(|(global, local1, local2)| {
+                pattern.matches_numbers(&[*global as u64, *local1 as u64, *local2 as u64])
+            })
+        })
+    }
+}
+
+impl TryFrom<String> for CommunityFilter {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.split(',')
+            .map(|community| {
+                let parts = community
+                    .trim()
+                    .split(':')
+                    .map(|part| {
+                        PartPattern::parse(part)
+                            .ok_or_else(|| format!("invalid community filter {:?}", community))
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(CommunityPattern(parts))
+            })
+            .collect::<Result<_, _>>()
+            .map(CommunityFilter)
+    }
+}
+
+impl From<CommunityFilter> for String {
+    fn from(filter: CommunityFilter) -> Self {
+        filter
+            .0
+            .iter()
+            .map(|pattern| {
+                pattern
+                    .0
+                    .iter()
+                    .map(|part| part.to_string())
+                    .collect::<Vec<_>>()
+                    .join(":")
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn match_communities_with_wildcards() {
+        let filter = CommunityFilter::try_from("64496:*,64497:100".to_string()).unwrap();
+        assert!(filter.matches(["64496:1", "64497:100"].into_iter()));
+        assert!(!filter.matches(["64496:1", "64497:1000"].into_iter()));
+        assert!(!filter.matches(["64496:1:2", "64497:100"].into_iter()));
+
+        let filter = CommunityFilter::try_from("target:*:100".to_string()).unwrap();
+        assert!(filter.matches(["target:192.0.2.1:100"].into_iter()));
+        assert!(!filter.matches(["origin:64496:100"].into_iter()));
+
+        assert!(CommunityFilter::try_from("64496:".to_string()).is_err());
+    }
+
+    #[test]
+    fn match_communities_with_ranges() {
+        let filter = CommunityFilter::try_from("64496:[100-199]".to_string()).unwrap();
+        assert!(filter.matches_communities(&[(64496, 100)]));
+        assert!(filter.matches_communities(&[(64497, 1), (64496, 199)]));
+        assert!(!filter.matches_communities(&[(64496, 200)]));
+        assert!(filter.matches(["64496:150"].into_iter()));
+        assert_eq!(String::from(filter), "64496:[100-199]");
+
+        let filter = CommunityFilter::try_from("64496:*:[1-2]".to_string()).unwrap();
+        assert!(filter.matches_large_communities([(64496, 7, 2)].iter()));
+        assert!(!filter.matches_large_communities([(64496, 7, 3)].iter()));
+        assert!(!filter.matches_communities(&[(64496, 1)]));
+
+        assert!(CommunityFilter::try_from("64496:[200-100]".to_string()).is_err());
+        assert!(CommunityFilter::try_from("64496:[100-]".to_string()).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::store::RouteAttrs;
+
+/// Rewrites or strips communities before routes are stored
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommunityRuleConfig {
+    /// Regex matched against the textual form of a community, e.g. `65000:1\d\d` or `65000:1:2`
+    #[serde(rename = "match")]
+    pub match_regex: String,
+    /// Replacement for matching communities, may contain capture groups like `$1`. Matching
+    /// communities are dropped if this is not set.
+    pub rewrite: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct CommunityRules(Vec<(Regex, Option<String>)>);
+
+impl CommunityRules {
+    pub fn compile(cfg: &[CommunityRuleConfig]) -> anyhow::Result<Self> {
+        Ok(Self(
+            cfg.iter()
+                .map(|rule| {
+                    Ok((
+                        Regex::new(&format!("^{}$", rule.match_regex))?,
+                        rule.rewrite.clone(),
+                    ))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        ))
+    }
+
+    /// Returns the rewritten community, or None if it should be dropped
+    fn apply_str(&self, community: String) -> Option<String> {
+        for (regex, rewrite) in &self.0 {
+            if regex.is_match(&community) {
+                return rewrite
+                    .as_ref()
+                    .map(|rewrite| regex.replace(&community, rewrite).into_owned());
+            }
+        }
+        Some(community)
+    }
+
+    pub fn apply(&self, attrs: &mut RouteAttrs) {
+        if self.0.is_empty() {
+            return;
+        }
+        if let Some(communities) = attrs.communities.take() {
+            attrs.communities = Some(
+                communities
+                    .into_iter()
+                    .filter_map(|(a, b)| {
+                        let rewritten = self.apply_str(format!("{}:{}", a, b))?;
+                        let (a, b) = rewritten.split_once(':')?;
+                        Some((a.parse().ok()?, b.parse().ok()?))
+                    })
+                    .collect(),
+            );
+        }
+        if let Some(large_communities) = attrs.large_communities.take() {
+            attrs.large_communities = Some(
+                large_communities
+                    .into_iter()
+                    .filter_map(|(a, b, c)| {
+                        let rewritten = self.apply_str(format!("{}:{}:{}", a, b, c))?;
+                        let mut parts = rewritten.splitn(3, ':');
+                        Some((
+                            parts.next()?.parse().ok()?,
+                            parts.next()?.parse().ok()?,
+                            parts.next()?.parse().ok()?,
+                        ))
+                    })
+                    .collect(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strip_and_rewrite_communities() {
+        let rules = CommunityRules::compile(&[
+            CommunityRuleConfig {
+                match_regex: r"65000:9\d\d".to_string(),
+                rewrite: None,
+            },
+            CommunityRuleConfig {
+                match_regex: r"65000:(\d+)".to_string(),
+                rewrite: Some("65001:$1".to_string()),
+            },
+            CommunityRuleConfig {
+                match_regex: r"65000:1:(\d+)".to_string(),
+                rewrite: None,
+            },
+        ])
+        .unwrap();
+
+        let mut attrs = RouteAttrs {
+            communities: Some(vec![(65000, 900), (65000, 100), (64496, 1)]),
+            large_communities: Some(vec![(65000, 1, 2), (65000, 2, 2)]),
+            ..Default::default()
+        };
+        rules.apply(&mut attrs);
+
+        assert_eq!(attrs.communities, Some(vec![(65001, 100), (64496, 1)]));
+        assert_eq!(attrs.large_communities, Some(vec![(65000, 2, 2)]));
+    }
+}
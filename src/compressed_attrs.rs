@@ -1,28 +1,57 @@
-use std::hash::Hash;
-use std::net::IpAddr;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::{Arc, Weak};
 use weak_table::traits::WeakKey;
 use weak_table::WeakHashSet;
 
+use crate::as_path::AsPath;
+use crate::ext_community::ExtCommunity;
 use crate::store::*;
 
+/// Interned large communities, the list itself is interned as well
+pub type LargeCommunities = Vec<Arc<(u32, u32, u32)>>;
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub struct CompressedRouteAttrs {
     pub origin: Option<RouteOrigin>,
-    pub as_path: Option<Arc<Vec<u32>>>,
+    pub as_path: Option<Arc<AsPath>>,
     pub communities: Option<Arc<Vec<(u16, u16)>>>,
-    pub large_communities: Option<Arc<Vec<Arc<(u32, u32, u32)>>>>,
+    pub large_communities: Option<Arc<LargeCommunities>>,
+    pub ext_communities: Option<Arc<Vec<ExtCommunity>>>,
     pub med: Option<u32>,
     pub local_pref: Option<u32>,
     pub nexthop: Option<IpAddr>,
+    pub nexthop_link_local: Option<Ipv6Addr>,
+    pub labels: Option<Vec<u32>>,
+    pub aggregator: Option<Aggregator>,
+    pub atomic_aggregate: bool,
+    pub originator_id: Option<Ipv4Addr>,
+    pub cluster_list: Option<Vec<Ipv4Addr>>,
+    pub unknown_attrs: Option<Vec<UnknownAttr>>,
+    pub truncated: bool,
+}
+
+impl CompressedRouteAttrs {
+    /// Identifies the attributes for correlating results, as long as the process is running. Not
+    /// using the address of the interned attributes, as paths stored as nexthop deltas are not
+    /// interned.
+    pub fn id(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        // as string, because JavaScript can not represent all u64 values
+        format!("{:016x}", hasher.finish())
+    }
 }
 
 #[derive(Default)]
 pub struct Caches {
     large_communities_cache: WeakHashSet<Weak<(u32, u32, u32)>>,
-    large_communities_list_cache: WeakHashSet<Weak<Vec<Arc<(u32, u32, u32)>>>>,
+    large_communities_list_cache: WeakHashSet<Weak<LargeCommunities>>,
     communities_list_cache: WeakHashSet<Weak<Vec<(u16, u16)>>>,
-    as_path_cache: WeakHashSet<Weak<Vec<u32>>>,
+    ext_communities_list_cache: WeakHashSet<Weak<Vec<ExtCommunity>>>,
+    as_path_cache: WeakHashSet<Weak<AsPath>>,
     route_attrs_cache: WeakHashSet<Weak<CompressedRouteAttrs>>,
 }
 
@@ -60,18 +89,72 @@ impl Caches {
                     .get_or_insert(list)
                     .clone()
             }),
+            ext_communities: route
+                .ext_communities
+                .map(|x| self.ext_communities_list_cache.get_or_insert(x).clone()),
             local_pref: route.local_pref,
             med: route.med,
             origin: route.origin,
             nexthop: route.nexthop,
+            nexthop_link_local: route.nexthop_link_local,
+            labels: route.labels,
+            aggregator: route.aggregator,
+            atomic_aggregate: route.atomic_aggregate,
+            originator_id: route.originator_id,
+            cluster_list: route.cluster_list,
+            unknown_attrs: route.unknown_attrs,
+            truncated: route.truncated,
         };
         self.route_attrs_cache.get_or_insert(route)
     }
 
+    /// Intern attributes whose parts were already compressed
+    pub fn intern_route_attrs(&mut self, attrs: CompressedRouteAttrs) -> Arc<CompressedRouteAttrs> {
+        self.route_attrs_cache.get_or_insert(attrs)
+    }
+
+    /// Rough estimate of the memory used by the cached attributes, in bytes
+    pub fn memory_estimate(&self) -> usize {
+        // the reference counts of each allocation
+        const ARC_OVERHEAD: usize = 2 * size_of::<usize>();
+        let list =
+            |len: usize, item_size: usize| ARC_OVERHEAD + size_of::<Vec<()>>() + len * item_size;
+
+        self.large_communities_cache.len() * (ARC_OVERHEAD + size_of::<(u32, u32, u32)>())
+            + self
+                .large_communities_list_cache
+                .iter()
+                .map(|l| list(l.len(), size_of::<Arc<(u32, u32, u32)>>()))
+                .sum::<usize>()
+            + self
+                .communities_list_cache
+                .iter()
+                .map(|l| list(l.len(), size_of::<(u16, u16)>()))
+                .sum::<usize>()
+            + self
+                .ext_communities_list_cache
+                .iter()
+                .map(|l| list(l.len(), size_of::<ExtCommunity>()))
+                .sum::<usize>()
+            + self
+                .as_path_cache
+                .iter()
+                .map(|as_path| {
+                    as_path
+                        .0
+                        .iter()
+                        .map(|segment| list(segment.asns().len(), size_of::<u32>()))
+                        .sum::<usize>()
+                })
+                .sum::<usize>()
+            + self.route_attrs_cache.len() * (ARC_OVERHEAD + size_of::<CompressedRouteAttrs>())
+    }
+
     pub fn remove_expired(&mut self) {
         self.large_communities_cache.remove_expired();
         self.large_communities_list_cache.remove_expired();
         self.communities_list_cache.remove_expired();
+        self.ext_communities_list_cache.remove_expired();
         self.as_path_cache.remove_expired();
         self.route_attrs_cache.remove_expired();
     }
@@ -85,9 +168,18 @@ pub fn decompress_route_attrs(route: &CompressedRouteAttrs) -> RouteAttrs {
             .large_communities
             .as_ref()
             .map(|x| x.iter().map(|x| **x).collect()),
+        ext_communities: route.ext_communities.as_ref().map(|x| (**x).clone()),
         local_pref: route.local_pref,
         med: route.med,
         origin: route.origin.clone(),
         nexthop: route.nexthop,
+        nexthop_link_local: route.nexthop_link_local,
+        labels: route.labels.clone(),
+        aggregator: route.aggregator,
+        atomic_aggregate: route.atomic_aggregate,
+        originator_id: route.originator_id,
+        cluster_list: route.cluster_list.clone(),
+        unknown_attrs: route.unknown_attrs.clone(),
+        truncated: route.truncated,
     }
 }
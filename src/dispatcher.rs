@@ -0,0 +1,329 @@
+//! Dispatcher mode, to spread the ingest over multiple fernglas instances. The dispatcher accepts
+//! the BMP connections and forwards each of them to one of the workers, chosen by consistent
+//! hashing of the router, so adding or removing a worker only moves the routers of that worker.
+//! Queries to the API of the dispatcher are sent to all workers and the results are combined.
+//!
+//! Routers are identified by their address, as BMP only carries the router ID with the first Peer
+//! Up message.
+
+use axum::body::Body;
+use axum::extract::{OriginalUri, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use futures_util::future::join_all;
+use futures_util::FutureExt;
+use log::*;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::api::{get_metrics, ApiServerConfig};
+
+fn default_virtual_nodes() -> usize {
+    100
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkerConfig {
+    /// Address of the BMP collector of the worker
+    pub bmp: SocketAddr,
+    /// Address of the API server of the worker
+    pub api: SocketAddr,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DispatcherConfig {
+    /// Address the BMP connections of the routers are accepted on
+    pub bind: SocketAddr,
+    pub workers: Vec<WorkerConfig>,
+    /// Points of each worker on the hash ring, more points spread the routers more evenly
+    #[serde(default = "default_virtual_nodes")]
+    pub virtual_nodes: usize,
+    /// Seconds to wait for the response of a worker to a query
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Consistent hash ring of the workers
+pub struct HashRing {
+    points: Vec<(u64, usize)>,
+    workers: usize,
+}
+
+impl HashRing {
+    /// The points of a worker only depend on its address, not on its position in the list
+    pub fn new(workers: &[WorkerConfig], virtual_nodes: usize) -> Self {
+        let mut points = workers
+            .iter()
+            .enumerate()
+            .flat_map(|(index, worker)| {
+                (0..virtual_nodes.max(1)).map(move |node| (hash((worker.bmp, node)), index))
+            })
+            .collect::<Vec<_>>();
+        points.sort_unstable();
+        Self {
+            points,
+            workers: workers.len(),
+        }
+    }
+
+    /// The workers in the order they are tried for the router, the first one is its owner
+    pub fn workers_for(&self, router: IpAddr) -> Vec<usize> {
+        let key = hash(router);
+        let start = self.points.partition_point(|(point, _)| *point < key);
+        let mut order = Vec::with_capacity(self.workers);
+        for (_, worker) in self.points[start..].iter().chain(&self.points[..start]) {
+            if !order.contains(worker) {
+                order.push(*worker);
+                if order.len() == self.workers {
+                    break;
+                }
+            }
+        }
+        order
+    }
+}
+
+/// Connect to the owner of the router, or to the next worker on the ring if it is unreachable
+async fn connect_worker(
+    cfg: &DispatcherConfig,
+    ring: &HashRing,
+    router: IpAddr,
+) -> Option<TcpStream> {
+    for worker in ring.workers_for(router) {
+        let addr = cfg.workers[worker].bmp;
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Some(stream),
+            Err(e) => warn!("failed to connect to worker {} for {}: {}", addr, router, e),
+        }
+    }
+    None
+}
+
+async fn forward(
+    cfg: Arc<DispatcherConfig>,
+    ring: Arc<HashRing>,
+    mut io: TcpStream,
+    client_addr: SocketAddr,
+) {
+    let Some(mut worker) = connect_worker(&cfg, &ring, client_addr.ip()).await else {
+        warn!("no worker reachable for {}", client_addr);
+        return;
+    };
+    debug!("forwarding {} to {:?}", client_addr, worker.peer_addr());
+    if let Err(e) = tokio::io::copy_bidirectional(&mut io, &mut worker).await {
+        debug!("forwarding {} ended: {}", client_addr, e);
+    }
+}
+
+/// Accept the BMP connections and forward them to the workers
+pub async fn run(
+    cfg: DispatcherConfig,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    if cfg.workers.is_empty() {
+        anyhow::bail!("dispatcher has no workers");
+    }
+    let ring = Arc::new(HashRing::new(&cfg.workers, cfg.virtual_nodes));
+    let cfg = Arc::new(cfg);
+    let listener = TcpListener::bind(cfg.bind).await?;
+    loop {
+        tokio::select! {
+            new_conn = listener.accept() => {
+                let (io, client_addr) = new_conn?;
+                info!("connected {:?}", client_addr);
+                let forwarding = forward(cfg.clone(), ring.clone(), io, client_addr);
+                let mut shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    tokio::select! {
+                        _ = forwarding => info!("disconnected {}", client_addr),
+                        _ = shutdown.changed() => {}
+                    }
+                });
+            }
+            _ = shutdown.changed() => break Ok(()),
+        }
+    }
+}
+
+/// GET request to the API of a worker, returns the body of a successful response. HTTP/1.0 is
+/// used, so the response is not chunked and ends with the connection.
+async fn fetch(addr: SocketAddr, path_and_query: &str) -> anyhow::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path_and_query, addr
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await?;
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("invalid HTTP response"))?;
+    let status_line = response[..header_end]
+        .split(|b| *b == b'\n')
+        .next()
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow::anyhow!("invalid HTTP status line: {}", status_line))?;
+    if status != "200" {
+        anyhow::bail!("worker responded with {}", status_line.trim());
+    }
+    Ok(response.split_off(header_end + 4))
+}
+
+/// Combine the responses of the workers. JSON arrays are concatenated and JSON objects merged,
+/// anything else, e.g. the newline delimited results of `/api/query`, is concatenated as is.
+fn combine(bodies: Vec<Vec<u8>>) -> Vec<u8> {
+    if bodies.iter().any(|body| body.ends_with(b"\n")) {
+        return bodies.concat();
+    }
+    let values = bodies
+        .iter()
+        .map(|body| serde_json::from_slice::<serde_json::Value>(body))
+        .collect::<Result<Vec<_>, _>>();
+    match values {
+        Ok(values) if values.iter().all(|value| value.is_array()) => {
+            let combined = values
+                .into_iter()
+                .flat_map(|value| match value {
+                    serde_json::Value::Array(items) => items,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_vec(&combined).unwrap()
+        }
+        Ok(values) if values.iter().all(|value| value.is_object()) => {
+            let mut combined = serde_json::Map::new();
+            for value in values {
+                if let serde_json::Value::Object(map) = value {
+                    combined.extend(map);
+                }
+            }
+            serde_json::to_vec(&combined).unwrap()
+        }
+        _ => bodies.concat(),
+    }
+}
+
+/// Send the request to the API of every worker and combine the results. Workers which fail are
+/// left out, only if all of them fail the request fails.
+async fn fan_out(
+    State(cfg): State<Arc<DispatcherConfig>>,
+    OriginalUri(uri): OriginalUri,
+) -> Response {
+    let path_and_query = uri
+        .path_and_query()
+        .map(|path_and_query| path_and_query.as_str())
+        .unwrap_or("/");
+    let timeout = Duration::from_secs(cfg.timeout);
+    let responses = join_all(cfg.workers.iter().map(|worker| {
+        tokio::time::timeout(timeout, fetch(worker.api, path_and_query)).map(move |res| {
+            res.map_err(anyhow::Error::from)
+                .and_then(|res| res)
+                .inspect_err(|e| warn!("query to worker {} failed: {}", worker.api, e))
+                .ok()
+        })
+    }))
+    .await;
+    let bodies = responses.into_iter().flatten().collect::<Vec<_>>();
+    if bodies.is_empty() {
+        return (StatusCode::BAD_GATEWAY, "no worker responded").into_response();
+    }
+    Body::from(combine(bodies)).into_response()
+}
+
+/// Serve the API by fanning out the requests to the workers
+pub async fn run_api_server(
+    api_cfg: ApiServerConfig,
+    cfg: DispatcherConfig,
+    shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let router = Router::new()
+        .route("/api/*path", get(fan_out))
+        .with_state(Arc::new(cfg))
+        .route("/metrics", get(get_metrics));
+
+    crate::api::serve(&api_cfg, router, shutdown).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn worker(port: u16) -> WorkerConfig {
+        WorkerConfig {
+            bmp: SocketAddr::from(([192, 0, 2, 1], port)),
+            api: SocketAddr::from(([192, 0, 2, 1], port + 1000)),
+        }
+    }
+
+    #[test]
+    fn consistent_assignment() {
+        let workers = vec![worker(1), worker(2), worker(3)];
+        let ring = HashRing::new(&workers, 100);
+        let routers = (0..=255u8)
+            .map(|i| IpAddr::from([198, 51, 100, i]))
+            .collect::<Vec<_>>();
+        let owners = routers
+            .iter()
+            .map(|router| ring.workers_for(*router)[0])
+            .collect::<Vec<_>>();
+        for worker in 0..3 {
+            assert!(owners.contains(&worker));
+        }
+        assert_eq!(ring.workers_for(routers[0]).len(), 3);
+
+        // only the routers of the removed worker move
+        let ring = HashRing::new(&[worker(1), worker(3)], 100);
+        for (router, owner) in routers.iter().zip(owners) {
+            let new_owner = match ring.workers_for(*router)[0] {
+                0 => 0,
+                _ => 2,
+            };
+            if owner != 1 {
+                assert_eq!(new_owner, owner);
+            }
+        }
+    }
+
+    #[test]
+    fn combine_responses() {
+        assert_eq!(
+            combine(vec![b"[1,2]".to_vec(), b"[3]".to_vec()]),
+            b"[1,2,3]".to_vec()
+        );
+        assert_eq!(
+            combine(vec![b"{\"a\":1}".to_vec(), b"{\"b\":2}".to_vec()]),
+            b"{\"a\":1,\"b\":2}".to_vec()
+        );
+        assert_eq!(
+            combine(vec![b"{\"a\":1}\n".to_vec(), b"{\"a\":2}\n".to_vec()]),
+            b"{\"a\":1}\n{\"a\":2}\n".to_vec()
+        );
+    }
+}
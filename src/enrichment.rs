@@ -0,0 +1,427 @@
+use crate::api::ApiResult;
+use crate::asn_info;
+use crate::geoip::{GeoIpConfig, GeoIpEnricher};
+use crate::irr;
+use crate::ownership::{OwnedPrefix, OwnershipEnricher};
+use crate::rpki;
+use crate::store::QueryResult;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use hickory_resolver::TokioAsyncResolver;
+use ipnet::IpNet;
+use regex::Regex;
+use regex::RegexSet;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+static COMMUNITIES_LIST: &[u8] = include_bytes!("communities.json");
+
+pub type EnrichmentFuture = BoxFuture<'static, Option<ApiResult>>;
+
+/// Adds additional information to the results of a query
+pub trait Enricher: Send + Sync {
+    /// Items of a route which should be looked up. Each key is only looked up once per query.
+    fn keys(&self, route: &QueryResult) -> Vec<String>;
+
+    fn lookup(&self, key: String) -> EnrichmentFuture;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum EnricherConfig {
+    /// Reverse DNS names of nexthops
+    ReverseDns,
+    /// AS names looked up via the `asn_dns_zone`
+    AsnName,
+    /// Descriptions of communities from the communities list
+    CommunityDescription,
+    /// Owner and description of the configured owned prefixes overlapping the route
+    PrefixOwnership,
+    /// Country and AS of the prefix and nexthop from the configured `geoip` databases
+    GeoIp,
+    /// Origin validation state of the route from the VRPs of the `rpki` source
+    RpkiStatus,
+    /// Names of the ASes of the AS path from the `asn_info` file
+    AsnInfo,
+    /// Route object validation state of the route from the `irr` databases
+    IrrStatus,
+}
+
+pub fn default_enrichers() -> Vec<EnricherConfig> {
+    vec![
+        EnricherConfig::ReverseDns,
+        EnricherConfig::AsnName,
+        EnricherConfig::CommunityDescription,
+        EnricherConfig::PrefixOwnership,
+        EnricherConfig::GeoIp,
+        EnricherConfig::RpkiStatus,
+        EnricherConfig::AsnInfo,
+        EnricherConfig::IrrStatus,
+    ]
+}
+
+/// Reference data which is loaded in the background, independent of the API configuration
+#[derive(Clone, Default)]
+pub struct EnrichmentSources {
+    pub rpki: rpki::Validator,
+    pub asn_db: asn_info::AsnDb,
+    pub irr: irr::Registry,
+}
+
+/// The enrichers configured for the API, in order
+pub struct EnrichmentPipeline(Vec<Box<dyn Enricher>>);
+
+impl EnrichmentPipeline {
+    pub async fn new(
+        cfg: &[EnricherConfig],
+        resolver: &TokioAsyncResolver,
+        asn_dns_zone: Option<&String>,
+        communities_file: Option<&String>,
+        owned_prefixes: &[OwnedPrefix],
+        geoip: Option<&GeoIpConfig>,
+        sources: &EnrichmentSources,
+    ) -> anyhow::Result<Self> {
+        let mut enrichers: Vec<Box<dyn Enricher>> = vec![];
+        for enricher in cfg {
+            match enricher {
+                EnricherConfig::ReverseDns => enrichers.push(Box::new(ReverseDnsEnricher {
+                    resolver: resolver.clone(),
+                })),
+                EnricherConfig::AsnName => {
+                    if let Some(asn_dns_zone) = asn_dns_zone {
+                        enrichers.push(Box::new(AsnNameEnricher {
+                            resolver: resolver.clone(),
+                            asn_dns_zone: asn_dns_zone.clone(),
+                        }))
+                    }
+                }
+                EnricherConfig::CommunityDescription => {
+                    let community_lists: CommunitiesLists = if let Some(path) = communities_file {
+                        let path = path.clone();
+                        serde_json::from_slice(
+                            &tokio::task::spawn_blocking(move || std::fs::read(path)).await??,
+                        )?
+                    } else {
+                        serde_json::from_slice(COMMUNITIES_LIST)?
+                    };
+                    enrichers.push(Box::new(community_lists.compile()?));
+                }
+                EnricherConfig::PrefixOwnership => {
+                    enrichers.push(Box::new(OwnershipEnricher(owned_prefixes.to_vec())))
+                }
+                EnricherConfig::GeoIp => {
+                    if let Some(geoip) = geoip {
+                        enrichers.push(Box::new(GeoIpEnricher::open(geoip).await?))
+                    }
+                }
+                EnricherConfig::RpkiStatus => {
+                    enrichers.push(Box::new(RpkiEnricher(sources.rpki.clone())))
+                }
+                EnricherConfig::AsnInfo => {
+                    enrichers.push(Box::new(AsnInfoEnricher(sources.asn_db.clone())))
+                }
+                EnricherConfig::IrrStatus => {
+                    enrichers.push(Box::new(IrrEnricher(sources.irr.clone())))
+                }
+            }
+        }
+        Ok(Self(enrichers))
+    }
+
+    /// Start enriching the results of a new query
+    pub fn start(self: &Arc<Self>) -> QueryEnrichment {
+        QueryEnrichment {
+            pipeline: self.clone(),
+            seen: HashSet::new(),
+        }
+    }
+}
+
+/// Deduplicates lookups over the results of a single query
+pub struct QueryEnrichment {
+    pipeline: Arc<EnrichmentPipeline>,
+    seen: HashSet<(usize, String)>,
+}
+
+impl QueryEnrichment {
+    pub fn enrich(&mut self, route: &QueryResult) -> Vec<EnrichmentFuture> {
+        let mut futures = vec![];
+        for (i, enricher) in self.pipeline.0.iter().enumerate() {
+            for key in enricher.keys(route) {
+                if self.seen.insert((i, key.clone())) {
+                    futures.push(enricher.lookup(key));
+                }
+            }
+        }
+        futures
+    }
+}
+
+struct ReverseDnsEnricher {
+    resolver: TokioAsyncResolver,
+}
+
+impl Enricher for ReverseDnsEnricher {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        route
+            .attrs
+            .nexthop
+            .iter()
+            .map(|nexthop| nexthop.to_string())
+            .collect()
+    }
+
+    fn lookup(&self, key: String) -> EnrichmentFuture {
+        let resolver = self.resolver.clone();
+        async move {
+            let nexthop: IpAddr = key.parse().ok()?;
+            resolver
+                .reverse_lookup(nexthop)
+                .await
+                .ok()
+                .and_then(|reverse| reverse.iter().next().map(|x| x.0.to_string()))
+                .map(|nexthop_resolved| ApiResult::ReverseDns {
+                    nexthop,
+                    nexthop_resolved,
+                })
+        }
+        .boxed()
+    }
+}
+
+struct AsnNameEnricher {
+    resolver: TokioAsyncResolver,
+    asn_dns_zone: String,
+}
+
+impl Enricher for AsnNameEnricher {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        route
+            .attrs
+            .as_path
+            .iter()
+            .flat_map(|as_path| as_path.asns())
+            .map(|asn| asn.to_string())
+            .collect()
+    }
+
+    fn lookup(&self, key: String) -> EnrichmentFuture {
+        let resolver = self.resolver.clone();
+        let name = self.asn_dns_zone.replace("{}", &key);
+        async move {
+            let asn: u32 = key.parse().ok()?;
+            resolver
+                .txt_lookup(name)
+                .await
+                .ok()
+                .and_then(|txt| {
+                    txt.iter().next().and_then(|x| {
+                        x.iter()
+                            .next()
+                            .and_then(|data| std::str::from_utf8(data).ok())
+                            .and_then(|s| s.split(" | ").nth(4).map(|name| name.to_string()))
+                    })
+                })
+                .map(|asn_name| ApiResult::AsnName { asn, asn_name })
+        }
+        .boxed()
+    }
+}
+
+/// Key of the validations which depend on the prefix and the origin of a route
+fn origin_key(route: &QueryResult) -> String {
+    let origin_asn = route
+        .attrs
+        .as_path
+        .as_ref()
+        .and_then(|as_path| as_path.origin_asn());
+    match origin_asn {
+        Some(asn) => format!("{} {}", route.net, asn),
+        None => route.net.to_string(),
+    }
+}
+
+fn parse_origin_key(key: &str) -> Option<(IpNet, Option<u32>)> {
+    let mut parts = key.split(' ');
+    let net = parts.next()?.parse().ok()?;
+    let origin_asn = parts.next().map(|asn| asn.parse()).transpose().ok()?;
+    Some((net, origin_asn))
+}
+
+/// Routes are validated per prefix and origin, with the VRPs which are current at lookup time
+struct RpkiEnricher(rpki::Validator);
+
+impl Enricher for RpkiEnricher {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        vec![origin_key(route)]
+    }
+
+    fn lookup(&self, key: String) -> EnrichmentFuture {
+        let result = self.0.current().and_then(|vrps| {
+            let (net, origin_asn) = parse_origin_key(&key)?;
+            Some(ApiResult::RpkiStatus {
+                net,
+                origin_asn,
+                rpki_status: vrps.validate(&net, origin_asn),
+            })
+        });
+        futures_util::future::ready(result).boxed()
+    }
+}
+
+/// Validated like the RPKI, with the route objects which are current at lookup time
+struct IrrEnricher(irr::Registry);
+
+impl Enricher for IrrEnricher {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        vec![origin_key(route)]
+    }
+
+    fn lookup(&self, key: String) -> EnrichmentFuture {
+        let result = self.0.current().and_then(|objects| {
+            let (net, origin_asn) = parse_origin_key(&key)?;
+            Some(ApiResult::IrrStatus {
+                net,
+                origin_asn,
+                irr_status: objects.validate(&net, origin_asn),
+            })
+        });
+        futures_util::future::ready(result).boxed()
+    }
+}
+
+/// Like the `AsnName` enricher, but from the loaded `asn_info` file instead of DNS lookups
+struct AsnInfoEnricher(asn_info::AsnDb);
+
+impl Enricher for AsnInfoEnricher {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        route
+            .attrs
+            .as_path
+            .iter()
+            .flat_map(|as_path| as_path.asns())
+            .map(|asn| asn.to_string())
+            .collect()
+    }
+
+    fn lookup(&self, key: String) -> EnrichmentFuture {
+        let result = self.0.current().and_then(|names| {
+            let asn: u32 = key.parse().ok()?;
+            let info = names.get(asn)?.clone();
+            Some(ApiResult::AsnInfo { asn, info })
+        });
+        futures_util::future::ready(result).boxed()
+    }
+}
+
+#[derive(Deserialize)]
+struct CommunitiesLists {
+    regular: CommunitiesList,
+    large: CommunitiesList,
+}
+impl CommunitiesLists {
+    fn compile(self) -> anyhow::Result<CompiledCommunitiesLists> {
+        Ok(CompiledCommunitiesLists {
+            regular: self.regular.compile()?,
+            large: self.large.compile()?,
+        })
+    }
+}
+
+struct CompiledCommunitiesLists {
+    regular: CompiledCommunitiesList,
+    large: CompiledCommunitiesList,
+}
+
+impl Enricher for CompiledCommunitiesLists {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        let communities = route
+            .attrs
+            .communities
+            .iter()
+            .flatten()
+            .map(|community| format!("{}:{}", community.0, community.1));
+        let large_communities =
+            route
+                .attrs
+                .large_communities
+                .iter()
+                .flatten()
+                .map(|large_community| {
+                    format!(
+                        "{}:{}:{}",
+                        large_community.0, large_community.1, large_community.2
+                    )
+                });
+        communities.chain(large_communities).collect()
+    }
+
+    fn lookup(&self, community: String) -> EnrichmentFuture {
+        let list = if community.matches(':').count() == 2 {
+            &self.large
+        } else {
+            &self.regular
+        };
+        let result = list
+            .lookup(&community)
+            .map(|lookup| lookup.to_string())
+            .map(|community_description| ApiResult::CommunityDescription {
+                community,
+                community_description,
+            });
+        futures_util::future::ready(result).boxed()
+    }
+}
+
+#[derive(Deserialize)]
+struct CommunitiesList(HashMap<String, String>);
+
+impl CommunitiesList {
+    fn compile(self) -> anyhow::Result<CompiledCommunitiesList> {
+        let mut sorted = self.0.into_iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|(regex, _)| regex.len());
+        Ok(CompiledCommunitiesList {
+            regex_set: RegexSet::new(sorted.iter().map(|(regex, _desc)| format!("^{}$", regex)))?,
+            list: sorted
+                .into_iter()
+                .map(|(key, value)| Ok((Regex::new(&format!("^{}$", key))?, value)))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+struct CompiledCommunitiesList {
+    regex_set: RegexSet,
+    list: Vec<(Regex, String)>,
+}
+impl CompiledCommunitiesList {
+    fn lookup(&self, community: &str) -> Option<Cow<'_, str>> {
+        self.regex_set
+            .matches(community)
+            .iter()
+            .next()
+            .map(|index| {
+                let (regex, desc) = &self.list[index];
+                let mut desc_templated: Cow<str> = desc.into();
+                for (i, subcapture) in regex
+                    .captures(community)
+                    .unwrap()
+                    .iter()
+                    .skip(1)
+                    .enumerate()
+                {
+                    if let Some(subcapture) = subcapture {
+                        let searchstr = format!("${}", i);
+                        if desc_templated.contains(&searchstr) {
+                            desc_templated =
+                                desc_templated.replace(&searchstr, subcapture.into()).into()
+                        }
+                    }
+                }
+                desc_templated
+            })
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::net::Ipv4Addr;
+
+use crate::route_distinguisher::RouteDistinguisher;
+
+const TYPE_TWO_OCTET_AS: u8 = 0x00;
+const TYPE_IPV4_ADDRESS: u8 = 0x01;
+const TYPE_FOUR_OCTET_AS: u8 = 0x02;
+const TYPE_OPAQUE: u8 = 0x03;
+const TYPE_NON_TRANSITIVE_TWO_OCTET_AS: u8 = 0x40;
+
+const SUBTYPE_ROUTE_TARGET: u8 = 0x02;
+const SUBTYPE_ROUTE_ORIGIN: u8 = 0x03;
+const SUBTYPE_LINK_BANDWIDTH: u8 = 0x04;
+const SUBTYPE_COLOR: u8 = 0x0b;
+
+/// Extended community (RFC 4360). Route targets and route origins have the same administrator and
+/// value formats as route distinguishers, so they are represented as such.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ExtCommunity {
+    RouteTarget(RouteDistinguisher),
+    /// Site of Origin
+    RouteOrigin(RouteDistinguisher),
+    /// Link bandwidth in bytes per second (draft-ietf-idr-link-bandwidth)
+    LinkBandwidth {
+        asn: u16,
+        bandwidth: u64,
+    },
+    /// Color used for steering into SR policies (RFC 9012)
+    Color {
+        flags: u16,
+        color: u32,
+    },
+    /// Any other extended community, with the six octet value
+    Other {
+        ctype: u8,
+        subtype: u8,
+        value: u64,
+    },
+}
+
+impl ExtCommunity {
+    /// Decode an extended community from its type, subtype, and the two and four octet parts of
+    /// its value
+    pub fn from_parts(ctype: u8, subtype: u8, a: u16, b: u32) -> Self {
+        let high = a.to_be_bytes();
+        let low = b.to_be_bytes();
+        let admin = match ctype {
+            TYPE_TWO_OCTET_AS => Some(RouteDistinguisher::Type0 { asn: a, value: b }),
+            TYPE_IPV4_ADDRESS => Some(RouteDistinguisher::Type1 {
+                ip: Ipv4Addr::from([high[0], high[1], low[0], low[1]]),
+                value: u16::from_be_bytes([low[2], low[3]]),
+            }),
+            TYPE_FOUR_OCTET_AS => Some(RouteDistinguisher::Type2 {
+                asn: u32::from_be_bytes([high[0], high[1], low[0], low[1]]),
+                value: u16::from_be_bytes([low[2], low[3]]),
+            }),
+            _ => None,
+        };
+        match (ctype, subtype, admin) {
+            (_, SUBTYPE_ROUTE_TARGET, Some(admin)) => ExtCommunity::RouteTarget(admin),
+            (_, SUBTYPE_ROUTE_ORIGIN, Some(admin)) => ExtCommunity::RouteOrigin(admin),
+            (TYPE_NON_TRANSITIVE_TWO_OCTET_AS, SUBTYPE_LINK_BANDWIDTH, _) => {
+                ExtCommunity::LinkBandwidth {
+                    asn: a,
+                    bandwidth: f32::from_bits(b) as u64,
+                }
+            }
+            (TYPE_OPAQUE, SUBTYPE_COLOR, _) => ExtCommunity::Color { flags: a, color: b },
+            _ => ExtCommunity::Other {
+                ctype,
+                subtype,
+                value: ((a as u64) << 32) | b as u64,
+            },
+        }
+    }
+
+    /// Decode an extended community from its eight octet wire format
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self::from_parts(
+            bytes[0],
+            bytes[1],
+            u16::from_be_bytes([bytes[2], bytes[3]]),
+            u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        )
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let (ctype, subtype, value) = match *self {
+            ExtCommunity::RouteTarget(admin) | ExtCommunity::RouteOrigin(admin) => {
+                let subtype = match self {
+                    ExtCommunity::RouteTarget(_) => SUBTYPE_ROUTE_TARGET,
+                    _ => SUBTYPE_ROUTE_ORIGIN,
+                };
+                let (ctype, value) = match admin {
+                    RouteDistinguisher::Default => (TYPE_TWO_OCTET_AS, 0),
+                    RouteDistinguisher::Type0 { asn, value } => {
+                        (TYPE_TWO_OCTET_AS, ((asn as u64) << 32) | value as u64)
+                    }
+                    RouteDistinguisher::Type1 { ip, value } => (
+                        TYPE_IPV4_ADDRESS,
+                        ((u32::from(ip) as u64) << 16) | value as u64,
+                    ),
+                    RouteDistinguisher::Type2 { asn, value } => {
+                        (TYPE_FOUR_OCTET_AS, ((asn as u64) << 16) | value as u64)
+                    }
+                };
+                (ctype, subtype, value)
+            }
+            ExtCommunity::LinkBandwidth { asn, bandwidth } => (
+                TYPE_NON_TRANSITIVE_TWO_OCTET_AS,
+                SUBTYPE_LINK_BANDWIDTH,
+                ((asn as u64) << 32) | (bandwidth as f32).to_bits() as u64,
+            ),
+            ExtCommunity::Color { flags, color } => (
+                TYPE_OPAQUE,
+                SUBTYPE_COLOR,
+                ((flags as u64) << 32) | color as u64,
+            ),
+            ExtCommunity::Other {
+                ctype,
+                subtype,
+                value,
+            } => (ctype, subtype, value),
+        };
+        let mut bytes = value.to_be_bytes();
+        bytes[0] = ctype;
+        bytes[1] = subtype;
+        bytes
+    }
+}
+
+/// Textual form as used by community filters, e.g. `target:64496:100`
+impl Display for ExtCommunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtCommunity::RouteTarget(admin) => write!(f, "target:{admin}"),
+            ExtCommunity::RouteOrigin(admin) => write!(f, "origin:{admin}"),
+            ExtCommunity::LinkBandwidth { asn, bandwidth } => {
+                write!(f, "bandwidth:{asn}:{bandwidth}")
+            }
+            ExtCommunity::Color { color, .. } => write!(f, "color:{color}"),
+            ExtCommunity::Other {
+                ctype,
+                subtype,
+                value,
+            } => write!(f, "other:{ctype}:{subtype}:{value}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_ext_communities() {
+        assert_eq!(
+            ExtCommunity::from_bytes([0x00, 0x02, 0xfb, 0xf0, 0x00, 0x00, 0x00, 0x64]),
+            ExtCommunity::RouteTarget(RouteDistinguisher::Type0 {
+                asn: 64496,
+                value: 100
+            })
+        );
+        assert_eq!(
+            ExtCommunity::from_bytes([0x01, 0x03, 192, 0, 2, 1, 0x00, 0x64]),
+            ExtCommunity::RouteOrigin(RouteDistinguisher::Type1 {
+                ip: Ipv4Addr::new(192, 0, 2, 1),
+                value: 100
+            })
+        );
+        assert_eq!(
+            ExtCommunity::from_bytes([0x03, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2a]),
+            ExtCommunity::Color {
+                flags: 0,
+                color: 42
+            }
+        );
+        let bandwidth = ExtCommunity::from_parts(0x40, 0x04, 64496, 1.25e9f32.to_bits());
+        assert_eq!(
+            bandwidth,
+            ExtCommunity::LinkBandwidth {
+                asn: 64496,
+                bandwidth: 1_250_000_000
+            }
+        );
+    }
+
+    #[test]
+    fn encode_ext_communities() {
+        for bytes in [
+            [0x00, 0x02, 0xfb, 0xf0, 0x00, 0x00, 0x00, 0x64],
+            [0x01, 0x02, 192, 0, 2, 1, 0x00, 0x64],
+            [0x02, 0x03, 0x00, 0x01, 0x11, 0x70, 0x00, 0x64],
+            [0x03, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2a],
+            [0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ] {
+            assert_eq!(ExtCommunity::from_bytes(bytes).to_bytes(), bytes);
+        }
+    }
+}
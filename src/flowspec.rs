@@ -0,0 +1,304 @@
+//! BGP Flow Specification (RFC 8955, RFC 8956) rules and their traffic filtering actions
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use crate::ext_community::ExtCommunity;
+use crate::store::{RouteAttrs, TableQuery, TableSelector};
+
+const TYPE_FLOWSPEC: u8 = 0x80;
+
+const SUBTYPE_TRAFFIC_RATE: u8 = 0x06;
+const SUBTYPE_TRAFFIC_ACTION: u8 = 0x07;
+const SUBTYPE_REDIRECT: u8 = 0x08;
+const SUBTYPE_TRAFFIC_MARKING: u8 = 0x09;
+
+/// Kind of a match component of a flowspec rule, besides the prefixes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowspecComponentType {
+    Protocol,
+    Port,
+    DestinationPort,
+    SourcePort,
+    IcmpType,
+    IcmpCode,
+    TcpFlags,
+    PacketLength,
+    Dscp,
+    Fragment,
+    FlowLabel,
+}
+
+/// A match component, with the operators and values as text, e.g. `>=1024&<=2048|=80`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct FlowspecComponent {
+    #[serde(rename = "type")]
+    pub component_type: FlowspecComponentType,
+    pub condition: String,
+}
+
+/// The match part of a flowspec route. Rules are identified by all their components, like
+/// prefixes identify unicast routes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct FlowspecRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<IpNet>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<IpNet>,
+    /// The other components, in the order of the NLRI
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<FlowspecComponent>,
+}
+
+/// Traffic filtering action, carried as extended community (RFC 8955 section 7)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowspecAction {
+    /// Limit the traffic to `rate` bytes per second, a rate of 0 discards the traffic
+    TrafficRate {
+        asn: u16,
+        rate: f32,
+    },
+    TrafficAction {
+        sample: bool,
+        terminal: bool,
+    },
+    /// Redirect the traffic to the VRF with this route target
+    Redirect {
+        asn: u16,
+        value: u32,
+    },
+    TrafficMarking {
+        dscp: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowspecActionType {
+    /// Traffic rate of 0
+    Discard,
+    /// Traffic rate other than 0
+    RateLimit,
+    Redirect,
+    Marking,
+    Sample,
+}
+
+impl FlowspecAction {
+    pub fn from_ext_community(community: &ExtCommunity) -> Option<Self> {
+        let ExtCommunity::Other {
+            ctype: TYPE_FLOWSPEC,
+            subtype,
+            value,
+        } = *community
+        else {
+            return None;
+        };
+        let asn = (value >> 32) as u16;
+        let low = value as u32;
+        match subtype {
+            SUBTYPE_TRAFFIC_RATE => Some(FlowspecAction::TrafficRate {
+                asn,
+                rate: f32::from_bits(low),
+            }),
+            SUBTYPE_TRAFFIC_ACTION => Some(FlowspecAction::TrafficAction {
+                sample: low & 0x02 != 0,
+                terminal: low & 0x01 != 0,
+            }),
+            SUBTYPE_REDIRECT => Some(FlowspecAction::Redirect { asn, value: low }),
+            SUBTYPE_TRAFFIC_MARKING => Some(FlowspecAction::TrafficMarking {
+                dscp: (low & 0x3f) as u8,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn is_type(&self, action_type: FlowspecActionType) -> bool {
+        match (self, action_type) {
+            (FlowspecAction::TrafficRate { rate, .. }, FlowspecActionType::Discard) => *rate == 0.0,
+            (FlowspecAction::TrafficRate { rate, .. }, FlowspecActionType::RateLimit) => {
+                *rate != 0.0
+            }
+            (FlowspecAction::TrafficAction { sample, .. }, FlowspecActionType::Sample) => *sample,
+            (FlowspecAction::Redirect { .. }, FlowspecActionType::Redirect) => true,
+            (FlowspecAction::TrafficMarking { .. }, FlowspecActionType::Marking) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The actions among the extended communities of a flowspec route
+pub fn actions(attrs: &RouteAttrs) -> Vec<FlowspecAction> {
+    attrs
+        .ext_communities
+        .iter()
+        .flatten()
+        .filter_map(FlowspecAction::from_ext_community)
+        .collect()
+}
+
+/// Numeric operator and value of a component, as in the NLRI
+pub struct NumericOp {
+    pub and: bool,
+    pub lt: bool,
+    pub gt: bool,
+    pub eq: bool,
+    pub value: u32,
+}
+
+/// Bitmask operator and value of a component, as in the NLRI
+pub struct BitmaskOp {
+    pub and: bool,
+    pub not: bool,
+    pub matches: bool,
+    pub value: u32,
+}
+
+fn separator(condition: &mut String, and: bool) {
+    if !condition.is_empty() {
+        condition.push(if and { '&' } else { '|' });
+    }
+}
+
+pub fn numeric_condition(ops: impl IntoIterator<Item = NumericOp>) -> String {
+    let mut condition = String::new();
+    for op in ops {
+        separator(&mut condition, op.and);
+        let operator = match (op.lt, op.gt, op.eq) {
+            (false, false, false) => "false",
+            (true, true, true) => "true",
+            (false, false, true) => "=",
+            (true, false, false) => "<",
+            (true, false, true) => "<=",
+            (false, true, false) => ">",
+            (false, true, true) => ">=",
+            (true, true, false) => "!=",
+        };
+        match operator {
+            "false" | "true" => condition.push_str(operator),
+            _ => write!(condition, "{}{}", operator, op.value).unwrap(),
+        }
+    }
+    condition
+}
+
+pub fn bitmask_condition(ops: impl IntoIterator<Item = BitmaskOp>) -> String {
+    let mut condition = String::new();
+    for op in ops {
+        separator(&mut condition, op.and);
+        if op.not {
+            condition.push('!');
+        }
+        if op.matches {
+            condition.push('=');
+        }
+        write!(condition, "{:#x}", op.value).unwrap();
+    }
+    condition
+}
+
+/// Selection of flowspec rules
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FlowspecQuery {
+    #[serde(flatten)]
+    pub table_query: Option<TableQuery>,
+    /// Only rules with a destination prefix overlapping this prefix
+    pub destination: Option<IpNet>,
+    /// Only rules with a source prefix overlapping this prefix
+    pub source: Option<IpNet>,
+    /// Only rules with an action of this type
+    pub action: Option<FlowspecActionType>,
+}
+
+fn overlaps(filter: &Option<IpNet>, net: &Option<IpNet>) -> bool {
+    match (filter, net) {
+        (None, _) => true,
+        (Some(filter), Some(net)) => filter.contains(net) || net.contains(filter),
+        (Some(_), None) => false,
+    }
+}
+
+impl FlowspecQuery {
+    /// Whether the rule and its actions match, the table is not checked
+    pub fn matches(&self, rule: &FlowspecRule, actions: &[FlowspecAction]) -> bool {
+        overlaps(&self.destination, &rule.destination)
+            && overlaps(&self.source, &rule.source)
+            && self
+                .action
+                .map(|action_type| actions.iter().any(|action| action.is_type(action_type)))
+                .unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowspecResult {
+    #[serde(flatten)]
+    pub table: TableSelector,
+    #[serde(flatten)]
+    pub rule: FlowspecRule,
+    pub actions: Vec<FlowspecAction>,
+    #[serde(flatten)]
+    pub attrs: RouteAttrs,
+    /// Unix timestamp in seconds since which the rule is installed
+    pub since: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_actions() {
+        let discard = ExtCommunity::from_parts(TYPE_FLOWSPEC, SUBTYPE_TRAFFIC_RATE, 64496, 0);
+        let action = FlowspecAction::from_ext_community(&discard).unwrap();
+        assert_eq!(
+            action,
+            FlowspecAction::TrafficRate {
+                asn: 64496,
+                rate: 0.0
+            }
+        );
+        assert!(action.is_type(FlowspecActionType::Discard));
+        assert!(!action.is_type(FlowspecActionType::RateLimit));
+
+        let marking = ExtCommunity::from_parts(TYPE_FLOWSPEC, SUBTYPE_TRAFFIC_MARKING, 0, 46);
+        assert_eq!(
+            FlowspecAction::from_ext_community(&marking),
+            Some(FlowspecAction::TrafficMarking { dscp: 46 })
+        );
+
+        let route_target = ExtCommunity::from_parts(0x00, 0x02, 64496, 1);
+        assert_eq!(FlowspecAction::from_ext_community(&route_target), None);
+    }
+
+    #[test]
+    fn format_conditions() {
+        let op = |and, lt, gt, eq, value| NumericOp {
+            and,
+            lt,
+            gt,
+            eq,
+            value,
+        };
+        assert_eq!(
+            numeric_condition([
+                op(false, false, true, true, 1024),
+                op(true, true, false, true, 2048),
+                op(false, false, false, true, 80),
+            ]),
+            ">=1024&<=2048|=80"
+        );
+        assert_eq!(
+            bitmask_condition([BitmaskOp {
+                and: false,
+                not: true,
+                matches: true,
+                value: 0x02,
+            }]),
+            "!=0x2"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::api::ApiResult;
+use crate::enrichment::{Enricher, EnrichmentFuture};
+use crate::store::QueryResult;
+use futures_util::FutureExt;
+use ipnet::IpNet;
+use maxminddb::{geoip2, Reader};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// MaxMind databases, e.g. GeoLite2
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeoIpConfig {
+    /// Path of a `GeoLite2-Country` or `GeoLite2-City` database
+    pub country_db: Option<PathBuf>,
+    /// Path of a `GeoLite2-ASN` database
+    pub asn_db: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GeoIpInfo {
+    /// ISO 3166-1 country code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_org: Option<String>,
+}
+
+/// Country and AS of the prefixes and nexthops of the results
+pub struct GeoIpEnricher {
+    country_db: Option<Arc<Reader<Vec<u8>>>>,
+    asn_db: Option<Arc<Reader<Vec<u8>>>>,
+}
+
+impl GeoIpEnricher {
+    pub async fn open(cfg: &GeoIpConfig) -> anyhow::Result<Self> {
+        let open = |path: Option<PathBuf>| async move {
+            let Some(path) = path else {
+                return Ok::<_, anyhow::Error>(None);
+            };
+            let reader = tokio::task::spawn_blocking(move || Reader::open_readfile(path)).await??;
+            Ok(Some(Arc::new(reader)))
+        };
+        Ok(Self {
+            country_db: open(cfg.country_db.clone()).await?,
+            asn_db: open(cfg.asn_db.clone()).await?,
+        })
+    }
+
+    fn info(&self, addr: IpAddr) -> GeoIpInfo {
+        let mut info = GeoIpInfo::default();
+        if let Some(country) = self
+            .country_db
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::Country>(addr).ok())
+        {
+            info.country = country
+                .country
+                .and_then(|country| country.iso_code)
+                .map(|iso_code| iso_code.to_string());
+        }
+        if let Some(asn) = self
+            .asn_db
+            .as_ref()
+            .and_then(|db| db.lookup::<geoip2::Asn>(addr).ok())
+        {
+            info.asn = asn.autonomous_system_number;
+            info.as_org = asn
+                .autonomous_system_organization
+                .map(|as_org| as_org.to_string());
+        }
+        info
+    }
+}
+
+impl Enricher for GeoIpEnricher {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        std::iter::once(route.net.to_string())
+            .chain(
+                route
+                    .attrs
+                    .nexthop
+                    .iter()
+                    .map(|nexthop| nexthop.to_string()),
+            )
+            .collect()
+    }
+
+    fn lookup(&self, key: String) -> EnrichmentFuture {
+        // prefixes are looked up by their network address
+        let addr = key
+            .parse::<IpNet>()
+            .map(|net| net.network())
+            .or_else(|_| key.parse::<IpAddr>());
+        let result = addr
+            .ok()
+            .map(|addr| self.info(addr))
+            .filter(|info| info.country.is_some() || info.asn.is_some())
+            .map(|info| ApiResult::GeoIp { target: key, info });
+        futures_util::future::ready(result).boxed()
+    }
+}
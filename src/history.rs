@@ -0,0 +1,109 @@
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use crate::store::*;
+use crate::table_impl::*;
+
+/// A change as recorded in the history, with the state before the change
+pub enum HistoryChange {
+    Path {
+        table: TableSelector,
+        net: IpNet,
+        path_id: PathId,
+        /// None if the path did not exist before
+        old: Option<RouteEntry>,
+    },
+    /// The removed table is kept as it was, until the change drops out of the history
+    TableRemoved {
+        table: TableSelector,
+        contents: InMemoryTable,
+    },
+}
+
+/// State of a table at an earlier point in time, relative to its current state
+#[derive(Default)]
+pub struct RewoundTable {
+    /// The table was removed since, these were its contents
+    pub contents: Option<InMemoryTable>,
+    /// Paths which changed since, with their previous state
+    pub paths: HashMap<(IpNet, PathId), Option<RouteEntry>>,
+}
+
+/// Bounded log of the changes with their timestamps, so the tables can be rewound to an earlier
+/// point in time
+pub struct History {
+    entries: VecDeque<(u64, HistoryChange)>,
+    max_size: usize,
+    /// All changes after this unix timestamp are still in the log
+    complete_since: u64,
+}
+
+impl History {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_size,
+            complete_since: unix_timestamp(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_size > 0
+    }
+
+    pub fn push(&mut self, change: HistoryChange) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.entries.len() >= self.max_size {
+            if let Some((timestamp, _)) = self.entries.pop_front() {
+                self.complete_since = timestamp;
+            }
+        }
+        self.entries.push_back((unix_timestamp(), change));
+    }
+
+    /// Oldest unix timestamp the tables can be rewound to
+    pub fn complete_since(&self) -> Option<u64> {
+        self.is_enabled().then_some(self.complete_since)
+    }
+
+    /// Undo the changes after `at`, going back from the newest one. Returns None if the history
+    /// does not reach back far enough.
+    pub fn rewind(&self, at: u64) -> Option<HashMap<TableSelector, RewoundTable>> {
+        if at < self.complete_since()? {
+            return None;
+        }
+
+        let mut tables: HashMap<TableSelector, RewoundTable> = HashMap::new();
+        for (_, change) in self
+            .entries
+            .iter()
+            .rev()
+            .take_while(|(timestamp, _)| *timestamp > at)
+        {
+            match change {
+                HistoryChange::Path {
+                    table,
+                    net,
+                    path_id,
+                    old,
+                } => {
+                    tables
+                        .entry(table.clone())
+                        .or_default()
+                        .paths
+                        .insert((*net, *path_id), old.clone());
+                }
+                HistoryChange::TableRemoved { table, contents } => {
+                    // changes after the removal belong to a new table
+                    let rewound = tables.entry(table.clone()).or_default();
+                    rewound.contents = Some(contents.clone());
+                    rewound.paths.clear();
+                }
+            }
+        }
+        Some(tables)
+    }
+}
@@ -0,0 +1,189 @@
+use ipnet::IpNet;
+use log::*;
+use nibbletree::Node;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+fn default_refresh_interval() -> u64 {
+    3600
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IrrConfig {
+    /// RPSL dumps of IRR databases, e.g. `ripe.db.route` and `ripe.db.route6` of the RIPE NCC or
+    /// the export of an IRRd instance
+    pub paths: Vec<String>,
+    /// Seconds between reloads of the files
+    #[serde(default = "default_refresh_interval")]
+    pub refresh_interval: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum IrrStatus {
+    /// A route object for exactly this prefix has the origin of the route
+    Valid,
+    /// Only a route object for a less specific prefix has the origin of the route
+    Covered,
+    /// Route objects for this prefix or less specific ones exist, but none with the origin
+    Invalid,
+    NotFound,
+}
+
+/// Origins of the route and route6 objects
+#[derive(Default)]
+pub struct RouteObjects {
+    table: Node<IpNet, Vec<u32>>,
+    count: usize,
+}
+
+impl RouteObjects {
+    fn insert(&mut self, net: IpNet, origin: u32) {
+        match self.table.exact_mut(&net) {
+            Some(origins) if origins.contains(&origin) => return,
+            Some(origins) => origins.push(origin),
+            None => {
+                self.table.insert(&net, vec![origin]);
+            }
+        }
+        self.count += 1;
+    }
+
+    pub fn validate(&self, net: &IpNet, origin_asn: Option<u32>) -> IrrStatus {
+        let mut status = IrrStatus::NotFound;
+        for (object_net, origins) in self.table.matches(net) {
+            if !origins.iter().any(|origin| Some(*origin) == origin_asn) {
+                if status == IrrStatus::NotFound {
+                    status = IrrStatus::Invalid;
+                }
+                continue;
+            }
+            if object_net == *net {
+                return IrrStatus::Valid;
+            }
+            status = IrrStatus::Covered;
+        }
+        status
+    }
+
+    /// Add the route and route6 objects of an RPSL dump, other objects are skipped
+    fn parse_rpsl(&mut self, data: &str) {
+        let mut route = None;
+        let mut origin = None;
+        for line in data.lines().chain(std::iter::once("")) {
+            if line.trim().is_empty() {
+                if let (Some(net), Some(origin)) = (route.take(), origin.take()) {
+                    self.insert(net, origin);
+                }
+                route = None;
+                origin = None;
+                continue;
+            }
+            // comments and continuation lines
+            if line.starts_with(['#', '%', ' ', '\t', '+']) {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            // remarks at the end of the value
+            let value = value.split('#').next().unwrap_or_default().trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "route" | "route6" => {
+                    route = value.parse::<IpNet>().ok().map(|net| net.trunc());
+                }
+                "origin" => {
+                    origin = value
+                        .get(2..)
+                        .filter(|_| value[..2].eq_ignore_ascii_case("as"))
+                        .and_then(|asn| asn.parse().ok());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Shared handle to the most recently loaded route objects
+#[derive(Clone, Default)]
+pub struct Registry(Arc<RwLock<Option<Arc<RouteObjects>>>>);
+
+impl Registry {
+    /// The current route objects, None if IRR is not configured or nothing was loaded yet
+    pub fn current(&self) -> Option<Arc<RouteObjects>> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn replace(&self, objects: RouteObjects) {
+        crate::metrics::IRR_ROUTE_OBJECTS.set(objects.count as i64);
+        *self.0.write().unwrap() = Some(Arc::new(objects));
+    }
+}
+
+async fn load(paths: &[String]) -> anyhow::Result<RouteObjects> {
+    let paths = paths.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut objects = RouteObjects::default();
+        for path in paths {
+            let data = std::fs::read(&path)?;
+            objects.parse_rpsl(&String::from_utf8_lossy(&data));
+        }
+        Ok(objects)
+    })
+    .await?
+}
+
+pub async fn run(
+    cfg: IrrConfig,
+    registry: Registry,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.refresh_interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                match load(&cfg.paths).await {
+                    Ok(objects) => {
+                        debug!("loaded {} IRR route objects", objects.count);
+                        registry.replace(objects);
+                    }
+                    Err(e) => warn!("failed to load IRR route objects: {}", e),
+                }
+            }
+            _ = shutdown.changed() => break Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut objects = RouteObjects::default();
+        objects.parse_rpsl(
+            "% comment\n\
+             route:          192.0.2.0/24\n\
+             descr:          Example\n\
+             origin:         AS64500 # primary\n\
+             \n\
+             route6:         2001:db8::/32\n\
+             origin:         as64501\n\
+             mnt-by:         EXAMPLE-MNT\n\
+             \n\
+             aut-num:        AS64502\n\
+             \n\
+             route:          198.51.100.0/24\n",
+        );
+        assert_eq!(objects.count, 2);
+
+        let validate = |net: &str, asn| objects.validate(&net.parse().unwrap(), Some(asn));
+        assert_eq!(validate("192.0.2.0/24", 64500), IrrStatus::Valid);
+        assert_eq!(validate("192.0.2.0/25", 64500), IrrStatus::Covered);
+        assert_eq!(validate("192.0.2.0/24", 64501), IrrStatus::Invalid);
+        assert_eq!(validate("2001:db8:1::/48", 64501), IrrStatus::Covered);
+        assert_eq!(validate("198.51.100.0/24", 64500), IrrStatus::NotFound);
+    }
+}
@@ -1,12 +1,82 @@
+//! Fernglas collects routes from BMP, BGP and MRT sources into an in-memory store and serves
+//! them over an HTTP API. Besides running the `fernglas` daemon, the collectors and the store can
+//! be embedded into other tools.
+//!
+//! The collectors and the API server are behind the `bmp`, `bgp` and `api` features, which are all
+//! enabled by default. Only the store, the MRT import and the persistence are always available.
+//!
+//! ```no_run
+//! use fernglas::mrt_import::{self, MrtImportConfig};
+//! use fernglas::store::{NetQuery, Query, Store};
+//! use fernglas::store_impl::InMemoryStore;
+//! use futures_util::StreamExt;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let store = InMemoryStore::new(&Default::default())?;
+//! let cfg = MrtImportConfig {
+//!     path: "rib.mrt".to_string(),
+//!     name_override: None,
+//! };
+//! mrt_import::import(&cfg, &store).await?;
+//!
+//! let query = Query::new(NetQuery::MostSpecific("192.0.2.1/32".parse()?));
+//! let mut routes = store.get_routes(query)?;
+//! while let Some(route) = routes.next().await {
+//!     println!("{} {:?}", route.net, route.attrs.as_path);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod acl;
+pub mod anomalies;
+#[cfg(feature = "api")]
 pub mod api;
+pub mod as_path;
+pub mod asn_info;
+pub mod bestpath;
+#[cfg(feature = "bgp")]
 pub mod bgp_collector;
+#[cfg(feature = "bgp")]
 mod bgpdumper;
+#[cfg(feature = "bmp")]
 pub mod bmp_collector;
+pub mod change_filter;
+mod change_log;
+pub mod cold_storage;
+pub mod community_filter;
+pub mod community_rules;
 mod compressed_attrs;
+#[cfg(feature = "api")]
+pub mod dispatcher;
+#[cfg(feature = "api")]
+pub mod enrichment;
+pub mod ext_community;
+pub mod flowspec;
+#[cfg(feature = "api")]
+pub mod geoip;
+mod history;
+pub mod irr;
+pub mod linkstate;
+pub mod metrics;
+mod mrt_export;
+pub mod mrt_import;
+#[cfg(feature = "api")]
+pub mod ownership;
+pub mod persistence;
+#[cfg(feature = "api")]
+pub mod query_cache;
+pub mod retention;
 pub mod route_distinguisher;
+pub mod rpki;
+#[cfg(feature = "api")]
+pub mod show_route;
+pub mod stats;
 pub mod store;
 pub mod store_impl;
+pub mod supervisor;
 pub mod table_impl;
+pub mod tls;
 
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -27,17 +97,39 @@ pub fn usage() -> ! {
     std::process::exit(1)
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "collector_type")]
 pub enum CollectorConfig {
+    #[cfg(feature = "bmp")]
     Bmp(bmp_collector::BmpCollectorConfig),
+    #[cfg(feature = "bgp")]
     Bgp(bgp_collector::BgpCollectorConfig),
+    Mrt(mrt_import::MrtImportConfig),
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub collectors: HashMap<String, CollectorConfig>,
+    #[cfg(feature = "api")]
     pub api: api::ApiServerConfig,
+    #[serde(default)]
+    pub store: store_impl::InMemoryStoreConfig,
+    /// Source of validated ROA payloads for the `RpkiStatus` enricher of the API
+    pub rpki: Option<rpki::RpkiConfig>,
+    /// Names of the ASes for the `AsnInfo` enricher of the API
+    pub asn_info: Option<asn_info::AsnInfoConfig>,
+    /// Route objects of IRR databases for the `IrrStatus` enricher of the API
+    pub irr: Option<irr::IrrConfig>,
+    /// Periodically write the store to disk and restore it on startup
+    pub persistence: Option<persistence::PersistenceConfig>,
+    /// Move routes which did not change for a while from memory to disk
+    pub cold_storage: Option<cold_storage::ColdStorageConfig>,
+    /// Forward the BMP connections to worker instances and fan out the queries to them
+    #[cfg(feature = "api")]
+    pub dispatcher: Option<dispatcher::DispatcherConfig>,
+    /// Backoff of the restarts of failed tasks
+    #[serde(default)]
+    pub supervisor: supervisor::SupervisorConfig,
     /// Only check config and exit
     #[serde(default)]
     pub config_check: bool,
@@ -0,0 +1,618 @@
+//! BGP Link-State (RFC 7752): the nodes, links and prefixes of the IGP topology, as exported by
+//! routers via BGP-LS. zettabgp does not decode the address family, so the updates are parsed here.
+
+use bytes::Buf;
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::mrt_import::read_prefix;
+use crate::store::SessionId;
+
+const AFI_LINKSTATE: u16 = 16388;
+
+const ATTR_FLAG_EXTENDED_LENGTH: u8 = 0x10;
+const ATTR_MP_REACH_NLRI: u8 = 14;
+const ATTR_MP_UNREACH_NLRI: u8 = 15;
+const ATTR_LINKSTATE: u8 = 29;
+
+const BGP_UPDATE: u8 = 2;
+
+const NLRI_NODE: u16 = 1;
+const NLRI_LINK: u16 = 2;
+const NLRI_IPV4_PREFIX: u16 = 3;
+const NLRI_IPV6_PREFIX: u16 = 4;
+
+const TLV_LOCAL_NODE: u16 = 256;
+const TLV_REMOTE_NODE: u16 = 257;
+const TLV_LINK_IDS: u16 = 258;
+const TLV_IPV4_INTERFACE: u16 = 259;
+const TLV_IPV4_NEIGHBOR: u16 = 260;
+const TLV_IPV6_INTERFACE: u16 = 261;
+const TLV_IPV6_NEIGHBOR: u16 = 262;
+const TLV_MULTI_TOPOLOGY: u16 = 263;
+const TLV_IP_REACHABILITY: u16 = 265;
+
+const SUBTLV_AS: u16 = 512;
+const SUBTLV_BGP_LS_ID: u16 = 513;
+const SUBTLV_OSPF_AREA: u16 = 514;
+const SUBTLV_IGP_ROUTER_ID: u16 = 515;
+
+const TLV_NODE_NAME: u16 = 1026;
+const TLV_IPV4_ROUTER_ID: u16 = 1028;
+const TLV_IPV6_ROUTER_ID: u16 = 1029;
+const TLV_MAX_LINK_BANDWIDTH: u16 = 1089;
+const TLV_TE_METRIC: u16 = 1092;
+const TLV_IGP_METRIC: u16 = 1095;
+const TLV_PREFIX_METRIC: u16 = 1155;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    IsisL1,
+    IsisL2,
+    Ospfv2,
+    Direct,
+    Static,
+    Ospfv3,
+    Other(u8),
+}
+
+impl From<u8> for Protocol {
+    fn from(id: u8) -> Self {
+        match id {
+            1 => Protocol::IsisL1,
+            2 => Protocol::IsisL2,
+            3 => Protocol::Ospfv2,
+            4 => Protocol::Direct,
+            5 => Protocol::Static,
+            6 => Protocol::Ospfv3,
+            other => Protocol::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct NodeDescriptor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bgp_ls_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ospf_area: Option<u32>,
+    #[serde(skip_serializing)]
+    pub igp_router_id: Vec<u8>,
+}
+
+impl NodeDescriptor {
+    /// IS-IS system IDs in their usual dotted form, OSPF router IDs as IPv4 address
+    fn igp_router_id(&self) -> String {
+        let id = &self.igp_router_id;
+        let iso = |id: &[u8]| {
+            id.chunks(2)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<String>()
+                })
+                .collect::<Vec<_>>()
+                .join(".")
+        };
+        match id.len() {
+            4 => Ipv4Addr::new(id[0], id[1], id[2], id[3]).to_string(),
+            6 => iso(id),
+            // pseudonode
+            7 => format!("{}.{:02x}", iso(&id[..6]), id[6]),
+            // OSPF designated router and interface address
+            8 => format!(
+                "{}:{}",
+                Ipv4Addr::new(id[0], id[1], id[2], id[3]),
+                Ipv4Addr::new(id[4], id[5], id[6], id[7])
+            ),
+            _ => id.iter().fold(String::new(), |mut s, b| {
+                write!(s, "{:02x}", b).unwrap();
+                s
+            }),
+        }
+    }
+
+    /// Identifies the node in the topology
+    pub fn id(&self) -> String {
+        format!("{}/{}", self.asn.unwrap_or_default(), self.igp_router_id())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct LinkDescriptor {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_link_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_link_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_address: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_address: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multi_topology: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LinkStateNlri {
+    Node {
+        protocol: Protocol,
+        node: NodeDescriptor,
+    },
+    Link {
+        protocol: Protocol,
+        local: NodeDescriptor,
+        remote: NodeDescriptor,
+        link: LinkDescriptor,
+    },
+    Prefix {
+        protocol: Protocol,
+        node: NodeDescriptor,
+        prefix: IpNet,
+        multi_topology: Option<u16>,
+    },
+}
+
+/// The attributes of the BGP-LS attribute which are shown in the topology
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LinkStateAttrs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub router_ids: Vec<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub igp_metric: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub te_metric: Option<u32>,
+    /// Bytes per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix_metric: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LinkStateUpdate {
+    pub reach: Vec<LinkStateNlri>,
+    pub unreach: Vec<LinkStateNlri>,
+    pub attrs: LinkStateAttrs,
+}
+
+/// Iterate over type-length-value records with two octet type and length
+fn tlvs<'a>(mut buf: &'a [u8]) -> impl Iterator<Item = anyhow::Result<(u16, &'a [u8])>> + 'a {
+    std::iter::from_fn(move || {
+        if buf.is_empty() {
+            return None;
+        }
+        if buf.remaining() < 4 {
+            buf = &[];
+            return Some(Err(anyhow::anyhow!("truncated BGP-LS TLV")));
+        }
+        let tlv_type = buf.get_u16();
+        let len = buf.get_u16() as usize;
+        if buf.remaining() < len {
+            buf = &[];
+            return Some(Err(anyhow::anyhow!("truncated BGP-LS TLV {}", tlv_type)));
+        }
+        let (value, rest) = buf.split_at(len);
+        buf = rest;
+        Some(Ok((tlv_type, value)))
+    })
+}
+
+fn read_u32(mut value: &[u8]) -> Option<u32> {
+    (value.len() == 4).then(|| value.get_u32())
+}
+
+fn read_ip(value: &[u8]) -> Option<IpAddr> {
+    match value.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(value).ok()?)),
+        16 => Some(IpAddr::from(Ipv6Addr::from(
+            <[u8; 16]>::try_from(value).ok()?,
+        ))),
+        _ => None,
+    }
+}
+
+fn parse_node_descriptor(buf: &[u8]) -> anyhow::Result<NodeDescriptor> {
+    let mut node = NodeDescriptor::default();
+    for tlv in tlvs(buf) {
+        let (tlv_type, value) = tlv?;
+        match tlv_type {
+            SUBTLV_AS => node.asn = read_u32(value),
+            SUBTLV_BGP_LS_ID => node.bgp_ls_id = read_u32(value),
+            SUBTLV_OSPF_AREA => node.ospf_area = read_u32(value),
+            SUBTLV_IGP_ROUTER_ID => node.igp_router_id = value.to_vec(),
+            _ => {}
+        }
+    }
+    Ok(node)
+}
+
+fn parse_nlri(nlri_type: u16, mut buf: &[u8]) -> anyhow::Result<Option<LinkStateNlri>> {
+    anyhow::ensure!(buf.remaining() >= 9, "truncated BGP-LS NLRI");
+    let protocol = Protocol::from(buf.get_u8());
+    buf.advance(8); // identifier of the routing universe
+
+    let mut local = None;
+    let mut remote = None;
+    let mut link = LinkDescriptor::default();
+    let mut prefix = None;
+    for tlv in tlvs(buf) {
+        let (tlv_type, mut value) = tlv?;
+        match tlv_type {
+            TLV_LOCAL_NODE => local = Some(parse_node_descriptor(value)?),
+            TLV_REMOTE_NODE => remote = Some(parse_node_descriptor(value)?),
+            TLV_LINK_IDS if value.len() == 8 => {
+                link.local_link_id = Some(value.get_u32());
+                link.remote_link_id = Some(value.get_u32());
+            }
+            TLV_IPV4_INTERFACE | TLV_IPV6_INTERFACE => link.local_address = read_ip(value),
+            TLV_IPV4_NEIGHBOR | TLV_IPV6_NEIGHBOR => link.remote_address = read_ip(value),
+            TLV_MULTI_TOPOLOGY if value.len() >= 2 => {
+                link.multi_topology = Some(value.get_u16() & 0x0fff)
+            }
+            TLV_IP_REACHABILITY => {
+                prefix = Some(read_prefix(&mut value, nlri_type == NLRI_IPV6_PREFIX)?)
+            }
+            _ => {}
+        }
+    }
+    let local = local.ok_or_else(|| anyhow::anyhow!("BGP-LS NLRI without local node"))?;
+    Ok(match (nlri_type, remote, prefix) {
+        (NLRI_NODE, _, _) => Some(LinkStateNlri::Node {
+            protocol,
+            node: local,
+        }),
+        (NLRI_LINK, Some(remote), _) => Some(LinkStateNlri::Link {
+            protocol,
+            local,
+            remote,
+            link,
+        }),
+        (NLRI_IPV4_PREFIX | NLRI_IPV6_PREFIX, _, Some(prefix)) => Some(LinkStateNlri::Prefix {
+            protocol,
+            node: local,
+            prefix,
+            multi_topology: link.multi_topology,
+        }),
+        _ => None,
+    })
+}
+
+fn parse_nlris(buf: &[u8]) -> anyhow::Result<Vec<LinkStateNlri>> {
+    let mut nlris = vec![];
+    for tlv in tlvs(buf) {
+        let (nlri_type, value) = tlv?;
+        nlris.extend(parse_nlri(nlri_type, value)?);
+    }
+    Ok(nlris)
+}
+
+fn parse_linkstate_attr(buf: &[u8]) -> anyhow::Result<LinkStateAttrs> {
+    let mut attrs = LinkStateAttrs::default();
+    for tlv in tlvs(buf) {
+        let (tlv_type, mut value) = tlv?;
+        match tlv_type {
+            TLV_NODE_NAME => attrs.name = Some(String::from_utf8_lossy(value).into_owned()),
+            TLV_IPV4_ROUTER_ID | TLV_IPV6_ROUTER_ID => attrs.router_ids.extend(read_ip(value)),
+            TLV_MAX_LINK_BANDWIDTH if value.len() == 4 => {
+                attrs.max_bandwidth = Some(value.get_f32())
+            }
+            TLV_TE_METRIC => attrs.te_metric = read_u32(value),
+            // one to three octets, depending on the IGP
+            TLV_IGP_METRIC if (1..=3).contains(&value.len()) => {
+                attrs.igp_metric = Some(value.iter().fold(0, |metric, b| (metric << 8) | *b as u32))
+            }
+            TLV_PREFIX_METRIC => attrs.prefix_metric = read_u32(value),
+            _ => {}
+        }
+    }
+    Ok(attrs)
+}
+
+/// The BGP-LS contents of the path attributes of an UPDATE, None if it does not carry BGP-LS NLRI
+fn parse_update_attrs(mut buf: &[u8]) -> anyhow::Result<Option<LinkStateUpdate>> {
+    let mut update = LinkStateUpdate::default();
+    let mut linkstate = false;
+    while buf.has_remaining() {
+        anyhow::ensure!(buf.remaining() >= 3, "truncated path attribute");
+        let flags = buf.get_u8();
+        let attr_type = buf.get_u8();
+        let len = if flags & ATTR_FLAG_EXTENDED_LENGTH != 0 {
+            anyhow::ensure!(buf.remaining() >= 2, "truncated path attribute");
+            buf.get_u16() as usize
+        } else {
+            buf.get_u8() as usize
+        };
+        anyhow::ensure!(buf.remaining() >= len, "truncated path attribute");
+        let mut value = &buf[..len];
+        buf.advance(len);
+
+        match attr_type {
+            ATTR_MP_REACH_NLRI if len >= 5 => {
+                if value.get_u16() != AFI_LINKSTATE {
+                    return Ok(None);
+                }
+                value.advance(1); // SAFI, VPN routes are not distinguished
+                let nexthop_len = value.get_u8() as usize;
+                anyhow::ensure!(value.remaining() > nexthop_len, "truncated MP_REACH_NLRI");
+                value.advance(nexthop_len + 1);
+                update.reach = parse_nlris(value)?;
+                linkstate = true;
+            }
+            ATTR_MP_UNREACH_NLRI if len >= 3 => {
+                if value.get_u16() != AFI_LINKSTATE {
+                    return Ok(None);
+                }
+                value.advance(1);
+                update.unreach = parse_nlris(value)?;
+                linkstate = true;
+            }
+            ATTR_LINKSTATE => update.attrs = parse_linkstate_attr(value)?,
+            _ => {}
+        }
+    }
+    Ok(linkstate.then_some(update))
+}
+
+/// Parse a BGP-LS update from a Route Monitoring message following the common header. Returns the
+/// peer type and address of the per-peer header with the update, or None if the message does not
+/// carry BGP-LS NLRI.
+pub fn parse_route_monitoring(
+    mut buf: &[u8],
+) -> anyhow::Result<Option<(u8, IpAddr, LinkStateUpdate)>> {
+    anyhow::ensure!(buf.remaining() >= 42 + 19, "truncated route monitoring");
+    let peer_type = buf.get_u8();
+    let peer_flags = buf.get_u8();
+    buf.advance(8); // peer distinguisher
+    let mut address = [0u8; 16];
+    buf.copy_to_slice(&mut address);
+    let peer_address = if peer_flags & 0x80 != 0 {
+        IpAddr::from(Ipv6Addr::from(address))
+    } else {
+        IpAddr::from(Ipv4Addr::new(
+            address[12],
+            address[13],
+            address[14],
+            address[15],
+        ))
+    };
+    buf.advance(16); // peer AS, BGP ID and timestamp
+
+    buf.advance(16); // marker
+    let len = buf.get_u16() as usize;
+    if buf.get_u8() != BGP_UPDATE {
+        return Ok(None);
+    }
+    anyhow::ensure!(buf.remaining() + 19 >= len, "truncated BGP UPDATE");
+    anyhow::ensure!(buf.remaining() >= 2, "truncated BGP UPDATE");
+    let withdrawn_len = buf.get_u16() as usize;
+    anyhow::ensure!(buf.remaining() >= withdrawn_len + 2, "truncated BGP UPDATE");
+    buf.advance(withdrawn_len);
+    let attrs_len = buf.get_u16() as usize;
+    anyhow::ensure!(buf.remaining() >= attrs_len, "truncated BGP UPDATE");
+    Ok(parse_update_attrs(&buf[..attrs_len])?.map(|update| (peer_type, peer_address, update)))
+}
+
+/// Add the node to the topology, nodes which are only known from links and prefixes have no
+/// attributes
+fn add_node(
+    nodes: &mut BTreeMap<String, TopologyNode>,
+    protocol: Protocol,
+    node: &NodeDescriptor,
+    attrs: Option<&LinkStateAttrs>,
+) {
+    let entry = nodes.entry(node.id()).or_insert_with(|| TopologyNode {
+        id: node.id(),
+        protocol,
+        igp_router_id: node.igp_router_id(),
+        descriptor: node.clone(),
+        attrs: Default::default(),
+    });
+    if let Some(attrs) = attrs {
+        entry.attrs = attrs.clone();
+    }
+}
+
+/// The BGP-LS NLRI of every session with their attributes
+#[derive(Default)]
+pub struct LinkStateDb {
+    sessions: HashMap<SessionId, HashMap<LinkStateNlri, LinkStateAttrs>>,
+}
+
+impl LinkStateDb {
+    pub fn apply(&mut self, session: SessionId, update: LinkStateUpdate) {
+        let nlris = self.sessions.entry(session.clone()).or_default();
+        for nlri in update.unreach {
+            nlris.remove(&nlri);
+        }
+        for nlri in update.reach {
+            nlris.insert(nlri, update.attrs.clone());
+        }
+        if nlris.is_empty() {
+            self.sessions.remove(&session);
+        }
+    }
+
+    pub fn remove_session(&mut self, session: &SessionId) {
+        self.sessions.remove(session);
+    }
+
+    pub fn remove_client(&mut self, client_addr: &SocketAddr) {
+        self.sessions
+            .retain(|session, _| &session.from_client != client_addr);
+    }
+
+    /// The topology of all sessions combined. The same nodes, links and prefixes received from
+    /// multiple sessions are only included once.
+    pub fn topology(&self) -> Topology {
+        let mut nodes = BTreeMap::new();
+        let mut links = vec![];
+        let mut prefixes = vec![];
+        let mut seen = HashSet::new();
+        for (nlri, attrs) in self.sessions.values().flatten() {
+            if !seen.insert(nlri) {
+                continue;
+            }
+            match nlri {
+                LinkStateNlri::Node { protocol, node } => {
+                    add_node(&mut nodes, *protocol, node, Some(attrs))
+                }
+                LinkStateNlri::Link {
+                    protocol,
+                    local,
+                    remote,
+                    link,
+                } => {
+                    add_node(&mut nodes, *protocol, local, None);
+                    add_node(&mut nodes, *protocol, remote, None);
+                    links.push(TopologyLink {
+                        source: local.id(),
+                        target: remote.id(),
+                        protocol: *protocol,
+                        descriptor: link.clone(),
+                        attrs: attrs.clone(),
+                    });
+                }
+                LinkStateNlri::Prefix {
+                    protocol,
+                    node,
+                    prefix,
+                    multi_topology,
+                } => {
+                    add_node(&mut nodes, *protocol, node, None);
+                    prefixes.push(TopologyPrefix {
+                        node: node.id(),
+                        protocol: *protocol,
+                        prefix: *prefix,
+                        multi_topology: *multi_topology,
+                        attrs: attrs.clone(),
+                    });
+                }
+            }
+        }
+        Topology {
+            nodes: nodes.into_values().collect(),
+            links,
+            prefixes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyNode {
+    pub id: String,
+    pub protocol: Protocol,
+    pub igp_router_id: String,
+    #[serde(flatten)]
+    pub descriptor: NodeDescriptor,
+    #[serde(flatten)]
+    pub attrs: LinkStateAttrs,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyLink {
+    /// ID of the local node
+    pub source: String,
+    /// ID of the remote node
+    pub target: String,
+    pub protocol: Protocol,
+    #[serde(flatten)]
+    pub descriptor: LinkDescriptor,
+    #[serde(flatten)]
+    pub attrs: LinkStateAttrs,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TopologyPrefix {
+    /// ID of the node originating the prefix
+    pub node: String,
+    pub protocol: Protocol,
+    pub prefix: IpNet,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multi_topology: Option<u16>,
+    #[serde(flatten)]
+    pub attrs: LinkStateAttrs,
+}
+
+/// The IGP topology as graph of nodes and links
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+    pub links: Vec<TopologyLink>,
+    pub prefixes: Vec<TopologyPrefix>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tlv(tlv_type: u16, value: &[u8]) -> Vec<u8> {
+        let mut buf = tlv_type.to_be_bytes().to_vec();
+        buf.extend((value.len() as u16).to_be_bytes());
+        buf.extend(value);
+        buf
+    }
+
+    fn node(system_id: [u8; 6]) -> Vec<u8> {
+        [
+            tlv(SUBTLV_AS, &64496u32.to_be_bytes()),
+            tlv(SUBTLV_IGP_ROUTER_ID, &system_id),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn parse_link() {
+        let a = [0, 0, 0, 0, 0, 1];
+        let b = [0, 0, 0, 0, 0, 2];
+        let mut nlri = vec![2]; // IS-IS level 2
+        nlri.extend(0u64.to_be_bytes());
+        nlri.extend(tlv(TLV_LOCAL_NODE, &node(a)));
+        nlri.extend(tlv(TLV_REMOTE_NODE, &node(b)));
+        nlri.extend(tlv(TLV_IPV4_INTERFACE, &[192, 0, 2, 1]));
+        nlri.extend(tlv(TLV_IPV4_NEIGHBOR, &[192, 0, 2, 2]));
+        let nlri = tlv(NLRI_LINK, &nlri);
+
+        let mut mp_reach = AFI_LINKSTATE.to_be_bytes().to_vec();
+        mp_reach.extend([71, 4, 192, 0, 2, 254, 0]);
+        mp_reach.extend(&nlri);
+        let linkstate_attr = tlv(TLV_IGP_METRIC, &[0, 0, 10]);
+
+        let mut attrs = vec![0x90, ATTR_MP_REACH_NLRI];
+        attrs.extend((mp_reach.len() as u16).to_be_bytes());
+        attrs.extend(&mp_reach);
+        attrs.extend([0x80, ATTR_LINKSTATE, linkstate_attr.len() as u8]);
+        attrs.extend(&linkstate_attr);
+
+        let update = parse_update_attrs(&attrs).unwrap().unwrap();
+        assert_eq!(update.reach.len(), 1);
+        assert_eq!(update.attrs.igp_metric, Some(10));
+
+        let mut db = LinkStateDb::default();
+        let session = SessionId {
+            from_client: "192.0.2.254:11019".parse().unwrap(),
+            peer_address: "192.0.2.253".parse().unwrap(),
+        };
+        db.apply(session.clone(), update);
+        let topology = db.topology();
+        assert_eq!(topology.nodes.len(), 2);
+        assert_eq!(topology.nodes[0].id, "64496/0000.0000.0001");
+        assert_eq!(topology.links.len(), 1);
+        assert_eq!(topology.links[0].target, "64496/0000.0000.0002");
+        assert_eq!(
+            topology.links[0].descriptor.remote_address,
+            Some("192.0.2.2".parse().unwrap())
+        );
+
+        db.remove_session(&session);
+        assert!(db.topology().nodes.is_empty());
+    }
+}
@@ -1,7 +1,8 @@
 use fernglas::*;
 use figment::providers::{Env, Format, Yaml};
 use figment::Figment;
-use futures_util::future::{join_all, select_all};
+use futures_util::future::{join_all, select_all, BoxFuture};
+use futures_util::FutureExt;
 use log::*;
 use tokio::signal::unix::{signal, SignalKind};
 
@@ -9,6 +10,8 @@ use tokio::signal::unix::{signal, SignalKind};
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+type Task = BoxFuture<'static, anyhow::Result<()>>;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
@@ -27,7 +30,17 @@ async fn main() -> anyhow::Result<()> {
         std::process::exit(0);
     }
 
-    let store: store_impl::InMemoryStore = Default::default();
+    let sources = enrichment::EnrichmentSources::default();
+    let store = store_impl::InMemoryStore::new(&cfg.store)?;
+
+    if let Some(persistence_cfg) = &cfg.persistence {
+        if let Err(e) = persistence::restore(persistence_cfg, &store).await {
+            warn!(
+                "failed to restore snapshot from {}: {}",
+                persistence_cfg.path, e
+            );
+        }
+    }
 
     let mut futures = vec![];
 
@@ -36,24 +49,135 @@ async fn main() -> anyhow::Result<()> {
     // Set up the exporter to collect metrics
     let _exporter = autometrics::global_metrics_exporter();
 
-    futures.push(tokio::task::spawn(api::run_api_server(
-        cfg.api,
-        store.clone(),
-        shutdown_rx.clone(),
-    )));
+    // every task is restarted by its supervisor if it fails, until the shutdown
+    let supervise = |name: &str, make_task: Box<dyn FnMut() -> Task + Send>| {
+        tokio::task::spawn(supervisor::supervise(
+            name.to_string(),
+            cfg.supervisor.clone(),
+            shutdown_rx.clone(),
+            make_task,
+        ))
+    };
+
+    match cfg.dispatcher.clone() {
+        Some(dispatcher_cfg) => {
+            let (api_cfg, api_dispatcher_cfg, shutdown) =
+                (cfg.api.clone(), dispatcher_cfg.clone(), shutdown_rx.clone());
+            futures.push(supervise(
+                "api",
+                Box::new(move || {
+                    dispatcher::run_api_server(
+                        api_cfg.clone(),
+                        api_dispatcher_cfg.clone(),
+                        shutdown.clone(),
+                    )
+                    .boxed()
+                }),
+            ));
+            let shutdown = shutdown_rx.clone();
+            futures.push(supervise(
+                "dispatcher",
+                Box::new(move || dispatcher::run(dispatcher_cfg.clone(), shutdown.clone()).boxed()),
+            ));
+        }
+        None => {
+            let (api_cfg, store, sources, shutdown) = (
+                cfg.api.clone(),
+                store.clone(),
+                sources.clone(),
+                shutdown_rx.clone(),
+            );
+            futures.push(supervise(
+                "api",
+                Box::new(move || {
+                    api::run_api_server(
+                        api_cfg.clone(),
+                        store.clone(),
+                        sources.clone(),
+                        shutdown.clone(),
+                    )
+                    .boxed()
+                }),
+            ));
+        }
+    }
+
+    if let Some(rpki_cfg) = cfg.rpki.clone() {
+        let (rpki_validator, shutdown) = (sources.rpki.clone(), shutdown_rx.clone());
+        futures.push(supervise(
+            "rpki",
+            Box::new(move || {
+                rpki::run(rpki_cfg.clone(), rpki_validator.clone(), shutdown.clone()).boxed()
+            }),
+        ));
+    }
+
+    if let Some(asn_info_cfg) = cfg.asn_info.clone() {
+        let (asn_db, shutdown) = (sources.asn_db.clone(), shutdown_rx.clone());
+        futures.push(supervise(
+            "asn_info",
+            Box::new(move || {
+                asn_info::run(asn_info_cfg.clone(), asn_db.clone(), shutdown.clone()).boxed()
+            }),
+        ));
+    }
 
-    futures.extend(
-        cfg.collectors
-            .into_values()
-            .map(|collector| match collector {
+    if let Some(irr_cfg) = cfg.irr.clone() {
+        let (irr_registry, shutdown) = (sources.irr.clone(), shutdown_rx.clone());
+        futures.push(supervise(
+            "irr",
+            Box::new(move || {
+                irr::run(irr_cfg.clone(), irr_registry.clone(), shutdown.clone()).boxed()
+            }),
+        ));
+    }
+
+    let (metrics_store, shutdown) = (store.clone(), shutdown_rx.clone());
+    futures.push(supervise(
+        "metrics",
+        Box::new(move || store_impl::run_metrics(metrics_store.clone(), shutdown.clone()).boxed()),
+    ));
+
+    if let Some(persistence_cfg) = cfg.persistence.clone() {
+        let (store, shutdown) = (store.clone(), shutdown_rx.clone());
+        futures.push(supervise(
+            "persistence",
+            Box::new(move || {
+                persistence::run(persistence_cfg.clone(), store.clone(), shutdown.clone()).boxed()
+            }),
+        ));
+    }
+
+    if let Some(cold_storage_cfg) = cfg.cold_storage.clone() {
+        let (store, shutdown) = (store.clone(), shutdown_rx.clone());
+        futures.push(supervise(
+            "cold_storage",
+            Box::new(move || {
+                cold_storage::run(cold_storage_cfg.clone(), store.clone(), shutdown.clone()).boxed()
+            }),
+        ));
+    }
+
+    for (name, collector) in &cfg.collectors {
+        let name = format!("collector {}", name);
+        let (collector, store, shutdown) = (collector.clone(), store.clone(), shutdown_rx.clone());
+        futures.push(supervise(
+            &name,
+            Box::new(move || match collector.clone() {
+                #[cfg(feature = "bmp")]
                 CollectorConfig::Bmp(cfg) => {
-                    tokio::task::spawn(bmp_collector::run(cfg, store.clone(), shutdown_rx.clone()))
+                    bmp_collector::run(cfg, store.clone(), shutdown.clone()).boxed()
                 }
+                #[cfg(feature = "bgp")]
                 CollectorConfig::Bgp(cfg) => {
-                    tokio::task::spawn(bgp_collector::run(cfg, store.clone(), shutdown_rx.clone()))
+                    bgp_collector::run(cfg, store.clone(), shutdown.clone()).boxed()
+                }
+                CollectorConfig::Mrt(cfg) => {
+                    mrt_import::run(cfg, store.clone(), shutdown.clone()).boxed()
                 }
             }),
-    );
+        ));
+    }
 
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sigterm = signal(SignalKind::terminate())?;
@@ -72,6 +196,15 @@ async fn main() -> anyhow::Result<()> {
             result?
         }
     };
+    // the collectors remove their routes on shutdown, so take the last snapshot before
+    if let Some(persistence_cfg) = &cfg.persistence {
+        if let Err(e) = persistence::write_snapshot(persistence_cfg, &store).await {
+            warn!(
+                "failed to write snapshot to {}: {}",
+                persistence_cfg.path, e
+            );
+        }
+    }
     shutdown_tx.send(true)?;
     join_all(futures).await;
     res
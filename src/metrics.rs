@@ -0,0 +1,230 @@
+//! Metrics which are not covered by the autometrics function annotations. They are registered in
+//! the default registry of the prometheus crate, which the autometrics exporter also encodes on
+//! the `/metrics` endpoint.
+
+use prometheus::{
+    register_gauge_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, GaugeVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use std::sync::LazyLock;
+
+pub static TASK_RESTARTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_task_restarts_total",
+        "Restarts of supervised tasks",
+        &["task"]
+    )
+    .unwrap()
+});
+
+pub static REJECTED_CONNECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_rejected_connections_total",
+        "Connections rejected by the access list of a listener",
+        &["listener"]
+    )
+    .unwrap()
+});
+
+pub static QUERY_CACHE_HITS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "fernglas_query_cache_hits_total",
+        "Queries answered from the query cache"
+    )
+    .unwrap()
+});
+
+pub static QUERY_CACHE_MISSES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "fernglas_query_cache_misses_total",
+        "Queries not found in the query cache"
+    )
+    .unwrap()
+});
+
+pub static QUERY_CACHE_ENTRIES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "fernglas_query_cache_entries",
+        "Query results held in the query cache"
+    )
+    .unwrap()
+});
+
+pub static TRUNCATED_ATTRIBUTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_truncated_attributes_total",
+        "Route attributes truncated to the configured attribute limits",
+        &["attribute"]
+    )
+    .unwrap()
+});
+
+pub static ROUTE_UPDATES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_route_updates_total",
+        "Route updates by address family and table type",
+        &["afi", "safi", "table_type"]
+    )
+    .unwrap()
+});
+
+pub static ROUTE_WITHDRAWS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_route_withdraws_total",
+        "Route withdraws by address family and table type",
+        &["afi", "safi", "table_type"]
+    )
+    .unwrap()
+});
+
+pub static ROUTE_QUOTA_EXCEEDED: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "fernglas_route_quota_exceeded",
+        "Whether a route distinguisher exceeds its route quota",
+        &["route_distinguisher"]
+    )
+    .unwrap()
+});
+
+pub static ROUTE_QUOTA_REJECTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_route_quota_rejected_total",
+        "Routes dropped because their route distinguisher exceeds its route quota",
+        &["route_distinguisher"]
+    )
+    .unwrap()
+});
+
+pub static DECODE_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_decode_errors_total",
+        "Messages of a client which could not be decoded",
+        &["client"]
+    )
+    .unwrap()
+});
+
+pub static BMP_MESSAGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_bmp_messages_total",
+        "BMP messages received by type",
+        &["client", "type"]
+    )
+    .unwrap()
+});
+
+pub static BMP_MIRRORING_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "fernglas_bmp_mirroring_errors_total",
+        "Route Mirroring messages which are malformed, errored or report lost messages",
+        &["client", "peer", "reason"]
+    )
+    .unwrap()
+});
+
+pub static SESSION_INGEST_LAG: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "fernglas_session_ingest_lag_seconds",
+        "Time between the router receiving the last update of the session and processing it",
+        &["client", "peer"]
+    )
+    .unwrap()
+});
+
+pub static TABLE_ROUTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "fernglas_table_routes",
+        "Routes stored in each table",
+        &[
+            "client",
+            "peer",
+            "table_type",
+            "afi",
+            "safi",
+            "route_distinguisher"
+        ]
+    )
+    .unwrap()
+});
+
+pub static VRF_ROUTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "fernglas_vrf_routes",
+        "Routes stored for each routing instance other than the default one",
+        &["client", "route_distinguisher", "vrf"]
+    )
+    .unwrap()
+});
+
+pub static VRF_LAST_UPDATE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "fernglas_vrf_last_update",
+        "Unix timestamp of the last route change of each routing instance",
+        &["client", "route_distinguisher", "vrf"]
+    )
+    .unwrap()
+});
+
+pub static PEER_FUNNEL_ROUTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "fernglas_peer_funnel_routes",
+        "Routes of each peer by stage, from received to selected as best path",
+        &["client", "peer", "stage"]
+    )
+    .unwrap()
+});
+
+pub static CLIENT_ROUTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "fernglas_client_routes",
+        "Routes stored for each router",
+        &["client"]
+    )
+    .unwrap()
+});
+
+pub static CLIENT_SESSIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "fernglas_client_sessions",
+        "Sessions of each router",
+        &["client"]
+    )
+    .unwrap()
+});
+
+pub static CLIENT_UPDATE_RATE: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "fernglas_client_update_rate",
+        "Route updates per second of each router",
+        &["client"]
+    )
+    .unwrap()
+});
+
+pub static CLIENT_WITHDRAW_RATE: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "fernglas_client_withdraw_rate",
+        "Route withdraws per second of each router",
+        &["client"]
+    )
+    .unwrap()
+});
+
+pub static ATTRIBUTE_CACHE_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "fernglas_attribute_cache_bytes",
+        "Estimated memory used by the deduplicated route attributes"
+    )
+    .unwrap()
+});
+
+pub static RPKI_VRPS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("fernglas_rpki_vrps", "Loaded RPKI validated ROA payloads").unwrap()
+});
+
+pub static IRR_ROUTE_OBJECTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("fernglas_irr_route_objects", "Loaded IRR route objects").unwrap()
+});
+
+pub static ASN_NAMES: LazyLock<IntGauge> =
+    LazyLock::new(|| register_int_gauge!("fernglas_asn_names", "Loaded AS names").unwrap());
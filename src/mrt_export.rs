@@ -0,0 +1,474 @@
+use bytes::BufMut;
+use ipnet::IpNet;
+use log::*;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use crate::as_path::AsPathSegment;
+use crate::compressed_attrs::{Caches, CompressedRouteAttrs};
+use crate::store::{
+    AfiSafi, PathId, QueryResult, RouteOrigin, RouterId, SessionId, TableSelector, TableType,
+};
+
+const MRT_TABLE_DUMP_V2: u16 = 13;
+const MRT_BGP4MP: u16 = 16;
+
+const BGP4MP_MESSAGE_AS4: u16 = 4;
+
+const PEER_INDEX_TABLE: u16 = 1;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+
+const FLAG_OPTIONAL: u8 = 0x80;
+const FLAG_TRANSITIVE: u8 = 0x40;
+const FLAG_EXTENDED_LENGTH: u8 = 0x10;
+
+const ATTR_ORIGIN: u8 = 1;
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_NEXT_HOP: u8 = 3;
+const ATTR_MED: u8 = 4;
+const ATTR_LOCAL_PREF: u8 = 5;
+const ATTR_ATOMIC_AGGREGATE: u8 = 6;
+const ATTR_AGGREGATOR: u8 = 7;
+const ATTR_COMMUNITIES: u8 = 8;
+const ATTR_ORIGINATOR_ID: u8 = 9;
+const ATTR_CLUSTER_LIST: u8 = 10;
+const ATTR_MP_REACH_NLRI: u8 = 14;
+const ATTR_EXT_COMMUNITIES: u8 = 16;
+const ATTR_LARGE_COMMUNITIES: u8 = 32;
+
+const AS_SET: u8 = 1;
+const AS_SEQUENCE: u8 = 2;
+const AS_CONFED_SEQUENCE: u8 = 3;
+const AS_CONFED_SET: u8 = 4;
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+const SAFI_UNICAST: u8 = 1;
+
+const BGP_UPDATE: u8 = 2;
+
+/// Collects routes of multiple tables and writes them as a MRT TABLE_DUMP_V2 RIB snapshot. Every
+/// session and table type becomes a separate peer of the dump.
+pub struct MrtWriter {
+    timestamp: u32,
+    peers: Vec<(SessionId, RouterId)>,
+    peer_index: HashMap<(SessionId, TableType), u16>,
+    ribs: BTreeMap<IpNet, Vec<(u16, PathId, Arc<CompressedRouteAttrs>)>>,
+}
+
+fn put_attr(buf: &mut Vec<u8>, flags: u8, attr_type: u8, value: &[u8]) {
+    if value.len() > 255 {
+        buf.put_u8(flags | FLAG_EXTENDED_LENGTH);
+        buf.put_u8(attr_type);
+        buf.put_u16(value.len() as u16);
+    } else {
+        buf.put_u8(flags);
+        buf.put_u8(attr_type);
+        buf.put_u8(value.len() as u8);
+    }
+    buf.put_slice(value);
+}
+
+pub(crate) fn put_addr(buf: &mut Vec<u8>, addr: IpAddr) {
+    match addr {
+        IpAddr::V4(addr) => buf.put_slice(&addr.octets()),
+        IpAddr::V6(addr) => buf.put_slice(&addr.octets()),
+    }
+}
+
+pub(crate) fn put_prefix(buf: &mut Vec<u8>, net: &IpNet) {
+    buf.put_u8(net.prefix_len());
+    let prefix_bytes = (net.prefix_len() as usize).div_ceil(8);
+    match net.network() {
+        IpAddr::V4(addr) => buf.put_slice(&addr.octets()[..prefix_bytes]),
+        IpAddr::V6(addr) => buf.put_slice(&addr.octets()[..prefix_bytes]),
+    }
+}
+
+fn afi(addr: IpAddr) -> u16 {
+    match addr {
+        IpAddr::V4(_) => AFI_IPV4,
+        IpAddr::V6(_) => AFI_IPV6,
+    }
+}
+
+/// Whether the prefix is carried in MP_REACH_NLRI instead of the NLRI field of an UPDATE message
+fn in_mp_reach(net: &IpNet, nexthop: Option<IpAddr>) -> bool {
+    !matches!((net, nexthop), (IpNet::V4(_), None | Some(IpAddr::V4(_))))
+}
+
+/// Path attributes as written in RIB entries, with four octet AS numbers and the abbreviated
+/// MP_REACH_NLRI which only contains the nexthop
+pub(crate) fn encode_attrs(net: &IpNet, attrs: &CompressedRouteAttrs) -> Vec<u8> {
+    encode_path_attrs(net, attrs, false)
+}
+
+/// With `full_mp_reach`, MP_REACH_NLRI is written as in UPDATE messages, with the address family
+/// and the prefix
+fn encode_path_attrs(net: &IpNet, attrs: &CompressedRouteAttrs, full_mp_reach: bool) -> Vec<u8> {
+    let mut buf = vec![];
+    if let Some(origin) = &attrs.origin {
+        let origin = match origin {
+            RouteOrigin::Igp => 0,
+            RouteOrigin::Egp => 1,
+            RouteOrigin::Incomplete => 2,
+        };
+        put_attr(&mut buf, FLAG_TRANSITIVE, ATTR_ORIGIN, &[origin]);
+    }
+    if let Some(as_path) = &attrs.as_path {
+        let mut value = vec![];
+        for segment in &as_path.0 {
+            let segment_type = match segment {
+                AsPathSegment::Set(_) => AS_SET,
+                AsPathSegment::Sequence(_) => AS_SEQUENCE,
+                AsPathSegment::ConfedSequence(_) => AS_CONFED_SEQUENCE,
+                AsPathSegment::ConfedSet(_) => AS_CONFED_SET,
+            };
+            for chunk in segment.asns().chunks(255) {
+                value.put_u8(segment_type);
+                value.put_u8(chunk.len() as u8);
+                for asn in chunk {
+                    value.put_u32(*asn);
+                }
+            }
+        }
+        put_attr(&mut buf, FLAG_TRANSITIVE, ATTR_AS_PATH, &value);
+    }
+    match (net, attrs.nexthop) {
+        (IpNet::V4(_), Some(IpAddr::V4(nexthop))) => {
+            put_attr(&mut buf, FLAG_TRANSITIVE, ATTR_NEXT_HOP, &nexthop.octets())
+        }
+        (_, nexthop) if in_mp_reach(net, nexthop) && (nexthop.is_some() || full_mp_reach) => {
+            let mut value = vec![];
+            if full_mp_reach {
+                value.put_u16(afi(net.network()));
+                value.put_u8(SAFI_UNICAST);
+            }
+            let link_local = attrs
+                .nexthop_link_local
+                .filter(|_| nexthop.is_some_and(|nexthop| nexthop.is_ipv6()));
+            value.put_u8(match (nexthop, link_local) {
+                (None, _) => 0,
+                (Some(IpAddr::V4(_)), _) => 4,
+                (Some(IpAddr::V6(_)), None) => 16,
+                (Some(IpAddr::V6(_)), Some(_)) => 32,
+            });
+            if let Some(nexthop) = nexthop {
+                put_addr(&mut value, nexthop);
+            }
+            if let Some(link_local) = link_local {
+                value.put_slice(&link_local.octets());
+            }
+            if full_mp_reach {
+                // reserved
+                value.put_u8(0);
+                put_prefix(&mut value, net);
+            }
+            put_attr(&mut buf, FLAG_OPTIONAL, ATTR_MP_REACH_NLRI, &value);
+        }
+        _ => {}
+    }
+    if let Some(med) = attrs.med {
+        put_attr(&mut buf, FLAG_OPTIONAL, ATTR_MED, &med.to_be_bytes());
+    }
+    if let Some(local_pref) = attrs.local_pref {
+        put_attr(
+            &mut buf,
+            FLAG_TRANSITIVE,
+            ATTR_LOCAL_PREF,
+            &local_pref.to_be_bytes(),
+        );
+    }
+    if attrs.atomic_aggregate {
+        put_attr(&mut buf, FLAG_TRANSITIVE, ATTR_ATOMIC_AGGREGATE, &[]);
+    }
+    if let Some(aggregator) = &attrs.aggregator {
+        let mut value = vec![];
+        value.put_u32(aggregator.asn);
+        value.put_slice(&aggregator.address.octets());
+        put_attr(
+            &mut buf,
+            FLAG_OPTIONAL | FLAG_TRANSITIVE,
+            ATTR_AGGREGATOR,
+            &value,
+        );
+    }
+    if let Some(communities) = &attrs.communities {
+        let mut value = vec![];
+        for (asn, value_part) in communities.iter() {
+            value.put_u16(*asn);
+            value.put_u16(*value_part);
+        }
+        put_attr(
+            &mut buf,
+            FLAG_OPTIONAL | FLAG_TRANSITIVE,
+            ATTR_COMMUNITIES,
+            &value,
+        );
+    }
+    if let Some(originator_id) = &attrs.originator_id {
+        put_attr(
+            &mut buf,
+            FLAG_OPTIONAL,
+            ATTR_ORIGINATOR_ID,
+            &originator_id.octets(),
+        );
+    }
+    if let Some(cluster_list) = &attrs.cluster_list {
+        let value = cluster_list
+            .iter()
+            .flat_map(|cluster_id| cluster_id.octets())
+            .collect::<Vec<_>>();
+        put_attr(&mut buf, FLAG_OPTIONAL, ATTR_CLUSTER_LIST, &value);
+    }
+    if let Some(ext_communities) = &attrs.ext_communities {
+        let mut value = vec![];
+        for community in ext_communities.iter() {
+            value.put_slice(&community.to_bytes());
+        }
+        put_attr(
+            &mut buf,
+            FLAG_OPTIONAL | FLAG_TRANSITIVE,
+            ATTR_EXT_COMMUNITIES,
+            &value,
+        );
+    }
+    if let Some(large_communities) = &attrs.large_communities {
+        let mut value = vec![];
+        for community in large_communities.iter() {
+            value.put_u32(community.0);
+            value.put_u32(community.1);
+            value.put_u32(community.2);
+        }
+        put_attr(
+            &mut buf,
+            FLAG_OPTIONAL | FLAG_TRANSITIVE,
+            ATTR_LARGE_COMMUNITIES,
+            &value,
+        );
+    }
+    // after the known attributes, in the order they were received
+    for unknown in attrs.unknown_attrs.iter().flatten() {
+        // the length is recomputed
+        put_attr(
+            &mut buf,
+            unknown.flags & !FLAG_EXTENDED_LENGTH,
+            unknown.type_code,
+            &unknown.value,
+        );
+    }
+    buf
+}
+
+fn put_record(out: &mut Vec<u8>, timestamp: u32, mrt_type: u16, subtype: u16, record: &[u8]) {
+    out.put_u32(timestamp);
+    out.put_u16(mrt_type);
+    out.put_u16(subtype);
+    out.put_u32(record.len() as u32);
+    out.put_slice(record);
+}
+
+impl MrtWriter {
+    pub fn new(timestamp: u64) -> Self {
+        Self {
+            timestamp: timestamp as u32,
+            peers: vec![],
+            peer_index: HashMap::new(),
+            ribs: BTreeMap::new(),
+        }
+    }
+
+    /// Routes of tables with a route distinguisher are not supported by TABLE_DUMP_V2 and are
+    /// skipped
+    pub fn add_route(
+        &mut self,
+        table: &TableSelector,
+        router_id: RouterId,
+        net: IpNet,
+        path_id: PathId,
+        attrs: Arc<CompressedRouteAttrs>,
+    ) {
+        if !table.route_distinguisher.is_default() {
+            return;
+        }
+        let key = (table.session_id.clone(), table.table_type.clone());
+        let index = match self.peer_index.get(&key) {
+            Some(index) => *index,
+            None => {
+                let Ok(index) = u16::try_from(self.peers.len()) else {
+                    warn!("too many peers for MRT export, skipping {:?}", key);
+                    return;
+                };
+                self.peers.push((table.session_id.clone(), router_id));
+                self.peer_index.insert(key, index);
+                index
+            }
+        };
+        self.ribs
+            .entry(net)
+            .or_default()
+            .push((index, path_id, attrs));
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = vec![];
+
+        let mut record = vec![];
+        record.put_slice(&Ipv4Addr::UNSPECIFIED.octets());
+        let view_name = b"fernglas";
+        record.put_u16(view_name.len() as u16);
+        record.put_slice(view_name);
+        record.put_u16(self.peers.len() as u16);
+        for (session_id, router_id) in &self.peers {
+            // four octet AS numbers, the AS of the peer is not known
+            let peer_type = if session_id.peer_address.is_ipv6() {
+                0b11
+            } else {
+                0b10
+            };
+            record.put_u8(peer_type);
+            // the BGP ID of the peer is not known, use the one of the router which received the
+            // routes instead
+            record.put_slice(&router_id.octets());
+            put_addr(&mut record, session_id.peer_address);
+            record.put_u32(0);
+        }
+        put_record(
+            &mut out,
+            self.timestamp,
+            MRT_TABLE_DUMP_V2,
+            PEER_INDEX_TABLE,
+            &record,
+        );
+
+        for (sequence, (net, entries)) in self.ribs.into_iter().enumerate() {
+            let add_path = entries.iter().any(|(_, path_id, _)| *path_id != 0);
+            let subtype = match (net, add_path) {
+                (IpNet::V4(_), false) => RIB_IPV4_UNICAST,
+                (IpNet::V6(_), false) => RIB_IPV6_UNICAST,
+                (IpNet::V4(_), true) => RIB_IPV4_UNICAST_ADDPATH,
+                (IpNet::V6(_), true) => RIB_IPV6_UNICAST_ADDPATH,
+            };
+
+            let mut record = vec![];
+            record.put_u32(sequence as u32);
+            put_prefix(&mut record, &net);
+            record.put_u16(entries.len().min(u16::MAX as usize) as u16);
+            for (peer_index, path_id, attrs) in entries.into_iter().take(u16::MAX as usize) {
+                record.put_u16(peer_index);
+                record.put_u32(self.timestamp);
+                if add_path {
+                    record.put_u32(path_id);
+                }
+                let attrs = encode_attrs(&net, &attrs);
+                record.put_u16(attrs.len() as u16);
+                record.put_slice(&attrs);
+            }
+            put_record(
+                &mut out,
+                self.timestamp,
+                MRT_TABLE_DUMP_V2,
+                subtype,
+                &record,
+            );
+        }
+
+        out
+    }
+}
+
+/// Renders routes as MRT BGP4MP records with one UPDATE message each, e.g. to replay query results
+/// into a simulator. Only unicast routes of the default routing instance without labels can be
+/// expressed, other routes are skipped.
+pub struct MrtUpdateWriter {
+    caches: Caches,
+    out: Vec<u8>,
+}
+
+impl Default for MrtUpdateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MrtUpdateWriter {
+    pub fn new() -> Self {
+        Self {
+            caches: Caches::default(),
+            out: vec![],
+        }
+    }
+
+    /// The record is timestamped with the time the path was announced. The AS of the peer is the
+    /// expected AS from the session metadata config, if any.
+    pub fn add_result(&mut self, result: &QueryResult) {
+        let unicast = matches!(
+            result.table.afi_safi,
+            AfiSafi::Ipv4Unicast | AfiSafi::Ipv6Unicast
+        );
+        if !unicast
+            || !result.table.route_distinguisher.is_default()
+            || result.attrs.labels.is_some()
+        {
+            return;
+        }
+        let net = result.net;
+        let attrs = self.caches.compress_route_attrs(result.attrs.clone());
+        let path_attrs = encode_path_attrs(&net, &attrs, true);
+
+        let mut message = vec![];
+        message.put_slice(&[0xff; 16]);
+        // length, filled in below
+        message.put_u16(0);
+        message.put_u8(BGP_UPDATE);
+        // withdrawn routes
+        message.put_u16(0);
+        message.put_u16(path_attrs.len() as u16);
+        message.put_slice(&path_attrs);
+        if !in_mp_reach(&net, attrs.nexthop) {
+            put_prefix(&mut message, &net);
+        }
+        let length = message.len() as u16;
+        message[16..18].copy_from_slice(&length.to_be_bytes());
+
+        let peer_address = result.table.session_id.peer_address;
+        let session = result.session.as_ref();
+        let peer_asn = session
+            .and_then(|session| session.peer_asn.or(session.expected_asn))
+            .unwrap_or(0);
+        let local_asn = session.and_then(|session| session.local_asn).unwrap_or(0);
+        // if the local address of the session is not known, use the router id for IPv4
+        let local_address = session
+            .and_then(|session| session.local_address)
+            .filter(|local_address| local_address.is_ipv4() == peer_address.is_ipv4())
+            .unwrap_or(match peer_address {
+                IpAddr::V4(_) => IpAddr::V4(result.client.router_id),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            });
+
+        let mut record = vec![];
+        record.put_u32(peer_asn);
+        record.put_u32(local_asn);
+        // interface index
+        record.put_u16(0);
+        record.put_u16(afi(peer_address));
+        put_addr(&mut record, peer_address);
+        put_addr(&mut record, local_address);
+        record.put_slice(&message);
+        put_record(
+            &mut self.out,
+            result.age.since as u32,
+            MRT_BGP4MP,
+            BGP4MP_MESSAGE_AS4,
+            &record,
+        );
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.out
+    }
+}
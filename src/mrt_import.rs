@@ -0,0 +1,370 @@
+use crate::as_path::AsPath;
+use crate::ext_community::ExtCommunity;
+use crate::store::{
+    AfiSafi, Aggregator, Client, RouteAttrs, RouteOrigin, RouteState, Session, SessionId, Store,
+    TableSelector, TableType, UnknownAttr,
+};
+use bytes::Buf;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use log::*;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncReadExt, BufReader};
+
+const MRT_TABLE_DUMP_V2: u16 = 13;
+
+const PEER_INDEX_TABLE: u16 = 1;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+const RIB_IPV4_UNICAST_ADDPATH: u16 = 8;
+const RIB_IPV6_UNICAST_ADDPATH: u16 = 10;
+
+const ATTR_ORIGIN: u8 = 1;
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_NEXT_HOP: u8 = 3;
+const ATTR_MED: u8 = 4;
+const ATTR_LOCAL_PREF: u8 = 5;
+const ATTR_ATOMIC_AGGREGATE: u8 = 6;
+const ATTR_AGGREGATOR: u8 = 7;
+const ATTR_COMMUNITIES: u8 = 8;
+const ATTR_ORIGINATOR_ID: u8 = 9;
+const ATTR_CLUSTER_LIST: u8 = 10;
+const ATTR_MP_REACH_NLRI: u8 = 14;
+const ATTR_EXT_COMMUNITIES: u8 = 16;
+const ATTR_LARGE_COMMUNITIES: u8 = 32;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MrtImportConfig {
+    /// Uncompressed MRT file in TABLE_DUMP_V2 format
+    pub path: String,
+    /// Name of the synthetic client, defaults to the view name of the dump or the file name
+    pub name_override: Option<String>,
+}
+
+pub(crate) fn ensure(buf: &[u8], len: usize) -> anyhow::Result<()> {
+    if buf.remaining() < len {
+        anyhow::bail!("truncated MRT record");
+    }
+    Ok(())
+}
+
+pub(crate) fn read_addr(buf: &mut &[u8], ipv6: bool) -> anyhow::Result<IpAddr> {
+    Ok(if ipv6 {
+        ensure(buf, 16)?;
+        IpAddr::from(Ipv6Addr::from(buf.get_u128()))
+    } else {
+        ensure(buf, 4)?;
+        IpAddr::from(Ipv4Addr::from(buf.get_u32()))
+    })
+}
+
+pub(crate) fn read_prefix(buf: &mut &[u8], ipv6: bool) -> anyhow::Result<IpNet> {
+    ensure(buf, 1)?;
+    let prefix_len = buf.get_u8();
+    let byte_len = (prefix_len as usize).div_ceil(8);
+    ensure(buf, byte_len)?;
+    let mut octets = [0u8; 16];
+    if byte_len > octets.len() {
+        anyhow::bail!("invalid prefix length {}", prefix_len);
+    }
+    buf.copy_to_slice(&mut octets[..byte_len]);
+    Ok(if ipv6 {
+        Ipv6Net::new(Ipv6Addr::from(octets), prefix_len)?.into()
+    } else {
+        let octets: [u8; 4] = octets[..4].try_into()?;
+        Ipv4Net::new(Ipv4Addr::from(octets), prefix_len)?.into()
+    })
+}
+
+/// Parse the path attributes of a RIB entry. AS numbers are always encoded with four octets and
+/// MP_REACH_NLRI only contains the nexthop.
+pub(crate) fn parse_attrs(mut buf: &[u8]) -> anyhow::Result<RouteAttrs> {
+    let mut attrs = RouteAttrs::default();
+    while buf.has_remaining() {
+        ensure(buf, 3)?;
+        let flags = buf.get_u8();
+        let attr_type = buf.get_u8();
+        let len = if flags & 0x10 != 0 {
+            ensure(buf, 2)?;
+            buf.get_u16() as usize
+        } else {
+            buf.get_u8() as usize
+        };
+        ensure(buf, len)?;
+        let mut value = &buf[..len];
+        buf.advance(len);
+
+        match attr_type {
+            ATTR_ORIGIN if len == 1 => {
+                attrs.origin = match value.get_u8() {
+                    0 => Some(RouteOrigin::Igp),
+                    1 => Some(RouteOrigin::Egp),
+                    _ => Some(RouteOrigin::Incomplete),
+                }
+            }
+            ATTR_AS_PATH => attrs.as_path = Some(AsPath::decode(value, true)?),
+            ATTR_NEXT_HOP if len == 4 => attrs.nexthop = Some(read_addr(&mut value, false)?),
+            ATTR_MED if len == 4 => attrs.med = Some(value.get_u32()),
+            ATTR_LOCAL_PREF if len == 4 => attrs.local_pref = Some(value.get_u32()),
+            ATTR_ATOMIC_AGGREGATE => attrs.atomic_aggregate = true,
+            ATTR_AGGREGATOR if len == 8 => {
+                attrs.aggregator = Some(Aggregator {
+                    asn: value.get_u32(),
+                    address: Ipv4Addr::from(value.get_u32()),
+                })
+            }
+            ATTR_ORIGINATOR_ID if len == 4 => {
+                attrs.originator_id = Some(Ipv4Addr::from(value.get_u32()))
+            }
+            ATTR_CLUSTER_LIST => {
+                attrs.cluster_list = Some(
+                    value
+                        .chunks_exact(4)
+                        .map(|mut c| Ipv4Addr::from(c.get_u32()))
+                        .collect(),
+                );
+            }
+            ATTR_COMMUNITIES => {
+                attrs.communities = Some(
+                    value
+                        .chunks_exact(4)
+                        .map(|mut c| (c.get_u16(), c.get_u16()))
+                        .collect(),
+                );
+            }
+            ATTR_EXT_COMMUNITIES => {
+                attrs.ext_communities = Some(
+                    value
+                        .chunks_exact(8)
+                        .map(|c| ExtCommunity::from_bytes(c.try_into().unwrap()))
+                        .collect(),
+                );
+            }
+            ATTR_LARGE_COMMUNITIES => {
+                attrs.large_communities = Some(
+                    value
+                        .chunks_exact(12)
+                        .map(|mut c| (c.get_u32(), c.get_u32(), c.get_u32()))
+                        .collect(),
+                );
+            }
+            ATTR_MP_REACH_NLRI if len > 0 => {
+                // some implementations write the full attribute instead of the abbreviated form
+                if value[0] as usize + 1 != len {
+                    ensure(value, 3)?;
+                    value.advance(3);
+                }
+                ensure(value, 1)?;
+                match value.get_u8() {
+                    4 => attrs.nexthop = Some(read_addr(&mut value, false)?),
+                    16 => attrs.nexthop = Some(read_addr(&mut value, true)?),
+                    32 => {
+                        attrs.nexthop = Some(read_addr(&mut value, true)?);
+                        if let IpAddr::V6(link_local) = read_addr(&mut value, true)? {
+                            attrs.nexthop_link_local = Some(link_local);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            ATTR_ORIGIN | ATTR_NEXT_HOP | ATTR_MED | ATTR_LOCAL_PREF | ATTR_AGGREGATOR
+            | ATTR_ORIGINATOR_ID | ATTR_MP_REACH_NLRI => {}
+            _ => attrs
+                .unknown_attrs
+                .get_or_insert_with(Vec::new)
+                .push(UnknownAttr {
+                    type_code: attr_type,
+                    flags,
+                    value: value.to_vec(),
+                }),
+        }
+    }
+    Ok(attrs)
+}
+
+struct PeerIndex {
+    collector_id: Ipv4Addr,
+    client_addr: SocketAddr,
+    view_name: Option<String>,
+    peers: Vec<IpAddr>,
+}
+
+fn parse_peer_index_table(mut buf: &[u8]) -> anyhow::Result<PeerIndex> {
+    ensure(buf, 6)?;
+    let collector_id = Ipv4Addr::from(buf.get_u32());
+    let view_name_len = buf.get_u16() as usize;
+    ensure(buf, view_name_len + 2)?;
+    let view_name = String::from_utf8_lossy(&buf[..view_name_len]).into_owned();
+    buf.advance(view_name_len);
+
+    let peer_count = buf.get_u16();
+    let mut peers = vec![];
+    for _ in 0..peer_count {
+        ensure(buf, 5)?;
+        let peer_type = buf.get_u8();
+        let _peer_bgp_id = buf.get_u32();
+        peers.push(read_addr(&mut buf, peer_type & 1 != 0)?);
+        let as_len = if peer_type & 2 != 0 { 4 } else { 2 };
+        ensure(buf, as_len)?;
+        buf.advance(as_len);
+    }
+
+    Ok(PeerIndex {
+        collector_id,
+        client_addr: SocketAddr::new(collector_id.into(), 0),
+        view_name: Some(view_name).filter(|name| !name.is_empty()),
+        peers,
+    })
+}
+
+async fn import_rib(
+    store: &impl Store,
+    index: &PeerIndex,
+    mut buf: &[u8],
+    ipv6: bool,
+    add_path: bool,
+) -> anyhow::Result<()> {
+    ensure(buf, 4)?;
+    let _sequence = buf.get_u32();
+    let net = read_prefix(&mut buf, ipv6)?;
+    ensure(buf, 2)?;
+    let entry_count = buf.get_u16();
+    for _ in 0..entry_count {
+        ensure(buf, 6)?;
+        let peer_index = buf.get_u16() as usize;
+        let _originated_time = buf.get_u32();
+        let path_id = if add_path {
+            ensure(buf, 4)?;
+            buf.get_u32()
+        } else {
+            0
+        };
+        ensure(buf, 2)?;
+        let attrs_len = buf.get_u16() as usize;
+        ensure(buf, attrs_len)?;
+        let attrs = parse_attrs(&buf[..attrs_len])?;
+        buf.advance(attrs_len);
+
+        let Some(peer_address) = index.peers.get(peer_index) else {
+            warn!("MRT RIB entry refers to unknown peer index {}", peer_index);
+            continue;
+        };
+        let table = TableSelector {
+            route_distinguisher: Default::default(),
+            session_id: SessionId {
+                from_client: index.client_addr,
+                peer_address: *peer_address,
+            },
+            table_type: TableType::PostPolicyAdjIn,
+            afi_safi: if ipv6 {
+                AfiSafi::Ipv6Unicast
+            } else {
+                AfiSafi::Ipv4Unicast
+            },
+        };
+        if let Err(e) = store.update_route(path_id, net, table, attrs).await {
+            debug!("route {} was not imported: {}", net, e);
+        }
+    }
+    Ok(())
+}
+
+/// Load a RIB snapshot into the store, as routes received by a synthetic client. Returns the
+/// address of the synthetic client.
+pub async fn import(cfg: &MrtImportConfig, store: &impl Store) -> anyhow::Result<SocketAddr> {
+    let mut reader = BufReader::new(tokio::fs::File::open(&cfg.path).await?);
+    let mut index: Option<PeerIndex> = None;
+    let mut record = vec![];
+    let mut routes = 0usize;
+
+    loop {
+        let mut header = [0u8; 12];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let mut header = &header[..];
+        let _timestamp = header.get_u32();
+        let record_type = header.get_u16();
+        let subtype = header.get_u16();
+        let len = header.get_u32() as usize;
+        record.resize(len, 0);
+        reader.read_exact(&mut record).await?;
+
+        if record_type != MRT_TABLE_DUMP_V2 {
+            continue;
+        }
+        let rib = matches!(
+            subtype,
+            RIB_IPV4_UNICAST
+                | RIB_IPV6_UNICAST
+                | RIB_IPV4_UNICAST_ADDPATH
+                | RIB_IPV6_UNICAST_ADDPATH
+        );
+        match (subtype, &index) {
+            (PEER_INDEX_TABLE, _) => {
+                let new_index = parse_peer_index_table(&record)?;
+                let client_name = cfg
+                    .name_override
+                    .clone()
+                    .or(new_index.view_name.clone())
+                    .unwrap_or(cfg.path.clone());
+                store
+                    .client_up(
+                        new_index.client_addr,
+                        RouteState::Selected,
+                        Client {
+                            client_name,
+                            router_id: new_index.collector_id,
+                            loc_rib_tables: Default::default(),
+                            sys_descr: None,
+                            metadata: Default::default(),
+                        },
+                    )
+                    .await;
+                for peer_address in &new_index.peers {
+                    store
+                        .session_up(
+                            SessionId {
+                                from_client: new_index.client_addr,
+                                peer_address: *peer_address,
+                            },
+                            Session::default(),
+                        )
+                        .await;
+                }
+                index = Some(new_index);
+            }
+            (_, Some(index)) if rib => {
+                let ipv6 = matches!(subtype, RIB_IPV6_UNICAST | RIB_IPV6_UNICAST_ADDPATH);
+                let add_path =
+                    matches!(subtype, RIB_IPV4_UNICAST_ADDPATH | RIB_IPV6_UNICAST_ADDPATH);
+                import_rib(store, index, &record, ipv6, add_path).await?;
+                routes += 1;
+            }
+            (_, None) if rib => anyhow::bail!("MRT RIB entry before the peer index table"),
+            // multicast and generic RIBs are not supported
+            _ => {}
+        }
+    }
+
+    let index = index.ok_or(anyhow::anyhow!(
+        "MRT file does not contain a peer index table"
+    ))?;
+    info!("imported {} prefixes from {}", routes, cfg.path);
+    Ok(index.client_addr)
+}
+
+pub async fn run(
+    cfg: MrtImportConfig,
+    store: impl Store,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let client_addr = import(&cfg, &store).await?;
+
+    // the imported routes are kept until shutdown
+    let _ = shutdown.changed().await;
+    store.client_down(client_addr, None).await;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use crate::api::ApiResult;
+use crate::enrichment::{Enricher, EnrichmentFuture};
+use crate::store::QueryResult;
+use futures_util::FutureExt;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+/// Address space operated by us
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OwnedPrefix {
+    pub net: IpNet,
+    pub owner: Option<String>,
+    pub description: Option<String>,
+    /// ASNs which are expected to originate the prefix or parts of it
+    #[serde(default)]
+    pub origin_asns: Vec<u32>,
+}
+
+impl OwnedPrefix {
+    pub fn overlaps(&self, net: &IpNet) -> bool {
+        self.net.contains(net) || net.contains(&self.net)
+    }
+
+    /// A route is external if it is originated by an AS not listed in `origin_asns`
+    pub fn is_external(&self, route: &QueryResult) -> bool {
+        match route
+            .attrs
+            .as_path
+            .as_ref()
+            .and_then(|path| path.origin_asn())
+        {
+            Some(origin) => !self.origin_asns.is_empty() && !self.origin_asns.contains(&origin),
+            None => false,
+        }
+    }
+}
+
+pub struct OwnershipEnricher(pub Vec<OwnedPrefix>);
+
+impl Enricher for OwnershipEnricher {
+    fn keys(&self, route: &QueryResult) -> Vec<String> {
+        if self.0.iter().any(|owned| owned.overlaps(&route.net)) {
+            vec![route.net.to_string()]
+        } else {
+            vec![]
+        }
+    }
+
+    fn lookup(&self, key: String) -> EnrichmentFuture {
+        let result = key.parse::<IpNet>().ok().map(|net| ApiResult::Ownership {
+            net,
+            owned_prefixes: self
+                .0
+                .iter()
+                .filter(|owned| owned.overlaps(&net))
+                .cloned()
+                .collect(),
+        });
+        futures_util::future::ready(result).boxed()
+    }
+}
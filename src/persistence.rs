@@ -0,0 +1,433 @@
+//! Binary snapshots of the store, so the routes are available right after a restart instead of only
+//! after every router has sent its tables again.
+//!
+//! A snapshot consists of the magic bytes, followed by the clients, the sessions, the counters and
+//! event history of the store and the tables with their routes. Route attributes are encoded like
+//! in MRT RIB entries.
+//!
+//! In the metadata mode, the snapshot contains no tables. The routes are learned again from the
+//! routers, but the peer statistics and session history survive restarts.
+
+use bytes::{Buf, BufMut};
+use ipnet::IpNet;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::compressed_attrs::CompressedRouteAttrs;
+use crate::mrt_export::{encode_attrs, put_addr, put_prefix};
+use crate::mrt_import::{ensure, parse_attrs, read_addr, read_prefix};
+use crate::route_distinguisher::RouteDistinguisher;
+use crate::store::*;
+use crate::store_impl::InMemoryStore;
+
+const MAGIC: &[u8] = b"FGLS\x03";
+
+fn default_interval() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersistenceMode {
+    /// Clients, sessions, metadata and routes
+    #[default]
+    Full,
+    /// Everything but the routes
+    Metadata,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// File the snapshots are written to and restored from
+    pub path: String,
+    /// Seconds between snapshots
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub mode: PersistenceMode,
+}
+
+/// Counters and event history of the store, which are part of every snapshot
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotMetadata {
+    pub peer_stats: Vec<PeerStats>,
+    pub peer_states: Vec<PeerStatus>,
+    pub session_down_events: Vec<SessionDownEvent>,
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.put_u32(s.len() as u32);
+    buf.put_slice(s.as_bytes());
+}
+
+fn put_socket_addr(buf: &mut Vec<u8>, addr: &SocketAddr) {
+    buf.put_u8(addr.is_ipv6() as u8);
+    put_addr(buf, addr.ip());
+    buf.put_u16(addr.port());
+}
+
+fn put_session_id(buf: &mut Vec<u8>, session_id: &SessionId) {
+    put_socket_addr(buf, &session_id.from_client);
+    buf.put_u8(session_id.peer_address.is_ipv6() as u8);
+    put_addr(buf, session_id.peer_address);
+}
+
+fn put_table_selector(buf: &mut Vec<u8>, table: &TableSelector) {
+    put_session_id(buf, &table.session_id);
+    match table.route_distinguisher {
+        RouteDistinguisher::Default => buf.put_u8(0),
+        RouteDistinguisher::Type0 { asn, value } => {
+            buf.put_u8(1);
+            buf.put_u16(asn);
+            buf.put_u32(value);
+        }
+        RouteDistinguisher::Type1 { ip, value } => {
+            buf.put_u8(2);
+            buf.put_slice(&ip.octets());
+            buf.put_u16(value);
+        }
+        RouteDistinguisher::Type2 { asn, value } => {
+            buf.put_u8(3);
+            buf.put_u32(asn);
+            buf.put_u16(value);
+        }
+    }
+    match &table.table_type {
+        TableType::PrePolicyAdjIn => buf.put_u8(0),
+        TableType::PostPolicyAdjIn => buf.put_u8(1),
+        TableType::PrePolicyAdjOut => buf.put_u8(3),
+        TableType::PostPolicyAdjOut => buf.put_u8(4),
+        TableType::Mirrored => buf.put_u8(5),
+        TableType::LocRib { route_state } => {
+            buf.put_u8(2);
+            buf.put_u8(*route_state as u8);
+        }
+    }
+    buf.put_u8(match table.afi_safi {
+        AfiSafi::Ipv4Unicast => 0,
+        AfiSafi::Ipv6Unicast => 1,
+        AfiSafi::Ipv4MplsVpn => 2,
+        AfiSafi::Ipv6MplsVpn => 3,
+        AfiSafi::Ipv4Flowspec => 4,
+        AfiSafi::Ipv6Flowspec => 5,
+    });
+}
+
+/// MPLS label stack, as a count followed by the labels. No labels are stored as a count of 0.
+pub(crate) fn put_labels(buf: &mut Vec<u8>, labels: Option<&[u32]>) {
+    let labels = labels.unwrap_or_default();
+    buf.put_u8(labels.len() as u8);
+    for label in labels {
+        buf.put_u32(*label);
+    }
+}
+
+fn read_string(buf: &mut &[u8]) -> anyhow::Result<String> {
+    ensure(buf, 4)?;
+    let len = buf.get_u32() as usize;
+    ensure(buf, len)?;
+    let s = String::from_utf8(buf[..len].to_vec())?;
+    buf.advance(len);
+    Ok(s)
+}
+
+pub(crate) fn read_labels(buf: &mut &[u8]) -> anyhow::Result<Option<Vec<u32>>> {
+    ensure(buf, 1)?;
+    let count = buf.get_u8() as usize;
+    ensure(buf, count * 4)?;
+    let labels = (0..count).map(|_| buf.get_u32()).collect::<Vec<_>>();
+    Ok((!labels.is_empty()).then_some(labels))
+}
+
+fn read_socket_addr(buf: &mut &[u8]) -> anyhow::Result<SocketAddr> {
+    ensure(buf, 1)?;
+    let ipv6 = buf.get_u8() != 0;
+    let ip = read_addr(buf, ipv6)?;
+    ensure(buf, 2)?;
+    Ok(SocketAddr::new(ip, buf.get_u16()))
+}
+
+fn read_session_id(buf: &mut &[u8]) -> anyhow::Result<SessionId> {
+    let from_client = read_socket_addr(buf)?;
+    ensure(buf, 1)?;
+    let ipv6 = buf.get_u8() != 0;
+    Ok(SessionId {
+        from_client,
+        peer_address: read_addr(buf, ipv6)?,
+    })
+}
+
+fn read_table_selector(buf: &mut &[u8]) -> anyhow::Result<TableSelector> {
+    let session_id = read_session_id(buf)?;
+    ensure(buf, 1)?;
+    let route_distinguisher = match buf.get_u8() {
+        0 => RouteDistinguisher::Default,
+        1 => {
+            ensure(buf, 6)?;
+            RouteDistinguisher::Type0 {
+                asn: buf.get_u16(),
+                value: buf.get_u32(),
+            }
+        }
+        2 => {
+            ensure(buf, 6)?;
+            RouteDistinguisher::Type1 {
+                ip: Ipv4Addr::from(buf.get_u32()),
+                value: buf.get_u16(),
+            }
+        }
+        3 => {
+            ensure(buf, 6)?;
+            RouteDistinguisher::Type2 {
+                asn: buf.get_u32(),
+                value: buf.get_u16(),
+            }
+        }
+        other => anyhow::bail!("invalid route distinguisher type {}", other),
+    };
+    ensure(buf, 1)?;
+    let table_type = match buf.get_u8() {
+        0 => TableType::PrePolicyAdjIn,
+        1 => TableType::PostPolicyAdjIn,
+        2 => {
+            ensure(buf, 1)?;
+            let route_state = match buf.get_u8() {
+                0 => RouteState::Seen,
+                1 => RouteState::Accepted,
+                2 => RouteState::Active,
+                _ => RouteState::Selected,
+            };
+            TableType::LocRib { route_state }
+        }
+        3 => TableType::PrePolicyAdjOut,
+        4 => TableType::PostPolicyAdjOut,
+        5 => TableType::Mirrored,
+        other => anyhow::bail!("invalid table type {}", other),
+    };
+    ensure(buf, 1)?;
+    let afi_safi = match buf.get_u8() {
+        0 => AfiSafi::Ipv4Unicast,
+        1 => AfiSafi::Ipv6Unicast,
+        2 => AfiSafi::Ipv4MplsVpn,
+        3 => AfiSafi::Ipv6MplsVpn,
+        4 => AfiSafi::Ipv4Flowspec,
+        5 => AfiSafi::Ipv6Flowspec,
+        other => anyhow::bail!("invalid address family {}", other),
+    };
+    Ok(TableSelector {
+        route_distinguisher,
+        session_id,
+        table_type,
+        afi_safi,
+    })
+}
+
+/// Position of a route in a table. The table is encoded like in snapshots, so the encoded cursors
+/// of the tables also define their order for paginated queries.
+pub(crate) fn encode_cursor(cursor: &Cursor) -> Vec<u8> {
+    let mut buf = table_sort_key(&cursor.table);
+    buf.put_u8(cursor.net.network().is_ipv6() as u8);
+    put_prefix(&mut buf, &cursor.net);
+    buf.put_u32(cursor.path_id);
+    buf
+}
+
+pub(crate) fn decode_cursor(mut buf: &[u8]) -> anyhow::Result<Cursor> {
+    let table = read_table_selector(&mut buf)?;
+    ensure(buf, 1)?;
+    let ipv6 = buf.get_u8() != 0;
+    let net = read_prefix(&mut buf, ipv6)?;
+    ensure(buf, 4)?;
+    Ok(Cursor {
+        table,
+        net,
+        path_id: buf.get_u32(),
+    })
+}
+
+/// Tables of paginated queries are scanned in the order of these keys
+pub(crate) fn table_sort_key(table: &TableSelector) -> Vec<u8> {
+    let mut buf = vec![];
+    put_table_selector(&mut buf, table);
+    buf
+}
+
+/// Builds a snapshot. Clients, sessions, metadata and tables have to be written in this order,
+/// each list preceded by its count.
+pub struct SnapshotWriter(Vec<u8>);
+
+impl Default for SnapshotWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotWriter {
+    pub fn new() -> Self {
+        Self(MAGIC.to_vec())
+    }
+
+    pub fn count(&mut self, count: usize) {
+        self.0.put_u32(count as u32);
+    }
+
+    pub fn client(&mut self, client_addr: &SocketAddr, client: &Client) {
+        put_socket_addr(&mut self.0, client_addr);
+        put_string(&mut self.0, &client.client_name);
+        self.0.put_slice(&client.router_id.octets());
+    }
+
+    pub fn session(&mut self, session_id: &SessionId, session: &Session) {
+        put_session_id(&mut self.0, session_id);
+        put_string(&mut self.0, &serde_json::to_string(session).unwrap());
+    }
+
+    pub fn metadata(&mut self, metadata: &SnapshotMetadata) {
+        put_string(&mut self.0, &serde_json::to_string(metadata).unwrap());
+    }
+
+    /// Has to be followed by `route_count` routes
+    pub fn table(&mut self, table: &TableSelector, route_count: usize) {
+        put_table_selector(&mut self.0, table);
+        self.count(route_count);
+    }
+
+    pub fn route(&mut self, net: &IpNet, path_id: PathId, attrs: &CompressedRouteAttrs) {
+        self.0.put_u8(net.network().is_ipv6() as u8);
+        put_prefix(&mut self.0, net);
+        self.0.put_u32(path_id);
+        self.0.put_u8(attrs.truncated as u8);
+        put_labels(&mut self.0, attrs.labels.as_deref());
+        let attrs = encode_attrs(net, attrs);
+        self.0.put_u32(attrs.len() as u32);
+        self.0.put_slice(&attrs);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+pub struct SnapshotReader<'a>(&'a [u8]);
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(data: &'a [u8]) -> anyhow::Result<Self> {
+        if !data.starts_with(MAGIC) {
+            anyhow::bail!("not a fernglas snapshot or unsupported version");
+        }
+        Ok(Self(&data[MAGIC.len()..]))
+    }
+
+    pub fn count(&mut self) -> anyhow::Result<usize> {
+        ensure(self.0, 4)?;
+        Ok(self.0.get_u32() as usize)
+    }
+
+    pub fn client(&mut self) -> anyhow::Result<(SocketAddr, Client)> {
+        let client_addr = read_socket_addr(&mut self.0)?;
+        let client_name = read_string(&mut self.0)?;
+        ensure(self.0, 4)?;
+        let router_id = Ipv4Addr::from(self.0.get_u32());
+        Ok((
+            client_addr,
+            Client {
+                client_name,
+                router_id,
+                loc_rib_tables: Default::default(),
+                sys_descr: None,
+                metadata: Default::default(),
+            },
+        ))
+    }
+
+    pub fn session(&mut self) -> anyhow::Result<(SessionId, Session)> {
+        let session_id = read_session_id(&mut self.0)?;
+        let session = serde_json::from_str(&read_string(&mut self.0)?)?;
+        Ok((session_id, session))
+    }
+
+    pub fn metadata(&mut self) -> anyhow::Result<SnapshotMetadata> {
+        Ok(serde_json::from_str(&read_string(&mut self.0)?)?)
+    }
+
+    /// Returns the table and the number of routes following it
+    pub fn table(&mut self) -> anyhow::Result<(TableSelector, usize)> {
+        let table = read_table_selector(&mut self.0)?;
+        Ok((table, self.count()?))
+    }
+
+    pub fn route(&mut self) -> anyhow::Result<(IpNet, PathId, RouteAttrs)> {
+        ensure(self.0, 1)?;
+        let ipv6 = self.0.get_u8() != 0;
+        let net = read_prefix(&mut self.0, ipv6)?;
+        ensure(self.0, 5)?;
+        let path_id = self.0.get_u32();
+        let truncated = self.0.get_u8() != 0;
+        let labels = read_labels(&mut self.0)?;
+        ensure(self.0, 4)?;
+        let len = self.0.get_u32() as usize;
+        ensure(self.0, len)?;
+        let mut attrs = parse_attrs(&self.0[..len])?;
+        attrs.truncated = truncated;
+        attrs.labels = labels;
+        self.0.advance(len);
+        Ok((net, path_id, attrs))
+    }
+}
+
+pub async fn write_snapshot(cfg: &PersistenceConfig, store: &InMemoryStore) -> anyhow::Result<()> {
+    let store = store.clone();
+    let path = cfg.path.clone();
+    let routes = cfg.mode == PersistenceMode::Full;
+    tokio::task::spawn_blocking(move || {
+        let snapshot = store.snapshot(routes);
+        // write to a temporary file first, so a crash never leaves a partial snapshot behind
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, snapshot)?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    })
+    .await?
+}
+
+/// Restore the last snapshot, if there is one
+pub async fn restore(cfg: &PersistenceConfig, store: &InMemoryStore) -> anyhow::Result<()> {
+    let data = match tokio::fs::read(&cfg.path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("no snapshot found at {}", cfg.path);
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    store
+        .restore(&data, cfg.mode == PersistenceMode::Full)
+        .await?;
+    info!("restored snapshot from {}", cfg.path);
+    Ok(())
+}
+
+/// Periodically write snapshots. The final snapshot is written before shutting down the collectors,
+/// as they remove their routes from the store.
+pub async fn run(
+    cfg: PersistenceConfig,
+    store: InMemoryStore,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.interval.max(1)));
+    // the first tick completes immediately, right after the snapshot was restored
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = write_snapshot(&cfg, &store).await {
+                    warn!("failed to write snapshot to {}: {}", cfg.path, e);
+                }
+            }
+            _ = shutdown.changed() => break Ok(()),
+        }
+    }
+}
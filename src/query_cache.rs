@@ -0,0 +1,157 @@
+//! Short lived cache of query results, so the same prefix being looked up by many users at once
+//! only has to be searched in the tables once
+
+use futures_util::{Stream, StreamExt};
+use ipnet::IpNet;
+use log::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::store::{Query, QueryResult, RouteChange, Store, StoreError};
+
+fn default_ttl() -> u64 {
+    10
+}
+
+fn default_max_entries() -> usize {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueryCacheConfig {
+    /// Seconds a result is served from the cache
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+struct CacheEntry {
+    net: IpNet,
+    inserted: Instant,
+    results: Arc<Vec<QueryResult>>,
+}
+
+pub struct QueryCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl QueryCache {
+    pub fn new(cfg: &QueryCacheConfig) -> Self {
+        Self {
+            ttl: Duration::from_secs(cfg.ttl),
+            max_entries: cfg.max_entries,
+            entries: Default::default(),
+        }
+    }
+
+    /// The query is already normalized by the API, so the serialized query can be used as key
+    fn key(query: &Query) -> String {
+        serde_json::to_string(query).unwrap()
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<Vec<QueryResult>>> {
+        let entries = self.entries.lock().unwrap();
+        let results = entries
+            .get(key)
+            .filter(|entry| entry.inserted.elapsed() < self.ttl)
+            .map(|entry| entry.results.clone());
+        match results {
+            Some(_) => crate::metrics::QUERY_CACHE_HITS.inc(),
+            None => crate::metrics::QUERY_CACHE_MISSES.inc(),
+        }
+        results
+    }
+
+    fn insert(&self, key: String, net: IpNet, results: Arc<Vec<QueryResult>>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, entry| entry.inserted.elapsed() < self.ttl);
+        }
+        if entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        if self.max_entries > 0 {
+            entries.insert(
+                key,
+                CacheEntry {
+                    net,
+                    inserted: Instant::now(),
+                    results,
+                },
+            );
+        }
+        crate::metrics::QUERY_CACHE_ENTRIES.set(entries.len() as i64);
+    }
+
+    /// Drop all results a change could have affected, i.e. those of queries for prefixes
+    /// overlapping the changed one
+    fn invalidate(&self, change: &RouteChange) {
+        let mut entries = self.entries.lock().unwrap();
+        match change.net() {
+            Some(net) => {
+                entries.retain(|_, entry| !entry.net.contains(net) && !net.contains(&entry.net))
+            }
+            None => entries.clear(),
+        }
+        crate::metrics::QUERY_CACHE_ENTRIES.set(entries.len() as i64);
+    }
+
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+        crate::metrics::QUERY_CACHE_ENTRIES.set(0);
+    }
+
+    /// Results of the query, from the cache if they are recent enough
+    pub fn get_routes<T: Store>(
+        self: &Arc<Self>,
+        store: &T,
+        query: Query,
+    ) -> Result<Pin<Box<dyn Stream<Item = QueryResult> + Send>>, StoreError> {
+        let key = Self::key(&query);
+        if let Some(results) = self.get(&key) {
+            return Ok(futures_util::stream::iter((*results).clone()).boxed());
+        }
+
+        let cache = self.clone();
+        let net = *query.net_query.net();
+        let routes = store.get_routes(query)?;
+        Ok(futures_util::stream::once(async move {
+            let results = Arc::new(routes.collect::<Vec<_>>().await);
+            cache.insert(key, net, results.clone());
+            futures_util::stream::iter((*results).clone())
+        })
+        .flatten()
+        .boxed())
+    }
+
+    /// Invalidate cached results as the routes change, until the store is dropped
+    pub async fn run_invalidation(
+        self: Arc<Self>,
+        mut changes: tokio::sync::broadcast::Receiver<RouteChange>,
+    ) {
+        loop {
+            match changes.recv().await {
+                Ok(change) => self.invalidate(&change),
+                Err(RecvError::Lagged(skipped)) => {
+                    debug!("query cache missed {} changes, clearing it", skipped);
+                    self.clear();
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Retention of the pre-policy Adj-RIB-In tables. Pre-policy full feeds dominate the memory usage
+//! of a collector, but are rarely queried in full, so they can be reduced to a subset of their
+//! routes.
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+use crate::route_distinguisher::RouteDistinguisher;
+use crate::store::PathId;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PrePolicyRetention {
+    /// Keep all routes
+    #[default]
+    All,
+    /// Keep the routes of one in `one_in` prefixes. Prefixes are selected by their hash, so the
+    /// same prefixes are kept on every router and across restarts.
+    Sampled { one_in: u32 },
+    /// Keep the `routes` most recently updated routes of each table
+    MostRecent { routes: usize },
+    /// Keep only the routes of prefixes which are also in the post-policy table of the session
+    PostPolicy,
+}
+
+/// Seconds a pre-policy route waits for the post-policy route of its prefix, once the post-policy
+/// table is complete
+pub const POST_POLICY_GRACE: u64 = 60;
+
+impl PrePolicyRetention {
+    /// Whether the prefix is in the sample, always true if not sampling
+    pub fn sampled(&self, net: &IpNet) -> bool {
+        let PrePolicyRetention::Sampled { one_in } = *self else {
+            return true;
+        };
+        let mut hasher = DefaultHasher::new();
+        net.hash(&mut hasher);
+        hasher.finish().is_multiple_of(u64::from(one_in.max(1)))
+    }
+}
+
+/// Order in which the paths of a table were last updated, to evict the least recently updated
+#[derive(Debug, Default)]
+pub struct RecencyIndex {
+    next: u64,
+    order: BTreeMap<u64, (PathId, IpNet)>,
+    paths: HashMap<(PathId, IpNet), u64>,
+}
+
+impl RecencyIndex {
+    /// Record an update of the path. Returns the paths which have to be evicted to keep at most
+    /// `limit` paths.
+    pub fn touch(&mut self, path_id: PathId, net: IpNet, limit: usize) -> Vec<(PathId, IpNet)> {
+        if let Some(seq) = self.paths.insert((path_id, net), self.next) {
+            self.order.remove(&seq);
+        }
+        self.order.insert(self.next, (path_id, net));
+        self.next += 1;
+
+        let mut evicted = vec![];
+        while self.paths.len() > limit {
+            let Some((_, path)) = self.order.pop_first() else {
+                break;
+            };
+            self.paths.remove(&path);
+            evicted.push(path);
+        }
+        evicted
+    }
+
+    pub fn remove(&mut self, path_id: PathId, net: IpNet) {
+        if let Some(seq) = self.paths.remove(&(path_id, net)) {
+            self.order.remove(&seq);
+        }
+    }
+}
+
+/// Prefixes of the pre-policy tables of a session and address family which were stored before the
+/// post-policy table had a route for them. Routers usually send the pre-policy route first, so it
+/// is only given up on after the post-policy route had time to arrive.
+#[derive(Debug, Default)]
+pub struct PendingPrePolicy {
+    /// The post-policy table sent End-of-RIB, before that it is incomplete
+    synced: bool,
+    /// Unix timestamps in seconds of when the prefixes were received, oldest first
+    prefixes: VecDeque<(u64, RouteDistinguisher, IpNet)>,
+}
+
+impl PendingPrePolicy {
+    pub fn push(&mut self, route_distinguisher: RouteDistinguisher, net: IpNet, now: u64) {
+        self.prefixes.push_back((now, route_distinguisher, net));
+    }
+
+    /// The post-policy table is complete, all pending prefixes are due to be checked
+    pub fn synced(&mut self) -> Vec<(RouteDistinguisher, IpNet)> {
+        self.synced = true;
+        self.prefixes
+            .drain(..)
+            .map(|(_, rd, net)| (rd, net))
+            .collect()
+    }
+
+    /// Prefixes which waited for `grace` seconds or longer. None are due while the post-policy
+    /// table is incomplete.
+    pub fn due(&mut self, now: u64, grace: u64) -> Vec<(RouteDistinguisher, IpNet)> {
+        let mut due = vec![];
+        while self.synced {
+            match self.prefixes.front() {
+                Some((received, _, _)) if now.saturating_sub(*received) >= grace => {
+                    due.extend(self.prefixes.pop_front().map(|(_, rd, net)| (rd, net)));
+                }
+                _ => break,
+            }
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evict_least_recent() {
+        let net = |s: &str| s.parse::<IpNet>().unwrap();
+        let mut index = RecencyIndex::default();
+        assert!(index.touch(0, net("192.0.2.0/24"), 2).is_empty());
+        assert!(index.touch(0, net("198.51.100.0/24"), 2).is_empty());
+        // updating a path makes it the most recent one
+        assert!(index.touch(0, net("192.0.2.0/24"), 2).is_empty());
+        assert_eq!(
+            index.touch(0, net("203.0.113.0/24"), 2),
+            vec![(0, net("198.51.100.0/24"))]
+        );
+        index.remove(0, net("192.0.2.0/24"));
+        assert!(index.touch(1, net("203.0.113.0/24"), 2).is_empty());
+    }
+
+    #[test]
+    fn pending_pre_policy() {
+        let rd = RouteDistinguisher::Default;
+        let net = |s: &str| (rd, s.parse::<IpNet>().unwrap());
+        let mut pending = PendingPrePolicy::default();
+        pending.push(rd, net("192.0.2.0/24").1, 100);
+        pending.push(rd, net("198.51.100.0/24").1, 200);
+        // the initial dump of the post-policy table may take longer than the grace period
+        assert!(pending.due(1000, 60).is_empty());
+        assert_eq!(
+            pending.synced(),
+            vec![net("192.0.2.0/24"), net("198.51.100.0/24")]
+        );
+
+        pending.push(rd, net("203.0.113.0/24").1, 1000);
+        pending.push(rd, net("192.0.2.0/24").1, 1030);
+        assert!(pending.due(1059, 60).is_empty());
+        assert_eq!(pending.due(1060, 60), vec![net("203.0.113.0/24")]);
+        assert_eq!(pending.due(2000, 60), vec![net("192.0.2.0/24")]);
+    }
+
+    #[test]
+    fn sample_prefixes() {
+        let nets = (0..=255u8)
+            .map(|i| format!("10.{}.0.0/16", i).parse::<IpNet>().unwrap())
+            .collect::<Vec<_>>();
+        let sampled = PrePolicyRetention::Sampled { one_in: 4 };
+        let count = nets.iter().filter(|net| sampled.sampled(net)).count();
+        assert!(count > 0 && count < nets.len());
+        assert!(nets.iter().all(|net| PrePolicyRetention::All.sampled(net)));
+    }
+}
@@ -1,7 +1,7 @@
 use zettabgp::afi::BgpRD;
 
 use serde::de::{self, Visitor};
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 use std::fmt::{self, Display};
 use std::net::Ipv4Addr;
 use std::str::FromStr;
@@ -150,10 +150,66 @@ impl TryFrom<BgpRD> for RouteDistinguisher {
     }
 }
 
+/// Comma separated list of route distinguishers, e.g. `64496:1,64496:2`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RouteDistinguishers(pub Vec<RouteDistinguisher>);
+
+impl RouteDistinguishers {
+    pub fn contains(&self, rd: &RouteDistinguisher) -> bool {
+        self.0.contains(rd)
+    }
+}
+
+impl TryFrom<String> for RouteDistinguishers {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let mut rds = vec![];
+        for rd in s.split(',').map(str::trim).filter(|rd| !rd.is_empty()) {
+            let rd = rd.parse()?;
+            if !rds.contains(&rd) {
+                rds.push(rd);
+            }
+        }
+        Ok(RouteDistinguishers(rds))
+    }
+}
+
+impl From<RouteDistinguishers> for String {
+    fn from(rds: RouteDistinguishers) -> String {
+        rds.0
+            .iter()
+            .map(|rd| rd.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn decode_route_distinguisher_list() {
+        let rds = RouteDistinguishers::try_from("64496:1, 192.0.2.1:2,64496:1".to_string());
+        assert_eq!(
+            rds,
+            Ok(RouteDistinguishers(vec![
+                RouteDistinguisher::Type0 {
+                    asn: 64496,
+                    value: 1
+                },
+                RouteDistinguisher::Type1 {
+                    ip: Ipv4Addr::new(192, 0, 2, 1),
+                    value: 2
+                },
+            ]))
+        );
+        assert_eq!(String::from(rds.unwrap()), "64496:1,192.0.2.1:2");
+        assert!(RouteDistinguishers::try_from("64496".to_string()).is_err());
+    }
+
     #[test]
     fn decode_route_distingisher_from_string() {
         let default = "0:0".parse();
@@ -0,0 +1,247 @@
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use log::*;
+use nibbletree::Node;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn default_refresh_interval() -> u64 {
+    600
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "source_type")]
+pub enum RpkiConfig {
+    /// JSON export as written by routinator (`--format json`) or rpki-client
+    Json {
+        path: String,
+        /// Seconds between reloads of the file
+        #[serde(default = "default_refresh_interval")]
+        refresh_interval: u64,
+    },
+    /// RPKI-to-Router protocol (RFC 8210) server
+    Rtr {
+        address: String,
+        /// Seconds between full reloads from the server
+        #[serde(default = "default_refresh_interval")]
+        refresh_interval: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RpkiStatus {
+    Valid,
+    Invalid,
+    NotFound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Vrp {
+    max_length: u8,
+    asn: u32,
+}
+
+/// Validated ROA payloads
+#[derive(Default)]
+pub struct Vrps {
+    table: Node<IpNet, Vec<Vrp>>,
+    count: usize,
+}
+
+impl Vrps {
+    fn insert(&mut self, net: IpNet, max_length: u8, asn: u32) {
+        let vrp = Vrp { max_length, asn };
+        match self.table.exact_mut(&net) {
+            Some(vrps) => vrps.push(vrp),
+            None => {
+                self.table.insert(&net, vec![vrp]);
+            }
+        }
+        self.count += 1;
+    }
+
+    /// Route origin validation as described in RFC 6811
+    pub fn validate(&self, net: &IpNet, origin_asn: Option<u32>) -> RpkiStatus {
+        let mut covered = false;
+        for (_, vrps) in self.table.matches(net) {
+            for vrp in vrps {
+                covered = true;
+                if Some(vrp.asn) == origin_asn && vrp.asn != 0 && net.prefix_len() <= vrp.max_length
+                {
+                    return RpkiStatus::Valid;
+                }
+            }
+        }
+        if covered {
+            RpkiStatus::Invalid
+        } else {
+            RpkiStatus::NotFound
+        }
+    }
+}
+
+/// Shared handle to the most recently loaded VRPs
+#[derive(Clone, Default)]
+pub struct Validator(Arc<RwLock<Option<Arc<Vrps>>>>);
+
+impl Validator {
+    /// The current VRPs, None if RPKI is not configured or nothing was loaded yet
+    pub fn current(&self) -> Option<Arc<Vrps>> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn replace(&self, vrps: Vrps) {
+        crate::metrics::RPKI_VRPS.set(vrps.count as i64);
+        *self.0.write().unwrap() = Some(Arc::new(vrps));
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonAsn {
+    Number(u32),
+    /// routinator writes `AS65000`
+    Text(String),
+}
+
+#[derive(Deserialize)]
+struct JsonRoa {
+    asn: JsonAsn,
+    prefix: IpNet,
+    #[serde(rename = "maxLength")]
+    max_length: u8,
+}
+
+#[derive(Deserialize)]
+struct JsonExport {
+    roas: Vec<JsonRoa>,
+}
+
+async fn load_json(path: &str) -> anyhow::Result<Vrps> {
+    let path = path.to_string();
+    let export: JsonExport =
+        serde_json::from_slice(&tokio::task::spawn_blocking(move || std::fs::read(path)).await??)?;
+
+    let mut vrps = Vrps::default();
+    for roa in export.roas {
+        let asn = match roa.asn {
+            JsonAsn::Number(asn) => asn,
+            JsonAsn::Text(text) => text.trim_start_matches("AS").parse()?,
+        };
+        vrps.insert(roa.prefix.trunc(), roa.max_length, asn);
+    }
+    Ok(vrps)
+}
+
+const RTR_CACHE_RESPONSE: u8 = 3;
+const RTR_IPV4_PREFIX: u8 = 4;
+const RTR_IPV6_PREFIX: u8 = 6;
+const RTR_END_OF_DATA: u8 = 7;
+const RTR_CACHE_RESET: u8 = 8;
+const RTR_ERROR_REPORT: u8 = 10;
+const RTR_UNSUPPORTED_VERSION: u16 = 4;
+
+/// Fetch the full set of VRPs using a Reset Query. Returns None if the server does not support
+/// the protocol version.
+async fn load_rtr_version(address: &str, version: u8) -> anyhow::Result<Option<Vrps>> {
+    let mut stream = TcpStream::connect(address).await?;
+    stream.write_all(&[version, 2, 0, 0, 0, 0, 0, 8]).await?;
+
+    let mut vrps = Vrps::default();
+    loop {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await?;
+        let pdu_type = header[1];
+        let session_or_code = u16::from_be_bytes([header[2], header[3]]);
+        let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if !(8..=65536).contains(&length) {
+            anyhow::bail!("invalid RTR PDU length {}", length);
+        }
+        let mut body = vec![0u8; length - 8];
+        stream.read_exact(&mut body).await?;
+
+        match pdu_type {
+            RTR_CACHE_RESPONSE => {}
+            // withdrawals can not occur in response to a Reset Query
+            RTR_IPV4_PREFIX if body.len() == 12 && body[0] & 1 == 1 => {
+                let addr = Ipv4Addr::new(body[4], body[5], body[6], body[7]);
+                let asn = u32::from_be_bytes([body[8], body[9], body[10], body[11]]);
+                vrps.insert(Ipv4Net::new(addr, body[1])?.trunc().into(), body[2], asn);
+            }
+            RTR_IPV6_PREFIX if body.len() == 24 && body[0] & 1 == 1 => {
+                let addr = Ipv6Addr::from(<[u8; 16]>::try_from(&body[4..20])?);
+                let asn = u32::from_be_bytes([body[20], body[21], body[22], body[23]]);
+                vrps.insert(Ipv6Net::new(addr, body[1])?.trunc().into(), body[2], asn);
+            }
+            RTR_END_OF_DATA => return Ok(Some(vrps)),
+            RTR_CACHE_RESET => anyhow::bail!("RTR server has no data available"),
+            RTR_ERROR_REPORT if session_or_code == RTR_UNSUPPORTED_VERSION => return Ok(None),
+            RTR_ERROR_REPORT => anyhow::bail!("RTR server reported error {}", session_or_code),
+            // router keys and unknown PDUs are not needed for origin validation
+            _ => {}
+        }
+    }
+}
+
+async fn load_rtr(address: &str) -> anyhow::Result<Vrps> {
+    if let Some(vrps) = load_rtr_version(address, 1).await? {
+        return Ok(vrps);
+    }
+    load_rtr_version(address, 0).await?.ok_or(anyhow::anyhow!(
+        "RTR server supports neither version 1 nor 0"
+    ))
+}
+
+pub async fn run(
+    cfg: RpkiConfig,
+    validator: Validator,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let (RpkiConfig::Json {
+        refresh_interval, ..
+    }
+    | RpkiConfig::Rtr {
+        refresh_interval, ..
+    }) = cfg;
+    let mut interval = tokio::time::interval(Duration::from_secs(refresh_interval.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let res = match &cfg {
+                    RpkiConfig::Json { path, .. } => load_json(path).await,
+                    RpkiConfig::Rtr { address, .. } => load_rtr(address).await,
+                };
+                match res {
+                    Ok(vrps) => {
+                        debug!("loaded {} VRPs", vrps.count);
+                        validator.replace(vrps);
+                    }
+                    Err(e) => warn!("failed to load VRPs: {}", e),
+                }
+            }
+            _ = shutdown.changed() => break Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut vrps = Vrps::default();
+        vrps.insert("192.0.2.0/24".parse().unwrap(), 24, 64500);
+        vrps.insert("2001:db8::/32".parse().unwrap(), 48, 64501);
+
+        let validate = |net: &str, asn| vrps.validate(&net.parse().unwrap(), Some(asn));
+        assert_eq!(validate("192.0.2.0/24", 64500), RpkiStatus::Valid);
+        assert_eq!(validate("192.0.2.0/24", 64501), RpkiStatus::Invalid);
+        assert_eq!(validate("192.0.2.0/25", 64500), RpkiStatus::Invalid);
+        assert_eq!(validate("198.51.100.0/24", 64500), RpkiStatus::NotFound);
+        assert_eq!(validate("2001:db8:1::/48", 64501), RpkiStatus::Valid);
+    }
+}
@@ -0,0 +1,375 @@
+//! Query results rendered like the "show route" output of routers, for pasting into tickets
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::ext_community::ExtCommunity;
+use crate::route_distinguisher::RouteDistinguisher;
+use crate::rpki::RpkiStatus;
+use crate::store::{QueryResult, RouteAttrs, RouteOrigin, RouteState};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextStyle {
+    /// Like `show bgp <prefix>` of Cisco IOS XR
+    Cisco,
+    /// Like `show route <prefix> detail` of Junos
+    Junos,
+}
+
+fn origin_code(origin: &Option<RouteOrigin>) -> &'static str {
+    match origin {
+        Some(RouteOrigin::Igp) => "I",
+        Some(RouteOrigin::Egp) => "E",
+        Some(RouteOrigin::Incomplete) | None => "?",
+    }
+}
+
+fn origin_name(origin: &Option<RouteOrigin>) -> &'static str {
+    match origin {
+        Some(RouteOrigin::Igp) => "IGP",
+        Some(RouteOrigin::Egp) => "EGP",
+        Some(RouteOrigin::Incomplete) | None => "incomplete",
+    }
+}
+
+fn rpki_name(status: &RpkiStatus) -> &'static str {
+    match status {
+        RpkiStatus::Valid => "valid",
+        RpkiStatus::Invalid => "invalid",
+        RpkiStatus::NotFound => "not-found",
+    }
+}
+
+/// Origin validation states by prefix and origin AS, from the RPKI enrichment of the results
+pub type RpkiStates = HashMap<(IpNet, Option<u32>), RpkiStatus>;
+
+fn rpki_status<'a>(rpki: &'a RpkiStates, route: &QueryResult) -> Option<&'a RpkiStatus> {
+    let origin_asn = route
+        .attrs
+        .as_path
+        .as_ref()
+        .and_then(|as_path| as_path.origin_asn());
+    rpki.get(&(route.net, origin_asn))
+}
+
+fn as_path_text(attrs: &RouteAttrs) -> Option<String> {
+    attrs
+        .as_path
+        .as_ref()
+        .filter(|as_path| !as_path.is_empty())
+        .map(|as_path| as_path.to_string())
+}
+
+fn communities_text(attrs: &RouteAttrs) -> Option<String> {
+    attrs
+        .communities
+        .as_ref()
+        .filter(|communities| !communities.is_empty())
+        .map(|communities| {
+            communities
+                .iter()
+                .map(|(a, b)| format!("{}:{}", a, b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+}
+
+fn large_communities_text(attrs: &RouteAttrs) -> Option<String> {
+    attrs
+        .large_communities
+        .as_ref()
+        .filter(|communities| !communities.is_empty())
+        .map(|communities| {
+            communities
+                .iter()
+                .map(|(a, b, c)| format!("{}:{}:{}", a, b, c))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+}
+
+fn ext_communities_text(attrs: &RouteAttrs, style: TextStyle) -> Option<String> {
+    let community_text = |community: &ExtCommunity| match (style, community) {
+        (TextStyle::Junos, community) => community.to_string(),
+        (TextStyle::Cisco, ExtCommunity::RouteTarget(admin)) => format!("RT:{}", admin),
+        (TextStyle::Cisco, ExtCommunity::RouteOrigin(admin)) => format!("SoO:{}", admin),
+        (TextStyle::Cisco, ExtCommunity::LinkBandwidth { asn, bandwidth }) => {
+            format!("LB:{}:{}", asn, bandwidth)
+        }
+        (TextStyle::Cisco, ExtCommunity::Color { color, .. }) => format!("Color:{}", color),
+        (TextStyle::Cisco, community) => community.to_string(),
+    };
+    attrs
+        .ext_communities
+        .as_ref()
+        .filter(|communities| !communities.is_empty())
+        .map(|communities| {
+            communities
+                .iter()
+                .map(community_text)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+}
+
+fn cluster_list_text(attrs: &RouteAttrs, separator: &str) -> Option<String> {
+    attrs.cluster_list.as_ref().map(|cluster_list| {
+        cluster_list
+            .iter()
+            .map(|cluster_id| cluster_id.to_string())
+            .collect::<Vec<_>>()
+            .join(separator)
+    })
+}
+
+/// The label stack, outermost label first
+fn labels_text(attrs: &RouteAttrs) -> Option<String> {
+    attrs
+        .labels
+        .as_ref()
+        .filter(|labels| !labels.is_empty())
+        .map(|labels| {
+            labels
+                .iter()
+                .map(|label| label.to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+}
+
+fn render_cisco(
+    out: &mut String,
+    rd: &RouteDistinguisher,
+    net: &IpNet,
+    routes: &[&QueryResult],
+    rpki: &RpkiStates,
+) {
+    if rd.is_default() {
+        writeln!(out, "BGP routing table entry for {}", net).unwrap();
+    } else {
+        writeln!(
+            out,
+            "BGP routing table entry for {}, Route Distinguisher: {}",
+            net, rd
+        )
+        .unwrap();
+    }
+    writeln!(out, "Paths: ({} available)", routes.len()).unwrap();
+    for route in routes {
+        let attrs = &route.attrs;
+        writeln!(
+            out,
+            "  {}",
+            as_path_text(attrs).unwrap_or("Local".to_string())
+        )
+        .unwrap();
+        let nexthop = attrs
+            .nexthop
+            .map(|nexthop| nexthop.to_string())
+            .unwrap_or("0.0.0.0".to_string());
+        writeln!(
+            out,
+            "    {} from {} ({}, {})",
+            nexthop,
+            route.table.session_id.peer_address,
+            route.client.client_name,
+            route.table.table_type.name()
+        )
+        .unwrap();
+
+        let mut flags = vec![format!("Origin {}", origin_name(&attrs.origin))];
+        if let Some(med) = attrs.med {
+            flags.push(format!("metric {}", med));
+        }
+        if let Some(local_pref) = attrs.local_pref {
+            flags.push(format!("localpref {}", local_pref));
+        }
+        flags.push(
+            match route.state {
+                RouteState::Seen => "rejected",
+                RouteState::Accepted => "valid",
+                RouteState::Active => "valid, multipath",
+                RouteState::Selected => "valid, best",
+            }
+            .to_string(),
+        );
+        if route.stale {
+            flags.push("stale".to_string());
+        }
+        if attrs.atomic_aggregate {
+            flags.push("atomic-aggregate".to_string());
+        }
+        writeln!(out, "      {}", flags.join(", ")).unwrap();
+        if let Some(aggregator) = &attrs.aggregator {
+            writeln!(
+                out,
+                "      Aggregated by {} {}",
+                aggregator.asn, aggregator.address
+            )
+            .unwrap();
+        }
+        if let Some(originator_id) = &attrs.originator_id {
+            writeln!(
+                out,
+                "      Originator: {}, Cluster list: {}",
+                originator_id,
+                cluster_list_text(attrs, ", ").unwrap_or_default()
+            )
+            .unwrap();
+        }
+        if let Some(communities) = communities_text(attrs) {
+            writeln!(out, "      Community: {}", communities).unwrap();
+        }
+        if let Some(communities) = large_communities_text(attrs) {
+            writeln!(out, "      Large Community: {}", communities).unwrap();
+        }
+        if let Some(communities) = ext_communities_text(attrs, TextStyle::Cisco) {
+            writeln!(out, "      Extended community: {}", communities).unwrap();
+        }
+        if let Some(rpki_status) = rpki_status(rpki, route) {
+            writeln!(out, "      Origin-AS validity: {}", rpki_name(rpki_status)).unwrap();
+        }
+        if let Some(labels) = labels_text(attrs) {
+            writeln!(out, "      mpls labels in/out nolabel/{}", labels).unwrap();
+        }
+    }
+}
+
+fn render_junos(
+    out: &mut String,
+    rd: &RouteDistinguisher,
+    net: &IpNet,
+    routes: &[&QueryResult],
+    rpki: &RpkiStates,
+) {
+    let announced = routes
+        .iter()
+        .filter(|route| route.state == RouteState::Selected)
+        .count();
+    let net = match rd.is_default() {
+        true => net.to_string(),
+        false => format!("{}:{}", rd, net),
+    };
+    writeln!(
+        out,
+        "{} ({} entries, {} announced)",
+        net,
+        routes.len(),
+        announced
+    )
+    .unwrap();
+    for route in routes {
+        let attrs = &route.attrs;
+        let marker = match route.state {
+            RouteState::Selected => "*",
+            _ => " ",
+        };
+        writeln!(
+            out,
+            "       {}BGP    Peer: {} ({}, {})",
+            marker,
+            route.table.session_id.peer_address,
+            route.client.client_name,
+            route.table.table_type.name()
+        )
+        .unwrap();
+        match (attrs.nexthop, route.nexthop_resolved_via) {
+            (Some(nexthop), Some(via)) => {
+                writeln!(out, "                Next hop: {} via {}", nexthop, via).unwrap()
+            }
+            (Some(nexthop), None) => {
+                writeln!(out, "                Next hop: {}", nexthop).unwrap()
+            }
+            (None, _) => {}
+        }
+        if let Some(labels) = labels_text(attrs) {
+            writeln!(out, "                Route Label: {}", labels).unwrap();
+        }
+        let state = match route.state {
+            RouteState::Seen => "Hidden",
+            RouteState::Accepted => "NotBest",
+            RouteState::Active => "Active Multipath",
+            RouteState::Selected => "Active",
+        };
+        let stale = if route.stale { " Stale" } else { "" };
+        writeln!(out, "                State: <{}{}>", state, stale).unwrap();
+        writeln!(
+            out,
+            "                AS path: {} {}",
+            as_path_text(attrs).unwrap_or_default(),
+            origin_code(&attrs.origin)
+        )
+        .unwrap();
+        if let Some(rpki_status) = rpki_status(rpki, route) {
+            writeln!(
+                out,
+                "                Validation State: {}",
+                rpki_name(rpki_status)
+            )
+            .unwrap();
+        }
+        let communities = [
+            communities_text(attrs),
+            large_communities_text(attrs),
+            ext_communities_text(attrs, TextStyle::Junos),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if !communities.is_empty() {
+            writeln!(
+                out,
+                "                Communities: {}",
+                communities.join(" ")
+            )
+            .unwrap();
+        }
+        if let Some(local_pref) = attrs.local_pref {
+            writeln!(out, "                Localpref: {}", local_pref).unwrap();
+        }
+        if let Some(med) = attrs.med {
+            writeln!(out, "                Metric: {}", med).unwrap();
+        }
+        if let Some(aggregator) = &attrs.aggregator {
+            writeln!(
+                out,
+                "                Aggregator: {} {}",
+                aggregator.asn, aggregator.address
+            )
+            .unwrap();
+        }
+        if attrs.atomic_aggregate {
+            writeln!(out, "                AtomicAggregate").unwrap();
+        }
+        if let Some(originator_id) = &attrs.originator_id {
+            writeln!(out, "                Originator ID: {}", originator_id).unwrap();
+        }
+        if let Some(cluster_list) = cluster_list_text(attrs, " ") {
+            writeln!(out, "                Cluster list:  {}", cluster_list).unwrap();
+        }
+    }
+}
+
+/// Render the results grouped by prefix, in the order of the prefixes
+pub fn render(style: TextStyle, results: &[QueryResult], rpki: &RpkiStates) -> String {
+    let mut nets: BTreeMap<(String, IpNet), Vec<&QueryResult>> = BTreeMap::new();
+    for result in results {
+        nets.entry((result.table.route_distinguisher.to_string(), result.net))
+            .or_default()
+            .push(result);
+    }
+
+    let mut out = String::new();
+    for ((_, net), routes) in nets {
+        let rd = routes[0].table.route_distinguisher;
+        match style {
+            TextStyle::Cisco => render_cisco(&mut out, &rd, &net, &routes, rpki),
+            TextStyle::Junos => render_junos(&mut out, &rd, &net, &routes, rpki),
+        }
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,147 @@
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use crate::store::PrefixFlaps;
+
+/// Granularity of the counters, in seconds
+const BUCKET_SECONDS: u64 = 60;
+
+#[derive(Debug, Default)]
+struct Bucket {
+    /// Start of the bucket, as unix timestamp in seconds
+    start: u64,
+    updates: u64,
+    withdraws: u64,
+}
+
+#[derive(Debug, Default)]
+struct PrefixChurn {
+    buckets: VecDeque<Bucket>,
+    last_change: u64,
+}
+
+/// Counts of the updates and withdraws of each prefix, across all tables, within the retention
+/// time. The counts are kept in one minute buckets, so the memory per prefix is bounded.
+#[derive(Debug)]
+pub struct FlapStats {
+    prefixes: HashMap<IpNet, PrefixChurn>,
+    retention: u64,
+    last_sweep: u64,
+}
+
+impl FlapStats {
+    pub fn new(retention: u64) -> Self {
+        Self {
+            prefixes: HashMap::new(),
+            retention,
+            last_sweep: 0,
+        }
+    }
+
+    pub fn record(&mut self, net: IpNet, withdraw: bool, now: u64) {
+        if self.retention == 0 {
+            return;
+        }
+        // prefixes which did not change for a while are only dropped here, no need to do it on
+        // every change
+        if now >= self.last_sweep + self.retention {
+            self.sweep(now);
+        }
+
+        let start = now - now % BUCKET_SECONDS;
+        let churn = self.prefixes.entry(net).or_default();
+        if churn.buckets.back().map(|bucket| bucket.start) != Some(start) {
+            churn.buckets.push_back(Bucket {
+                start,
+                ..Default::default()
+            });
+        }
+        let bucket = churn.buckets.back_mut().unwrap();
+        if withdraw {
+            bucket.withdraws += 1;
+        } else {
+            bucket.updates += 1;
+        }
+        churn.last_change = now;
+        Self::expire(churn, now.saturating_sub(self.retention));
+    }
+
+    fn expire(churn: &mut PrefixChurn, since: u64) {
+        while churn
+            .buckets
+            .front()
+            .map(|bucket| bucket.start + BUCKET_SECONDS <= since)
+            .unwrap_or(false)
+        {
+            churn.buckets.pop_front();
+        }
+    }
+
+    fn sweep(&mut self, now: u64) {
+        let since = now.saturating_sub(self.retention);
+        self.prefixes.retain(|_, churn| {
+            Self::expire(churn, since);
+            !churn.buckets.is_empty()
+        });
+        self.last_sweep = now;
+    }
+
+    /// The prefixes with the most changes within the last `window` seconds (at most the retention
+    /// time), most changes first
+    pub fn top(&self, window: u64, limit: usize, now: u64) -> Vec<PrefixFlaps> {
+        let since = now.saturating_sub(window.min(self.retention));
+        let mut flaps = self
+            .prefixes
+            .iter()
+            .filter_map(|(net, churn)| {
+                let (updates, withdraws) = churn
+                    .buckets
+                    .iter()
+                    .filter(|bucket| bucket.start + BUCKET_SECONDS > since)
+                    .fold((0, 0), |(updates, withdraws), bucket| {
+                        (updates + bucket.updates, withdraws + bucket.withdraws)
+                    });
+                (updates + withdraws > 0).then_some(PrefixFlaps {
+                    net: *net,
+                    updates,
+                    withdraws,
+                    last_change: churn.last_change,
+                })
+            })
+            .collect::<Vec<_>>();
+        flaps.sort_by_key(|flaps| std::cmp::Reverse(flaps.updates + flaps.withdraws));
+        flaps.truncate(limit);
+        flaps
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn count_within_window() {
+        let a: IpNet = "192.0.2.0/24".parse().unwrap();
+        let b: IpNet = "198.51.100.0/24".parse().unwrap();
+        let mut stats = FlapStats::new(3600);
+        stats.record(a, false, 1000);
+        stats.record(a, true, 1010);
+        stats.record(a, false, 3000);
+        stats.record(b, false, 3000);
+
+        let top = stats.top(3600, 10, 3000);
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].net, top[0].updates, top[0].withdraws), (a, 2, 1));
+
+        let top = stats.top(60, 10, 3000);
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].updates, top[0].withdraws), (1, 0));
+
+        let top = stats.top(3600, 10, 5000);
+        assert_eq!((top[0].updates, top[0].withdraws), (1, 0));
+
+        stats.record(b, false, 7000);
+        assert_eq!(stats.top(3600, 10, 7000).len(), 1);
+    }
+}
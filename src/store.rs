@@ -2,17 +2,30 @@ use async_trait::async_trait;
 use futures_util::Stream;
 use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use log::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::pin::Pin;
+use std::time::SystemTime;
 use zettabgp::prelude::{BgpAddrV4, BgpAddrV6};
 
-use crate::route_distinguisher::RouteDistinguisher;
+use crate::anomalies::Anomaly;
+use crate::as_path::{AsPath, AsPathMatchType};
+use crate::community_filter::CommunityFilter;
+use crate::ext_community::ExtCommunity;
+use crate::flowspec::{
+    bitmask_condition, numeric_condition, BitmaskOp, FlowspecComponent, FlowspecComponentType,
+    FlowspecQuery, FlowspecResult, FlowspecRule, NumericOp,
+};
+use crate::linkstate::{LinkStateUpdate, Topology};
+use crate::route_distinguisher::{RouteDistinguisher, RouteDistinguishers};
 
 pub type PathId = u32;
 pub type RouterId = Ipv4Addr;
 
+/// Changes of the routes, see `Store::get_changes_since`
+pub type RouteChangeStream = Pin<Box<dyn Stream<Item = RouteChange> + Send>>;
+
 #[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
 pub enum RouteOrigin {
     Igp,
@@ -20,15 +33,63 @@ pub enum RouteOrigin {
     Incomplete,
 }
 
+/// AS and BGP ID of the speaker which formed an aggregate route
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Hash)]
+pub struct Aggregator {
+    pub asn: u32,
+    pub address: Ipv4Addr,
+}
+
+fn serialize_hex<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    serializer.serialize_str(&hex)
+}
+
+/// A path attribute fernglas does not decode, kept as received
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
+pub struct UnknownAttr {
+    pub type_code: u8,
+    pub flags: u8,
+    #[serde(serialize_with = "serialize_hex")]
+    pub value: Vec<u8>,
+}
+
 #[derive(Debug, Clone, Serialize, Default)]
 pub struct RouteAttrs {
     pub origin: Option<RouteOrigin>,
-    pub as_path: Option<Vec<u32>>,
+    pub as_path: Option<AsPath>,
     pub communities: Option<Vec<(u16, u16)>>,
     pub large_communities: Option<Vec<(u32, u32, u32)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ext_communities: Option<Vec<ExtCommunity>>,
     pub med: Option<u32>,
     pub local_pref: Option<u32>,
     pub nexthop: Option<IpAddr>,
+    /// Link-local nexthop sent alongside a global IPv6 nexthop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nexthop_link_local: Option<Ipv6Addr>,
+    /// MPLS label stack of labeled unicast and VPN routes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregator: Option<Aggregator>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub atomic_aggregate: bool,
+    /// BGP ID of the router which originated a reflected route (RFC 4456)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub originator_id: Option<Ipv4Addr>,
+    /// Cluster IDs of the route reflectors the route passed, the most recent first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster_list: Option<Vec<Ipv4Addr>>,
+    /// Attributes which are not decoded, in the order they were received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unknown_attrs: Option<Vec<UnknownAttr>>,
+    /// Attributes exceeded the configured limits and were cut off
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
@@ -38,7 +99,7 @@ pub struct SessionId {
     pub peer_address: IpAddr,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum RouteState {
     /// The route has been received from a neighbor, but was rejected in a filter
     Seen,
@@ -55,6 +116,12 @@ pub enum RouteState {
 pub enum TableType {
     PrePolicyAdjIn,
     PostPolicyAdjIn,
+    /// Routes a router advertises to the peer, before the export policy is applied (RFC 8671)
+    PrePolicyAdjOut,
+    /// Routes a router advertises to the peer, after the export policy is applied (RFC 8671)
+    PostPolicyAdjOut,
+    /// Updates the router mirrored verbatim as received from the peer (RFC 7854 section 4.7)
+    Mirrored,
     LocRib {
         #[serde(skip_serializing)]
         route_state: RouteState,
@@ -66,13 +133,44 @@ impl Serialize for TableType {
     where
         S: serde::Serializer,
     {
-        let table_type = match self {
-            TableType::PrePolicyAdjIn => "PrePolicyAdjIn",
-            TableType::PostPolicyAdjIn => "PostPolicyAdjIn",
-            TableType::LocRib { .. } => "LocRib",
-        };
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// Address family and subsequent address family of the NLRI stored in a table
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum AfiSafi {
+    #[default]
+    Ipv4Unicast,
+    Ipv6Unicast,
+    Ipv4MplsVpn,
+    Ipv6MplsVpn,
+    /// Flowspec rules are kept apart from the routes, see [`Store::get_flowspec`]
+    Ipv4Flowspec,
+    Ipv6Flowspec,
+}
 
-        serializer.serialize_str(table_type)
+impl AfiSafi {
+    pub fn afi(&self) -> &'static str {
+        match self {
+            AfiSafi::Ipv4Unicast | AfiSafi::Ipv4MplsVpn | AfiSafi::Ipv4Flowspec => "ipv4",
+            AfiSafi::Ipv6Unicast | AfiSafi::Ipv6MplsVpn | AfiSafi::Ipv6Flowspec => "ipv6",
+        }
+    }
+    pub fn safi(&self) -> &'static str {
+        match self {
+            AfiSafi::Ipv4Unicast | AfiSafi::Ipv6Unicast => "unicast",
+            AfiSafi::Ipv4MplsVpn | AfiSafi::Ipv6MplsVpn => "mpls-vpn",
+            AfiSafi::Ipv4Flowspec | AfiSafi::Ipv6Flowspec => "flowspec",
+        }
+    }
+    /// Whether prefixes of the same address family as `net` are stored in tables of this kind
+    pub fn contains_family_of(&self, net: &IpNet) -> bool {
+        matches!(
+            (self, net),
+            (AfiSafi::Ipv4Unicast | AfiSafi::Ipv4MplsVpn, IpNet::V4(_))
+                | (AfiSafi::Ipv6Unicast | AfiSafi::Ipv6MplsVpn, IpNet::V6(_))
+        )
     }
 }
 
@@ -85,6 +183,20 @@ pub struct TableSelector {
     pub session_id: SessionId,
     #[serde(rename = "type")]
     pub table_type: TableType,
+    pub afi_safi: AfiSafi,
+}
+
+impl TableType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TableType::PrePolicyAdjIn => "PrePolicyAdjIn",
+            TableType::PostPolicyAdjIn => "PostPolicyAdjIn",
+            TableType::PrePolicyAdjOut => "PrePolicyAdjOut",
+            TableType::PostPolicyAdjOut => "PostPolicyAdjOut",
+            TableType::Mirrored => "Mirrored",
+            TableType::LocRib { .. } => "LocRib",
+        }
+    }
 }
 
 impl TableSelector {
@@ -101,7 +213,9 @@ impl TableSelector {
         match self.table_type {
             TableType::LocRib { route_state, .. } => route_state,
             TableType::PostPolicyAdjIn => RouteState::Accepted,
-            TableType::PrePolicyAdjIn => RouteState::Seen,
+            TableType::PrePolicyAdjIn | TableType::Mirrored => RouteState::Seen,
+            // only selected routes are advertised
+            TableType::PrePolicyAdjOut | TableType::PostPolicyAdjOut => RouteState::Selected,
         }
     }
 }
@@ -123,6 +237,17 @@ pub enum NetQuery<T = IpNet> {
     OrLonger(T),
 }
 
+impl<T> NetQuery<T> {
+    pub fn net(&self) -> &T {
+        match self {
+            NetQuery::Contains(net)
+            | NetQuery::MostSpecific(net)
+            | NetQuery::Exact(net)
+            | NetQuery::OrLonger(net) => net,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Query<T = IpNet> {
@@ -133,8 +258,218 @@ pub struct Query<T = IpNet> {
     pub limits: Option<QueryLimits>,
     #[serde(default)]
     pub as_path_regex: Option<String>,
+    /// How `as_path_regex` is matched against the AS path
+    #[serde(default)]
+    pub as_path_match: AsPathMatchType,
     #[serde(default)]
     pub route_distinguisher: RouteDistinguisher,
+    /// Search the tables of all of these route distinguishers instead of `route_distinguisher`
+    #[serde(default)]
+    pub route_distinguishers: Option<RouteDistinguishers>,
+    /// Also return routes which are retained as stale after a session restart or while the router
+    /// sends its table again. Included by default for debugging, the web UI excludes them.
+    #[serde(default = "default_include_stale")]
+    pub include_stale: bool,
+    /// Summarize contiguous prefixes with identical attributes in the same table, only applied
+    /// by the API
+    #[serde(default)]
+    pub aggregate: bool,
+    /// Also query the IPv4 space embedded in 6to4 and NAT64 addresses, only applied by the API
+    #[serde(default)]
+    pub embedded_ipv4: bool,
+    /// Only routes originated by this AS, i.e. with it as the last ASN of the AS path
+    #[serde(default)]
+    pub origin_asn: Option<u32>,
+    /// Only routes announced with their current attributes at least this many seconds ago
+    #[serde(default)]
+    pub min_age: Option<u64>,
+    /// Only routes announced with their current attributes at most this many seconds ago
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// Only routes with a nexthop within this prefix, a single address matches exactly
+    #[serde(default, deserialize_with = "deserialize_addr_or_net")]
+    pub nexthop: Option<IpNet>,
+    /// Return the results in a stable order with a cursor each, so the query can be resumed after
+    /// the last result with `after`
+    #[serde(default)]
+    pub paginate: bool,
+    /// Only results following this cursor, implies `paginate`
+    #[serde(default)]
+    pub after: Option<Cursor>,
+    /// Unix timestamp in seconds, return the routes as they were at this time. Only available if
+    /// the store keeps a history.
+    #[serde(default)]
+    pub at: Option<u64>,
+    /// Order of the results, ignored for paginated queries which are always ordered by table
+    #[serde(default)]
+    pub sort: Option<SortOrder>,
+    /// Only routes carrying all of these communities
+    #[serde(default)]
+    pub communities: Option<CommunityFilter>,
+    #[serde(default)]
+    pub large_communities: Option<CommunityFilter>,
+    #[serde(default)]
+    pub ext_communities: Option<CommunityFilter>,
+    /// How communities are serialized in the response
+    #[serde(default)]
+    pub community_format: CommunityFormat,
+    /// Tables searched if no `table_query` is given, the API defaults to its configured scope
+    #[serde(default)]
+    pub table_scope: Option<TableScope>,
+    /// Only routes with an ASN repeated at least this many times in a row in the AS path
+    #[serde(default)]
+    pub min_prepend_count: Option<usize>,
+    /// Show repeated ASNs of the AS path once with their count, only applied by the API
+    #[serde(default)]
+    pub collapse_prepends: bool,
+}
+
+fn deserialize_addr_or_net<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<IpNet>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| {
+            s.parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                .map_err(serde::de::Error::custom)
+        })
+        .transpose()
+}
+
+pub(crate) fn default_include_stale() -> bool {
+    true
+}
+
+impl<T> Query<T> {
+    /// Query all tables for `net_query`, with the default limits and no further filters
+    pub fn new(net_query: NetQuery<T>) -> Self {
+        Self {
+            table_query: None,
+            net_query,
+            limits: None,
+            as_path_regex: None,
+            as_path_match: Default::default(),
+            route_distinguisher: Default::default(),
+            route_distinguishers: None,
+            include_stale: false,
+            aggregate: false,
+            embedded_ipv4: false,
+            origin_asn: None,
+            min_age: None,
+            max_age: None,
+            nexthop: None,
+            paginate: false,
+            after: None,
+            sort: None,
+            at: None,
+            communities: None,
+            large_communities: None,
+            ext_communities: None,
+            community_format: Default::default(),
+            table_scope: None,
+            min_prepend_count: None,
+            collapse_prepends: false,
+        }
+    }
+
+    /// Whether tables of the route distinguisher `rd` are searched
+    pub fn selects_route_distinguisher(&self, rd: &RouteDistinguisher) -> bool {
+        match &self.route_distinguishers {
+            Some(rds) => rds.contains(rd),
+            None => *rd == self.route_distinguisher,
+        }
+    }
+}
+
+/// Types of tables searched by queries which do not select the tables otherwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TableScope {
+    #[default]
+    All,
+    LocRib,
+    PostPolicyAdjIn,
+    PrePolicyAdjIn,
+    /// Pre- and post-policy Adj-RIB-In
+    AdjIn,
+    /// Pre- and post-policy Adj-RIB-Out
+    AdjOut,
+    Mirrored,
+}
+
+impl TableScope {
+    pub fn contains(&self, table_type: &TableType) -> bool {
+        matches!(
+            (self, table_type),
+            (TableScope::All, _)
+                | (TableScope::LocRib, TableType::LocRib { .. })
+                | (TableScope::PostPolicyAdjIn, TableType::PostPolicyAdjIn)
+                | (TableScope::PrePolicyAdjIn, TableType::PrePolicyAdjIn)
+                | (
+                    TableScope::AdjIn,
+                    TableType::PrePolicyAdjIn | TableType::PostPolicyAdjIn
+                )
+                | (
+                    TableScope::AdjOut,
+                    TableType::PrePolicyAdjOut | TableType::PostPolicyAdjOut
+                )
+                | (TableScope::Mirrored, TableType::Mirrored)
+        )
+    }
+}
+
+/// Order of query results. Ties are broken by prefix, then by table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    Prefix,
+    /// Less specific prefixes first
+    PrefixLength,
+    /// Shorter AS paths first
+    AsPathLength,
+    Table,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommunityFormat {
+    /// Lists of numbers, e.g. `[64496, 100]`
+    #[default]
+    List,
+    /// Text as commonly used in router configs, e.g. `"64496:100"` or `"64496:1:2"`
+    String,
+}
+
+/// Position after a result of a paginated query, serialized as opaque token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cursor {
+    pub table: TableSelector,
+    pub net: IpNet,
+    pub path_id: PathId,
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid cursor {:?}", s);
+        if !s.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        crate::persistence::decode_cursor(&bytes).map_err(|_| invalid())
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        crate::persistence::encode_cursor(&cursor)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -150,6 +485,63 @@ pub struct QueryResult {
     pub session: Option<Session>,
     #[serde(flatten)]
     pub attrs: RouteAttrs,
+    /// Equal for results with identical attributes, so paths can be grouped across tables
+    pub attrs_id: String,
+    pub age: RouteAge,
+    /// Position of the result in a paginated query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Cursor>,
+    pub stale: bool,
+    /// The path is the best one of its prefix within the table, see `bestpath`
+    pub best: bool,
+    /// Locally originated route of the router covering the nexthop of a Loc-RIB route, e.g. the
+    /// connected prefix the nexthop is reached through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nexthop_resolved_via: Option<IpNet>,
+}
+
+pub(crate) fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Time since a path was announced with its current attributes. It is computed when the result is
+/// serialized, so results served from the query cache are still accurate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteAge {
+    /// Unix timestamp in seconds
+    pub since: u64,
+}
+
+impl RouteAge {
+    pub fn seconds(&self) -> u64 {
+        unix_timestamp().saturating_sub(self.since)
+    }
+
+    pub fn bucket(&self) -> &'static str {
+        match self.seconds() {
+            0..=59 => "<1m",
+            60..=3599 => "<1h",
+            3600..=86399 => "<1d",
+            86400..=604799 => "<1w",
+            _ => ">1w",
+        }
+    }
+}
+
+impl Serialize for RouteAge {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("RouteAge", 2)?;
+        state.serialize_field("seconds", &self.seconds())?;
+        state.serialize_field("bucket", self.bucket())?;
+        state.end()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -163,11 +555,622 @@ pub struct QueryLimits {
 pub struct Client {
     pub client_name: String,
     pub router_id: RouterId, // Router ID used for LocRib
+    /// Names of the Loc-RIB instances exported via BMP (RFC 9069), by route distinguisher
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub loc_rib_tables: HashMap<RouteDistinguisher, String>,
+    /// sysDescr of the BMP Initiation message, usually the platform and software version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sys_descr: Option<String>,
+    #[serde(flatten)]
+    pub metadata: ClientMetadata,
+}
+
+/// Configured labels and settings of a router, see `client_metadata` of the store
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientMetadata {
+    /// Human readable name, e.g. `core1.fra`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Only routes of these address families are stored, all if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address_families: Option<Vec<AfiSafi>>,
+}
+
+/// Transport level state of the connection of a client
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientConnection {
+    pub remote_port: u16,
+    /// Unix timestamp in seconds
+    pub connected_since: u64,
+    pub bytes_received: u64,
+    pub messages_received: u64,
+    /// The last message of the client which could not be decoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Unix timestamp in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_timestamp: Option<u64>,
+}
+
+impl ClientConnection {
+    pub fn new(client_addr: SocketAddr) -> Self {
+        Self {
+            remote_port: client_addr.port(),
+            connected_since: unix_timestamp(),
+            bytes_received: 0,
+            messages_received: 0,
+            last_error: None,
+            last_error_timestamp: None,
+        }
+    }
+}
+
+/// Number of bytes of an undecodable message which are kept in a [`DecodeError`]
+const DECODE_ERROR_SAMPLE_BYTES: usize = 256;
+
+/// A message of a client which could not be decoded
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodeError {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    /// Peer the message was about, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_address: Option<IpAddr>,
+    pub error: String,
+    /// Length of the message in bytes
+    pub length: usize,
+    /// Hex dump of the start of the message
+    pub sample: String,
+}
+
+impl DecodeError {
+    pub fn new(peer_address: Option<IpAddr>, error: String, message: &[u8]) -> Self {
+        let sample = message
+            .iter()
+            .take(DECODE_ERROR_SAMPLE_BYTES)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Self {
+            timestamp: unix_timestamp(),
+            peer_address,
+            error,
+            length: message.len(),
+            sample,
+        }
+    }
 }
 
 /// information saved about a connected peer
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Session {}
+pub struct Session {
+    /// Capabilities both sides advertised in their OPEN messages
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_asn: Option<u32>,
+    /// BGP identifier of the peer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_router_id: Option<Ipv4Addr>,
+    /// AS the router uses towards the peer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_asn: Option<u32>,
+    /// Address of the router on the BGP connection, if reported via BMP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_address: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_port: Option<u16>,
+    /// Negotiated hold time in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold_time: Option<u16>,
+    /// Description of the peer from the session metadata config, or from the Peer Up message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// AS the peer is expected to have, from the session metadata config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_asn: Option<u32>,
+    /// Internet exchange the session is established over, from the session metadata config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ixp: Option<String>,
+    /// End-of-RIB was received for all address families since the session came up, so its tables
+    /// are complete
+    #[serde(default)]
+    pub synced: bool,
+    /// When and why the session last went down, if it did since fernglas was started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_down: Option<SessionDown>,
+}
+
+/// Why a session went down, as reported in the BMP Peer Down message (RFC 7854 section 4.9)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cause", rename_all = "snake_case")]
+pub enum PeerDownReason {
+    /// The router closed the session and sent a NOTIFICATION to the peer
+    LocalNotification {
+        code: u8,
+        subcode: u8,
+    },
+    /// The router closed the session without NOTIFICATION, because of this FSM event
+    LocalFsmEvent {
+        event: u16,
+    },
+    /// The peer closed the session with a NOTIFICATION
+    RemoteNotification {
+        code: u8,
+        subcode: u8,
+    },
+    /// The peer closed the session without NOTIFICATION
+    RemoteNoNotification,
+    /// The session was removed from the config of the router
+    PeerDeconfigured,
+    /// The Loc-RIB instance was removed (RFC 9069)
+    LocalSystemClosed,
+    /// The connection to the router was lost, the session itself might still be up
+    RouterDisconnected {
+        reason: Option<String>,
+    },
+    Unknown {
+        code: u8,
+    },
+}
+
+/// Name of a NOTIFICATION error code and subcode (RFC 4271 section 4.5, RFC 4486)
+fn notification_name(code: u8, subcode: u8) -> String {
+    let name = match code {
+        1 => "Message Header Error",
+        2 => "OPEN Message Error",
+        3 => "UPDATE Message Error",
+        4 => "Hold Timer Expired",
+        5 => "Finite State Machine Error",
+        6 => "Cease",
+        7 => "ROUTE-REFRESH Message Error",
+        _ => return format!("{}/{}", code, subcode),
+    };
+    let subcode_name = match (code, subcode) {
+        (_, 0) => return name.to_string(),
+        (6, 1) => "Maximum Number of Prefixes Reached",
+        (6, 2) => "Administrative Shutdown",
+        (6, 3) => "Peer De-configured",
+        (6, 4) => "Administrative Reset",
+        (6, 5) => "Connection Rejected",
+        (6, 6) => "Other Configuration Change",
+        (6, 7) => "Connection Collision Resolution",
+        (6, 8) => "Out of Resources",
+        (6, 9) => "Hard Reset",
+        (6, 10) => "BFD Down",
+        _ => return format!("{}/{}", name, subcode),
+    };
+    format!("{}/{}", name, subcode_name)
+}
+
+impl std::fmt::Display for PeerDownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PeerDownReason::LocalNotification { code, subcode } => write!(
+                f,
+                "local notification: {}",
+                notification_name(*code, *subcode)
+            ),
+            PeerDownReason::LocalFsmEvent { event } => write!(f, "local FSM event {}", event),
+            PeerDownReason::RemoteNotification { code, subcode } => write!(
+                f,
+                "remote notification: {}",
+                notification_name(*code, *subcode)
+            ),
+            PeerDownReason::RemoteNoNotification => write!(f, "remote closed without notification"),
+            PeerDownReason::PeerDeconfigured => write!(f, "peer de-configured"),
+            PeerDownReason::LocalSystemClosed => write!(f, "local system closed"),
+            PeerDownReason::RouterDisconnected {
+                reason: Some(reason),
+            } => {
+                write!(f, "router disconnected: {}", reason)
+            }
+            PeerDownReason::RouterDisconnected { reason: None } => write!(f, "router disconnected"),
+            PeerDownReason::Unknown { code } => write!(f, "unknown reason {}", code),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionDown {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<PeerDownReason>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerState {
+    Up,
+    Down,
+}
+
+/// Whether a peer is up, also for peers which went down and are no longer in the sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    #[serde(flatten)]
+    pub session_id: SessionId,
+    pub state: PeerState,
+    /// Unix timestamp in seconds of the last change of the state
+    pub since: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_down: Option<SessionDown>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Capability {
+    AddressFamily(String),
+    FourOctetAs,
+    RouteRefresh,
+    EnhancedRouteRefresh,
+    AddPath,
+    GracefulRestart,
+    LongLivedGracefulRestart,
+    Fqdn,
+    BfdStrictMode,
+    /// Written by earlier versions for capabilities without their own variant, only read from
+    /// snapshots
+    Other(String),
+}
+
+impl From<&zettabgp::BgpCapability> for Capability {
+    fn from(cap: &zettabgp::BgpCapability) -> Self {
+        use zettabgp::BgpCapability;
+        let afi = |name: &str| Capability::AddressFamily(name.to_string());
+        match cap {
+            BgpCapability::SafiIPv4u => afi("IPv4u"),
+            BgpCapability::SafiIPv4m => afi("IPv4m"),
+            BgpCapability::SafiIPv4mvpn => afi("IPv4mvpn"),
+            BgpCapability::SafiIPv4fu => afi("IPv4fu"),
+            BgpCapability::SafiVPNv4u => afi("VPNv4u"),
+            BgpCapability::SafiVPNv4fu => afi("VPNv4fu"),
+            BgpCapability::SafiVPNv4m => afi("VPNv4m"),
+            BgpCapability::SafiIPv4lu => afi("IPv4lu"),
+            BgpCapability::SafiIPv4mdt => afi("IPv4mdt"),
+            BgpCapability::SafiIPv6u => afi("IPv6u"),
+            BgpCapability::SafiIPv6lu => afi("IPv6lu"),
+            BgpCapability::SafiIPv6fu => afi("IPv6fu"),
+            BgpCapability::SafiVPNv6u => afi("VPNv6u"),
+            BgpCapability::SafiVPNv6m => afi("VPNv6m"),
+            BgpCapability::SafiIPv6mdt => afi("IPv6mdt"),
+            BgpCapability::SafiVPLS => afi("VPLS"),
+            BgpCapability::SafiEVPN => afi("EVPN"),
+            BgpCapability::CapGR { .. } => Capability::GracefulRestart,
+            BgpCapability::CapASN32(_) => Capability::FourOctetAs,
+            BgpCapability::CapRR => Capability::RouteRefresh,
+            BgpCapability::CapAddPath(_) => Capability::AddPath,
+            BgpCapability::CapEnhancedRR => Capability::EnhancedRouteRefresh,
+            BgpCapability::CapLLGR(_) => Capability::LongLivedGracefulRestart,
+            BgpCapability::CapFQDN(_, _) => Capability::Fqdn,
+            BgpCapability::CapBFD => Capability::BfdStrictMode,
+        }
+    }
+}
+
+/// Capabilities present in both OPEN messages of a session
+pub fn negotiated_capabilities(
+    sent: &[zettabgp::BgpCapability],
+    received: &[zettabgp::BgpCapability],
+) -> Vec<Capability> {
+    let received = received
+        .iter()
+        .map(Capability::from)
+        .collect::<HashSet<_>>();
+    let mut negotiated = vec![];
+    for cap in sent.iter().map(Capability::from) {
+        if received.contains(&cap) && !negotiated.contains(&cap) {
+            negotiated.push(cap);
+        }
+    }
+    negotiated
+}
+
+/// AS of the sender of an OPEN message, from the four-octet AS capability if present
+pub fn open_message_asn(open: &zettabgp::prelude::BgpOpenMessage) -> u32 {
+    open.caps
+        .iter()
+        .find_map(|cap| match cap {
+            zettabgp::BgpCapability::CapASN32(asn) => Some(*asn),
+            _ => None,
+        })
+        .unwrap_or(open.as_num)
+}
+
+/// The origin AS of a path changed
+#[derive(Debug, Clone, Serialize)]
+pub struct OriginChange {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub net: IpNet,
+    #[serde(flatten)]
+    pub table: TableSelector,
+    pub old_origin_asn: u32,
+    pub new_origin_asn: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableStats {
+    #[serde(flatten)]
+    pub table: TableSelector,
+    pub routes: usize,
+    /// Routes of `routes` which are in the cold tier
+    pub cold_routes: usize,
+    /// Number of distinct origin ASNs
+    pub origin_asns: usize,
+    /// Unix timestamp in seconds of the last update or withdraw
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_update: Option<u64>,
+}
+
+/// How the routes of a table are found for a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TableScan {
+    /// Lookup of the prefixes containing the queried one
+    Lookup,
+    /// Walk of all prefixes within the queried one
+    Subtree,
+    /// Prefixes of the origin AS from the index of the table
+    OriginIndex,
+    /// Like the others, but with the changes since `at` undone
+    History,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TablePlan {
+    #[serde(flatten)]
+    pub table: TableSelector,
+    pub scan: TableScan,
+    /// Routes which are checked against the filters. For a walk of a subtree, this is estimated
+    /// with the route count of the whole table.
+    pub candidate_routes: usize,
+}
+
+/// How a query would be executed
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryPlan {
+    /// Tables which would be scanned, after the table query, route distinguisher and address
+    /// family are applied
+    pub tables: Vec<TablePlan>,
+    /// Filters which are applied to the candidate routes
+    pub filters: Vec<String>,
+    /// Candidate routes of all tables
+    pub estimated_cost: usize,
+    pub limits: QueryLimits,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortOrder>,
+    pub paginate: bool,
+}
+
+/// A path of a prefix was announced, changed its attributes or was withdrawn
+#[derive(Debug, Clone, Serialize)]
+pub struct PrefixChange {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub table: TableSelector,
+    pub path_id: PathId,
+    /// None if the path was announced
+    pub old_as_path: Option<AsPath>,
+    /// None if the path was withdrawn
+    pub new_as_path: Option<AsPath>,
+    pub old_nexthop: Option<IpAddr>,
+    pub new_nexthop: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathDiffKind {
+    Added,
+    Withdrawn,
+    /// The path exists at both points in time, with different attributes
+    Changed,
+}
+
+/// A path which differs between an earlier point in time and now
+#[derive(Debug, Clone, Serialize)]
+pub struct PathDiff {
+    pub change: PathDiffKind,
+    pub net: IpNet,
+    #[serde(flatten)]
+    pub table: TableSelector,
+    pub path_id: PathId,
+    /// Attributes at the earlier point in time, None if the path did not exist
+    pub old: Option<RouteAttrs>,
+    /// Current attributes, None if the path was withdrawn
+    pub new: Option<RouteAttrs>,
+}
+
+/// Number of changes of a prefix within a time window, across all tables
+#[derive(Debug, Clone, Serialize)]
+pub struct PrefixFlaps {
+    pub net: IpNet,
+    pub updates: u64,
+    pub withdraws: u64,
+    /// Unix timestamp in seconds
+    pub last_change: u64,
+}
+
+/// Cumulative statistics of a peer, identified by the router id instead of the address of the
+/// client so they are kept when the router reconnects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStats {
+    pub router_id: RouterId,
+    pub peer_address: IpAddr,
+    pub updates: u64,
+    pub withdraws: u64,
+    /// Number of times the session went down
+    pub flaps: u64,
+    /// Counters of the most recent BMP Statistics Reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bmp_statistics: Option<BmpStatistics>,
+}
+
+/// Routes of a peer at each stage of the route processing of the router, like the summary views
+/// of router CLIs. A stage is only counted if the router exports the corresponding tables.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerFunnel {
+    #[serde(flatten)]
+    pub session_id: SessionId,
+    /// Paths in the pre-policy Adj-RIB-In, may be less than received by the router if the
+    /// pre-policy retention is limited
+    pub received: usize,
+    /// Paths in the post-policy Adj-RIB-In
+    pub accepted: usize,
+    /// Accepted paths which are also in the Loc-RIB of the router, with the same next hop and
+    /// AS path
+    pub installed: usize,
+    /// Installed paths which are the best path for their prefix
+    pub selected: usize,
+}
+
+/// Progress of the initial table dump of a session, compared to the size of its tables the last
+/// time it was complete
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncProgress {
+    /// Routes currently stored for the session
+    pub routes: usize,
+    /// Routes stored for the session after the previous complete dump, or as reported in BMP
+    /// Statistics Reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_routes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percentage: Option<f64>,
+    /// End-of-RIB was received for all address families
+    pub complete: bool,
+}
+
+/// Counters maintained by the router and sent in BMP Statistics Reports. Each report may only
+/// contain some of them, so the last reported value of each counter is kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BmpStatistics {
+    /// Unix timestamp in seconds of the last report
+    pub timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected_prefixes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_prefix_advertisements: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_withdraws: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_updates: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updates_treated_as_withdraw: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adj_rib_in_routes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loc_rib_routes: Option<u64>,
+}
+
+impl BmpStatistics {
+    /// Replace the counters contained in a newer report
+    pub fn merge(&mut self, newer: BmpStatistics) {
+        self.timestamp = newer.timestamp;
+        self.rejected_prefixes = newer.rejected_prefixes.or(self.rejected_prefixes);
+        self.duplicate_prefix_advertisements = newer
+            .duplicate_prefix_advertisements
+            .or(self.duplicate_prefix_advertisements);
+        self.duplicate_withdraws = newer.duplicate_withdraws.or(self.duplicate_withdraws);
+        self.duplicate_updates = newer.duplicate_updates.or(self.duplicate_updates);
+        self.updates_treated_as_withdraw = newer
+            .updates_treated_as_withdraw
+            .or(self.updates_treated_as_withdraw);
+        self.adj_rib_in_routes = newer.adj_rib_in_routes.or(self.adj_rib_in_routes);
+        self.loc_rib_routes = newer.loc_rib_routes.or(self.loc_rib_routes);
+    }
+}
+
+/// Sessions of a router which went down, correlated into a single event if they went down within a
+/// short time of each other
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionDownEvent {
+    /// Unix timestamp in seconds of the first session going down
+    pub timestamp: u64,
+    pub from_client: SocketAddr,
+    /// Multiple sessions went down together or the connection to the router was lost, so the
+    /// cause is likely the router instead of the individual peers
+    pub router_level: bool,
+    pub peers: Vec<IpAddr>,
+}
+
+/// A change of the state of a router connection or session, as exported to external consumers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    RouterConnected {
+        /// Unix timestamp in seconds
+        timestamp: u64,
+        from_client: SocketAddr,
+        client_name: String,
+        router_id: RouterId,
+    },
+    RouterDisconnected {
+        timestamp: u64,
+        from_client: SocketAddr,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Also sent when a router announces a session again which is already up
+    PeerUp {
+        timestamp: u64,
+        #[serde(flatten)]
+        session: SessionId,
+    },
+    PeerDown {
+        timestamp: u64,
+        #[serde(flatten)]
+        session: SessionId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+}
+
+/// A change of the routes in the store, as exported to external consumers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum RouteChange {
+    Update {
+        #[serde(flatten)]
+        table: TableSelector,
+        net: IpNet,
+        path_id: PathId,
+        #[serde(flatten)]
+        attrs: Box<RouteAttrs>,
+    },
+    Withdraw {
+        #[serde(flatten)]
+        table: TableSelector,
+        net: IpNet,
+        path_id: PathId,
+    },
+    /// All routes of the table were removed, e.g. because the session went down
+    TableRemoved {
+        #[serde(flatten)]
+        table: TableSelector,
+    },
+}
+
+impl RouteChange {
+    pub fn table(&self) -> &TableSelector {
+        match self {
+            RouteChange::Update { table, .. }
+            | RouteChange::Withdraw { table, .. }
+            | RouteChange::TableRemoved { table } => table,
+        }
+    }
+
+    /// None if the change affects the whole table
+    pub fn net(&self) -> Option<&IpNet> {
+        match self {
+            RouteChange::Update { net, .. } | RouteChange::Withdraw { net, .. } => Some(net),
+            RouteChange::TableRemoved { .. } => None,
+        }
+    }
+}
 
 impl Default for QueryLimits {
     fn default() -> Self {
@@ -178,6 +1181,45 @@ impl Default for QueryLimits {
     }
 }
 
+/// Errors of the store, so the collectors and the API can tell apart what went wrong
+#[derive(Debug)]
+pub enum StoreError {
+    /// The table does not exist (anymore)
+    TableMissing(TableSelector),
+    /// A configured limit, e.g. the route quota of a route distinguisher, prevents the change
+    LimitExceeded(String),
+    /// The query can not be served, e.g. because of an invalid AS path pattern
+    InvalidQuery(String),
+    /// The backend failed to read or write its storage
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::TableMissing(table) => write!(f, "table does not exist: {:?}", table),
+            StoreError::LimitExceeded(limit) => write!(f, "limit exceeded: {}", limit),
+            StoreError::InvalidQuery(reason) => write!(f, "invalid query: {}", reason),
+            StoreError::Io(e) => write!(f, "storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
 #[async_trait]
 pub trait Store: Clone + Send + Sync + 'static {
     async fn update_route(
@@ -186,16 +1228,128 @@ pub trait Store: Clone + Send + Sync + 'static {
         net: IpNet,
         table: TableSelector,
         attrs: RouteAttrs,
-    );
+    ) -> Result<(), StoreError>;
+
+    /// Withdrawing a path which is not stored is not an error, but the table has to exist
+    async fn withdraw_route(
+        &self,
+        path_id: PathId,
+        net: IpNet,
+        table: TableSelector,
+    ) -> Result<(), StoreError>;
+
+    /// Mark all routes in the tables of a session (any route distinguisher or address family) as
+    /// stale until they are announced again
+    async fn mark_stale(&self, session: TableSelector) -> Result<(), StoreError>;
+
+    /// Purge the routes which are still stale from the tables of a session with the address family
+    /// of `table`
+    async fn end_of_rib(&self, table: TableSelector) -> Result<(), StoreError>;
+
+    /// Install a flowspec rule in a flowspec table, or replace the attributes of the rule
+    async fn update_flowspec(
+        &self,
+        table: TableSelector,
+        rule: FlowspecRule,
+        attrs: RouteAttrs,
+    ) -> Result<(), StoreError>;
 
-    async fn withdraw_route(&self, path_id: PathId, net: IpNet, table: TableSelector);
+    async fn withdraw_flowspec(
+        &self,
+        table: TableSelector,
+        rule: FlowspecRule,
+    ) -> Result<(), StoreError>;
+
+    fn get_routes(
+        &self,
+        query: Query,
+    ) -> Result<Pin<Box<dyn Stream<Item = QueryResult> + Send>>, StoreError>;
 
-    fn get_routes(&self, query: Query) -> Pin<Box<dyn Stream<Item = QueryResult> + Send>>;
+    fn get_flowspec(&self, query: FlowspecQuery) -> Vec<FlowspecResult>;
+
+    /// Apply the BGP-LS NLRI of an update received from the session
+    async fn update_linkstate(&self, session: SessionId, update: LinkStateUpdate);
+
+    /// The IGP topology received via BGP-LS from all sessions
+    fn get_topology(&self) -> Topology;
+
+    /// How `get_routes` would execute the query, without executing it
+    fn explain_query(&self, query: &Query) -> Result<QueryPlan, StoreError>;
 
     fn get_routers(&self) -> HashMap<SocketAddr, Client>;
 
+    fn get_sessions(&self) -> Vec<(SessionId, Session)>;
+
+    /// Size and activity of every table
+    fn get_table_stats(&self) -> Vec<TableStats>;
+
+    fn get_client_connections(&self) -> HashMap<SocketAddr, ClientConnection>;
+
+    /// A message of `bytes` length was received from the client
+    fn count_client_message(&self, client_addr: SocketAddr, bytes: usize);
+
+    /// A message of the client could not be decoded
+    fn record_client_error(&self, client_addr: SocketAddr, error: DecodeError);
+
+    /// Recent decode errors of each client address, oldest first. They are kept after the client
+    /// disconnected.
+    fn get_decode_errors(&self) -> HashMap<IpAddr, Vec<DecodeError>>;
+
+    fn get_peer_stats(&self) -> Vec<PeerStats>;
+
+    fn get_sync_progress(&self) -> HashMap<SessionId, SyncProgress>;
+
+    /// Received, accepted, installed and selected routes of every peer
+    fn get_peer_funnels(&self) -> Vec<PeerFunnel>;
+
+    /// Recent session down events, oldest first
+    fn get_session_down_events(&self) -> Vec<SessionDownEvent>;
+
+    /// All peers seen since fernglas was started, with their current state
+    fn get_peers(&self) -> Vec<PeerStatus>;
+
+    /// Routes which fail one of the checks of [`crate::anomalies`]
+    fn get_anomalies(&self) -> Vec<Anomaly>;
+
+    /// Recent origin AS changes of prefixes covered by `net`, oldest first
+    fn get_origin_changes(&self, net: Option<IpNet>) -> Vec<OriginChange>;
+
+    /// Recent attribute changes of the paths of exactly `net`, oldest first
+    fn get_prefix_history(&self, net: IpNet) -> Vec<PrefixChange>;
+
+    /// Oldest unix timestamp for which routes can be queried with `Query::at`, None if no history
+    /// is kept
+    fn get_history_start(&self) -> Option<u64>;
+
+    /// The paths of the selected tables (or all tables) which were added, withdrawn or changed
+    /// their attributes since the unix timestamp, from the history
+    fn get_diff_since(
+        &self,
+        table_query: Option<TableQuery>,
+        since: u64,
+    ) -> Result<Vec<PathDiff>, StoreError>;
+
+    /// The prefixes with the most updates and withdraws within the last `window` seconds
+    fn get_flaps(&self, window: u64, limit: usize) -> Vec<PrefixFlaps>;
+
     fn get_routing_instances(&self) -> HashMap<SocketAddr, HashSet<RouteDistinguisher>>;
 
+    /// The current version of the store and the changes since `version`, keeping only the latest
+    /// change of each path. Without a version, all routes are streamed as updates, one table at a
+    /// time. Returns None if `version` is too old to be served from the change log and a full
+    /// resync is needed.
+    fn get_changes_since(&self, version: Option<u64>) -> Option<(u64, RouteChangeStream)>;
+
+    /// Snapshot of the unicast routes of the selected tables (or all tables) in MRT TABLE_DUMP_V2
+    /// format. This is blocking and should not be called from async code directly.
+    fn export_mrt(&self, table_query: Option<TableQuery>) -> Vec<u8>;
+
+    /// Receive every change of the routes as it happens
+    fn subscribe_changes(&self) -> tokio::sync::broadcast::Receiver<RouteChange>;
+
+    /// Receive every router connect/disconnect and session up/down as it happens
+    fn subscribe_session_events(&self) -> tokio::sync::broadcast::Receiver<SessionEvent>;
+
     async fn client_up(
         &self,
         client_addr: SocketAddr,
@@ -203,37 +1357,84 @@ pub trait Store: Clone + Send + Sync + 'static {
         client_data: Client,
     );
 
-    async fn client_down(&self, client_addr: SocketAddr);
+    /// Address families stored for the client, None if all are stored
+    fn client_address_families(&self, client_addr: &SocketAddr) -> Option<Vec<AfiSafi>>;
+
+    /// The client announced a new name or description, e.g. in another BMP Initiation message
+    async fn update_client(
+        &self,
+        client_addr: SocketAddr,
+        client_name: String,
+        sys_descr: Option<String>,
+    );
+
+    /// The connection to the client was lost, `reason` is reported to subscribers of the session
+    /// events
+    async fn client_down(&self, client_addr: SocketAddr, reason: Option<String>);
 
     async fn session_up(&self, session: SessionId, session_data: Session);
 
-    async fn session_down(&self, session: SessionId, new_state: Option<Session>);
+    async fn session_down(
+        &self,
+        session: SessionId,
+        new_state: Option<Session>,
+        reason: Option<PeerDownReason>,
+    );
+
+    /// A Loc-RIB instance exported via BMP came up, with the table name of its Peer Up message
+    async fn loc_rib_up(&self, table: TableSelector, table_name: Option<String>);
+
+    /// Remove the tables of a Loc-RIB instance exported via BMP. For the default instance, the
+    /// tables of all route distinguishers are removed, as it contains the VPN routes.
+    async fn loc_rib_down(&self, table: TableSelector);
+
+    /// Counters of a BMP Statistics Report received for the session
+    async fn update_bmp_statistics(&self, session: SessionId, statistics: BmpStatistics);
 
+    /// Insert the routes of a BGP UPDATE message into the tables of a session. The address family
+    /// and (unless it is the default) the route distinguisher of `session` are replaced by the ones
+    /// of each NLRI. All NLRIs are processed, even if some of them fail; the first error is
+    /// returned. `raw_attrs` are the attributes decoded from the raw message, which zettabgp
+    /// doesn't keep completely; without the AS path from it the path is a single sequence.
     async fn insert_bgp_update(
         &self,
         session: TableSelector,
         update: zettabgp::prelude::BgpUpdateMessage,
-    ) {
+        mut raw_attrs: RawUpdateAttrs,
+    ) -> Result<(), StoreError> {
         use zettabgp::prelude::*;
+        if let Some(afi_safi) = end_of_rib_marker(&update) {
+            return self
+                .end_of_rib(TableSelector {
+                    afi_safi,
+                    ..session
+                })
+                .await;
+        }
+
         let mut attrs: RouteAttrs = Default::default();
         let mut nexthop = None;
         let mut update_nets = vec![];
         let mut withdraw_nets = vec![];
+        let mut update_rules = vec![];
+        let mut withdraw_rules = vec![];
         for attr in update.attrs {
             match attr {
                 BgpAttrItem::MPUpdates(updates) => {
+                    update_rules.extend(bgp_addrs_to_flowspec(&updates.addrs));
                     let nexthop = match updates.nexthop {
-                        BgpAddr::V4(v4) => Some(IpAddr::from(v4)),
-                        BgpAddr::V6(v6) => Some(IpAddr::from(v6)),
-                        _ => None,
+                        BgpAddr::V4(v4) => (Some(IpAddr::from(v4)), None),
+                        BgpAddr::V6(v6) => (Some(IpAddr::from(v6)), raw_attrs.nexthop_link_local),
+                        _ => (None, None),
                     };
                     for net in bgp_addrs_to_nets(&updates.addrs) {
                         update_nets.push((net, nexthop));
                     }
                 }
                 BgpAttrItem::MPWithdraws(withdraws) => {
-                    for net in bgp_addrs_to_nets(&withdraws.addrs) {
-                        withdraw_nets.push(net);
+                    withdraw_rules.extend(bgp_addrs_to_flowspec(&withdraws.addrs));
+                    for (afi_safi, rd, path, prefix, _) in bgp_addrs_to_nets(&withdraws.addrs) {
+                        withdraw_nets.push((afi_safi, rd, path, prefix));
                     }
                 }
                 BgpAttrItem::NextHop(BgpNextHop { value }) => {
@@ -263,11 +1464,9 @@ pub trait Store: Clone + Send + Sync + 'static {
                     })
                 }
                 BgpAttrItem::ASPath(BgpASpath { value }) => {
-                    let mut as_path = vec![];
-                    for asn in value {
-                        as_path.push(asn.value);
-                    }
-                    attrs.as_path = Some(as_path);
+                    attrs.as_path = Some(raw_attrs.as_path.take().unwrap_or_else(|| {
+                        AsPath::from_sequence(value.into_iter().map(|asn| asn.value).collect())
+                    }));
                 }
                 BgpAttrItem::LargeCommunityList(BgpLargeCommunityList { value }) => {
                     let mut communities = vec![];
@@ -276,99 +1475,473 @@ pub trait Store: Clone + Send + Sync + 'static {
                     }
                     attrs.large_communities = Some(communities);
                 }
+                BgpAttrItem::AggregatorAS(aggregator) => {
+                    attrs.aggregator = Some(Aggregator {
+                        asn: aggregator.asn,
+                        address: aggregator.addr,
+                    });
+                }
+                BgpAttrItem::AtomicAggregate(_) => {
+                    attrs.atomic_aggregate = true;
+                }
+                // zettabgp decodes the router IDs as IPv6 addresses for IPv6 transport, which the
+                // sessions are never set up with
+                BgpAttrItem::OriginatorID(BgpOriginatorID {
+                    value: IpAddr::V4(value),
+                }) => {
+                    attrs.originator_id = Some(value);
+                }
+                BgpAttrItem::ClusterList(BgpClusterList { value }) => {
+                    attrs.cluster_list = Some(
+                        value
+                            .into_iter()
+                            .filter_map(|id| match id {
+                                IpAddr::V4(id) => Some(id),
+                                IpAddr::V6(_) => None,
+                            })
+                            .collect(),
+                    );
+                }
+                BgpAttrItem::Unknown(unknown) => {
+                    attrs
+                        .unknown_attrs
+                        .get_or_insert_with(Vec::new)
+                        .push(UnknownAttr {
+                            type_code: unknown.params.typecode,
+                            flags: unknown.params.flags,
+                            value: unknown.value,
+                        });
+                }
+                BgpAttrItem::ExtCommunityList(BgpExtCommunityList { value }) => {
+                    let mut communities = vec![];
+                    for community in value.into_iter() {
+                        communities.push(ExtCommunity::from_parts(
+                            community.ctype,
+                            community.subtype,
+                            community.a,
+                            community.b,
+                        ));
+                    }
+                    attrs.ext_communities = Some(communities);
+                }
                 _ => {}
             }
         }
         for net in bgp_addrs_to_nets(&update.updates).into_iter() {
-            update_nets.push((net, nexthop));
+            update_nets.push((net, (nexthop, None)));
         }
-        for net in bgp_addrs_to_nets(&update.withdraws).into_iter() {
-            withdraw_nets.push(net);
+        for (afi_safi, rd, path, prefix, _) in bgp_addrs_to_nets(&update.withdraws).into_iter() {
+            withdraw_nets.push((afi_safi, rd, path, prefix));
         }
 
-        for ((mut rd, path, prefix), nexthop) in update_nets {
+        // routes of address families which are not stored for the client are dropped here, before
+        // their tables are created
+        if let Some(address_families) = self.client_address_families(session.client_addr()) {
+            update_nets.retain(|((afi_safi, ..), _)| address_families.contains(afi_safi));
+            withdraw_nets.retain(|(afi_safi, ..)| address_families.contains(afi_safi));
+            update_rules.retain(|(afi_safi, _)| address_families.contains(afi_safi));
+            withdraw_rules.retain(|(afi_safi, _)| address_families.contains(afi_safi));
+        }
+
+        let mut result = Ok(());
+        for ((afi_safi, mut rd, path, prefix, labels), (nexthop, nexthop_link_local)) in update_nets
+        {
             if rd.is_default() {
                 rd = session.route_distinguisher
             }
             let mut attrs = attrs.clone();
             attrs.nexthop = nexthop;
-            self.update_route(
-                path,
-                prefix,
-                TableSelector {
-                    route_distinguisher: rd,
-                    ..session.clone()
-                },
-                attrs,
-            )
-            .await;
-        }
-        for (mut rd, path, prefix) in withdraw_nets {
+            attrs.nexthop_link_local = nexthop_link_local;
+            attrs.labels = labels;
+            let res = self
+                .update_route(
+                    path,
+                    prefix,
+                    TableSelector {
+                        route_distinguisher: rd,
+                        afi_safi,
+                        ..session.clone()
+                    },
+                    attrs,
+                )
+                .await;
+            result = result.and(res);
+        }
+        for (afi_safi, mut rd, path, prefix) in withdraw_nets {
             if rd.is_default() {
                 rd = session.route_distinguisher
             }
-            self.withdraw_route(
-                path,
-                prefix,
-                TableSelector {
-                    route_distinguisher: rd,
-                    ..session.clone()
-                },
-            )
-            .await;
+            let res = self
+                .withdraw_route(
+                    path,
+                    prefix,
+                    TableSelector {
+                        route_distinguisher: rd,
+                        afi_safi,
+                        ..session.clone()
+                    },
+                )
+                .await;
+            result = result.and(res);
         }
+        for (afi_safi, rule) in update_rules {
+            let res = self
+                .update_flowspec(
+                    TableSelector {
+                        afi_safi,
+                        ..session.clone()
+                    },
+                    rule,
+                    attrs.clone(),
+                )
+                .await;
+            result = result.and(res);
+        }
+        for (afi_safi, rule) in withdraw_rules {
+            let res = self
+                .withdraw_flowspec(
+                    TableSelector {
+                        afi_safi,
+                        ..session.clone()
+                    },
+                    rule,
+                )
+                .await;
+            result = result.and(res);
+        }
+        result
+    }
+}
+
+fn bgp_addrs_afi_safi(addrs: &zettabgp::prelude::BgpAddrs) -> Option<AfiSafi> {
+    use zettabgp::prelude::*;
+    match addrs {
+        BgpAddrs::IPV4U(_) | BgpAddrs::IPV4UP(_) => Some(AfiSafi::Ipv4Unicast),
+        BgpAddrs::IPV6U(_) | BgpAddrs::IPV6UP(_) => Some(AfiSafi::Ipv6Unicast),
+        BgpAddrs::VPNV4U(_) => Some(AfiSafi::Ipv4MplsVpn),
+        BgpAddrs::VPNV6U(_) => Some(AfiSafi::Ipv6MplsVpn),
+        BgpAddrs::FS4U(_) => Some(AfiSafi::Ipv4Flowspec),
+        BgpAddrs::FS6U(_) => Some(AfiSafi::Ipv6Flowspec),
+        _ => None,
+    }
+}
+
+const ATTR_AS_PATH: u8 = 2;
+const ATTR_MP_REACH_NLRI: u8 = 14;
+
+/// Path attributes of an UPDATE message which zettabgp doesn't decode completely
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawUpdateAttrs {
+    /// AS path with its segments, zettabgp only keeps the ASNs
+    pub as_path: Option<AsPath>,
+    /// Link-local address of an IPv6 MP_REACH_NLRI nexthop, zettabgp only keeps the global one
+    pub nexthop_link_local: Option<Ipv6Addr>,
+}
+
+impl RawUpdateAttrs {
+    /// Decode the attributes from the raw UPDATE message following the BGP header
+    pub fn decode(msg: &[u8], four_octet_asns: bool) -> anyhow::Result<Self> {
+        let read_u16 = |buf: &[u8]| -> anyhow::Result<usize> {
+            anyhow::ensure!(buf.len() >= 2, "truncated UPDATE message");
+            Ok(u16::from_be_bytes([buf[0], buf[1]]) as usize)
+        };
+        let withdrawn_len = read_u16(msg)?;
+        let msg = msg
+            .get(2 + withdrawn_len..)
+            .ok_or(anyhow::anyhow!("truncated withdrawn routes"))?;
+        let attrs_len = read_u16(msg)?;
+        let mut attrs = msg
+            .get(2..2 + attrs_len)
+            .ok_or(anyhow::anyhow!("truncated path attributes"))?;
+        let mut raw_attrs = Self::default();
+        while !attrs.is_empty() {
+            anyhow::ensure!(attrs.len() >= 3, "truncated path attribute");
+            let flags = attrs[0];
+            let attr_type = attrs[1];
+            let (len, header_len) = if flags & 0x10 != 0 {
+                (read_u16(&attrs[2..])?, 4)
+            } else {
+                (attrs[2] as usize, 3)
+            };
+            let value = attrs
+                .get(header_len..header_len + len)
+                .ok_or(anyhow::anyhow!("truncated path attribute"))?;
+            match attr_type {
+                ATTR_AS_PATH => raw_attrs.as_path = Some(AsPath::decode(value, four_octet_asns)?),
+                ATTR_MP_REACH_NLRI => raw_attrs.nexthop_link_local = mp_reach_link_local(value),
+                _ => {}
+            }
+            attrs = &attrs[header_len + len..];
+        }
+        Ok(raw_attrs)
+    }
+}
+
+/// Link-local address following the global address of an IPv6 nexthop (RFC 2545 section 3),
+/// after the route distinguisher of VPN nexthops (RFC 4659 section 3.2.1.1)
+fn mp_reach_link_local(value: &[u8]) -> Option<Ipv6Addr> {
+    const AFI_IPV6: [u8; 2] = [0, 2];
+    if value.get(..2)? != AFI_IPV6 {
+        return None;
+    }
+    let nexthop = value.get(4..4 + *value.get(3)? as usize)?;
+    let link_local: [u8; 16] = match nexthop.len() {
+        32 => nexthop[16..].try_into().ok()?,
+        48 => nexthop[32..].try_into().ok()?,
+        _ => return None,
+    };
+    Some(Ipv6Addr::from(link_local))
+}
+
+/// Returns the address family if the update is an End-of-RIB marker (RFC 4724)
+pub(crate) fn end_of_rib_marker(update: &zettabgp::prelude::BgpUpdateMessage) -> Option<AfiSafi> {
+    use zettabgp::prelude::*;
+    match &update.attrs[..] {
+        [] if bgp_addrs_is_empty(&update.updates) && bgp_addrs_is_empty(&update.withdraws) => {
+            Some(AfiSafi::Ipv4Unicast)
+        }
+        [BgpAttrItem::MPWithdraws(withdraws)] if bgp_addrs_is_empty(&withdraws.addrs) => {
+            bgp_addrs_afi_safi(&withdraws.addrs)
+        }
+        _ => None,
+    }
+}
+
+fn bgp_addrs_is_empty(addrs: &zettabgp::prelude::BgpAddrs) -> bool {
+    bgp_addrs_to_nets(addrs).is_empty() && bgp_addrs_to_flowspec(addrs).is_empty()
+}
+
+/// Flowspec rules of the NLRI. zettabgp decodes every flowspec NLRI into a single component, so
+/// each rule has either a prefix or the operators of one component.
+fn bgp_addrs_to_flowspec(addrs: &zettabgp::prelude::BgpAddrs) -> Vec<(AfiSafi, FlowspecRule)> {
+    use zettabgp::prelude::*;
+    match addrs {
+        BgpAddrs::FS4U(ref rules) => rules
+            .iter()
+            .map(|rule| {
+                (
+                    AfiSafi::Ipv4Flowspec,
+                    flowspec_rule(rule, bgpv4addr_to_ipnet),
+                )
+            })
+            .collect(),
+        BgpAddrs::FS6U(ref rules) => rules
+            .iter()
+            .map(|rule| {
+                (
+                    AfiSafi::Ipv6Flowspec,
+                    flowspec_rule(rule, |prefix: &FS6| bgpv6addr_to_ipnet(&prefix.ipv6)),
+                )
+            })
+            .collect(),
+        _ => vec![],
     }
 }
 
-fn bgp_addrs_to_nets(
-    addrs: &zettabgp::prelude::BgpAddrs,
-) -> Vec<(RouteDistinguisher, PathId, IpNet)> {
+/// The operators of a flowspec component. zettabgp keeps them private and only exposes them
+/// through the serde representation of `FSOperVec`, which is the list of operators.
+fn flowspec_ops<T>(ops: &zettabgp::prelude::FSOperVec<T>) -> Vec<T>
+where
+    T: zettabgp::prelude::FSOperItem + serde::Serialize + serde::de::DeserializeOwned,
+{
+    serde_json::to_value(ops)
+        .and_then(serde_json::from_value)
+        .inspect_err(|e| warn!("invalid flowspec operators: {}", e))
+        .unwrap_or_default()
+}
+
+fn flowspec_rule<T: zettabgp::prelude::FSItem<T>>(
+    flowspec: &zettabgp::prelude::BgpFlowSpec<T>,
+    to_ipnet: impl Fn(&T) -> Option<IpNet>,
+) -> FlowspecRule {
+    use zettabgp::prelude::*;
+    let numeric = |ops: &FSOperVec<FSOperValItem>| {
+        numeric_condition(flowspec_ops(ops).into_iter().map(|op| NumericOp {
+            and: op.and_bit,
+            lt: op.lt_cmp,
+            gt: op.gt_cmp,
+            eq: op.eq_cmp,
+            value: op.value,
+        }))
+    };
+    let bitmask = |ops: &FSOperVec<FSOperMaskItem>| {
+        bitmask_condition(flowspec_ops(ops).into_iter().map(|op| BitmaskOp {
+            and: op.and_bit,
+            not: op.bit_not,
+            matches: op.bit_match,
+            value: op.value,
+        }))
+    };
+    let mut rule = FlowspecRule {
+        destination: None,
+        source: None,
+        components: vec![],
+    };
+    let (component_type, condition) = match flowspec {
+        BgpFlowSpec::PrefixDst(prefix) => {
+            rule.destination = to_ipnet(prefix);
+            return rule;
+        }
+        BgpFlowSpec::PrefixSrc(prefix) => {
+            rule.source = to_ipnet(prefix);
+            return rule;
+        }
+        BgpFlowSpec::Proto(ops) => (FlowspecComponentType::Protocol, numeric(ops)),
+        BgpFlowSpec::PortAny(ops) => (FlowspecComponentType::Port, numeric(ops)),
+        BgpFlowSpec::PortDst(ops) => (FlowspecComponentType::DestinationPort, numeric(ops)),
+        BgpFlowSpec::PortSrc(ops) => (FlowspecComponentType::SourcePort, numeric(ops)),
+        BgpFlowSpec::IcmpType(ops) => (FlowspecComponentType::IcmpType, numeric(ops)),
+        BgpFlowSpec::IcmpCode(ops) => (FlowspecComponentType::IcmpCode, numeric(ops)),
+        BgpFlowSpec::TcpFlags(ops) => (FlowspecComponentType::TcpFlags, bitmask(ops)),
+        BgpFlowSpec::PacketLength(ops) => (FlowspecComponentType::PacketLength, numeric(ops)),
+        BgpFlowSpec::Dscp(ops) => (FlowspecComponentType::Dscp, numeric(ops)),
+        BgpFlowSpec::Fragment(ops) => (FlowspecComponentType::Fragment, bitmask(ops)),
+        BgpFlowSpec::FlowLabel(ops) => (FlowspecComponentType::FlowLabel, numeric(ops)),
+    };
+    rule.components.push(FlowspecComponent {
+        component_type,
+        condition,
+    });
+    rule
+}
+
+/// A prefix of the NLRI with its address family, route distinguisher, path ID and MPLS labels
+type Nlri = (AfiSafi, RouteDistinguisher, PathId, IpNet, Option<Vec<u32>>);
+
+/// Prefixes of the NLRI, with the MPLS labels of labeled unicast and VPN routes
+fn bgp_addrs_to_nets(addrs: &zettabgp::prelude::BgpAddrs) -> Vec<Nlri> {
     use zettabgp::prelude::*;
     match addrs {
         BgpAddrs::IPV4UP(ref addrs) => addrs
             .iter()
             .filter_map(|addr| {
                 let WithPathId { pathid, nlri } = addr;
-                bgpv4addr_to_ipnet(nlri).map(|net| (RouteDistinguisher::Default, *pathid, net))
+                bgpv4addr_to_ipnet(nlri).map(|net| {
+                    (
+                        AfiSafi::Ipv4Unicast,
+                        RouteDistinguisher::Default,
+                        *pathid,
+                        net,
+                        None,
+                    )
+                })
             })
             .collect(),
         BgpAddrs::IPV6UP(ref addrs) => addrs
             .iter()
             .filter_map(|addr| {
                 let WithPathId { pathid, nlri } = addr;
-                bgpv6addr_to_ipnet(nlri).map(|net| (RouteDistinguisher::Default, *pathid, net))
+                bgpv6addr_to_ipnet(nlri).map(|net| {
+                    (
+                        AfiSafi::Ipv6Unicast,
+                        RouteDistinguisher::Default,
+                        *pathid,
+                        net,
+                        None,
+                    )
+                })
             })
             .collect(),
         BgpAddrs::IPV4U(ref addrs) => addrs
             .iter()
-            .filter_map(|addr| bgpv4addr_to_ipnet(addr).map(|net| (RouteDistinguisher::Default, 0, net)))
+            .filter_map(|addr| {
+                bgpv4addr_to_ipnet(addr).map(|net| {
+                    (
+                        AfiSafi::Ipv4Unicast,
+                        RouteDistinguisher::Default,
+                        0,
+                        net,
+                        None,
+                    )
+                })
+            })
             .collect(),
         BgpAddrs::IPV6U(ref addrs) => addrs
             .iter()
-            .filter_map(|addr| bgpv6addr_to_ipnet(addr).map(|net| (RouteDistinguisher::Default, 0, net)))
+            .filter_map(|addr| {
+                bgpv6addr_to_ipnet(addr).map(|net| {
+                    (
+                        AfiSafi::Ipv6Unicast,
+                        RouteDistinguisher::Default,
+                        0,
+                        net,
+                        None,
+                    )
+                })
+            })
+            .collect(),
+        BgpAddrs::IPV4LU(ref addrs) => addrs
+            .iter()
+            .filter_map(|labeled| {
+                bgpv4addr_to_ipnet(&labeled.prefix).map(|net| {
+                    (
+                        AfiSafi::Ipv4Unicast,
+                        RouteDistinguisher::Default,
+                        0,
+                        net,
+                        mpls_labels(&labeled.labels),
+                    )
+                })
+            })
+            .collect(),
+        BgpAddrs::IPV6LU(ref addrs) => addrs
+            .iter()
+            .filter_map(|labeled| {
+                bgpv6addr_to_ipnet(&labeled.prefix).map(|net| {
+                    (
+                        AfiSafi::Ipv6Unicast,
+                        RouteDistinguisher::Default,
+                        0,
+                        net,
+                        mpls_labels(&labeled.labels),
+                    )
+                })
+            })
             .collect(),
         BgpAddrs::VPNV4U(ref addrs) => addrs
             .iter()
             .filter_map(|labeled| {
-                let rd = RouteDistinguisher::try_from(labeled.prefix.rd.clone())
-                    .inspect_err(|_| warn!("invalid Bgp Route distinguisher"))
-                    .ok()?;
-                bgpv4addr_to_ipnet(&labeled.prefix.prefix).map(|net| (rd, 0, net))
+                let rd = vpn_route_distinguisher(&labeled.prefix.rd)?;
+                bgpv4addr_to_ipnet(&labeled.prefix.prefix).map(|net| {
+                    (
+                        AfiSafi::Ipv4MplsVpn,
+                        rd,
+                        0,
+                        net,
+                        mpls_labels(&labeled.labels),
+                    )
+                })
             })
             .collect(),
         BgpAddrs::VPNV6U(ref addrs) => addrs
             .iter()
             .filter_map(|labeled| {
-                let rd = RouteDistinguisher::try_from(labeled.prefix.rd.clone())
-                    .inspect_err(|_| warn!("invalid Bgp Route distinguisher"))
-                    .ok()?;
-                bgpv6addr_to_ipnet(&labeled.prefix.prefix).map(|net| (rd, 0, net))
+                let rd = vpn_route_distinguisher(&labeled.prefix.rd)?;
+                bgpv6addr_to_ipnet(&labeled.prefix.prefix).map(|net| {
+                    (
+                        AfiSafi::Ipv6MplsVpn,
+                        rd,
+                        0,
+                        net,
+                        mpls_labels(&labeled.labels),
+                    )
+                })
             })
             .collect(),
         _ => vec![],
     }
 }
 
+fn vpn_route_distinguisher(rd: &zettabgp::prelude::BgpRD) -> Option<RouteDistinguisher> {
+    RouteDistinguisher::try_from(rd.clone())
+        .inspect_err(|_| warn!("invalid Bgp Route distinguisher"))
+        .ok()
+}
+
+fn mpls_labels(labels: &zettabgp::prelude::MplsLabels) -> Option<Vec<u32>> {
+    (!labels.labels.is_empty()).then(|| labels.labels.clone())
+}
+
 fn bgpv4addr_to_ipnet(addr: &BgpAddrV4) -> Option<IpNet> {
     Ipv4Net::new(addr.addr, addr.prefixlen)
         .inspect_err(|_| warn!("invalid BgpAddrs prefixlen"))
@@ -382,3 +1955,166 @@ fn bgpv6addr_to_ipnet(addr: &BgpAddrV6) -> Option<IpNet> {
         .map(IpNet::V6)
         .ok()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zettabgp::prelude::*;
+
+    #[test]
+    fn test_raw_update_attrs() {
+        use crate::as_path::AsPathSegment;
+
+        #[rustfmt::skip]
+        let msg = [
+            0, 0, // withdrawn routes
+            0, 24, // path attributes
+            0x40, 1, 1, 0, // ORIGIN
+            0x50, 2, 0, 16, // AS_PATH with extended length
+            2, 2, 0, 0, 0xfb, 0xf0, 0, 0, 0xfb, 0xf1, // AS_SEQUENCE 64496 64497
+            1, 1, 0, 0, 0xfb, 0xf2, // AS_SET 64498
+            24, 192, 0, 2, // NLRI
+        ];
+        assert_eq!(
+            RawUpdateAttrs::decode(&msg, true).unwrap().as_path,
+            Some(AsPath(vec![
+                AsPathSegment::Sequence(vec![64496, 64497]),
+                AsPathSegment::Set(vec![64498]),
+            ]))
+        );
+
+        #[rustfmt::skip]
+        let msg = [
+            0, 0,
+            0, 11,
+            0x40, 2, 8,
+            3, 1, 0xfd, 0xe9, // AS_CONFED_SEQUENCE 65001
+            2, 1, 0xfb, 0xf0, // AS_SEQUENCE 64496
+        ];
+        assert_eq!(
+            RawUpdateAttrs::decode(&msg, false).unwrap().as_path,
+            Some(AsPath(vec![
+                AsPathSegment::ConfedSequence(vec![65001]),
+                AsPathSegment::Sequence(vec![64496]),
+            ]))
+        );
+
+        #[rustfmt::skip]
+        let msg = [
+            0, 0,
+            0, 45,
+            0x80, 14, 42, // MP_REACH_NLRI
+            0, 2, 1, 32, // IPv6 unicast, nexthop length
+            0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, // 2001:db8::1
+            0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, // fe80::1
+            0, // SNPA
+            32, 0x20, 0x01, 0x0d, 0xb8, // 2001:db8::/32
+        ];
+        let raw_attrs = RawUpdateAttrs::decode(&msg, true).unwrap();
+        assert_eq!(
+            raw_attrs.nexthop_link_local,
+            Some("fe80::1".parse().unwrap())
+        );
+        let params = BgpSessionParams::new(
+            64496,
+            180,
+            BgpTransportMode::IPv4,
+            "192.0.2.1".parse().unwrap(),
+            vec![],
+        );
+        let mut update = BgpUpdateMessage::new();
+        update.decode_from(&params, &msg).unwrap();
+        assert!(matches!(
+            &update.attrs[..],
+            [BgpAttrItem::MPUpdates(BgpMPUpdates {
+                nexthop: BgpAddr::V6(nexthop),
+                ..
+            })] if *nexthop == "2001:db8::1".parse::<Ipv6Addr>().unwrap()
+        ));
+
+        assert_eq!(
+            RawUpdateAttrs::decode(&[0, 0, 0, 0], true).unwrap(),
+            Default::default()
+        );
+        assert!(RawUpdateAttrs::decode(&[0, 0, 0, 4, 0x40, 2, 6, 2], true).is_err());
+    }
+
+    fn component(component_type: FlowspecComponentType, condition: &str) -> FlowspecRule {
+        FlowspecRule {
+            destination: None,
+            source: None,
+            components: vec![FlowspecComponent {
+                component_type,
+                condition: condition.to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_negotiated_capabilities() {
+        use zettabgp::BgpCapability;
+        let graceful_restart = |restart_time| BgpCapability::CapGR {
+            restart_time,
+            restart_state: false,
+            afis: vec![],
+        };
+        let sent = [
+            BgpCapability::SafiIPv4u,
+            BgpCapability::SafiIPv6u,
+            graceful_restart(120),
+        ];
+        let received = [BgpCapability::SafiIPv4u, graceful_restart(90)];
+        assert_eq!(
+            negotiated_capabilities(&sent, &received),
+            vec![
+                Capability::AddressFamily("IPv4u".to_string()),
+                Capability::GracefulRestart
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flowspec_rules() {
+        let params = BgpSessionParams::new(
+            64496,
+            180,
+            BgpTransportMode::IPv4,
+            "192.0.2.1".parse().unwrap(),
+            vec![],
+        );
+        #[rustfmt::skip]
+        let nlri = [
+            3, 3, 0x81, 6, // protocol =6
+            7, 5, 0x13, 0x04, 0x00, 0xd5, 0x08, 0x00, // destination port >=1024&<=2048
+            3, 9, 0x81, 0x02, // TCP flags =0x2
+            5, 1, 24, 192, 0, 2, // destination 192.0.2.0/24
+        ];
+        let (addrs, _) = BgpAddrs::decode_from(&params, 1, 133, &nlri).unwrap();
+        assert_eq!(
+            bgp_addrs_to_flowspec(&addrs),
+            vec![
+                (
+                    AfiSafi::Ipv4Flowspec,
+                    component(FlowspecComponentType::Protocol, "=6"),
+                ),
+                (
+                    AfiSafi::Ipv4Flowspec,
+                    component(FlowspecComponentType::DestinationPort, ">=1024&<=2048"),
+                ),
+                (
+                    AfiSafi::Ipv4Flowspec,
+                    component(FlowspecComponentType::TcpFlags, "=0x2"),
+                ),
+                (
+                    AfiSafi::Ipv4Flowspec,
+                    FlowspecRule {
+                        destination: Some("192.0.2.0/24".parse().unwrap()),
+                        source: None,
+                        components: vec![],
+                    },
+                ),
+            ]
+        );
+        assert!(bgp_addrs_is_empty(&BgpAddrs::FS4U(vec![])));
+    }
+}
@@ -3,28 +3,402 @@ use futures_util::Stream;
 use futures_util::StreamExt;
 use ipnet::IpNet;
 use log::*;
+use prometheus::IntCounterVec;
+use rayon::iter::IndexedParallelIterator;
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
-use regex::Regex;
+use rayon::slice::ParallelSliceMut;
+use serde::Deserialize;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::net::SocketAddr;
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
 use tokio_stream::wrappers::ReceiverStream;
 
+use crate::anomalies::{Anomaly, RouteTargetCheck, VpnInstanceConfig};
+use crate::as_path::{AsPath, AsPathMatcher};
+use crate::bestpath::{self, PathCandidate};
+use crate::change_log::*;
+use crate::community_rules::{CommunityRuleConfig, CommunityRules};
 use crate::compressed_attrs::*;
+use crate::flowspec::{self, FlowspecQuery, FlowspecResult, FlowspecRule};
+use crate::history::{History, HistoryChange};
+use crate::linkstate::{LinkStateDb, LinkStateUpdate, Topology};
+use crate::mrt_export::MrtWriter;
+use crate::persistence::{table_sort_key, SnapshotMetadata, SnapshotReader, SnapshotWriter};
+use crate::retention::{PendingPrePolicy, PrePolicyRetention, RecencyIndex, POST_POLICY_GRACE};
 use crate::route_distinguisher::RouteDistinguisher;
+use crate::stats::FlapStats;
 use crate::store::*;
 use crate::table_impl::*;
 
-#[derive(Default, Clone)]
+/// Number of changes a slow subscriber may lag behind before it is disconnected
+const CHANGE_BROADCAST_CAPACITY: usize = 4096;
+
+/// Number of session events a slow subscriber may lag behind before it is disconnected
+const SESSION_EVENT_BROADCAST_CAPACITY: usize = 256;
+
+/// Withdrawn prefixes whose origin is remembered to detect origin changes on re-announcement
+const WITHDRAWN_ORIGINS_CAPACITY: usize = 100_000;
+
+/// Seconds the origin of a withdrawn prefix is remembered if there are too many of them
+const WITHDRAWN_ORIGIN_RETENTION: u64 = 24 * 3600;
+
+/// Previous state of the paths which changed since the point in time of a query, by table
+type Overlays = HashMap<TableSelector, HashMap<(IpNet, PathId), Option<RouteEntry>>>;
+
+type FlowspecRules = HashMap<FlowspecRule, (RouteAttrs, u64)>;
+
+/// Origin and unix timestamp of the withdrawal of prefixes without any path left, by table
+type WithdrawnOrigins = HashMap<(TableSelector, IpNet), (u32, u64)>;
+
+/// Update and withdraw counters of a peer, incremented on every route change
+#[derive(Default)]
+struct PeerCounters {
+    updates: AtomicU64,
+    withdraws: AtomicU64,
+}
+
+impl From<&PeerStats> for PeerCounters {
+    fn from(stats: &PeerStats) -> Self {
+        PeerCounters {
+            updates: AtomicU64::new(stats.updates),
+            withdraws: AtomicU64::new(stats.withdraws),
+        }
+    }
+}
+
+/// Stats of each peer with its update and withdraw counters
+type PeerStatsMap = HashMap<(RouterId, IpAddr), (PeerStats, Arc<PeerCounters>)>;
+
+/// Filter of the routes of a query, combined from the query parameters
+type RouteFilter = Box<dyn Fn(&(TableSelector, IpNet, RouteEntry)) -> bool + Send + Sync>;
+
+fn default_origin_change_log_size() -> usize {
+    1000
+}
+
+fn default_session_event_log_size() -> usize {
+    1000
+}
+
+fn default_decode_error_log_size() -> usize {
+    20
+}
+
+fn default_session_down_correlation_window() -> u64 {
+    10
+}
+
+fn default_change_log_size() -> usize {
+    100_000
+}
+
+fn default_flap_stats_retention() -> u64 {
+    3600
+}
+
+fn default_history_size() -> usize {
+    0
+}
+
+fn default_prefix_history_size() -> usize {
+    0
+}
+
+fn default_metrics_interval() -> u64 {
+    30
+}
+
+/// Upper bounds for the size of attributes of a single route. Longer attributes are truncated, to
+/// protect against pathological announcements.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttributeLimits {
+    pub max_as_path_length: Option<usize>,
+    pub max_communities: Option<usize>,
+    pub max_large_communities: Option<usize>,
+}
+
+fn truncate<T>(list: &mut Option<Vec<T>>, max: Option<usize>, attribute: &str) -> bool {
+    let (Some(list), Some(max)) = (list, max) else {
+        return false;
+    };
+    if list.len() <= max {
+        return false;
+    }
+    list.truncate(max);
+    crate::metrics::TRUNCATED_ATTRIBUTES
+        .with_label_values(&[attribute])
+        .inc();
+    true
+}
+
+fn truncate_as_path(as_path: &mut Option<AsPath>, max: Option<usize>) -> bool {
+    let (Some(as_path), Some(max)) = (as_path, max) else {
+        return false;
+    };
+    if !as_path.truncate(max) {
+        return false;
+    }
+    crate::metrics::TRUNCATED_ATTRIBUTES
+        .with_label_values(&["as_path"])
+        .inc();
+    true
+}
+
+impl AttributeLimits {
+    pub fn apply(&self, attrs: &mut RouteAttrs) {
+        // not short-circuiting, so every oversized attribute is truncated and counted
+        attrs.truncated = truncate_as_path(&mut attrs.as_path, self.max_as_path_length)
+            | truncate(&mut attrs.communities, self.max_communities, "communities")
+            | truncate(
+                &mut attrs.large_communities,
+                self.max_large_communities,
+                "large_communities",
+            );
+    }
+}
+
+/// Limits on the number of paths stored for a VRF, so a customer VRF leaking a full table can not
+/// fill up the memory. The paths of all tables with the route distinguisher are counted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteQuotas {
+    /// Quota of every route distinguisher which is not listed in `route_distinguishers`
+    pub default: Option<usize>,
+    #[serde(default)]
+    pub route_distinguishers: HashMap<RouteDistinguisher, usize>,
+}
+
+impl RouteQuotas {
+    /// The default routing instance never has a quota
+    fn limit(&self, rd: &RouteDistinguisher) -> Option<usize> {
+        if rd.is_default() {
+            return None;
+        }
+        self.route_distinguishers.get(rd).copied().or(self.default)
+    }
+}
+
+/// Metadata of a peer for routers which do not export useful peer descriptions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionMetadataConfig {
+    /// Router the session is on, applies to sessions of all routers with this peer if not set
+    pub router_id: Option<RouterId>,
+    pub peer_address: IpAddr,
+    pub description: Option<String>,
+    pub expected_asn: Option<u32>,
+    pub ixp: Option<String>,
+}
+
+impl SessionMetadataConfig {
+    fn apply(&self, session: &mut Session) {
+        if self.description.is_some() {
+            session.description.clone_from(&self.description);
+        }
+        if self.expected_asn.is_some() {
+            session.expected_asn = self.expected_asn;
+        }
+        if self.ixp.is_some() {
+            session.ixp.clone_from(&self.ixp);
+        }
+    }
+}
+
+/// Names and labels of a router, matched by the address it connects from or by its router id
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientMetadataConfig {
+    pub address: Option<IpAddr>,
+    pub router_id: Option<RouterId>,
+    pub display_name: Option<String>,
+    pub location: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Only store routes of these address families
+    pub address_families: Option<Vec<AfiSafi>>,
+}
+
+impl ClientMetadataConfig {
+    fn matches(&self, client_addr: &SocketAddr, client: &Client) -> bool {
+        (self.address.is_some() || self.router_id.is_some())
+            && self
+                .address
+                .map(|addr| addr == client_addr.ip())
+                .unwrap_or(true)
+            && self
+                .router_id
+                .map(|id| id == client.router_id)
+                .unwrap_or(true)
+    }
+
+    fn apply(&self, metadata: &mut ClientMetadata) {
+        if self.display_name.is_some() {
+            metadata.display_name.clone_from(&self.display_name);
+        }
+        if self.location.is_some() {
+            metadata.location.clone_from(&self.location);
+        }
+        for tag in &self.tags {
+            if !metadata.tags.contains(tag) {
+                metadata.tags.push(tag.clone());
+            }
+        }
+        if self.address_families.is_some() {
+            metadata.address_families.clone_from(&self.address_families);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InMemoryStoreConfig {
+    /// Rules to rewrite or strip communities before routes are stored, applied in order
+    #[serde(default)]
+    pub community_rules: Vec<CommunityRuleConfig>,
+    /// Number of origin AS changes to keep in memory
+    #[serde(default = "default_origin_change_log_size")]
+    pub origin_change_log_size: usize,
+    /// Number of route changes to keep in memory for differential exports
+    #[serde(default = "default_change_log_size")]
+    pub change_log_size: usize,
+    #[serde(default)]
+    pub attribute_limits: AttributeLimits,
+    /// Number of session down events to keep in memory
+    #[serde(default = "default_session_event_log_size")]
+    pub session_event_log_size: usize,
+    /// Sessions of a router going down within this many seconds are reported as one event
+    #[serde(default = "default_session_down_correlation_window")]
+    pub session_down_correlation_window: u64,
+    /// Number of undecodable messages to keep in memory for each client
+    #[serde(default = "default_decode_error_log_size")]
+    pub decode_error_log_size: usize,
+    #[serde(default)]
+    pub route_quotas: RouteQuotas,
+    #[serde(default)]
+    pub session_metadata: Vec<SessionMetadataConfig>,
+    #[serde(default)]
+    pub client_metadata: Vec<ClientMetadataConfig>,
+    /// Seconds for which the updates and withdraws of each prefix are counted, 0 disables it
+    #[serde(default = "default_flap_stats_retention")]
+    pub flap_stats_retention: u64,
+    /// Number of route changes to keep in memory for historical queries, 0 disables them
+    #[serde(default = "default_history_size")]
+    pub history_size: usize,
+    /// Number of attribute changes to keep in memory for each prefix, 0 disables them
+    #[serde(default = "default_prefix_history_size")]
+    pub prefix_history_size: usize,
+    /// Seconds between updates of the per-client and per-table gauges, 0 disables them
+    #[serde(default = "default_metrics_interval")]
+    pub metrics_interval: u64,
+    /// Which routes of the pre-policy Adj-RIB-In tables are kept
+    #[serde(default)]
+    pub pre_policy_retention: PrePolicyRetention,
+    /// VPNs whose route distinguishers are checked against the route targets of their routes
+    #[serde(default)]
+    pub vpn_instances: Vec<VpnInstanceConfig>,
+}
+
+impl Default for InMemoryStoreConfig {
+    fn default() -> Self {
+        Self {
+            community_rules: vec![],
+            origin_change_log_size: default_origin_change_log_size(),
+            change_log_size: default_change_log_size(),
+            attribute_limits: Default::default(),
+            session_event_log_size: default_session_event_log_size(),
+            session_down_correlation_window: default_session_down_correlation_window(),
+            decode_error_log_size: default_decode_error_log_size(),
+            route_quotas: Default::default(),
+            session_metadata: vec![],
+            client_metadata: vec![],
+            flap_stats_retention: default_flap_stats_retention(),
+            history_size: default_history_size(),
+            prefix_history_size: default_prefix_history_size(),
+            metrics_interval: default_metrics_interval(),
+            pre_policy_retention: Default::default(),
+            vpn_instances: vec![],
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct InMemoryStore {
     clients: Arc<Mutex<HashMap<SocketAddr, Client>>>,
+    connections: Arc<Mutex<HashMap<SocketAddr, ClientConnection>>>,
     sessions: Arc<Mutex<HashMap<SessionId, Session>>>,
     tables: Arc<Mutex<HashMap<TableSelector, InMemoryTable>>>,
+    /// Flowspec rules with their attributes and the unix timestamp they were installed at, by table
+    flowspec: Arc<Mutex<HashMap<TableSelector, FlowspecRules>>>,
+    linkstate: Arc<Mutex<LinkStateDb>>,
     caches: Arc<Mutex<Caches>>,
+    community_rules: Arc<CommunityRules>,
+    attribute_limits: AttributeLimits,
+    origin_changes: Arc<Mutex<VecDeque<OriginChange>>>,
+    origin_change_log_size: usize,
+    withdrawn_origins: Arc<Mutex<WithdrawnOrigins>>,
+    prefix_history: Arc<Mutex<HashMap<IpNet, VecDeque<PrefixChange>>>>,
+    prefix_history_size: usize,
+    change_log: Arc<Mutex<ChangeLog>>,
+    change_broadcast: tokio::sync::broadcast::Sender<RouteChange>,
+    session_event_broadcast: tokio::sync::broadcast::Sender<SessionEvent>,
+    peer_stats: Arc<Mutex<PeerStatsMap>>,
+    /// Counters of the peer of each session, so route updates are counted without locking the
+    /// clients and the peer stats
+    session_counters: Arc<RwLock<HashMap<SessionId, Arc<PeerCounters>>>>,
+    session_down_events: Arc<Mutex<VecDeque<SessionDownEvent>>>,
+    session_event_log_size: usize,
+    peer_states: Arc<Mutex<HashMap<SessionId, PeerStatus>>>,
+    session_down_correlation_window: u64,
+    decode_errors: Arc<Mutex<HashMap<IpAddr, VecDeque<DecodeError>>>>,
+    decode_error_log_size: usize,
+    /// Clients restored from a snapshot which have not reconnected yet
+    restored_clients: Arc<Mutex<HashSet<SocketAddr>>>,
+    route_quotas: Arc<RouteQuotas>,
+    quota_counts: Arc<Mutex<HashMap<RouteDistinguisher, Arc<AtomicUsize>>>>,
+    /// Route distinguishers which currently exceed their quota
+    quotas_exceeded: Arc<Mutex<HashSet<RouteDistinguisher>>>,
+    /// Address families of each session for which End-of-RIB was received since the session came up
+    end_of_rib_received: Arc<Mutex<HashMap<SessionId, HashSet<AfiSafi>>>>,
+    /// Number of routes of each peer when its tables were last complete
+    table_sizes: Arc<Mutex<HashMap<(RouterId, IpAddr), usize>>>,
+    session_metadata: Arc<Vec<SessionMetadataConfig>>,
+    client_metadata: Arc<Vec<ClientMetadataConfig>>,
+    flap_stats: Arc<Mutex<FlapStats>>,
+    history: Arc<Mutex<History>>,
+    metrics_interval: u64,
+    pre_policy_retention: PrePolicyRetention,
+    /// Update order of the paths of the pre-policy tables, if only the most recent are kept
+    pre_policy_recency: Arc<Mutex<HashMap<TableSelector, RecencyIndex>>>,
+    /// Pre-policy prefixes waiting for their post-policy route, if only those are kept
+    pre_policy_pending: Arc<Mutex<HashMap<(SessionId, AfiSafi), PendingPrePolicy>>>,
+    route_target_check: Arc<RouteTargetCheck>,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::new(&Default::default()).unwrap()
+    }
+}
+
+fn count_route_change(metric: &IntCounterVec, table: &TableSelector) {
+    metric
+        .with_label_values(&[
+            table.afi_safi.afi(),
+            table.afi_safi.safi(),
+            table.table_type.name(),
+        ])
+        .inc();
 }
 
 fn tables_for_client_fn(
@@ -33,6 +407,93 @@ fn tables_for_client_fn(
     move |(k, _): &(_, _)| k.client_addr() == query_from_client
 }
 
+/// Resolves the nexthops of the results of a query to the most specific locally originated route,
+/// e.g. a redistributed connected, host or static route, in the Loc-RIB of the router which covers
+/// the nexthop. This approximates the "resolved via" output of router CLIs. The Loc-RIB tables are
+/// selected once when the query starts and each nexthop is only looked up once per query.
+struct NexthopResolver {
+    loc_ribs: HashMap<LocRibInstance, Vec<(TableSelector, InMemoryTable)>>,
+    /// Local routes covering the nexthop, most specific first
+    cache: Mutex<HashMap<(LocRibInstance, IpAddr), CoveringRoutes>>,
+}
+
+/// Loc-RIB instance of a router, by its client address and route distinguisher
+type LocRibInstance = (SocketAddr, RouteDistinguisher);
+type CoveringRoutes = Arc<Vec<IpNet>>;
+
+impl NexthopResolver {
+    /// Only the Loc-RIB tables of the routers of the queried Loc-RIB tables are kept
+    fn new(
+        tables: &HashMap<TableSelector, InMemoryTable>,
+        queried: &[(TableSelector, InMemoryTable)],
+    ) -> Self {
+        let keys = queried
+            .iter()
+            .filter(|(k, _)| matches!(k.table_type, TableType::LocRib { .. }))
+            .map(|(k, _)| (*k.client_addr(), k.route_distinguisher))
+            .collect::<HashSet<_>>();
+        let mut loc_ribs: HashMap<_, Vec<_>> = HashMap::new();
+        if !keys.is_empty() {
+            for (k, table) in tables {
+                let key = (*k.client_addr(), k.route_distinguisher);
+                if matches!(k.table_type, TableType::LocRib { .. }) && keys.contains(&key) {
+                    loc_ribs
+                        .entry(key)
+                        .or_default()
+                        .push((k.clone(), table.clone()));
+                }
+            }
+        }
+        Self {
+            loc_ribs,
+            cache: Default::default(),
+        }
+    }
+
+    fn resolve(&self, table: &TableSelector, net: &IpNet, nexthop: IpAddr) -> Option<IpNet> {
+        if !matches!(table.table_type, TableType::LocRib { .. }) || nexthop.is_unspecified() {
+            return None;
+        }
+        let key = ((*table.client_addr(), table.route_distinguisher), nexthop);
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        let covering = match cached {
+            Some(covering) => covering,
+            None => {
+                let covering = Arc::new(self.local_routes_covering(&key.0, nexthop));
+                self.cache.lock().unwrap().insert(key, covering.clone());
+                covering
+            }
+        };
+        // a route is not resolved via itself
+        covering.iter().find(|connected| *connected != net).copied()
+    }
+
+    fn local_routes_covering(&self, loc_rib: &LocRibInstance, nexthop: IpAddr) -> Vec<IpNet> {
+        let host = IpNet::from(nexthop);
+        let mut covering = self
+            .loc_ribs
+            .get(loc_rib)
+            .into_iter()
+            .flatten()
+            .filter(|(k, _)| k.afi_safi.contains_family_of(&host))
+            .flat_map(|(_, t)| t.get_routes(Some(&NetQuery::Contains(host))))
+            .filter(|(connected, route)| {
+                connected.prefix_len() > 0
+                    && route
+                        .attrs
+                        .as_path
+                        .as_ref()
+                        .map(|as_path| as_path.is_empty())
+                        .unwrap_or(true)
+            })
+            .map(|(connected, _)| connected)
+            .collect::<Vec<_>>();
+        covering.sort_by_key(|connected| Reverse(connected.prefix_len()));
+        covering.dedup();
+        covering
+    }
+}
+
 fn tables_for_session_fn(
     session_id: &SessionId,
 ) -> impl Fn(&(&TableSelector, &InMemoryTable)) -> bool + '_ {
@@ -40,6 +501,779 @@ fn tables_for_session_fn(
 }
 
 impl InMemoryStore {
+    pub fn new(cfg: &InMemoryStoreConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            clients: Default::default(),
+            connections: Default::default(),
+            sessions: Default::default(),
+            tables: Default::default(),
+            flowspec: Default::default(),
+            linkstate: Default::default(),
+            caches: Default::default(),
+            community_rules: Arc::new(CommunityRules::compile(&cfg.community_rules)?),
+            attribute_limits: cfg.attribute_limits.clone(),
+            origin_changes: Default::default(),
+            withdrawn_origins: Default::default(),
+            origin_change_log_size: cfg.origin_change_log_size,
+            prefix_history: Default::default(),
+            prefix_history_size: cfg.prefix_history_size,
+            change_log: Arc::new(Mutex::new(ChangeLog::new(cfg.change_log_size))),
+            change_broadcast: tokio::sync::broadcast::channel(CHANGE_BROADCAST_CAPACITY).0,
+            session_event_broadcast: tokio::sync::broadcast::channel(
+                SESSION_EVENT_BROADCAST_CAPACITY,
+            )
+            .0,
+            peer_stats: Default::default(),
+            session_counters: Default::default(),
+            session_down_events: Default::default(),
+            session_event_log_size: cfg.session_event_log_size,
+            peer_states: Default::default(),
+            session_down_correlation_window: cfg.session_down_correlation_window,
+            decode_errors: Default::default(),
+            decode_error_log_size: cfg.decode_error_log_size,
+            restored_clients: Default::default(),
+            route_quotas: Arc::new(cfg.route_quotas.clone()),
+            quota_counts: Default::default(),
+            quotas_exceeded: Default::default(),
+            end_of_rib_received: Default::default(),
+            table_sizes: Default::default(),
+            session_metadata: Arc::new(cfg.session_metadata.clone()),
+            client_metadata: Arc::new(cfg.client_metadata.clone()),
+            flap_stats: Arc::new(Mutex::new(FlapStats::new(cfg.flap_stats_retention))),
+            history: Arc::new(Mutex::new(History::new(cfg.history_size))),
+            metrics_interval: cfg.metrics_interval,
+            pre_policy_retention: cfg.pre_policy_retention.clone(),
+            pre_policy_recency: Default::default(),
+            pre_policy_pending: Default::default(),
+            route_target_check: Arc::new(RouteTargetCheck::new(&cfg.vpn_instances)),
+        })
+    }
+
+    /// Serialize all clients, sessions and tables, see [`crate::persistence`]
+    pub fn snapshot(&self, routes: bool) -> Vec<u8> {
+        let clients = self.clients.lock().unwrap().clone();
+        let sessions = self.sessions.lock().unwrap().clone();
+        let tables = if routes {
+            self.tables.lock().unwrap().clone()
+        } else {
+            Default::default()
+        };
+        let metadata = SnapshotMetadata {
+            peer_stats: self.get_peer_stats(),
+            peer_states: self.get_peers(),
+            session_down_events: self.get_session_down_events(),
+        };
+
+        let mut writer = SnapshotWriter::new();
+        writer.count(clients.len());
+        for (client_addr, client) in &clients {
+            writer.client(client_addr, client);
+        }
+        writer.count(sessions.len());
+        for (session_id, session) in &sessions {
+            writer.session(session_id, session);
+        }
+        writer.metadata(&metadata);
+        writer.count(tables.len());
+        for (table_sel, table) in tables {
+            let routes = table.get_routes(None);
+            writer.table(&table_sel, routes.len());
+            for (net, route) in routes {
+                writer.route(&net, route.path_id, &route.attrs);
+            }
+        }
+        writer.finish()
+    }
+
+    /// Move the paths of all tables which did not change since `before` to the cold tier in `dir`.
+    /// This is blocking and should not be called from async code directly.
+    pub fn demote_stable_routes(&self, before: u64, dir: &Path) -> std::io::Result<usize> {
+        let tables = self
+            .tables
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut moved = 0;
+        for table in tables {
+            moved += table.demote(before, dir)?;
+        }
+        Ok(moved)
+    }
+
+    /// Load a snapshot written by [`InMemoryStore::snapshot`]. All restored routes are marked as
+    /// stale, until the router reconnects and sends them again. Without `routes`, only the clients,
+    /// sessions and metadata are restored.
+    pub async fn restore(&self, data: &[u8], routes: bool) -> anyhow::Result<()> {
+        let mut reader = SnapshotReader::new(data)?;
+        for _ in 0..reader.count()? {
+            let (client_addr, mut client) = reader.client()?;
+            self.apply_client_metadata(&client_addr, &mut client);
+            self.clients.lock().unwrap().insert(client_addr, client);
+            self.restored_clients.lock().unwrap().insert(client_addr);
+        }
+        for _ in 0..reader.count()? {
+            let (session_id, mut session) = reader.session()?;
+            // the restored routes are stale until the session is synced again
+            session.synced = false;
+            self.sessions.lock().unwrap().insert(session_id, session);
+        }
+        let metadata = reader.metadata()?;
+        {
+            let mut peer_stats = self.peer_stats.lock().unwrap();
+            for stats in metadata.peer_stats {
+                let counters = Arc::new(PeerCounters::from(&stats));
+                peer_stats.insert((stats.router_id, stats.peer_address), (stats, counters));
+            }
+        }
+        {
+            let mut peer_states = self.peer_states.lock().unwrap();
+            for status in metadata.peer_states {
+                peer_states.insert(status.session_id.clone(), status);
+            }
+        }
+        {
+            let mut events = self.session_down_events.lock().unwrap();
+            events.extend(metadata.session_down_events);
+            while events.len() > self.session_event_log_size {
+                events.pop_front();
+            }
+        }
+        if !routes {
+            return Ok(());
+        }
+        let mut route_count = 0;
+        for _ in 0..reader.count()? {
+            let (table_sel, count) = reader.table()?;
+            let table = self.get_table(table_sel);
+            for _ in 0..count {
+                let (net, path_id, attrs) = reader.route()?;
+                table.update_route(path_id, net, attrs).await;
+            }
+            table.mark_stale().await;
+            route_count += count;
+        }
+        info!("restored {} routes from snapshot", route_count);
+        Ok(())
+    }
+
+    /// A client restored from a snapshot reconnected, likely from a different source port. Move its
+    /// sessions and tables over to the new address.
+    fn take_over_restored_client(&self, old_addr: SocketAddr, new_addr: SocketAddr) {
+        self.clients.lock().unwrap().remove(&old_addr);
+        if old_addr == new_addr {
+            return;
+        }
+        let mut sessions = self.sessions.lock().unwrap();
+        let moved = sessions
+            .keys()
+            .filter(|k| k.from_client == old_addr)
+            .cloned()
+            .collect::<Vec<_>>();
+        for mut session_id in moved {
+            let session = sessions.remove(&session_id).unwrap();
+            session_id.from_client = new_addr;
+            sessions.insert(session_id, session);
+        }
+        drop(sessions);
+
+        let mut peer_states = self.peer_states.lock().unwrap();
+        let moved = peer_states
+            .keys()
+            .filter(|k| k.from_client == old_addr)
+            .cloned()
+            .collect::<Vec<_>>();
+        for session_id in moved {
+            let mut status = peer_states.remove(&session_id).unwrap();
+            status.session_id.from_client = new_addr;
+            peer_states.insert(status.session_id.clone(), status);
+        }
+        drop(peer_states);
+
+        let mut tables = self.tables.lock().unwrap();
+        let moved = tables
+            .keys()
+            .filter(|k| k.client_addr() == &old_addr)
+            .cloned()
+            .collect::<Vec<_>>();
+        for mut table_sel in moved {
+            let table = tables.remove(&table_sel).unwrap();
+            table_sel.session_id.from_client = new_addr;
+            tables.insert(table_sel, table);
+        }
+    }
+
+    /// Add the peers to a recent event of the same router, or start a new event
+    fn publish_session_event(&self, event: SessionEvent) {
+        // sending only fails if nobody is subscribed
+        let _ = self.session_event_broadcast.send(event);
+    }
+
+    fn record_session_down(&self, from_client: SocketAddr, peers: Vec<IpAddr>, router_down: bool) {
+        if peers.is_empty() || self.session_event_log_size == 0 {
+            return;
+        }
+        let now = unix_timestamp();
+        let mut events = self.session_down_events.lock().unwrap();
+        let recent = events.iter_mut().rev().find(|event| {
+            event.from_client == from_client
+                && event.timestamp + self.session_down_correlation_window >= now
+        });
+        match recent {
+            Some(event) => {
+                for peer in peers {
+                    if !event.peers.contains(&peer) {
+                        event.peers.push(peer);
+                    }
+                }
+                event.router_level = true;
+            }
+            None => {
+                if events.len() >= self.session_event_log_size {
+                    events.pop_front();
+                }
+                events.push_back(SessionDownEvent {
+                    timestamp: now,
+                    from_client,
+                    router_level: router_down || peers.len() > 1,
+                    peers,
+                });
+            }
+        }
+    }
+
+    /// Mark the peer as down, unless it already is, e.g. because its router disconnected before
+    fn record_peer_down(
+        &self,
+        session_id: &SessionId,
+        reason: Option<PeerDownReason>,
+    ) -> SessionDown {
+        let now = unix_timestamp();
+        let mut peer_states = self.peer_states.lock().unwrap();
+        let status = peer_states
+            .entry(session_id.clone())
+            .or_insert_with(|| PeerStatus {
+                session_id: session_id.clone(),
+                state: PeerState::Up,
+                since: now,
+                last_down: None,
+            });
+        if status.state == PeerState::Up {
+            status.state = PeerState::Down;
+            status.since = now;
+            status.last_down = Some(SessionDown {
+                timestamp: now,
+                reason,
+            });
+        }
+        status.last_down.clone().unwrap()
+    }
+
+    fn update_peer_stats(
+        &self,
+        session_id: Option<&SessionId>,
+        f: impl FnOnce(&mut PeerStats),
+    ) -> Option<Arc<PeerCounters>> {
+        let session_id = session_id?;
+        let router_id = self
+            .clients
+            .lock()
+            .unwrap()
+            .get(&session_id.from_client)
+            .map(|client| client.router_id)?;
+        let mut peer_stats = self.peer_stats.lock().unwrap();
+        let (stats, counters) = peer_stats
+            .entry((router_id, session_id.peer_address))
+            .or_insert_with(|| {
+                let stats = PeerStats {
+                    router_id,
+                    peer_address: session_id.peer_address,
+                    updates: 0,
+                    withdraws: 0,
+                    flaps: 0,
+                    bmp_statistics: None,
+                };
+                (stats, Default::default())
+            });
+        f(stats);
+        Some(counters.clone())
+    }
+
+    /// Counters of the peer of the session, looked up in the peer stats once per session
+    fn session_counters(&self, session_id: Option<&SessionId>) -> Option<Arc<PeerCounters>> {
+        let session_id = session_id?;
+        if let Some(counters) = self.session_counters.read().unwrap().get(session_id) {
+            return Some(counters.clone());
+        }
+        let counters = self.update_peer_stats(Some(session_id), |_| {})?;
+        self.session_counters
+            .write()
+            .unwrap()
+            .insert(session_id.clone(), counters.clone());
+        Some(counters)
+    }
+
+    /// Routes stored for the session and its address families. Pre- and post-policy tables contain
+    /// mostly the same routes, so the table type with the most routes is counted.
+    fn session_routes(&self, session_id: &SessionId) -> (usize, HashSet<AfiSafi>) {
+        let mut by_type: HashMap<TableType, usize> = HashMap::new();
+        let mut afi_safis = HashSet::new();
+        for (sel, table) in self.tables.lock().unwrap().iter() {
+            if &sel.session_id == session_id {
+                *by_type.entry(sel.table_type.clone()).or_default() += table.route_count();
+                afi_safis.insert(sel.afi_safi);
+            }
+        }
+        (by_type.into_values().max().unwrap_or(0), afi_safis)
+    }
+
+    fn sync_complete(&self, session_id: &SessionId, afi_safis: &HashSet<AfiSafi>) -> bool {
+        self.end_of_rib_received
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map(|received| !received.is_empty() && afi_safis.is_subset(received))
+            .unwrap_or(false)
+    }
+
+    /// Fill in the configured metadata of the peer, entries for a specific router take precedence
+    fn apply_session_metadata(&self, session_id: &SessionId, session: &mut Session) {
+        if self.session_metadata.is_empty() {
+            return;
+        }
+        let router_id = self
+            .clients
+            .lock()
+            .unwrap()
+            .get(&session_id.from_client)
+            .map(|client| client.router_id);
+        let entries = self
+            .session_metadata
+            .iter()
+            .filter(|entry| entry.peer_address == session_id.peer_address);
+        for entry in entries.clone().filter(|entry| entry.router_id.is_none()) {
+            entry.apply(session);
+        }
+        for entry in
+            entries.filter(|entry| entry.router_id.is_some() && entry.router_id == router_id)
+        {
+            entry.apply(session);
+        }
+    }
+
+    /// Fill in the configured names and labels of the router, entries matching both address and
+    /// router id are applied last and take precedence
+    fn apply_client_metadata(&self, client_addr: &SocketAddr, client: &mut Client) {
+        let entries = self
+            .client_metadata
+            .iter()
+            .filter(|entry| entry.matches(client_addr, client))
+            .collect::<Vec<_>>();
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.address.is_none() || entry.router_id.is_none())
+        {
+            entry.apply(&mut client.metadata);
+        }
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.address.is_some() && entry.router_id.is_some())
+        {
+            entry.apply(&mut client.metadata);
+        }
+    }
+
+    /// Remember the number of routes of a completely synced session, as the expected size for the
+    /// next time the session comes up
+    fn record_table_size(&self, session_id: &SessionId) {
+        let (routes, afi_safis) = self.session_routes(session_id);
+        if !self.sync_complete(session_id, &afi_safis) {
+            return;
+        }
+        let Some(router_id) = self
+            .clients
+            .lock()
+            .unwrap()
+            .get(&session_id.from_client)
+            .map(|client| client.router_id)
+        else {
+            return;
+        };
+        self.table_sizes
+            .lock()
+            .unwrap()
+            .insert((router_id, session_id.peer_address), routes);
+    }
+
+    fn log_change(&self, change: LoggedChange) {
+        if self.change_broadcast.receiver_count() > 0 {
+            let _ = self.change_broadcast.send(change.decompress());
+        }
+        self.change_log.lock().unwrap().push(change);
+    }
+
+    /// Remove the tables matching `filter` and record their removal in the change log
+    fn remove_tables(&self, filter: impl Fn(&TableSelector) -> bool) {
+        self.flowspec.lock().unwrap().retain(|k, _| !filter(k));
+        self.pre_policy_recency
+            .lock()
+            .unwrap()
+            .retain(|k, _| !filter(k));
+        self.pre_policy_pending
+            .lock()
+            .unwrap()
+            .retain(|(session_id, afi_safi), _| {
+                !filter(&TableSelector {
+                    route_distinguisher: Default::default(),
+                    session_id: session_id.clone(),
+                    table_type: TableType::PrePolicyAdjIn,
+                    afi_safi: *afi_safi,
+                })
+            });
+        let mut removed = vec![];
+        self.tables.lock().unwrap().retain(|k, v| {
+            if filter(k) {
+                removed.push((k.clone(), v.clone()));
+            }
+            !filter(k)
+        });
+        for (table_sel, table) in removed {
+            table.release_quota();
+            self.record_history(|| HistoryChange::TableRemoved {
+                table: table_sel.clone(),
+                contents: table,
+            });
+            self.log_change(LoggedChange::TableRemoved(table_sel));
+        }
+        self.caches.lock().unwrap().remove_expired();
+    }
+
+    /// Set the per-client and per-table gauges. `previous` holds the update and withdraw counters
+    /// of each router at the last call, to compute the rates over `elapsed` seconds.
+    fn update_metrics(&self, previous: &mut HashMap<RouterId, (u64, u64)>, elapsed: f64) {
+        let clients = self.clients.lock().unwrap().clone();
+        let tables = self.tables.lock().unwrap().clone();
+
+        crate::metrics::TABLE_ROUTES.reset();
+        let mut client_routes: HashMap<SocketAddr, usize> = HashMap::new();
+        for (table_sel, table) in &tables {
+            let Some(client) = clients.get(table_sel.client_addr()) else {
+                continue;
+            };
+            let routes = table.route_count();
+            *client_routes.entry(*table_sel.client_addr()).or_default() += routes;
+            crate::metrics::TABLE_ROUTES
+                .with_label_values(&[
+                    &client.client_name,
+                    &table_sel.session_id.peer_address.to_string(),
+                    table_sel.table_type.name(),
+                    table_sel.afi_safi.afi(),
+                    table_sel.afi_safi.safi(),
+                    &table_sel.route_distinguisher.to_string(),
+                ])
+                .set(routes as i64);
+        }
+
+        // routing instances other than the default one, e.g. customer VRFs
+        let mut vrfs: HashMap<(SocketAddr, RouteDistinguisher), (usize, Option<u64>)> =
+            HashMap::new();
+        for (table_sel, table) in &tables {
+            if table_sel.route_distinguisher.is_default() {
+                continue;
+            }
+            let vrf = vrfs
+                .entry((*table_sel.client_addr(), table_sel.route_distinguisher))
+                .or_default();
+            vrf.0 += table.route_count();
+            vrf.1 = vrf.1.max(table.last_update());
+        }
+        crate::metrics::VRF_ROUTES.reset();
+        crate::metrics::VRF_LAST_UPDATE.reset();
+        for ((client_addr, rd), (routes, last_update)) in vrfs {
+            let Some(client) = clients.get(&client_addr) else {
+                continue;
+            };
+            let labels = [
+                client.client_name.as_str(),
+                &rd.to_string(),
+                client
+                    .loc_rib_tables
+                    .get(&rd)
+                    .map(String::as_str)
+                    .unwrap_or(""),
+            ];
+            crate::metrics::VRF_ROUTES
+                .with_label_values(&labels)
+                .set(routes as i64);
+            if let Some(last_update) = last_update {
+                crate::metrics::VRF_LAST_UPDATE
+                    .with_label_values(&labels)
+                    .set(last_update as i64);
+            }
+        }
+
+        crate::metrics::PEER_FUNNEL_ROUTES.reset();
+        for funnel in self.get_peer_funnels() {
+            let Some(client) = clients.get(&funnel.session_id.from_client) else {
+                continue;
+            };
+            let peer = funnel.session_id.peer_address.to_string();
+            for (stage, routes) in [
+                ("received", funnel.received),
+                ("accepted", funnel.accepted),
+                ("installed", funnel.installed),
+                ("selected", funnel.selected),
+            ] {
+                crate::metrics::PEER_FUNNEL_ROUTES
+                    .with_label_values(&[&client.client_name, &peer, stage])
+                    .set(routes as i64);
+            }
+        }
+
+        let mut client_sessions: HashMap<SocketAddr, usize> = HashMap::new();
+        for session_id in self.sessions.lock().unwrap().keys() {
+            *client_sessions.entry(session_id.from_client).or_default() += 1;
+        }
+        let mut counters: HashMap<RouterId, (u64, u64)> = HashMap::new();
+        for ((router_id, _), (_, peer_counters)) in self.peer_stats.lock().unwrap().iter() {
+            let counter = counters.entry(*router_id).or_default();
+            counter.0 += peer_counters.updates.load(Ordering::Relaxed);
+            counter.1 += peer_counters.withdraws.load(Ordering::Relaxed);
+        }
+
+        crate::metrics::CLIENT_ROUTES.reset();
+        crate::metrics::CLIENT_SESSIONS.reset();
+        crate::metrics::CLIENT_UPDATE_RATE.reset();
+        crate::metrics::CLIENT_WITHDRAW_RATE.reset();
+        for (client_addr, client) in &clients {
+            let labels = [client.client_name.as_str()];
+            let routes = client_routes.get(client_addr).copied().unwrap_or(0);
+            crate::metrics::CLIENT_ROUTES
+                .with_label_values(&labels)
+                .set(routes as i64);
+            let sessions = client_sessions.get(client_addr).copied().unwrap_or(0);
+            crate::metrics::CLIENT_SESSIONS
+                .with_label_values(&labels)
+                .set(sessions as i64);
+
+            let (updates, withdraws) = counters.get(&client.router_id).copied().unwrap_or_default();
+            if let Some((previous_updates, previous_withdraws)) = previous.get(&client.router_id) {
+                if elapsed > 0.0 {
+                    crate::metrics::CLIENT_UPDATE_RATE
+                        .with_label_values(&labels)
+                        .set(updates.saturating_sub(*previous_updates) as f64 / elapsed);
+                    crate::metrics::CLIENT_WITHDRAW_RATE
+                        .with_label_values(&labels)
+                        .set(withdraws.saturating_sub(*previous_withdraws) as f64 / elapsed);
+                }
+            }
+        }
+        *previous = counters;
+
+        let memory = self.caches.lock().unwrap().memory_estimate();
+        crate::metrics::ATTRIBUTE_CACHE_BYTES.set(memory as i64);
+    }
+
+    fn record_history(&self, change: impl FnOnce() -> HistoryChange) {
+        let mut history = self.history.lock().unwrap();
+        if history.is_enabled() {
+            history.push(change());
+        }
+    }
+
+    /// The tables a query is executed on, and for queries of an earlier point in time the paths
+    /// which changed since in each table
+    fn query_tables(
+        &self,
+        query: &Query,
+    ) -> Result<(Vec<(TableSelector, InMemoryTable)>, Overlays), StoreError> {
+        let rewound = match query.at {
+            Some(at) => match self.history.lock().unwrap().rewind(at) {
+                Some(rewound) => rewound,
+                None => {
+                    return Err(StoreError::InvalidQuery(format!(
+                        "history does not reach back to {}",
+                        at
+                    )));
+                }
+            },
+            None => Default::default(),
+        };
+
+        let mut tables = self.get_tables(query.table_query.clone());
+
+        // tables which were removed since are queried with their contents at the time of removal
+        for (table_sel, table) in tables.iter_mut() {
+            if let Some(contents) = rewound.get(table_sel).and_then(|r| r.contents.as_ref()) {
+                *table = contents.clone();
+            }
+        }
+        for (table_sel, rewound_table) in &rewound {
+            if let Some(contents) = &rewound_table.contents {
+                if !tables.iter().any(|(sel, _)| sel == table_sel)
+                    && self.table_query_matches(&query.table_query, table_sel)
+                {
+                    tables.push((table_sel.clone(), contents.clone()));
+                }
+            }
+        }
+        let overlays = rewound
+            .into_iter()
+            .filter(|(_, rewound_table)| !rewound_table.paths.is_empty())
+            .map(|(table_sel, rewound_table)| (table_sel, rewound_table.paths))
+            .collect::<HashMap<_, _>>();
+
+        let table_scope = match query.table_query {
+            Some(_) => TableScope::All,
+            None => query.table_scope.unwrap_or_default(),
+        };
+        tables.retain(|table| {
+            query.selects_route_distinguisher(&table.0.route_distinguisher)
+                && table.0.afi_safi.contains_family_of(query.net_query.net())
+                && table_scope.contains(&table.0.table_type)
+        });
+        Ok((tables, overlays))
+    }
+
+    /// Whether a table which is no longer in the store would be selected by `table_query`
+    fn table_query_matches(&self, table_query: &Option<TableQuery>, table: &TableSelector) -> bool {
+        match table_query {
+            None => true,
+            Some(TableQuery::Table(sel)) => sel == table,
+            Some(TableQuery::Session(session_id)) => table.session_id() == Some(session_id),
+            Some(TableQuery::Client(client_addr)) => table.client_addr() == client_addr,
+            Some(TableQuery::Router(router_id)) => self
+                .clients
+                .lock()
+                .unwrap()
+                .get(table.client_addr())
+                .map(|client| &client.router_id == router_id)
+                .unwrap_or(false),
+        }
+    }
+
+    /// Compares the origin of an announced path against the previous origins of the prefix in
+    /// the table: the replaced path, the other paths, or the last path if the prefix was withdrawn
+    fn record_origin_change(
+        &self,
+        net: IpNet,
+        table_sel: TableSelector,
+        table: &InMemoryTable,
+        path_id: PathId,
+        old: Option<&CompressedRouteAttrs>,
+        new_origin_asn: Option<u32>,
+    ) {
+        let Some(new_origin_asn) = new_origin_asn else {
+            return;
+        };
+        if self.origin_change_log_size == 0 {
+            return;
+        }
+        let replaced = old.and_then(origin_asn);
+        if replaced == Some(new_origin_asn) {
+            return;
+        }
+        let previous = replaced
+            .into_iter()
+            .chain(
+                table
+                    .get_routes(Some(&NetQuery::Exact(net)))
+                    .iter()
+                    .filter(|(_, route)| route.path_id != path_id)
+                    .filter_map(|(_, route)| origin_asn(&route.attrs)),
+            )
+            .collect::<Vec<_>>();
+        if previous.contains(&new_origin_asn) {
+            return;
+        }
+        let old_origin_asn = match previous.first() {
+            Some(asn) => *asn,
+            None => match self
+                .withdrawn_origins
+                .lock()
+                .unwrap()
+                .remove(&(table_sel.clone(), net))
+            {
+                Some((asn, _)) if asn != new_origin_asn => asn,
+                _ => return,
+            },
+        };
+
+        let mut origin_changes = self.origin_changes.lock().unwrap();
+        if origin_changes.len() >= self.origin_change_log_size {
+            origin_changes.pop_front();
+        }
+        origin_changes.push_back(OriginChange {
+            timestamp: unix_timestamp(),
+            net,
+            table: table_sel,
+            old_origin_asn,
+            new_origin_asn,
+        });
+    }
+
+    /// Remember the origin of the last path of a prefix, so a re-announcement from another
+    /// origin is recorded as origin change
+    fn record_withdrawn_origin(
+        &self,
+        net: IpNet,
+        table_sel: &TableSelector,
+        table: &InMemoryTable,
+        old: &CompressedRouteAttrs,
+    ) {
+        let Some(asn) = origin_asn(old) else {
+            return;
+        };
+        if self.origin_change_log_size == 0
+            || !table.get_routes(Some(&NetQuery::Exact(net))).is_empty()
+        {
+            return;
+        }
+        let now = unix_timestamp();
+        let mut withdrawn_origins = self.withdrawn_origins.lock().unwrap();
+        if withdrawn_origins.len() >= WITHDRAWN_ORIGINS_CAPACITY {
+            withdrawn_origins
+                .retain(|_, (_, since)| now.saturating_sub(*since) < WITHDRAWN_ORIGIN_RETENTION);
+            if withdrawn_origins.len() >= WITHDRAWN_ORIGINS_CAPACITY {
+                return;
+            }
+        }
+        withdrawn_origins.insert((table_sel.clone(), net), (asn, now));
+    }
+
+    /// Record a path of `net` being announced, changed or withdrawn
+    fn record_prefix_change(
+        &self,
+        net: IpNet,
+        table: &TableSelector,
+        path_id: PathId,
+        old: Option<&CompressedRouteAttrs>,
+        new: Option<&CompressedRouteAttrs>,
+    ) {
+        if self.prefix_history_size == 0 || old == new {
+            return;
+        }
+
+        let as_path = |attrs: Option<&CompressedRouteAttrs>| {
+            attrs.and_then(|attrs| attrs.as_path.as_ref().map(|as_path| (**as_path).clone()))
+        };
+        let mut prefix_history = self.prefix_history.lock().unwrap();
+        let changes = prefix_history.entry(net).or_default();
+        if changes.len() >= self.prefix_history_size {
+            changes.pop_front();
+        }
+        changes.push_back(PrefixChange {
+            timestamp: unix_timestamp(),
+            table: table.clone(),
+            path_id,
+            old_as_path: as_path(old),
+            new_as_path: as_path(new),
+            old_nexthop: old.and_then(|attrs| attrs.nexthop),
+            new_nexthop: new.and_then(|attrs| attrs.nexthop),
+        });
+    }
+
     fn tables_for_router_fn<'a>(
         &self,
         query_router_id: &'a RouterId,
@@ -56,13 +1290,188 @@ impl InMemoryStore {
         }
     }
     fn get_table(&self, sel: TableSelector) -> InMemoryTable {
+        let quota_count = self.route_quotas.limit(&sel.route_distinguisher).map(|_| {
+            self.quota_counts
+                .lock()
+                .unwrap()
+                .entry(sel.route_distinguisher)
+                .or_default()
+                .clone()
+        });
         self.tables
             .lock()
             .unwrap()
             .entry(sel)
-            .or_insert(InMemoryTable::new(self.caches.clone()))
+            .or_insert_with(|| InMemoryTable::new(self.caches.clone(), quota_count))
             .clone()
     }
+
+    /// Remove a path from the table and record the withdraw
+    async fn remove_path(
+        &self,
+        table_sel: TableSelector,
+        table: &InMemoryTable,
+        path_id: PathId,
+        net: IpNet,
+    ) {
+        if let Some(old) = table.withdraw_route(path_id, net).await {
+            self.record_withdrawn_origin(net, &table_sel, table, &old.attrs);
+            self.record_prefix_change(net, &table_sel, path_id, Some(&old.attrs), None);
+            self.record_history(|| HistoryChange::Path {
+                table: table_sel.clone(),
+                net,
+                path_id,
+                old: Some(old),
+            });
+            self.log_change(LoggedChange::Route {
+                table: table_sel,
+                net,
+                path_id,
+                attrs: None,
+            });
+        }
+    }
+
+    fn post_policy_table(&self, table_sel: &TableSelector) -> Option<InMemoryTable> {
+        let post_policy = TableSelector {
+            table_type: TableType::PostPolicyAdjIn,
+            ..table_sel.clone()
+        };
+        self.tables.lock().unwrap().get(&post_policy).cloned()
+    }
+
+    /// Whether an update of a pre-policy path is stored according to the configured retention.
+    /// Paths evicted to make room for it are removed from the table.
+    async fn retain_pre_policy(
+        &self,
+        table_sel: &TableSelector,
+        path_id: PathId,
+        net: IpNet,
+    ) -> bool {
+        match self.pre_policy_retention {
+            PrePolicyRetention::All => true,
+            PrePolicyRetention::Sampled { .. } => self.pre_policy_retention.sampled(&net),
+            PrePolicyRetention::MostRecent { routes } => {
+                let evicted = self
+                    .pre_policy_recency
+                    .lock()
+                    .unwrap()
+                    .entry(table_sel.clone())
+                    .or_default()
+                    .touch(path_id, net, routes);
+                let table = self.tables.lock().unwrap().get(table_sel).cloned();
+                if let Some(table) = table {
+                    for (path_id, net) in evicted {
+                        self.remove_path(table_sel.clone(), &table, path_id, net)
+                            .await;
+                    }
+                }
+                routes > 0
+            }
+            PrePolicyRetention::PostPolicy => {
+                // the route is stored until it is known whether it passes the policy
+                let in_post_policy = self
+                    .post_policy_table(table_sel)
+                    .map(|table| !table.get_routes(Some(&NetQuery::Exact(net))).is_empty())
+                    .unwrap_or(false);
+                let now = unix_timestamp();
+                let due = {
+                    let mut pending = self.pre_policy_pending.lock().unwrap();
+                    let pending = pending
+                        .entry((table_sel.session_id.clone(), table_sel.afi_safi))
+                        .or_default();
+                    if !in_post_policy {
+                        pending.push(table_sel.route_distinguisher, net, now);
+                    }
+                    pending.due(now, POST_POLICY_GRACE)
+                };
+                self.prune_pre_policy(table_sel, due).await;
+                true
+            }
+        }
+    }
+
+    /// Remove the pre-policy routes of the prefixes which are not in the post-policy table
+    async fn prune_pre_policy(
+        &self,
+        table_sel: &TableSelector,
+        nets: Vec<(RouteDistinguisher, IpNet)>,
+    ) {
+        for (route_distinguisher, net) in nets {
+            let pre_policy = TableSelector {
+                route_distinguisher,
+                table_type: TableType::PrePolicyAdjIn,
+                ..table_sel.clone()
+            };
+            let in_post_policy = self
+                .post_policy_table(&pre_policy)
+                .map(|table| !table.get_routes(Some(&NetQuery::Exact(net))).is_empty())
+                .unwrap_or(false);
+            if in_post_policy {
+                continue;
+            }
+            let pre_policy_table = self.tables.lock().unwrap().get(&pre_policy).cloned();
+            if let Some(pre_policy_table) = pre_policy_table {
+                for (_, route) in pre_policy_table.get_routes(Some(&NetQuery::Exact(net))) {
+                    self.remove_path(pre_policy.clone(), &pre_policy_table, route.path_id, net)
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Whether the path may be stored without exceeding the quota of its route distinguisher.
+    /// Updates of paths which are already stored are always accepted.
+    fn within_route_quota(
+        &self,
+        table_sel: &TableSelector,
+        table: &InMemoryTable,
+        path_id: PathId,
+        net: IpNet,
+    ) -> bool {
+        let (Some(limit), Some(count)) = (
+            self.route_quotas.limit(&table_sel.route_distinguisher),
+            table.quota_count(),
+        ) else {
+            return true;
+        };
+        let rd = table_sel.route_distinguisher;
+        let mut exceeded = self.quotas_exceeded.lock().unwrap();
+        if count < limit {
+            if exceeded.remove(&rd) {
+                info!("route distinguisher {} is within its route quota again", rd);
+                crate::metrics::ROUTE_QUOTA_EXCEEDED
+                    .with_label_values(&[&rd.to_string()])
+                    .set(0);
+            }
+            return true;
+        }
+        if table.contains(path_id, net) {
+            return true;
+        }
+        if exceeded.insert(rd) {
+            warn!(
+                "route distinguisher {} exceeded its quota of {} routes, further routes are dropped",
+                rd, limit
+            );
+            crate::metrics::ROUTE_QUOTA_EXCEEDED
+                .with_label_values(&[&rd.to_string()])
+                .set(1);
+        }
+        crate::metrics::ROUTE_QUOTA_REJECTED
+            .with_label_values(&[&rd.to_string()])
+            .inc();
+        false
+    }
+    fn get_tables(&self, table_query: Option<TableQuery>) -> Vec<(TableSelector, InMemoryTable)> {
+        match table_query {
+            Some(TableQuery::Table(table)) => vec![(table.clone(), self.get_table(table))],
+            Some(TableQuery::Client(client_addr)) => self.get_tables_for_client(&client_addr),
+            Some(TableQuery::Router(router_id)) => self.get_tables_for_router(&router_id),
+            Some(TableQuery::Session(session_id)) => self.get_tables_for_session(&session_id),
+            None => self.tables.lock().unwrap().clone().into_iter().collect(),
+        }
+    }
     fn get_tables_for_client(
         &self,
         client_addr: &SocketAddr,
@@ -84,6 +1493,17 @@ impl InMemoryStore {
             .map(|(k, v)| (k.clone(), v.clone()))
             .collect()
     }
+    /// Tables of the session and table type of `sel`, with any route distinguisher and address
+    /// family
+    fn get_tables_for_peer(&self, sel: &TableSelector) -> Vec<(TableSelector, InMemoryTable)> {
+        self.tables
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(k, _)| k.session_id == sel.session_id && k.table_type == sel.table_type)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
     fn get_tables_for_session(
         &self,
         session_id: &SessionId,
@@ -98,6 +1518,51 @@ impl InMemoryStore {
     }
 }
 
+fn path_candidate(route: &RouteEntry) -> PathCandidate<'_> {
+    PathCandidate {
+        path_id: route.path_id,
+        local_pref: route.attrs.local_pref,
+        as_path_len: route
+            .attrs
+            .as_path
+            .as_ref()
+            .map(|as_path| as_path.len())
+            .unwrap_or(0),
+        neighbor_asn: route
+            .attrs
+            .as_path
+            .as_ref()
+            .and_then(|as_path| as_path.neighbor_asn()),
+        origin: route.attrs.origin.as_ref(),
+        med: route.attrs.med,
+        // all paths of a table are received from the same neighbor
+        router_id: None,
+    }
+}
+
+/// Key which orders routes by `sort`, then by prefix, table and path id
+fn route_sort_key(
+    sort: SortOrder,
+    (table_sel, net, route): &(TableSelector, IpNet, RouteEntry),
+) -> (usize, Vec<u8>, IpNet, Vec<u8>, PathId) {
+    let table_key = table_sort_key(table_sel);
+    let primary = match sort {
+        SortOrder::Prefix | SortOrder::Table => 0,
+        SortOrder::PrefixLength => net.prefix_len() as usize,
+        SortOrder::AsPathLength => route
+            .attrs
+            .as_path
+            .as_ref()
+            .map(|as_path| as_path.len())
+            .unwrap_or(0),
+    };
+    let table_first = match sort {
+        SortOrder::Table => table_key.clone(),
+        _ => vec![],
+    };
+    (primary, table_first, *net, table_key, route.path_id)
+}
+
 #[async_trait]
 impl Store for InMemoryStore {
     #[autometrics::autometrics]
@@ -106,50 +1571,367 @@ impl Store for InMemoryStore {
         path_id: PathId,
         net: IpNet,
         table: TableSelector,
-        route: RouteAttrs,
-    ) {
-        let table = self.get_table(table);
-        table.update_route(path_id, net, route).await;
+        mut route: RouteAttrs,
+    ) -> Result<(), StoreError> {
+        self.community_rules.apply(&mut route);
+        self.attribute_limits.apply(&mut route);
+        count_route_change(&crate::metrics::ROUTE_UPDATES, &table);
+        if let Some(counters) = self.session_counters(table.session_id()) {
+            counters.updates.fetch_add(1, Ordering::Relaxed);
+        }
+        self.flap_stats
+            .lock()
+            .unwrap()
+            .record(net, false, unix_timestamp());
+        if table.table_type == TableType::PrePolicyAdjIn
+            && !self.retain_pre_policy(&table, path_id, net).await
+        {
+            return Ok(());
+        }
+        let new_origin_asn = route
+            .as_path
+            .as_ref()
+            .and_then(|as_path| as_path.origin_asn());
+        let table_sel = table;
+        let table = self.get_table(table_sel.clone());
+        if !self.within_route_quota(&table_sel, &table, path_id, net) {
+            return Err(StoreError::LimitExceeded(format!(
+                "route quota of route distinguisher {}",
+                table_sel.route_distinguisher
+            )));
+        }
+        let (attrs, old) = table.update_route(path_id, net, route).await;
+        self.record_origin_change(
+            net,
+            table_sel.clone(),
+            &table,
+            path_id,
+            old.as_ref().map(|old| &*old.attrs),
+            new_origin_asn,
+        );
+        self.record_prefix_change(
+            net,
+            &table_sel,
+            path_id,
+            old.as_ref().map(|old| &*old.attrs),
+            Some(&*attrs),
+        );
+        self.record_history(|| HistoryChange::Path {
+            table: table_sel.clone(),
+            net,
+            path_id,
+            old,
+        });
+        self.log_change(LoggedChange::Route {
+            table: table_sel,
+            net,
+            path_id,
+            attrs: Some(attrs),
+        });
+        Ok(())
+    }
+
+    #[autometrics::autometrics]
+    async fn withdraw_route(
+        &self,
+        path_id: PathId,
+        net: IpNet,
+        table: TableSelector,
+    ) -> Result<(), StoreError> {
+        count_route_change(&crate::metrics::ROUTE_WITHDRAWS, &table);
+        if let Some(counters) = self.session_counters(table.session_id()) {
+            counters.withdraws.fetch_add(1, Ordering::Relaxed);
+        }
+        self.flap_stats
+            .lock()
+            .unwrap()
+            .record(net, true, unix_timestamp());
+        let reduced_pre_policy = table.table_type == TableType::PrePolicyAdjIn
+            && self.pre_policy_retention != PrePolicyRetention::All;
+        if reduced_pre_policy {
+            if let Some(recency) = self.pre_policy_recency.lock().unwrap().get_mut(&table) {
+                recency.remove(path_id, net);
+            }
+        }
+        // withdraws must not create the table
+        let Some(t) = self.tables.lock().unwrap().get(&table).cloned() else {
+            // the pre-policy table only exists once a route was retained
+            if reduced_pre_policy {
+                return Ok(());
+            }
+            return Err(StoreError::TableMissing(table));
+        };
+        self.remove_path(table.clone(), &t, path_id, net).await;
+
+        // the pre-policy routes of a prefix are only kept while it is in the post-policy table
+        if table.table_type == TableType::PostPolicyAdjIn
+            && self.pre_policy_retention == PrePolicyRetention::PostPolicy
+        {
+            self.prune_pre_policy(&table, vec![(table.route_distinguisher, net)])
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn update_flowspec(
+        &self,
+        table: TableSelector,
+        rule: FlowspecRule,
+        attrs: RouteAttrs,
+    ) -> Result<(), StoreError> {
+        count_route_change(&crate::metrics::ROUTE_UPDATES, &table);
+        let mut flowspec = self.flowspec.lock().unwrap();
+        let rules = flowspec.entry(table).or_default();
+        let since = rules
+            .get(&rule)
+            .map(|(_, since)| *since)
+            .unwrap_or_else(unix_timestamp);
+        rules.insert(rule, (attrs, since));
+        Ok(())
+    }
+
+    async fn withdraw_flowspec(
+        &self,
+        table: TableSelector,
+        rule: FlowspecRule,
+    ) -> Result<(), StoreError> {
+        count_route_change(&crate::metrics::ROUTE_WITHDRAWS, &table);
+        let mut flowspec = self.flowspec.lock().unwrap();
+        let Some(rules) = flowspec.get_mut(&table) else {
+            return Err(StoreError::TableMissing(table));
+        };
+        rules.remove(&rule);
+        if rules.is_empty() {
+            flowspec.remove(&table);
+        }
+        Ok(())
+    }
+
+    fn get_flowspec(&self, query: FlowspecQuery) -> Vec<FlowspecResult> {
+        let tables = self.flowspec.lock().unwrap().clone();
+        let mut results = vec![];
+        for (table, rules) in tables {
+            if !self.table_query_matches(&query.table_query, &table) {
+                continue;
+            }
+            for (rule, (attrs, since)) in rules {
+                let actions = flowspec::actions(&attrs);
+                if !query.matches(&rule, &actions) {
+                    continue;
+                }
+                results.push(FlowspecResult {
+                    table: table.clone(),
+                    rule,
+                    actions,
+                    attrs,
+                    since,
+                });
+            }
+        }
+        results
     }
 
-    #[autometrics::autometrics]
-    async fn withdraw_route(&self, path_id: PathId, net: IpNet, table: TableSelector) {
-        let table = self.get_table(table);
-        table.withdraw_route(path_id, net).await;
+    async fn update_linkstate(&self, session: SessionId, update: LinkStateUpdate) {
+        self.linkstate.lock().unwrap().apply(session, update);
     }
 
-    fn get_routes(&self, query: Query) -> Pin<Box<dyn Stream<Item = QueryResult> + Send>> {
-        let mut tables = match query.table_query {
-            Some(TableQuery::Table(table)) => vec![(table.clone(), self.get_table(table))],
-            Some(TableQuery::Client(client_addr)) => self.get_tables_for_client(&client_addr),
-            Some(TableQuery::Router(router_id)) => self.get_tables_for_router(&router_id),
-            Some(TableQuery::Session(session_id)) => self.get_tables_for_session(&session_id),
-            None => self.tables.lock().unwrap().clone().into_iter().collect(),
+    fn get_topology(&self) -> Topology {
+        self.linkstate.lock().unwrap().topology()
+    }
+
+    async fn mark_stale(&self, session: TableSelector) -> Result<(), StoreError> {
+        for (_, table) in self.get_tables_for_peer(&session) {
+            table.mark_stale().await;
+        }
+        Ok(())
+    }
+
+    async fn end_of_rib(&self, table: TableSelector) -> Result<(), StoreError> {
+        if let Some(received) = self
+            .end_of_rib_received
+            .lock()
+            .unwrap()
+            .get_mut(&table.session_id)
+        {
+            received.insert(table.afi_safi);
+        }
+        // pre-policy routes received before the post-policy table was complete are kept until now
+        if table.table_type == TableType::PostPolicyAdjIn
+            && self.pre_policy_retention == PrePolicyRetention::PostPolicy
+        {
+            let due = self
+                .pre_policy_pending
+                .lock()
+                .unwrap()
+                .entry((table.session_id.clone(), table.afi_safi))
+                .or_default()
+                .synced();
+            self.prune_pre_policy(&table, due).await;
+        }
+        let mut removed = 0;
+        for (sel, t) in self.get_tables_for_peer(&table) {
+            if sel.afi_safi == table.afi_safi {
+                for (net, old) in t.purge_stale().await {
+                    let path_id = old.path_id;
+                    self.record_prefix_change(net, &sel, path_id, Some(&old.attrs), None);
+                    self.record_history(|| HistoryChange::Path {
+                        table: sel.clone(),
+                        net,
+                        path_id,
+                        old: Some(old),
+                    });
+                    self.log_change(LoggedChange::Route {
+                        table: sel.clone(),
+                        net,
+                        path_id,
+                        attrs: None,
+                    });
+                    removed += 1;
+                }
+            }
+        }
+        if removed > 0 {
+            debug!(
+                "purged {} stale routes of {:?} {:?} on End-of-RIB",
+                removed, table.session_id, table.afi_safi
+            );
+            self.caches.lock().unwrap().remove_expired();
+        }
+        let (_, afi_safis) = self.session_routes(&table.session_id);
+        if self.sync_complete(&table.session_id, &afi_safis) {
+            if let Some(session) = self.sessions.lock().unwrap().get_mut(&table.session_id) {
+                session.synced = true;
+            }
+        }
+        self.record_table_size(&table.session_id);
+        Ok(())
+    }
+
+    fn get_routes(
+        &self,
+        query: Query,
+    ) -> Result<Pin<Box<dyn Stream<Item = QueryResult> + Send>>, StoreError> {
+        let (mut tables, overlays) = self.query_tables(&query)?;
+        let overlays = Arc::new(overlays);
+        let nexthop_resolver =
+            Arc::new(NexthopResolver::new(&self.tables.lock().unwrap(), &tables));
+
+        let paginate = query.paginate || query.after.is_some();
+        let sort = query.sort.filter(|_| !paginate);
+        let after = query
+            .after
+            .as_ref()
+            .map(|cursor| (table_sort_key(&cursor.table), cursor.net, cursor.path_id));
+        let tables = if paginate {
+            // Tables are scanned in a stable order, the results are sorted within each table
+            let mut tables = tables
+                .into_iter()
+                .map(|table| (table_sort_key(&table.0), table))
+                .filter(|(key, _)| match &after {
+                    Some((after_key, _, _)) => key >= after_key,
+                    None => true,
+                })
+                .collect::<Vec<_>>();
+            tables.sort_by(|a, b| a.0.cmp(&b.0));
+            tables
+        } else {
+            // Start scanning the biggest tables first, so a single huge table does not end up as
+            // the last job while the other workers are already idle.
+            tables.sort_by_cached_key(|(_, table)| Reverse(table.route_count()));
+            tables.into_iter().map(|table| (vec![], table)).collect()
         };
 
-        tables.retain(|table| table.0.route_distinguisher == query.route_distinguisher);
+        let mut nets_filter_fn: RouteFilter = Box::new(|_| true);
 
-        let mut nets_filter_fn: Box<
-            dyn Fn(&(TableSelector, IpNet, Arc<CompressedRouteAttrs>)) -> bool + Send + Sync,
-        > = Box::new(|_| true);
+        if !query.include_stale {
+            nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && !i.2.stale);
+        }
 
         if let Some(as_path_regex) = query.as_path_regex {
-            let regex = Regex::new(&as_path_regex).unwrap(); // FIXME error handling
-            let new_filter_fn =
-                move |(_, _, route): &(TableSelector, IpNet, Arc<CompressedRouteAttrs>)| {
-                    let as_path_text = match &route.as_path {
-                        Some(as_path) => as_path
-                            .iter()
-                            .map(|asn| asn.to_string())
-                            .collect::<Vec<_>>()
-                            .join(" "),
-                        None => return false,
-                    };
-                    regex.is_match(&as_path_text)
-                };
+            let matcher =
+                AsPathMatcher::compile(query.as_path_match, &as_path_regex).map_err(|e| {
+                    StoreError::InvalidQuery(format!(
+                        "invalid AS path pattern {:?}: {}",
+                        as_path_regex, e
+                    ))
+                })?;
+            let new_filter_fn = move |(_, _, route): &(TableSelector, IpNet, RouteEntry)| {
+                match &route.attrs.as_path {
+                    Some(as_path) => matcher.is_match(as_path),
+                    None => false,
+                }
+            };
             nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && new_filter_fn(i))
         };
 
+        if query.min_age.is_some() || query.max_age.is_some() {
+            let now = unix_timestamp();
+            let ages = query.min_age.unwrap_or(0)..=query.max_age.unwrap_or(u64::MAX);
+            let new_filter_fn = move |(_, _, route): &(TableSelector, IpNet, RouteEntry)| {
+                ages.contains(&now.saturating_sub(route.since))
+            };
+            nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && new_filter_fn(i))
+        }
+
+        if let Some(nexthop_filter) = query.nexthop {
+            let new_filter_fn = move |(_, _, route): &(TableSelector, IpNet, RouteEntry)| {
+                route
+                    .attrs
+                    .nexthop
+                    .map(|nexthop| nexthop_filter.contains(&nexthop))
+                    .unwrap_or(false)
+            };
+            nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && new_filter_fn(i))
+        }
+
+        if let Some(filter) = query.communities {
+            let new_filter_fn = move |(_, _, route): &(TableSelector, IpNet, RouteEntry)| {
+                let communities = route.attrs.communities.as_deref();
+                filter.matches_communities(communities.map(Vec::as_slice).unwrap_or_default())
+            };
+            nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && new_filter_fn(i))
+        }
+
+        if let Some(filter) = query.large_communities {
+            let new_filter_fn = move |(_, _, route): &(TableSelector, IpNet, RouteEntry)| {
+                filter.matches_large_communities(
+                    route
+                        .attrs
+                        .large_communities
+                        .iter()
+                        .flat_map(|communities| communities.iter())
+                        .map(|community| &**community),
+                )
+            };
+            nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && new_filter_fn(i))
+        }
+
+        if let Some(filter) = query.ext_communities {
+            let new_filter_fn = move |(_, _, route): &(TableSelector, IpNet, RouteEntry)| {
+                let communities = route
+                    .attrs
+                    .ext_communities
+                    .iter()
+                    .flat_map(|communities| communities.iter())
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>();
+                filter.matches(communities.iter().map(String::as_str))
+            };
+            nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && new_filter_fn(i))
+        }
+
+        if let Some(min_prepend_count) = query.min_prepend_count {
+            let new_filter_fn = move |(_, _, route): &(TableSelector, IpNet, RouteEntry)| {
+                route
+                    .attrs
+                    .as_path
+                    .as_ref()
+                    .map(|as_path| as_path.max_prepend_count() >= min_prepend_count)
+                    .unwrap_or(false)
+            };
+            nets_filter_fn = Box::new(move |i| nets_filter_fn(i) && new_filter_fn(i))
+        }
+
         let (tx, rx) = tokio::sync::mpsc::channel(2);
 
         let limits = query.limits.unwrap_or_default();
@@ -164,32 +1946,160 @@ impl Store for InMemoryStore {
             limits.max_results_per_table
         };
 
+        let best_overlays = overlays.clone();
+        let scan_table =
+            move |table_sel: &TableSelector,
+                  table: &InMemoryTable,
+                  filter: &(dyn Fn(&(TableSelector, IpNet, RouteEntry)) -> bool + Send + Sync),
+                  limit: usize| {
+                if let Some(overlay) = overlays.get(table_sel) {
+                    return table
+                        .get_routes_with_overlay(&query.net_query, overlay)
+                        .into_iter()
+                        .filter(|(_, route)| match query.origin_asn {
+                            Some(asn) => origin_asn(&route.attrs) == Some(asn),
+                            None => true,
+                        })
+                        .map(|(net, route)| (table_sel.clone(), net, route))
+                        .filter(filter)
+                        .take(limit)
+                        .collect::<Vec<_>>();
+                }
+                match query.origin_asn {
+                    Some(origin_asn) => table
+                        .get_routes_by_origin(origin_asn, &query.net_query)
+                        .into_iter()
+                        .map(|(net, route)| (table_sel.clone(), net, route))
+                        .filter(filter)
+                        .take(limit)
+                        .collect::<Vec<_>>(),
+                    None => table.with_routes(Some(&query.net_query), |routes| {
+                        routes
+                            .map(|(net, route)| (table_sel.clone(), net, route))
+                            .filter(filter)
+                            .take(limit)
+                            .collect::<Vec<_>>()
+                    }),
+                }
+            };
+
+        // The best path is selected among all paths of the prefix in the table, not only the ones
+        // matching the query
+        let with_best =
+            move |table_sel: &TableSelector,
+                  table: &InMemoryTable,
+                  routes: Vec<(TableSelector, IpNet, RouteEntry)>| {
+                let overlay = best_overlays.get(table_sel);
+                let mut best_paths = HashMap::new();
+                routes
+                    .into_iter()
+                    .map(|i| {
+                        let best_path = *best_paths.entry(i.1).or_insert_with(|| {
+                            let paths = match overlay {
+                                Some(overlay) => {
+                                    table.get_routes_with_overlay(&NetQuery::Exact(i.1), overlay)
+                                }
+                                None => table.get_routes(Some(&NetQuery::Exact(i.1))),
+                            };
+                            bestpath::best_path(
+                                paths.iter().map(|(_, route)| path_candidate(route)),
+                            )
+                        });
+                        let best = best_path == Some(i.2.path_id);
+                        (i, best)
+                    })
+                    .collect::<Vec<_>>()
+            };
+
         rayon::spawn(move || {
-            tables
+            if let Some(sort) = sort {
+                // Every table contributes its first results in the requested order, which are then
+                // merged. The per-table limit can not be applied while scanning, as that would
+                // keep arbitrary routes instead of the first ones.
+                let limit = std::cmp::min(max_results, max_results_per_table);
+                let mut routes = tables
+                    .into_par_iter()
+                    .with_max_len(1)
+                    .flat_map(|(_, (table_sel, table))| {
+                        let mut routes =
+                            scan_table(&table_sel, &table, &nets_filter_fn, usize::MAX)
+                                .into_iter()
+                                .map(|i| (route_sort_key(sort, &i), i))
+                                .collect::<Vec<_>>();
+                        if routes.len() > limit {
+                            routes.select_nth_unstable_by(limit, |a, b| a.0.cmp(&b.0));
+                            routes.truncate(limit);
+                        }
+                        let (keys, routes): (Vec<_>, Vec<_>) = routes.into_iter().unzip();
+                        keys.into_iter()
+                            .zip(with_best(&table_sel, &table, routes))
+                            .collect::<Vec<_>>()
+                            .into_par_iter()
+                    })
+                    .collect::<Vec<_>>();
+                routes.par_sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                for (_, res) in routes.into_iter().take(max_results) {
+                    if tx.blocking_send(res).is_err() {
+                        break;
+                    }
+                }
+                return;
+            }
+
+            if !paginate {
+                tables
+                    .into_par_iter()
+                    .with_max_len(1)
+                    .flat_map(|(_, (table_sel, table))| {
+                        let routes =
+                            scan_table(&table_sel, &table, &nets_filter_fn, max_results_per_table);
+                        with_best(&table_sel, &table, routes).into_par_iter()
+                    })
+                    .for_each_with(tx, |tx, res| drop(tx.blocking_send(res)));
+                return;
+            }
+
+            // Every table can only contribute `max_results` to a page, of which the first ones
+            // following the cursor are kept
+            let pages = tables
                 .into_par_iter()
-                .flat_map(move |(table_sel, table)| {
-                    let table = table.table.lock().unwrap();
-                    table
-                        .get_routes(Some(&query.net_query))
-                        .map(move |(net, _path_id, route)| {
-                            let table_sel = table_sel.clone();
-                            (table_sel.clone(), net, route.clone())
-                        })
-                        .filter(&nets_filter_fn)
-                        .take(max_results_per_table)
-                        .collect::<Vec<_>>()
-                        .into_par_iter()
+                .with_max_len(1)
+                .map(|(key, (table_sel, table))| {
+                    let after = after
+                        .as_ref()
+                        .filter(|(after_key, _, _)| *after_key == key)
+                        .map(|(_, net, path_id)| (*net, *path_id));
+                    let filter = |i: &(TableSelector, IpNet, RouteEntry)| {
+                        after
+                            .map(|after| (i.1, i.2.path_id) > after)
+                            .unwrap_or(true)
+                            && nets_filter_fn(i)
+                    };
+                    let mut routes = scan_table(&table_sel, &table, &filter, usize::MAX);
+                    let sort_key = |i: &(TableSelector, IpNet, RouteEntry)| (i.1, i.2.path_id);
+                    if routes.len() > max_results {
+                        routes.select_nth_unstable_by_key(max_results, sort_key);
+                        routes.truncate(max_results);
+                    }
+                    routes.sort_by_key(sort_key);
+                    with_best(&table_sel, &table, routes)
                 })
-                .for_each_with(tx, |tx, res| drop(tx.blocking_send(res)));
+                .collect::<Vec<_>>();
+            for res in pages.into_iter().flatten().take(max_results) {
+                if tx.blocking_send(res).is_err() {
+                    break;
+                }
+            }
         });
 
         let clients = self.clients.clone();
         let sessions = self.sessions.clone();
-        Box::pin(
+        Ok(Box::pin(
             ReceiverStream::new(rx)
-                .filter_map(move |(table, net, attrs)| {
+                .filter_map(move |((table, net, route), best)| {
                     let clients = clients.clone();
                     let sessions = sessions.clone();
+                    let nexthop_resolver = nexthop_resolver.clone();
                     async move {
                         let client = match clients.lock().unwrap().get(table.client_addr()) {
                             Some(v) => v.clone(),
@@ -202,24 +2112,518 @@ impl Store for InMemoryStore {
                             sessions.lock().unwrap().get(session_id).cloned()
                         });
 
+                        let attrs = decompress_route_attrs(&route.attrs);
+                        let nexthop_resolved_via = attrs
+                            .nexthop
+                            .and_then(|nexthop| nexthop_resolver.resolve(&table, &net, nexthop));
                         Some(QueryResult {
+                            attrs_id: route.attrs.id(),
+                            age: RouteAge { since: route.since },
+                            cursor: paginate.then(|| Cursor {
+                                table: table.clone(),
+                                net,
+                                path_id: route.path_id,
+                            }),
                             state: table.route_state(),
                             net,
                             table,
-                            attrs: decompress_route_attrs(&attrs),
+                            nexthop_resolved_via,
+                            attrs,
                             client,
                             session,
+                            stale: route.stale,
+                            best,
                         })
                     }
                 })
                 .take(max_results),
-        )
+        ))
+    }
+
+    fn explain_query(&self, query: &Query) -> Result<QueryPlan, StoreError> {
+        let mut filters = vec![];
+        if !query.include_stale {
+            filters.push("stale routes are excluded".to_string());
+        }
+        if let Some(as_path_regex) = &query.as_path_regex {
+            AsPathMatcher::compile(query.as_path_match, as_path_regex).map_err(|e| {
+                StoreError::InvalidQuery(format!(
+                    "invalid AS path pattern {:?}: {}",
+                    as_path_regex, e
+                ))
+            })?;
+            filters.push(format!(
+                "AS path matches {:?} {:?}",
+                query.as_path_match, as_path_regex
+            ));
+        }
+        if let Some(asn) = query.origin_asn {
+            filters.push(format!("origin AS {}", asn));
+        }
+        if let Some(min_age) = query.min_age {
+            filters.push(format!("announced at least {}s ago", min_age));
+        }
+        if let Some(max_age) = query.max_age {
+            filters.push(format!("announced at most {}s ago", max_age));
+        }
+        if let Some(nexthop) = query.nexthop {
+            filters.push(format!("nexthop within {}", nexthop));
+        }
+        for (name, filter) in [
+            ("communities", &query.communities),
+            ("large communities", &query.large_communities),
+            ("extended communities", &query.ext_communities),
+        ] {
+            if let Some(filter) = filter {
+                filters.push(format!("{} {}", name, String::from(filter.clone())));
+            }
+        }
+        if let Some(min_prepend_count) = query.min_prepend_count {
+            filters.push(format!(
+                "an ASN repeated at least {} times in the AS path",
+                min_prepend_count
+            ));
+        }
+        if query.after.is_some() {
+            filters.push("after the cursor".to_string());
+        }
+
+        let (tables, overlays) = self.query_tables(query)?;
+        let tables = tables
+            .into_iter()
+            .map(|(table_sel, table)| {
+                // Counting the routes of a subtree would take as long as the query itself, so the
+                // route count of the table is used. Lookups only visit the prefixes on the path to
+                // the queried one, which are counted.
+                let (scan, candidate_routes) = match (&query.net_query, query.origin_asn) {
+                    (NetQuery::Exact(_) | NetQuery::OrLonger(_), Some(asn)) => {
+                        (TableScan::OriginIndex, table.origin_route_count(asn))
+                    }
+                    (NetQuery::OrLonger(_), None) => (TableScan::Subtree, table.route_count()),
+                    (net_query, _) => (
+                        TableScan::Lookup,
+                        table.with_routes(Some(net_query), |routes| routes.count()),
+                    ),
+                };
+                TablePlan {
+                    scan: match overlays.contains_key(&table_sel) {
+                        true => TableScan::History,
+                        false => scan,
+                    },
+                    table: table_sel,
+                    candidate_routes,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let paginate = query.paginate || query.after.is_some();
+        Ok(QueryPlan {
+            estimated_cost: tables.iter().map(|table| table.candidate_routes).sum(),
+            tables,
+            filters,
+            limits: query.limits.clone().unwrap_or_default(),
+            sort: query.sort.filter(|_| !paginate),
+            paginate,
+        })
+    }
+
+    fn export_mrt(&self, table_query: Option<TableQuery>) -> Vec<u8> {
+        let clients = self.clients.lock().unwrap().clone();
+        let mut writer = MrtWriter::new(unix_timestamp());
+        for (table_sel, table) in self.get_tables(table_query) {
+            let Some(client) = clients.get(table_sel.client_addr()) else {
+                continue;
+            };
+            for (net, route) in table.get_routes(None) {
+                writer.add_route(
+                    &table_sel,
+                    client.router_id,
+                    net,
+                    route.path_id,
+                    route.attrs,
+                );
+            }
+        }
+        writer.finish()
+    }
+
+    fn subscribe_changes(&self) -> tokio::sync::broadcast::Receiver<RouteChange> {
+        self.change_broadcast.subscribe()
+    }
+
+    fn subscribe_session_events(&self) -> tokio::sync::broadcast::Receiver<SessionEvent> {
+        self.session_event_broadcast.subscribe()
     }
 
     fn get_routers(&self) -> HashMap<SocketAddr, Client> {
         self.clients.lock().unwrap().clone()
     }
 
+    fn get_sessions(&self) -> Vec<(SessionId, Session)> {
+        self.sessions.lock().unwrap().clone().into_iter().collect()
+    }
+
+    fn get_table_stats(&self) -> Vec<TableStats> {
+        let tables = self.tables.lock().unwrap().clone();
+        tables
+            .into_iter()
+            .map(|(table_sel, table)| TableStats {
+                table: table_sel,
+                routes: table.route_count(),
+                cold_routes: table.cold_route_count(),
+                origin_asns: table.origin_asn_count(),
+                last_update: table.last_update(),
+            })
+            .collect()
+    }
+
+    fn get_client_connections(&self) -> HashMap<SocketAddr, ClientConnection> {
+        self.connections.lock().unwrap().clone()
+    }
+
+    fn count_client_message(&self, client_addr: SocketAddr, bytes: usize) {
+        let mut connections = self.connections.lock().unwrap();
+        let connection = connections
+            .entry(client_addr)
+            .or_insert_with(|| ClientConnection::new(client_addr));
+        connection.bytes_received += bytes as u64;
+        connection.messages_received += 1;
+    }
+
+    fn record_client_error(&self, client_addr: SocketAddr, error: DecodeError) {
+        {
+            let mut connections = self.connections.lock().unwrap();
+            let connection = connections
+                .entry(client_addr)
+                .or_insert_with(|| ClientConnection::new(client_addr));
+            connection.last_error = Some(error.error.clone());
+            connection.last_error_timestamp = Some(error.timestamp);
+        }
+        crate::metrics::DECODE_ERRORS
+            .with_label_values(&[&client_addr.ip().to_string()])
+            .inc();
+        if self.decode_error_log_size == 0 {
+            return;
+        }
+        let mut decode_errors = self.decode_errors.lock().unwrap();
+        let errors = decode_errors.entry(client_addr.ip()).or_default();
+        if errors.len() >= self.decode_error_log_size {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    fn get_decode_errors(&self) -> HashMap<IpAddr, Vec<DecodeError>> {
+        self.decode_errors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, errors)| (*addr, errors.iter().cloned().collect()))
+            .collect()
+    }
+
+    fn get_peer_stats(&self) -> Vec<PeerStats> {
+        self.peer_stats
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(stats, counters)| PeerStats {
+                updates: counters.updates.load(Ordering::Relaxed),
+                withdraws: counters.withdraws.load(Ordering::Relaxed),
+                ..stats.clone()
+            })
+            .collect()
+    }
+
+    fn get_sync_progress(&self) -> HashMap<SessionId, SyncProgress> {
+        let clients = self.clients.lock().unwrap().clone();
+        let sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        sessions
+            .into_iter()
+            .map(|session_id| {
+                let (routes, afi_safis) = self.session_routes(&session_id);
+                let complete = self.sync_complete(&session_id, &afi_safis);
+                let key = clients
+                    .get(&session_id.from_client)
+                    .map(|client| (client.router_id, session_id.peer_address));
+                let expected_routes = key.and_then(|key| {
+                    self.table_sizes
+                        .lock()
+                        .unwrap()
+                        .get(&key)
+                        .copied()
+                        .or_else(|| {
+                            self.peer_stats
+                                .lock()
+                                .unwrap()
+                                .get(&key)
+                                .and_then(|(stats, _)| stats.bmp_statistics.as_ref())
+                                .and_then(|stats| stats.adj_rib_in_routes)
+                                .map(|routes| routes as usize)
+                        })
+                });
+                let percentage = if complete {
+                    Some(100.0)
+                } else {
+                    expected_routes
+                        .filter(|expected| *expected > 0)
+                        .map(|expected| (routes as f64 / expected as f64 * 100.0).min(100.0))
+                };
+                let progress = SyncProgress {
+                    routes,
+                    expected_routes,
+                    percentage,
+                    complete,
+                };
+                (session_id, progress)
+            })
+            .collect()
+    }
+
+    fn get_peer_funnels(&self) -> Vec<PeerFunnel> {
+        let tables = self.tables.lock().unwrap().clone();
+
+        // best route state of every path of the Loc-RIBs, by next hop and AS path since the
+        // Loc-RIB does not tell from which peer a path was learned
+        type LocRibKey = (
+            SocketAddr,
+            RouteDistinguisher,
+            AfiSafi,
+            IpNet,
+            Option<IpAddr>,
+            Option<Arc<AsPath>>,
+        );
+        let mut loc_rib: HashMap<LocRibKey, RouteState> = HashMap::new();
+        for (table_sel, table) in &tables {
+            let TableType::LocRib { route_state } = table_sel.table_type else {
+                continue;
+            };
+            for (net, route) in table.get_routes(None) {
+                let key = (
+                    *table_sel.client_addr(),
+                    table_sel.route_distinguisher,
+                    table_sel.afi_safi,
+                    net,
+                    route.attrs.nexthop,
+                    route.attrs.as_path.clone(),
+                );
+                let state = loc_rib.entry(key).or_insert(route_state);
+                *state = (*state).max(route_state);
+            }
+        }
+
+        let mut funnels: HashMap<SessionId, PeerFunnel> = HashMap::new();
+        for (table_sel, table) in &tables {
+            let Some(session_id) = table_sel.session_id() else {
+                continue;
+            };
+            let funnel = funnels
+                .entry(session_id.clone())
+                .or_insert_with(|| PeerFunnel {
+                    session_id: session_id.clone(),
+                    received: 0,
+                    accepted: 0,
+                    installed: 0,
+                    selected: 0,
+                });
+            match table_sel.table_type {
+                TableType::PrePolicyAdjIn => funnel.received += table.route_count(),
+                TableType::PostPolicyAdjIn => {
+                    for (net, route) in table.get_routes(None) {
+                        funnel.accepted += 1;
+                        let key = (
+                            *table_sel.client_addr(),
+                            table_sel.route_distinguisher,
+                            table_sel.afi_safi,
+                            net,
+                            route.attrs.nexthop,
+                            route.attrs.as_path.clone(),
+                        );
+                        if let Some(state) = loc_rib.get(&key) {
+                            funnel.installed += 1;
+                            if *state == RouteState::Selected {
+                                funnel.selected += 1;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut funnels = funnels.into_values().collect::<Vec<_>>();
+        funnels.sort_by_key(|funnel| {
+            (
+                funnel.session_id.from_client,
+                funnel.session_id.peer_address,
+            )
+        });
+        funnels
+    }
+
+    fn get_anomalies(&self) -> Vec<Anomaly> {
+        if self.route_target_check.is_empty() {
+            return vec![];
+        }
+        let tables = self.tables.lock().unwrap().clone();
+        let mut anomalies = vec![];
+        for (table_sel, table) in tables {
+            if table_sel.route_distinguisher.is_default() {
+                continue;
+            }
+            for (net, route) in table.get_routes(None) {
+                let ext_communities = route.attrs.ext_communities.iter().flat_map(|c| c.iter());
+                if let Some((instance, foreign_route_targets)) = self
+                    .route_target_check
+                    .check(&table_sel.route_distinguisher, ext_communities)
+                {
+                    anomalies.push(Anomaly::RouteTargetMismatch {
+                        table: table_sel.clone(),
+                        net,
+                        path_id: route.path_id,
+                        instance,
+                        foreign_route_targets,
+                    });
+                }
+            }
+        }
+        anomalies
+    }
+
+    fn get_peers(&self) -> Vec<PeerStatus> {
+        let mut peers = self
+            .peer_states
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        peers.sort_by_key(|peer| (peer.session_id.from_client, peer.session_id.peer_address));
+        peers
+    }
+
+    fn get_session_down_events(&self) -> Vec<SessionDownEvent> {
+        self.session_down_events
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn get_origin_changes(&self, net: Option<IpNet>) -> Vec<OriginChange> {
+        self.origin_changes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|change| net.map(|net| net.contains(&change.net)).unwrap_or(true))
+            .cloned()
+            .collect()
+    }
+
+    fn get_prefix_history(&self, net: IpNet) -> Vec<PrefixChange> {
+        self.prefix_history
+            .lock()
+            .unwrap()
+            .get(&net)
+            .map(|changes| changes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn get_history_start(&self) -> Option<u64> {
+        self.history.lock().unwrap().complete_since()
+    }
+
+    fn get_diff_since(
+        &self,
+        table_query: Option<TableQuery>,
+        since: u64,
+    ) -> Result<Vec<PathDiff>, StoreError> {
+        let Some(rewound) = self.history.lock().unwrap().rewind(since) else {
+            return Err(StoreError::InvalidQuery(format!(
+                "history does not reach back to {}",
+                since
+            )));
+        };
+        let tables = self.tables.lock().unwrap().clone();
+        let paths_of = |table: &InMemoryTable| {
+            table
+                .get_routes(None)
+                .into_iter()
+                .map(|(net, route)| ((net, route.path_id), route))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let mut diffs = vec![];
+        for (table_sel, rewound_table) in rewound {
+            if !self.table_query_matches(&table_query, &table_sel) {
+                continue;
+            }
+            let current = tables.get(&table_sel);
+            let (mut old, new) = match &rewound_table.contents {
+                // the table was removed since, the paths changed afterwards belong to a new one
+                Some(contents) => (
+                    paths_of(contents),
+                    current.map(paths_of).unwrap_or_default(),
+                ),
+                None => {
+                    let new = rewound_table
+                        .paths
+                        .keys()
+                        .filter_map(|(net, path_id)| {
+                            let route = current?
+                                .get_routes(Some(&NetQuery::Exact(*net)))
+                                .into_iter()
+                                .find(|(_, route)| route.path_id == *path_id)?
+                                .1;
+                            Some(((*net, *path_id), route))
+                        })
+                        .collect::<HashMap<_, _>>();
+                    (new.clone(), new)
+                }
+            };
+            for (key, route) in rewound_table.paths {
+                match route {
+                    Some(route) => old.insert(key, route),
+                    None => old.remove(&key),
+                };
+            }
+
+            let keys = old.keys().chain(new.keys()).collect::<HashSet<_>>();
+            for (net, path_id) in keys {
+                let (old, new) = (old.get(&(*net, *path_id)), new.get(&(*net, *path_id)));
+                let change = match (old, new) {
+                    (None, Some(_)) => PathDiffKind::Added,
+                    (Some(_), None) => PathDiffKind::Withdrawn,
+                    (Some(old), Some(new)) if old.attrs != new.attrs => PathDiffKind::Changed,
+                    _ => continue,
+                };
+                diffs.push(PathDiff {
+                    change,
+                    net: *net,
+                    table: table_sel.clone(),
+                    path_id: *path_id,
+                    old: old.map(|route| decompress_route_attrs(&route.attrs)),
+                    new: new.map(|route| decompress_route_attrs(&route.attrs)),
+                });
+            }
+        }
+        diffs.sort_by_key(|diff| (diff.net, diff.path_id));
+        Ok(diffs)
+    }
+
+    fn get_flaps(&self, window: u64, limit: usize) -> Vec<PrefixFlaps> {
+        self.flap_stats
+            .lock()
+            .unwrap()
+            .top(window, limit, unix_timestamp())
+    }
+
     fn get_routing_instances(&self) -> HashMap<SocketAddr, HashSet<RouteDistinguisher>> {
         let tables = self.tables.lock().unwrap().clone();
         let mut hm = HashMap::new();
@@ -232,35 +2636,196 @@ impl Store for InMemoryStore {
         hm
     }
 
+    fn get_changes_since(&self, version: Option<u64>) -> Option<(u64, RouteChangeStream)> {
+        if let Some(version) = version {
+            let change_log = self.change_log.lock().unwrap();
+            let changes = change_log
+                .since(version)?
+                .iter()
+                .map(LoggedChange::decompress)
+                .collect::<Vec<_>>();
+            return Some((
+                change_log.version(),
+                Box::pin(futures_util::stream::iter(changes)),
+            ));
+        }
+
+        // Changes which happen while the snapshot is taken may or may not be included, they are
+        // part of the next export in any case.
+        let version = self.change_log.lock().unwrap().version();
+        let tables = self.tables.lock().unwrap().clone();
+        let changes = futures_util::stream::iter(tables).flat_map(|(table_sel, table)| {
+            let routes = table.get_routes(None);
+            futures_util::stream::iter(routes.into_iter().map(move |(net, route)| {
+                RouteChange::Update {
+                    table: table_sel.clone(),
+                    net,
+                    path_id: route.path_id,
+                    attrs: Box::new(decompress_route_attrs(&route.attrs)),
+                }
+            }))
+        });
+        Some((version, Box::pin(changes)))
+    }
+
     async fn client_up(
         &self,
         client_addr: SocketAddr,
         _route_state: RouteState,
-        client_data: Client,
+        mut client_data: Client,
     ) {
+        self.apply_client_metadata(&client_addr, &mut client_data);
+        let restored = {
+            let mut restored_clients = self.restored_clients.lock().unwrap();
+            let restored = restored_clients
+                .iter()
+                .filter(|addr| addr.ip() == client_addr.ip())
+                .cloned()
+                .collect::<Vec<_>>();
+            for addr in &restored {
+                restored_clients.remove(addr);
+            }
+            restored
+        };
+        for old_addr in restored {
+            self.take_over_restored_client(old_addr, client_addr);
+        }
+        self.publish_session_event(SessionEvent::RouterConnected {
+            timestamp: unix_timestamp(),
+            from_client: client_addr,
+            client_name: client_data.client_name.clone(),
+            router_id: client_data.router_id,
+        });
         self.clients
             .lock()
             .unwrap()
             .insert(client_addr, client_data);
+        self.connections
+            .lock()
+            .unwrap()
+            .entry(client_addr)
+            .or_insert_with(|| ClientConnection::new(client_addr));
+    }
+    fn client_address_families(&self, client_addr: &SocketAddr) -> Option<Vec<AfiSafi>> {
+        self.clients
+            .lock()
+            .unwrap()
+            .get(client_addr)
+            .and_then(|client| client.metadata.address_families.clone())
+    }
+
+    async fn update_client(
+        &self,
+        client_addr: SocketAddr,
+        client_name: String,
+        sys_descr: Option<String>,
+    ) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&client_addr) {
+            client.client_name = client_name;
+            client.sys_descr = sys_descr;
+        }
     }
-    async fn client_down(&self, client_addr: SocketAddr) {
+    async fn client_down(&self, client_addr: SocketAddr, reason: Option<String>) {
+        let sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|k| k.from_client == client_addr)
+            .cloned()
+            .collect::<Vec<_>>();
+        for session in &sessions {
+            self.record_peer_down(
+                session,
+                Some(PeerDownReason::RouterDisconnected {
+                    reason: reason.clone(),
+                }),
+            );
+            self.update_peer_stats(Some(session), |stats| stats.flaps += 1);
+            self.session_counters.write().unwrap().remove(session);
+            self.record_table_size(session);
+            self.end_of_rib_received.lock().unwrap().remove(session);
+            self.publish_session_event(SessionEvent::PeerDown {
+                timestamp: unix_timestamp(),
+                session: session.clone(),
+                reason: reason.clone(),
+            });
+        }
+        self.publish_session_event(SessionEvent::RouterDisconnected {
+            timestamp: unix_timestamp(),
+            from_client: client_addr,
+            reason,
+        });
+        self.record_session_down(
+            client_addr,
+            sessions.iter().map(|s| s.peer_address).collect(),
+            true,
+        );
         self.clients.lock().unwrap().remove(&client_addr);
+        self.connections.lock().unwrap().remove(&client_addr);
+        self.linkstate.lock().unwrap().remove_client(&client_addr);
         self.sessions
             .lock()
             .unwrap()
             .retain(|k, _| k.from_client != client_addr);
-        self.tables
-            .lock()
-            .unwrap()
-            .retain(|k, v| !(tables_for_client_fn(&client_addr)(&(k, v))));
-        self.caches.lock().unwrap().remove_expired();
+        self.remove_tables(|k| k.client_addr() == &client_addr);
     }
 
-    async fn session_up(&self, session: SessionId, new_state: Session) {
+    async fn session_up(&self, session: SessionId, mut new_state: Session) {
+        self.apply_session_metadata(&session, &mut new_state);
+        new_state.synced = false;
+        {
+            let now = unix_timestamp();
+            let mut peer_states = self.peer_states.lock().unwrap();
+            let status = peer_states
+                .entry(session.clone())
+                .or_insert_with(|| PeerStatus {
+                    session_id: session.clone(),
+                    state: PeerState::Down,
+                    since: now,
+                    last_down: None,
+                });
+            if status.state == PeerState::Down {
+                status.state = PeerState::Up;
+                status.since = now;
+            }
+            new_state.last_down.clone_from(&status.last_down);
+        }
+        self.end_of_rib_received
+            .lock()
+            .unwrap()
+            .insert(session.clone(), HashSet::new());
+        self.publish_session_event(SessionEvent::PeerUp {
+            timestamp: unix_timestamp(),
+            session: session.clone(),
+        });
         self.sessions.lock().unwrap().insert(session, new_state);
     }
-    async fn session_down(&self, session: SessionId, new_state: Option<Session>) {
-        if let Some(new_state) = new_state {
+    async fn session_down(
+        &self,
+        session: SessionId,
+        new_state: Option<Session>,
+        reason: Option<PeerDownReason>,
+    ) {
+        let event_reason = reason.as_ref().map(ToString::to_string);
+        let last_down = self.record_peer_down(&session, reason);
+        self.update_peer_stats(Some(&session), |stats| stats.flaps += 1);
+        self.session_counters.write().unwrap().remove(&session);
+        self.record_table_size(&session);
+        self.end_of_rib_received.lock().unwrap().remove(&session);
+        // not sent again if the session was already removed with its client
+        if self.sessions.lock().unwrap().contains_key(&session) {
+            self.publish_session_event(SessionEvent::PeerDown {
+                timestamp: unix_timestamp(),
+                session: session.clone(),
+                reason: event_reason,
+            });
+        }
+        self.record_session_down(session.from_client, vec![session.peer_address], false);
+        if let Some(mut new_state) = new_state {
+            self.apply_session_metadata(&session, &mut new_state);
+            new_state.synced = false;
+            new_state.last_down = Some(last_down);
             self.sessions
                 .lock()
                 .unwrap()
@@ -268,10 +2833,255 @@ impl Store for InMemoryStore {
         } else {
             self.sessions.lock().unwrap().remove(&session);
         }
-        self.tables
-            .lock()
+        self.linkstate.lock().unwrap().remove_session(&session);
+        self.remove_tables(|k| k.session_id() == Some(&session));
+    }
+
+    async fn loc_rib_up(&self, table: TableSelector, table_name: Option<String>) {
+        let Some(table_name) = table_name else {
+            return;
+        };
+        if let Some(client) = self.clients.lock().unwrap().get_mut(table.client_addr()) {
+            client
+                .loc_rib_tables
+                .insert(table.route_distinguisher, table_name);
+        }
+    }
+
+    async fn loc_rib_down(&self, table: TableSelector) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(table.client_addr()) {
+            client.loc_rib_tables.remove(&table.route_distinguisher);
+        }
+        let all_rds = table.route_distinguisher.is_default();
+        self.remove_tables(|k| {
+            k.session_id == table.session_id
+                && matches!(k.table_type, TableType::LocRib { .. })
+                && (all_rds || k.route_distinguisher == table.route_distinguisher)
+        });
+    }
+
+    async fn update_bmp_statistics(&self, session: SessionId, statistics: BmpStatistics) {
+        self.update_peer_stats(Some(&session), |stats| {
+            stats
+                .bmp_statistics
+                .get_or_insert_with(Default::default)
+                .merge(statistics)
+        });
+    }
+}
+
+/// Periodically update the per-client and per-table gauges
+pub async fn run_metrics(
+    store: InMemoryStore,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    if store.metrics_interval == 0 {
+        let _ = shutdown.changed().await;
+        return Ok(());
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(store.metrics_interval));
+    let mut previous = HashMap::new();
+    let mut last_update = Instant::now();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let now = Instant::now();
+                store.update_metrics(&mut previous, (now - last_update).as_secs_f64());
+                last_update = now;
+            }
+            _ = shutdown.changed() => break Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(table_type: TableType) -> TableSelector {
+        TableSelector {
+            route_distinguisher: RouteDistinguisher::Default,
+            session_id: SessionId {
+                from_client: "192.0.2.1:11019".parse().unwrap(),
+                peer_address: "192.0.2.2".parse().unwrap(),
+            },
+            table_type,
+            afi_safi: AfiSafi::Ipv4Unicast,
+        }
+    }
+
+    async fn store(cfg: &InMemoryStoreConfig) -> InMemoryStore {
+        let store = InMemoryStore::new(cfg).unwrap();
+        let session_id = table(TableType::PostPolicyAdjIn).session_id;
+        store
+            .client_up(
+                session_id.from_client,
+                RouteState::Selected,
+                Client {
+                    client_name: "router".to_string(),
+                    router_id: "192.0.2.1".parse().unwrap(),
+                    loc_rib_tables: Default::default(),
+                    sys_descr: None,
+                    metadata: Default::default(),
+                },
+            )
+            .await;
+        store.session_up(session_id, Default::default()).await;
+        store
+    }
+
+    /// Prefixes of the routes in a table with their stale flag, sorted
+    async fn routes(
+        store: &InMemoryStore,
+        table: &TableSelector,
+        include_stale: bool,
+    ) -> Vec<(String, bool)> {
+        let mut query = Query::new(NetQuery::OrLonger("0.0.0.0/0".parse().unwrap()));
+        query.table_query = Some(TableQuery::Table(table.clone()));
+        query.include_stale = include_stale;
+        let mut routes = store
+            .get_routes(query)
             .unwrap()
-            .retain(|k, v| !(tables_for_session_fn(&session)(&(k, v))));
-        self.caches.lock().unwrap().remove_expired();
+            .map(|route| (route.net.to_string(), route.stale))
+            .collect::<Vec<_>>()
+            .await;
+        routes.sort();
+        routes
+    }
+
+    #[tokio::test]
+    async fn test_stale_routes() {
+        let store = store(&Default::default()).await;
+        let table = table(TableType::PostPolicyAdjIn);
+        for net in ["192.0.2.0/24", "198.51.100.0/24", "203.0.113.0/24"] {
+            store
+                .update_route(0, net.parse().unwrap(), table.clone(), Default::default())
+                .await
+                .unwrap();
+        }
+
+        // the router starts sending its table again
+        store.mark_stale(table.clone()).await.unwrap();
+        assert!(routes(&store, &table, false).await.is_empty());
+        assert_eq!(routes(&store, &table, true).await.len(), 3);
+
+        store
+            .update_route(
+                0,
+                "198.51.100.0/24".parse().unwrap(),
+                table.clone(),
+                Default::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            routes(&store, &table, false).await,
+            vec![("198.51.100.0/24".to_string(), false)]
+        );
+        assert_eq!(
+            routes(&store, &table, true).await,
+            vec![
+                ("192.0.2.0/24".to_string(), true),
+                ("198.51.100.0/24".to_string(), false),
+                ("203.0.113.0/24".to_string(), true),
+            ]
+        );
+
+        // routes which were not sent again are purged
+        store.end_of_rib(table.clone()).await.unwrap();
+        assert_eq!(
+            routes(&store, &table, true).await,
+            vec![("198.51.100.0/24".to_string(), false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_policy_retention() {
+        let store = store(&InMemoryStoreConfig {
+            pre_policy_retention: PrePolicyRetention::PostPolicy,
+            ..Default::default()
+        })
+        .await;
+        let pre_policy = table(TableType::PrePolicyAdjIn);
+        let post_policy = table(TableType::PostPolicyAdjIn);
+        let update = |table: &TableSelector, net: &str| {
+            store.update_route(0, net.parse().unwrap(), table.clone(), Default::default())
+        };
+        let nets = |routes: Vec<(String, bool)>| {
+            routes.into_iter().map(|(net, _)| net).collect::<Vec<_>>()
+        };
+
+        // the router sends the pre-policy routes first
+        update(&pre_policy, "192.0.2.0/24").await.unwrap();
+        update(&pre_policy, "198.51.100.0/24").await.unwrap();
+        update(&post_policy, "192.0.2.0/24").await.unwrap();
+        assert_eq!(
+            nets(routes(&store, &pre_policy, true).await),
+            vec!["192.0.2.0/24", "198.51.100.0/24"]
+        );
+
+        // 198.51.100.0/24 was rejected by the policy
+        store.end_of_rib(post_policy.clone()).await.unwrap();
+        assert_eq!(
+            nets(routes(&store, &pre_policy, true).await),
+            vec!["192.0.2.0/24"]
+        );
+
+        // once synced, a pre-policy route waits for its post-policy route
+        update(&pre_policy, "203.0.113.0/24").await.unwrap();
+        update(&post_policy, "203.0.113.0/24").await.unwrap();
+        assert_eq!(
+            nets(routes(&store, &pre_policy, true).await),
+            vec!["192.0.2.0/24", "203.0.113.0/24"]
+        );
+
+        store
+            .withdraw_route(0, "192.0.2.0/24".parse().unwrap(), post_policy.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            nets(routes(&store, &pre_policy, true).await),
+            vec!["203.0.113.0/24"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_origin_changes() {
+        let store = store(&Default::default()).await;
+        let table = table(TableType::PostPolicyAdjIn);
+        let net: IpNet = "192.0.2.0/24".parse().unwrap();
+        let route = |origin| RouteAttrs {
+            as_path: Some(AsPath::from_sequence(vec![64496, origin])),
+            ..Default::default()
+        };
+        let changes = || {
+            store
+                .get_origin_changes(Some(net))
+                .into_iter()
+                .map(|change| (change.old_origin_asn, change.new_origin_asn))
+                .collect::<Vec<_>>()
+        };
+
+        store
+            .update_route(0, net, table.clone(), route(64500))
+            .await
+            .unwrap();
+        store.withdraw_route(0, net, table.clone()).await.unwrap();
+        store
+            .update_route(0, net, table.clone(), route(64501))
+            .await
+            .unwrap();
+        assert_eq!(changes(), vec![(64500, 64501)]);
+
+        // add-path announcements are compared against the other paths of the prefix
+        store
+            .update_route(1, net, table.clone(), route(64501))
+            .await
+            .unwrap();
+        store
+            .update_route(2, net, table.clone(), route(64502))
+            .await
+            .unwrap();
+        assert_eq!(changes(), vec![(64500, 64501), (64501, 64502)]);
     }
 }
@@ -0,0 +1,123 @@
+//! Supervision of the long running tasks of the daemon. A collector or output which fails, panics
+//! or ends before the shutdown is logged and restarted with an exponential backoff, so a single
+//! failing listener does not silently take down its subsystem.
+
+use log::*;
+use serde::Deserialize;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+fn default_initial_backoff() -> u64 {
+    1
+}
+
+fn default_max_backoff() -> u64 {
+    60
+}
+
+fn default_reset_after() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SupervisorConfig {
+    /// Seconds to wait before the first restart, doubled for every further restart
+    #[serde(default = "default_initial_backoff")]
+    pub initial_backoff: u64,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+    /// Seconds after which a running task is considered healthy again and the backoff is reset
+    #[serde(default = "default_reset_after")]
+    pub reset_after: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: default_initial_backoff(),
+            max_backoff: default_max_backoff(),
+            reset_after: default_reset_after(),
+        }
+    }
+}
+
+fn next_backoff(backoff: Duration, max: Duration) -> Duration {
+    std::cmp::min(backoff * 2, max)
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// Run the tasks created by `make_task` until shutdown, starting a new one whenever the previous
+/// one fails, panics or ends. Restarts are counted in `fernglas_task_restarts_total`.
+pub async fn supervise<F, Fut>(
+    name: String,
+    cfg: SupervisorConfig,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    mut make_task: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let initial_backoff = Duration::from_secs(cfg.initial_backoff);
+    let max_backoff = Duration::from_secs(cfg.max_backoff);
+    let mut backoff = initial_backoff;
+    loop {
+        let started = Instant::now();
+        let result = tokio::task::spawn(make_task()).await;
+        if *shutdown.borrow() {
+            return Ok(());
+        }
+        match result {
+            Ok(Ok(())) => warn!("task {} ended unexpectedly", name),
+            Ok(Err(e)) => warn!("task {} failed: {:#}", name, e),
+            Err(e) if e.is_panic() => {
+                error!("task {} panicked: {}", name, panic_message(e.into_panic()))
+            }
+            Err(e) => warn!("task {} was cancelled: {}", name, e),
+        }
+
+        if started.elapsed() >= Duration::from_secs(cfg.reset_after) {
+            backoff = initial_backoff;
+        }
+        info!("restarting task {} in {:?}", name, backoff);
+        crate::metrics::TASK_RESTARTS
+            .with_label_values(&[&name])
+            .inc();
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.changed() => return Ok(()),
+        }
+        backoff = next_backoff(backoff, max_backoff);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let max = Duration::from_secs(60);
+        let mut backoff = Duration::from_secs(1);
+        let mut steps = vec![];
+        for _ in 0..8 {
+            steps.push(backoff.as_secs());
+            backoff = next_backoff(backoff, max);
+        }
+        assert_eq!(steps, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(
+            panic_message(Box::new("listener failed")),
+            "listener failed".to_string()
+        );
+    }
+}
@@ -1,88 +1,728 @@
+use crate::cold_storage::ColdTier;
 use crate::compressed_attrs::*;
 use crate::store::*;
 use ipnet::IpNet;
 use nibbletree::Node;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 
+#[derive(Debug, Clone)]
+pub struct RouteEntry {
+    pub path_id: PathId,
+    pub attrs: Arc<CompressedRouteAttrs>,
+    /// The route was retained across a session restart and has not been re-announced yet
+    pub stale: bool,
+    /// Unix timestamp in seconds of when the path was announced with its current attributes
+    pub since: u64,
+}
+
+/// Attributes of a path, relative to the base attributes of its prefix
+#[derive(Debug, Clone)]
+enum PathAttrs {
+    Base,
+    /// Same as the base attributes except for the nexthop, only the nexthop is stored
+    NexthopDelta {
+        nexthop: Option<IpAddr>,
+        nexthop_link_local: Option<Ipv6Addr>,
+    },
+    Full(Arc<CompressedRouteAttrs>),
+}
+
+#[derive(Debug, Clone)]
+struct StoredPath {
+    path_id: PathId,
+    stale: bool,
+    since: u64,
+    attrs: PathAttrs,
+}
+
+/// The paths of a prefix, sorted by path id. Add-path feeds often carry many paths which only
+/// differ in the nexthop, those are stored as deltas against the base attributes instead of being
+/// interned each. The base attributes are interned and always those of one of the paths.
+#[derive(Debug, Clone)]
+pub struct PathSet {
+    base: Arc<CompressedRouteAttrs>,
+    paths: Vec<StoredPath>,
+}
+
+fn same_except_nexthop(base: &CompressedRouteAttrs, route: &RouteAttrs) -> bool {
+    base.origin == route.origin
+        && base.med == route.med
+        && base.local_pref == route.local_pref
+        && base.truncated == route.truncated
+        && base.labels == route.labels
+        && base.aggregator == route.aggregator
+        && base.atomic_aggregate == route.atomic_aggregate
+        && base.originator_id == route.originator_id
+        && base.cluster_list == route.cluster_list
+        && base.unknown_attrs == route.unknown_attrs
+        && base.as_path.as_deref() == route.as_path.as_ref()
+        && base.communities.as_deref() == route.communities.as_ref()
+        && base.ext_communities.as_deref() == route.ext_communities.as_ref()
+        && match (&base.large_communities, &route.large_communities) {
+            (Some(a), Some(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| **a == *b),
+            (None, None) => true,
+            _ => false,
+        }
+}
+
+fn with_nexthop(
+    base: &CompressedRouteAttrs,
+    nexthop: Option<IpAddr>,
+    nexthop_link_local: Option<Ipv6Addr>,
+) -> CompressedRouteAttrs {
+    CompressedRouteAttrs {
+        nexthop,
+        nexthop_link_local,
+        ..base.clone()
+    }
+}
+
+impl PathSet {
+    /// Paths stored as nexthop deltas are materialized on every read, the copy is not kept
+    fn attrs(&self, path: &StoredPath) -> Arc<CompressedRouteAttrs> {
+        match &path.attrs {
+            PathAttrs::Base => self.base.clone(),
+            PathAttrs::NexthopDelta {
+                nexthop,
+                nexthop_link_local,
+            } => Arc::new(with_nexthop(&self.base, *nexthop, *nexthop_link_local)),
+            PathAttrs::Full(attrs) => attrs.clone(),
+        }
+    }
+
+    /// Make the attributes of the first path the base, if the path with the base attributes was
+    /// removed or changed
+    fn rebase(&mut self, caches: &Mutex<Caches>) {
+        if self
+            .paths
+            .iter()
+            .any(|path| matches!(path.attrs, PathAttrs::Base))
+        {
+            return;
+        }
+        let Some(first) = self.paths.first() else {
+            return;
+        };
+        let base = match &first.attrs {
+            PathAttrs::Full(attrs) => attrs.clone(),
+            _ => caches
+                .lock()
+                .unwrap()
+                .intern_route_attrs((*self.attrs(first)).clone()),
+        };
+        // deltas stay valid if the new base only differs in the nexthop
+        let keep_deltas =
+            with_nexthop(&base, self.base.nexthop, self.base.nexthop_link_local) == *self.base;
+        for index in 0..self.paths.len() {
+            let attrs = self.attrs(&self.paths[index]);
+            let path = &mut self.paths[index];
+            if *attrs == *base {
+                path.attrs = PathAttrs::Base;
+            } else if matches!(path.attrs, PathAttrs::NexthopDelta { .. }) && !keep_deltas {
+                path.attrs =
+                    PathAttrs::Full(caches.lock().unwrap().intern_route_attrs((*attrs).clone()));
+            }
+        }
+        self.base = base;
+    }
+
+    fn entry(&self, path: &StoredPath) -> RouteEntry {
+        RouteEntry {
+            path_id: path.path_id,
+            attrs: self.attrs(path),
+            stale: path.stale,
+            since: path.since,
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = RouteEntry> + '_ {
+        self.paths.iter().map(|path| self.entry(path))
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
 #[derive(Clone)]
 pub struct InMemoryTable {
-    pub table: Arc<Mutex<Node<IpNet, Vec<(PathId, Arc<CompressedRouteAttrs>)>>>>,
+    /// Hot tier, the paths which changed recently. Always locked before `cold`.
+    table: Arc<Mutex<Node<IpNet, PathSet>>>,
+    /// Paths which did not change for a while, if cold storage is configured
+    cold: Arc<Mutex<Option<ColdTier>>>,
     caches: Arc<Mutex<Caches>>,
+    route_count: Arc<AtomicUsize>,
+    /// Shared with the other tables of the route distinguisher, if it has a route quota
+    quota_count: Option<Arc<AtomicUsize>>,
+    /// Number of paths of each prefix by origin ASN, so the prefixes of an origin can be found
+    /// without scanning the whole table. Only updated while holding the lock of `table`.
+    origin_index: Arc<Mutex<HashMap<u32, HashMap<IpNet, usize>>>>,
+    /// Unix timestamp in seconds of the last update or withdraw, 0 if there was none yet
+    last_update: Arc<AtomicU64>,
+}
+
+pub fn origin_asn(attrs: &CompressedRouteAttrs) -> Option<u32> {
+    attrs
+        .as_path
+        .as_ref()
+        .and_then(|as_path| as_path.origin_asn())
 }
 
 pub trait NodeExt {
     fn get_routes(
         &self,
         net_query: Option<&NetQuery>,
-    ) -> Box<dyn Iterator<Item = (IpNet, PathId, Arc<CompressedRouteAttrs>)> + Send + '_>;
+    ) -> Box<dyn Iterator<Item = (IpNet, RouteEntry)> + Send + '_>;
 }
 
-impl NodeExt for Node<IpNet, Vec<(PathId, Arc<CompressedRouteAttrs>)>> {
+impl NodeExt for Node<IpNet, PathSet> {
     fn get_routes(
         &self,
         net_query: Option<&NetQuery>,
-    ) -> Box<dyn Iterator<Item = (IpNet, PathId, Arc<CompressedRouteAttrs>)> + Send + '_> {
-        let iter: Box<
-            dyn Iterator<Item = (IpNet, &Vec<(PathId, Arc<CompressedRouteAttrs>)>)> + Send + '_,
-        > = match net_query {
+    ) -> Box<dyn Iterator<Item = (IpNet, RouteEntry)> + Send + '_> {
+        let iter: Box<dyn Iterator<Item = (IpNet, &PathSet)> + Send + '_> = match net_query {
             None => Box::new(self.iter()),
             Some(NetQuery::Exact(net)) => Box::new(self.exact(net).map(|x| (*net, x)).into_iter()),
             Some(NetQuery::MostSpecific(net)) => Box::new(self.longest_match(net).into_iter()),
             Some(NetQuery::Contains(net)) => Box::new(self.matches(net)),
             Some(NetQuery::OrLonger(net)) => Box::new(self.or_longer(net)),
         };
-        Box::new(iter.flat_map(move |(net, routes)| {
-            routes
-                .iter()
-                .map(move |(path_id, route)| (net, *path_id, route.clone()))
-        }))
+        Box::new(iter.flat_map(move |(net, paths)| paths.entries().map(move |entry| (net, entry))))
     }
 }
 
 impl InMemoryTable {
-    pub fn new(caches: Arc<Mutex<Caches>>) -> Self {
+    pub fn new(caches: Arc<Mutex<Caches>>, quota_count: Option<Arc<AtomicUsize>>) -> Self {
         Self {
             table: Default::default(),
+            cold: Default::default(),
             caches,
+            route_count: Default::default(),
+            quota_count,
+            origin_index: Default::default(),
+            last_update: Default::default(),
+        }
+    }
+
+    /// Pass the routes of both tiers which match the query to `f`. The hot tier is locked for the
+    /// duration of `f`.
+    pub fn with_routes<R>(
+        &self,
+        net_query: Option<&NetQuery>,
+        f: impl FnOnce(&mut dyn Iterator<Item = (IpNet, RouteEntry)>) -> R,
+    ) -> R {
+        let table = self.table.lock().unwrap();
+        let cold = self.cold.lock().unwrap();
+        let Some(cold) = cold.as_ref().filter(|cold| !cold.is_empty()) else {
+            return f(&mut table.get_routes(net_query));
+        };
+        // the most specific prefix may be in either tier
+        let exact_query;
+        let net_query = match net_query {
+            Some(NetQuery::MostSpecific(net)) => {
+                let longest = [
+                    table.longest_match(net).map(|(net, _)| net),
+                    cold.longest_match(net),
+                ]
+                .into_iter()
+                .flatten()
+                .max_by_key(|net| net.prefix_len());
+                match longest {
+                    Some(longest) => {
+                        exact_query = NetQuery::Exact(longest);
+                        Some(&exact_query)
+                    }
+                    None => return f(&mut std::iter::empty::<(IpNet, RouteEntry)>()),
+                }
+            }
+            net_query => net_query,
+        };
+        let mut routes = table
+            .get_routes(net_query)
+            .chain(cold.get_routes(net_query, &self.caches));
+        f(&mut routes)
+    }
+
+    /// Routes of both tiers which match the query
+    pub fn get_routes(&self, net_query: Option<&NetQuery>) -> Vec<(IpNet, RouteEntry)> {
+        self.with_routes(net_query, |routes| routes.collect())
+    }
+
+    /// Number of paths currently stored in the table
+    pub fn route_count(&self) -> usize {
+        self.route_count.load(Ordering::Relaxed)
+    }
+
+    /// Number of paths originated by `asn`
+    pub fn origin_route_count(&self, asn: u32) -> usize {
+        self.origin_index
+            .lock()
+            .unwrap()
+            .get(&asn)
+            .map(|nets| nets.values().sum())
+            .unwrap_or(0)
+    }
+
+    /// Number of distinct origin ASNs of the paths in the table
+    pub fn origin_asn_count(&self) -> usize {
+        self.origin_index.lock().unwrap().len()
+    }
+
+    /// Number of paths in the cold tier
+    pub fn cold_route_count(&self) -> usize {
+        self.cold
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(ColdTier::len)
+            .unwrap_or(0)
+    }
+
+    pub fn last_update(&self) -> Option<u64> {
+        Some(self.last_update.load(Ordering::Relaxed)).filter(|timestamp| *timestamp != 0)
+    }
+
+    /// Number of paths stored in all tables sharing the quota counter of this table
+    pub fn quota_count(&self) -> Option<usize> {
+        self.quota_count
+            .as_ref()
+            .map(|count| count.load(Ordering::Relaxed))
+    }
+
+    /// The table is removed from the store, its paths no longer count towards the quota
+    pub fn release_quota(&self) {
+        if let Some(count) = &self.quota_count {
+            count.fetch_sub(self.route_count(), Ordering::Relaxed);
         }
     }
 
-    pub async fn update_route(&self, path_id: PathId, net: IpNet, route: RouteAttrs) {
-        let compressed = self.caches.lock().unwrap().compress_route_attrs(route);
+    fn add_paths(&self, n: usize) {
+        self.route_count.fetch_add(n, Ordering::Relaxed);
+        if let Some(count) = &self.quota_count {
+            count.fetch_add(n, Ordering::Relaxed);
+        }
+    }
 
-        let mut table = self.table.lock().unwrap();
+    fn remove_paths(&self, n: usize) {
+        self.route_count.fetch_sub(n, Ordering::Relaxed);
+        if let Some(count) = &self.quota_count {
+            count.fetch_sub(n, Ordering::Relaxed);
+        }
+    }
 
-        let mut new_insert = None;
-        let entry = table.exact_mut(&net).unwrap_or_else(|| {
-            new_insert = Some(Vec::new());
-            new_insert.as_mut().unwrap()
-        });
+    fn update_origin_index(
+        &self,
+        net: IpNet,
+        removed: Option<&CompressedRouteAttrs>,
+        added: Option<&CompressedRouteAttrs>,
+    ) {
+        let removed = removed.and_then(origin_asn);
+        let added = added.and_then(origin_asn);
+        if removed == added {
+            return;
+        }
+        let mut origin_index = self.origin_index.lock().unwrap();
+        if let Some(asn) = removed {
+            if let Some(nets) = origin_index.get_mut(&asn) {
+                if let Some(count) = nets.get_mut(&net) {
+                    *count -= 1;
+                    if *count == 0 {
+                        nets.remove(&net);
+                    }
+                }
+                if nets.is_empty() {
+                    origin_index.remove(&asn);
+                }
+            }
+        }
+        if let Some(asn) = added {
+            *origin_index.entry(asn).or_default().entry(net).or_default() += 1;
+        }
+    }
 
-        match entry.binary_search_by_key(&path_id, |(k, _)| *k) {
-            Ok(index) => drop(std::mem::replace(&mut entry[index], (path_id, compressed))),
-            Err(index) => entry.insert(index, (path_id, compressed)),
+    /// Routes originated by `asn` which match the query. Exact and or-longer queries are
+    /// answered from the origin index, the others scan the matching prefixes of the table.
+    pub fn get_routes_by_origin(&self, asn: u32, net_query: &NetQuery) -> Vec<(IpNet, RouteEntry)> {
+        let nets = match net_query {
+            NetQuery::Exact(query_net) | NetQuery::OrLonger(query_net) => {
+                let origin_index = self.origin_index.lock().unwrap();
+                let mut nets = origin_index
+                    .get(&asn)
+                    .into_iter()
+                    .flat_map(|nets| nets.keys())
+                    .filter(|net| match net_query {
+                        NetQuery::Exact(_) => *net == query_net,
+                        _ => query_net.contains(*net),
+                    })
+                    .copied()
+                    .collect::<Vec<_>>();
+                nets.sort();
+                nets
+            }
+            _ => {
+                let mut nets = self.with_routes(Some(net_query), |routes| {
+                    routes.map(|(net, _)| net).collect::<Vec<_>>()
+                });
+                nets.sort();
+                nets.dedup();
+                nets
+            }
+        };
+        nets.into_iter()
+            .flat_map(|net| self.get_routes(Some(&NetQuery::Exact(net))))
+            .filter(|(_, route)| origin_asn(&route.attrs) == Some(asn))
+            .collect()
+    }
+
+    /// Routes matching the query as they were before the paths in `overlay` changed, i.e. with
+    /// these paths replaced by their previous state (None if they did not exist)
+    pub fn get_routes_with_overlay(
+        &self,
+        net_query: &NetQuery,
+        overlay: &HashMap<(IpNet, PathId), Option<RouteEntry>>,
+    ) -> Vec<(IpNet, RouteEntry)> {
+        // the most specific prefix may be one of the overlay, so all candidates are collected
+        let candidates_query = match net_query {
+            NetQuery::MostSpecific(net) => NetQuery::Contains(*net),
+            net_query => net_query.clone(),
         };
+        let overlay_matches = |net: &IpNet| match &candidates_query {
+            NetQuery::Contains(query_net) | NetQuery::MostSpecific(query_net) => {
+                net.contains(query_net)
+            }
+            NetQuery::Exact(query_net) => net == query_net,
+            NetQuery::OrLonger(query_net) => query_net.contains(net),
+        };
+
+        let mut routes = self
+            .get_routes(Some(&candidates_query))
+            .into_iter()
+            .filter(|(net, route)| !overlay.contains_key(&(*net, route.path_id)))
+            .collect::<Vec<_>>();
+        routes.extend(
+            overlay
+                .iter()
+                .filter(|((net, _), _)| overlay_matches(net))
+                .filter_map(|((net, _), route)| Some((*net, route.clone()?))),
+        );
+        if let NetQuery::MostSpecific(_) = net_query {
+            let longest = routes.iter().map(|(net, _)| net.prefix_len()).max();
+            routes.retain(|(net, _)| Some(net.prefix_len()) == longest);
+        }
+        routes.sort_by_key(|(net, route)| (*net, route.path_id));
+        routes
+    }
+
+    pub fn contains(&self, path_id: PathId, net: IpNet) -> bool {
+        let table = self.table.lock().unwrap();
+        table
+            .exact(&net)
+            .map(|paths| paths.paths.iter().any(|path| path.path_id == path_id))
+            .unwrap_or(false)
+            || self
+                .cold
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(|cold| cold.contains(net, path_id))
+                .unwrap_or(false)
+    }
+
+    /// Remove a path from the cold tier, the hot tier has to be locked
+    fn remove_cold(&self, net: IpNet, path_id: PathId) -> Option<RouteEntry> {
+        self.cold
+            .lock()
+            .unwrap()
+            .as_mut()?
+            .remove(net, path_id, &self.caches)
+    }
+
+    /// Move the paths which did not change since `before` (unix timestamp in seconds) to the cold
+    /// tier, which is created in `dir` if needed. Returns the number of moved paths.
+    pub fn demote(&self, before: u64, dir: &Path) -> std::io::Result<usize> {
+        let mut table = self.table.lock().unwrap();
+        let mut cold = self.cold.lock().unwrap();
+        let routes = table
+            .iter()
+            .flat_map(|(net, paths)| {
+                paths
+                    .paths
+                    .iter()
+                    .filter(|path| path.since < before)
+                    .map(move |path| (net, paths.entry(path)))
+            })
+            .collect::<Vec<_>>();
+        if cold.is_none() {
+            if routes.is_empty() {
+                return Ok(0);
+            }
+            *cold = Some(ColdTier::create(dir)?);
+        }
+        let cold = cold.as_mut().unwrap();
+        cold.append(&routes)?;
 
-        if let Some(insert) = new_insert {
-            table.insert(&net, insert);
+        let mut empty_nets = vec![];
+        for (net, paths) in table.iter_mut() {
+            paths.paths.retain(|path| path.since >= before);
+            paths.rebase(&self.caches);
+            if paths.is_empty() {
+                empty_nets.push(net);
+            }
+        }
+        for net in empty_nets {
+            table.remove(&net);
         }
+        cold.compact()?;
+        Ok(routes.len())
     }
 
-    pub async fn withdraw_route(&self, path_id: PathId, net: IpNet) {
+    /// Returns the stored attributes and the path which was replaced, if any
+    pub async fn update_route(
+        &self,
+        path_id: PathId,
+        net: IpNet,
+        route: RouteAttrs,
+    ) -> (Arc<CompressedRouteAttrs>, Option<RouteEntry>) {
         let mut table = self.table.lock().unwrap();
+        self.last_update.store(unix_timestamp(), Ordering::Relaxed);
+        // a path is only stored in one of the tiers, an update moves it back to the hot tier
+        let cold_path = self.remove_cold(net, path_id);
+        let cold_since = |attrs: &CompressedRouteAttrs| {
+            cold_path
+                .as_ref()
+                .filter(|old| *old.attrs == *attrs)
+                .map(|old| old.since)
+        };
+
+        let Some(paths) = table.exact_mut(&net) else {
+            let compressed = self.caches.lock().unwrap().compress_route_attrs(route);
+            table.insert(
+                &net,
+                PathSet {
+                    base: compressed.clone(),
+                    paths: vec![StoredPath {
+                        path_id,
+                        stale: false,
+                        since: cold_since(&compressed).unwrap_or(unix_timestamp()),
+                        attrs: PathAttrs::Base,
+                    }],
+                },
+            );
+            if cold_path.is_none() {
+                self.add_paths(1);
+            }
+            self.update_origin_index(
+                net,
+                cold_path.as_ref().map(|old| &*old.attrs),
+                Some(&compressed),
+            );
+            return (compressed, cold_path);
+        };
+
+        let attrs = if !same_except_nexthop(&paths.base, &route) {
+            PathAttrs::Full(self.caches.lock().unwrap().compress_route_attrs(route))
+        } else if paths.base.nexthop == route.nexthop
+            && paths.base.nexthop_link_local == route.nexthop_link_local
+        {
+            PathAttrs::Base
+        } else {
+            PathAttrs::NexthopDelta {
+                nexthop: route.nexthop,
+                nexthop_link_local: route.nexthop_link_local,
+            }
+        };
+        let mut new_path = StoredPath {
+            path_id,
+            stale: false,
+            since: unix_timestamp(),
+            attrs,
+        };
+        let new_attrs = paths.attrs(&new_path);
+
+        let old = match paths.paths.binary_search_by_key(&path_id, |p| p.path_id) {
+            Ok(index) => {
+                // re-announcements with the same attributes do not reset the age
+                let old_path = &paths.paths[index];
+                if *paths.attrs(old_path) == *new_attrs {
+                    new_path.since = old_path.since;
+                }
+                let old = std::mem::replace(&mut paths.paths[index], new_path);
+                let old = paths.entry(&old);
+                paths.rebase(&self.caches);
+                Some(old)
+            }
+            Err(index) => {
+                if let Some(since) = cold_since(&new_attrs) {
+                    new_path.since = since;
+                }
+                paths.paths.insert(index, new_path);
+                if cold_path.is_none() {
+                    self.add_paths(1);
+                }
+                cold_path
+            }
+        };
+        self.update_origin_index(net, old.as_ref().map(|old| &*old.attrs), Some(&new_attrs));
 
+        (new_attrs, old)
+    }
+
+    /// Returns the removed path, if it existed
+    pub async fn withdraw_route(&self, path_id: PathId, net: IpNet) -> Option<RouteEntry> {
+        let mut table = self.table.lock().unwrap();
+        self.last_update.store(unix_timestamp(), Ordering::Relaxed);
+
+        let mut removed = None;
         let is_empty = match table.exact_mut(&net) {
-            Some(entry) => {
-                if let Ok(index) = entry.binary_search_by_key(&path_id, |(k, _)| *k) {
-                    entry.remove(index);
+            Some(paths) => {
+                if let Ok(index) = paths.paths.binary_search_by_key(&path_id, |p| p.path_id) {
+                    let path = paths.paths.remove(index);
+                    let path = paths.entry(&path);
+                    paths.rebase(&self.caches);
+                    self.update_origin_index(net, Some(&path.attrs), None);
+                    self.remove_paths(1);
+                    removed = Some(path);
                 }
-                entry.is_empty()
+                paths.is_empty()
             }
-            None => return,
+            None => false,
         };
         if is_empty {
             table.remove(&net);
         }
+        if removed.is_none() {
+            removed = self.remove_cold(net, path_id);
+            if let Some(path) = &removed {
+                self.update_origin_index(net, Some(&path.attrs), None);
+                self.remove_paths(1);
+            }
+        }
+        removed
+    }
+
+    /// Mark every route currently in the table as stale. Routes lose the mark once they are
+    /// updated again.
+    pub async fn mark_stale(&self) {
+        let mut table = self.table.lock().unwrap();
+        for paths in table.values_mut() {
+            for path in paths.paths.iter_mut() {
+                path.stale = true;
+            }
+        }
+        if let Some(cold) = self.cold.lock().unwrap().as_mut() {
+            cold.mark_stale();
+        }
+    }
+
+    /// Remove all routes which are still marked as stale, returns the removed paths
+    pub async fn purge_stale(&self) -> Vec<(IpNet, RouteEntry)> {
+        let mut table = self.table.lock().unwrap();
+
+        let mut removed = vec![];
+        let mut empty_nets = vec![];
+        for (net, paths) in table.iter_mut() {
+            let removed_paths = paths
+                .paths
+                .iter()
+                .filter(|path| path.stale)
+                .map(|path| paths.entry(path))
+                .collect::<Vec<_>>();
+            paths.paths.retain(|path| !path.stale);
+            paths.rebase(&self.caches);
+            for route in removed_paths {
+                self.update_origin_index(net, Some(&route.attrs), None);
+                removed.push((net, route));
+            }
+            if paths.is_empty() {
+                empty_nets.push(net);
+            }
+        }
+        for net in empty_nets {
+            table.remove(&net);
+        }
+        if let Some(cold) = self.cold.lock().unwrap().as_mut() {
+            for (net, route) in cold.purge_stale(&self.caches) {
+                self.update_origin_index(net, Some(&route.attrs), None);
+                removed.push((net, route));
+            }
+        }
+        self.remove_paths(removed.len());
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::as_path::AsPath;
+
+    fn route(nexthop: &str) -> RouteAttrs {
+        RouteAttrs {
+            as_path: Some(AsPath::from_sequence(vec![64496, 64497])),
+            nexthop: Some(nexthop.parse().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_base_path() {
+        let table = InMemoryTable::new(Default::default(), None);
+        let net = "192.0.2.0/24".parse().unwrap();
+        let base = Arc::downgrade(&table.update_route(1, net, route("198.51.100.1")).await.0);
+        table.update_route(2, net, route("198.51.100.2")).await;
+        table.update_route(3, net, route("198.51.100.3")).await;
+
+        let withdrawn = table.withdraw_route(1, net).await.unwrap();
+        assert_eq!(
+            withdrawn.attrs.nexthop,
+            Some("198.51.100.1".parse().unwrap())
+        );
+        drop(withdrawn);
+        // the attributes of the withdrawn path are not kept as the base
+        assert!(base.upgrade().is_none());
+        // the new base is interned
+        let interned = table
+            .caches
+            .lock()
+            .unwrap()
+            .compress_route_attrs(route("198.51.100.2"));
+
+        let routes = table.get_routes(None);
+        let nexthops = routes
+            .iter()
+            .map(|(_, route)| route.attrs.nexthop.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(nexthops, vec!["198.51.100.2", "198.51.100.3"]);
+        assert!(routes
+            .iter()
+            .all(|(_, route)| route.attrs.as_path.as_deref()
+                == Some(&AsPath::from_sequence(vec![64496, 64497]))));
+
+        {
+            let hot = table.table.lock().unwrap();
+            let paths = hot.exact(&net).unwrap();
+            assert_eq!(paths.base.nexthop, Some("198.51.100.2".parse().unwrap()));
+            assert!(Arc::ptr_eq(&paths.base, &interned));
+            assert!(matches!(paths.paths[0].attrs, PathAttrs::Base));
+            assert!(matches!(
+                paths.paths[1].attrs,
+                PathAttrs::NexthopDelta { .. }
+            ));
+        }
+
+        table.withdraw_route(2, net).await;
+        table.withdraw_route(3, net).await;
+        assert_eq!(table.route_count(), 0);
+        assert!(table.get_routes(None).is_empty());
     }
 }